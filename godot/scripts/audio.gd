@@ -1,5 +0,0 @@
-extends AudioStreamPlayer
-
-
-func _on_finished():
-	play()
@@ -1,9 +1,12 @@
-use crate::level::{Effect, EffectStats, EnemyKind, ItemKind};
+use crate::core::{Effect, EffectStats};
+use crate::level::{EnemyKind, ItemKind};
+use crate::mods;
 
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::OnceLock;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Ability {
     Whip,
     CrossbowIronBolt,
@@ -13,6 +16,10 @@ pub enum Ability {
     Hellfire,
     VampireBite,
     Mist,
+    Teleport,
+    Blessing,
+    Carry,
+    Smite,
     WoodenStake,
     Garlic,
     HolyWater,
@@ -20,9 +27,28 @@ pub enum Ability {
     VampireScratch,
     BigBatBite,
     SpawnBat,
+    DrainLife,
+    Resurrect,
+    Claw,
+    Charge,
+    ChillingTouch,
+    Key,
+    Lockpick,
+    Search,
+    Pull,
+    Spoil,
+    FireFlask,
+    Guard,
+    Taunt,
+    HolyFlash,
+    Darkness,
+    LungingStake,
+    Pounce,
+    // Brought in by a mod pack, which gives it its stats and puts it on a list
+    Custom(u16),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum DamageKind {
     Normal,
     Silver,
@@ -31,9 +57,37 @@ pub enum DamageKind {
     LifeSteal,
     Stake,
     Sunlight,
+    // Nothing built in deals it, it's there for mod packs' abilities
+    Pierce,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+impl DamageKind {
+    pub const ALL: [DamageKind; 8] = [
+        DamageKind::Normal,
+        DamageKind::Silver,
+        DamageKind::Holy,
+        DamageKind::Fire,
+        DamageKind::LifeSteal,
+        DamageKind::Stake,
+        DamageKind::Sunlight,
+        DamageKind::Pierce,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Normal => "Normal",
+            Self::Silver => "Silver",
+            Self::Holy => "Holy",
+            Self::Fire => "Fire",
+            Self::LifeSteal => "Life steal",
+            Self::Stake => "Stake",
+            Self::Sunlight => "Sunlight",
+            Self::Pierce => "Piercing",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum Action {
     Attack {
         damage_kind: DamageKind,
@@ -49,6 +103,10 @@ pub enum Action {
         effect: Effect,
         stats: EffectStats,
     },
+    Inflict {
+        effect: Effect,
+        stats: EffectStats,
+    },
     PlaceItem {
         kind: ItemKind,
     },
@@ -56,11 +114,71 @@ pub enum Action {
         enemy_kind: EnemyKind,
         cooldown: u16,
     },
+    Resurrect {
+        cooldown: u16,
+    },
+    Charge {
+        damage_kind: DamageKind,
+        damage: u16,
+        distance: u16,
+    },
+    // Runs up to distance tiles to a foe, around whatever is in the way, and strikes it from
+    // within range, all as one action
+    DashAttack {
+        damage_kind: DamageKind,
+        damage: u16,
+        distance: u16,
+    },
+    Teleport,
+    Carry,
+    // Works at an adjacent locked door, opening it once the lock has been worked at for as many
+    // turns
+    Unlock {
+        turns: u16,
+    },
+    // Goes through an adjacent chest or bookshelf, once
+    Search,
+    // Throws an adjacent lever, and with it everything on the lever's switch
+    Pull,
+    // Ruins an item lying on the ground in reach, so no one can pick it up
+    Spoil,
+}
+
+impl Action {
+    // Whether the ability flies or arcs over obstacles that don't block sight, such as low walls.
+    // Anything swung or bitten has to reach past them instead
+    pub fn clears_low_obstacles(&self) -> bool {
+        match self {
+            Self::Attack { damage_kind, .. }
+            | Self::Push { damage_kind, .. }
+            | Self::Charge { damage_kind, .. }
+            | Self::DashAttack { damage_kind, .. } => !matches!(
+                damage_kind,
+                DamageKind::Normal | DamageKind::LifeSteal | DamageKind::Stake
+            ),
+            _ => true,
+        }
+    }
+
+    // Whether enemies can be given it. The planner has no idea how to use the rest, which only
+    // the hunters ever do
+    pub fn plannable(&self) -> bool {
+        !matches!(
+            self,
+            Self::PlaceItem { .. }
+                | Self::Teleport
+                | Self::Carry
+                | Self::Unlock { .. }
+                | Self::Search
+                | Self::Pull
+        )
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AbilityStats {
     pub name: String,
+    pub description: String,
     pub action: Action,
     pub range: u16,
     pub acquirable: bool,
@@ -68,42 +186,119 @@ pub struct AbilityStats {
     pub persistent: bool,
 }
 
+// A starting loadout as a level scene writes it, by ability name. Names that aren't abilities are
+// left out
+pub fn parse_loadout(entries: &[(String, u16)]) -> Vec<(Ability, u16)> {
+    entries
+        .iter()
+        .filter_map(|(name, uses)| {
+            serde_json::from_value::<Ability>(serde_json::Value::String(name.clone()))
+                .ok()
+                .map(|ability| (ability, *uses))
+        })
+        .collect()
+}
+
 pub fn ability_lists() -> &'static Vec<Vec<(Ability, u16)>> {
     static ABILITY_LISTS: OnceLock<Vec<Vec<(Ability, u16)>>> = OnceLock::new();
-    ABILITY_LISTS.get_or_init(|| init_ability_lists())
+    ABILITY_LISTS.get_or_init(|| {
+        let mut lists = init_ability_lists();
+        mods::overlay_lists(&mut lists, |pack| &pack.ability_lists);
+        lists
+    })
 }
 
-fn init_ability_lists() -> Vec<Vec<(Ability, u16)>> {
+// The built-in lists the enemy scenes are given
+pub const ENEMY_ABILITY_LISTS: [usize; 6] = [2, 3, 4, 5, 6, 7];
+
+pub fn init_ability_lists() -> Vec<Vec<(Ability, u16)>> {
     vec![
         vec![
             (Ability::Whip, 1),
             (Ability::CrossbowIronBolt, 5),
             (Ability::CrossbowSilverBolt, 2),
             (Ability::Thwack, 2),
+            (Ability::Blessing, 2),
+            (Ability::Carry, 1),
+            (Ability::Lockpick, 1),
+            (Ability::Search, 1),
+            (Ability::Pull, 1),
+            (Ability::Guard, 1),
+            (Ability::Taunt, 1),
+            (Ability::LungingStake, 1),
         ],
         vec![
             (Ability::Sword, 1),
             (Ability::Hellfire, 3),
             (Ability::VampireBite, 1),
             (Ability::Mist, 1),
+            (Ability::Teleport, 2),
         ],
         vec![(Ability::BatBite, 1)],
-        vec![(Ability::VampireScratch, 1), (Ability::VampireBite, 1)],
+        vec![
+            (Ability::VampireScratch, 1),
+            (Ability::VampireBite, 1),
+            (Ability::Mist, 1),
+            (Ability::Spoil, 1),
+        ],
         vec![(Ability::BigBatBite, 1), (Ability::SpawnBat, 1)],
+        vec![
+            (Ability::DrainLife, 1),
+            (Ability::Resurrect, 1),
+            (Ability::Darkness, 1),
+        ],
+        vec![
+            (Ability::Claw, 1),
+            (Ability::Charge, 1),
+            (Ability::Pounce, 1),
+        ],
+        vec![(Ability::ChillingTouch, 1)],
+        vec![
+            (Ability::Smite, 1),
+            (Ability::Blessing, 3),
+            (Ability::HolyFlash, 2),
+            (Ability::Search, 1),
+            (Ability::Pull, 1),
+        ],
     ]
 }
 
 pub fn abilities() -> &'static HashMap<Ability, AbilityStats> {
     static ABILITIES: OnceLock<HashMap<Ability, AbilityStats>> = OnceLock::new();
-    ABILITIES.get_or_init(|| init_abilities())
+    ABILITIES.get_or_init(|| {
+        let mut abilities = init_abilities();
+        mods::overlay_abilities(&mut abilities);
+        abilities
+    })
+}
+
+// Picked up items an ally has on them, ammunition for their own weapons aside
+pub fn items_carried(uses: &HashMap<Ability, u16>) -> u16 {
+    uses.iter()
+        .filter(|(ability, _)| abilities().get(ability).unwrap().acquirable)
+        .map(|(_, uses)| *uses)
+        .sum()
+}
+
+// Anything that isn't a picked up item always fits
+pub fn has_room_for(uses: &HashMap<Ability, u16>, capacity: u16, ability: Ability) -> bool {
+    !abilities().get(&ability).unwrap().acquirable || items_carried(uses) < capacity
+}
+
+// Picked up items can go to anyone, anything else only to an ally who already has it
+pub fn can_hand_over(ability: Ability, known: bool) -> bool {
+    let stats = abilities().get(&ability).unwrap();
+    stats.consumable && (stats.acquirable || known)
 }
 
-fn init_abilities() -> HashMap<Ability, AbilityStats> {
+pub fn init_abilities() -> HashMap<Ability, AbilityStats> {
     [
         (
             Ability::Whip,
             AbilityStats {
                 name: "Whip".into(),
+
+                description: "A silver-tipped lash that reaches a tile further than a blade".into(),
                 action: Action::Attack {
                     damage_kind: DamageKind::Silver,
                     damage: 2,
@@ -119,6 +314,8 @@ fn init_abilities() -> HashMap<Ability, AbilityStats> {
             Ability::CrossbowIronBolt,
             AbilityStats {
                 name: "Crossbow (Iron Bolts)".into(),
+
+                description: "Fires an iron bolt in a straight line, stopped by anything in the way".into(),
                 action: Action::Attack {
                     damage_kind: DamageKind::Normal,
                     damage: 2,
@@ -134,6 +331,8 @@ fn init_abilities() -> HashMap<Ability, AbilityStats> {
             Ability::CrossbowSilverBolt,
             AbilityStats {
                 name: "Crossbow (Silver Bolts)".into(),
+
+                description: "Fires a silver bolt in a straight line, stopped by anything in the way".into(),
                 action: Action::Attack {
                     damage_kind: DamageKind::Silver,
                     damage: 2,
@@ -149,6 +348,8 @@ fn init_abilities() -> HashMap<Ability, AbilityStats> {
             Ability::Thwack,
             AbilityStats {
                 name: "Thwack".into(),
+
+                description: "A heavy swing of the whip that knocks the target back until it hits something".into(),
                 action: Action::Push {
                     damage_kind: DamageKind::Silver,
                     damage: 2,
@@ -164,6 +365,8 @@ fn init_abilities() -> HashMap<Ability, AbilityStats> {
             Ability::Sword,
             AbilityStats {
                 name: "Sword".into(),
+
+                description: "A plain steel blade for close quarters".into(),
                 action: Action::Attack {
                     damage_kind: DamageKind::Normal,
                     damage: 2,
@@ -179,6 +382,8 @@ fn init_abilities() -> HashMap<Ability, AbilityStats> {
             Ability::Hellfire,
             AbilityStats {
                 name: "Hellfire".into(),
+
+                description: "Hurls a ball of fire that sets the target burning for three turns".into(),
                 action: Action::Attack {
                     damage_kind: DamageKind::Fire,
                     damage: 2,
@@ -194,6 +399,8 @@ fn init_abilities() -> HashMap<Ability, AbilityStats> {
             Ability::VampireBite,
             AbilityStats {
                 name: "Vampire Bite".into(),
+
+                description: "Drinks from an adjacent foe, healing as much as it drains".into(),
                 action: Action::Attack {
                     damage_kind: DamageKind::LifeSteal,
                     damage: 1,
@@ -209,6 +416,8 @@ fn init_abilities() -> HashMap<Ability, AbilityStats> {
             Ability::Mist,
             AbilityStats {
                 name: "Mist".into(),
+
+                description: "Dissolves into mist, letting every hit pass through until it wears off".into(),
                 action: Action::Effect {
                     effect: Effect::Mist,
                     stats: EffectStats {
@@ -222,10 +431,74 @@ fn init_abilities() -> HashMap<Ability, AbilityStats> {
                 persistent: false,
             },
         ),
+        (
+            Ability::Teleport,
+            AbilityStats {
+                name: "Misty Step".into(),
+
+                description: "Vanishes in mist and reforms on a visible tile nearby".into(),
+                action: Action::Teleport,
+                range: 4,
+                acquirable: false,
+                consumable: true,
+                persistent: false,
+            },
+        ),
+        (
+            Ability::Blessing,
+            AbilityStats {
+                name: "Blessing".into(),
+
+                description: "Lays a blessing on a foe, leaving it open to holy damage".into(),
+                action: Action::Inflict {
+                    effect: Effect::Blessing,
+                    stats: EffectStats {
+                        magnitude: 0,
+                        duration: 2,
+                    },
+                },
+                range: 4,
+                acquirable: false,
+                consumable: true,
+                persistent: false,
+            },
+        ),
+        (
+            Ability::Carry,
+            AbilityStats {
+                name: "Carry".into(),
+
+                description: "Lifts an adjacent ally onto your back, or sets them down again".into(),
+                action: Action::Carry,
+                range: 1,
+                acquirable: false,
+                consumable: false,
+                persistent: false,
+            },
+        ),
+        (
+            Ability::Smite,
+            AbilityStats {
+                name: "Smite".into(),
+
+                description: "Strikes an adjacent foe with holy light".into(),
+                action: Action::Attack {
+                    damage_kind: DamageKind::Holy,
+                    damage: 2,
+                    aoe: false,
+                },
+                range: 1,
+                acquirable: false,
+                consumable: false,
+                persistent: false,
+            },
+        ),
         (
             Ability::WoodenStake,
             AbilityStats {
                 name: "Wooden Stake".into(),
+
+                description: "Driven through the heart, it ends any vampire outright".into(),
                 action: Action::Attack {
                     damage_kind: DamageKind::Stake,
                     damage: 1,
@@ -241,6 +514,8 @@ fn init_abilities() -> HashMap<Ability, AbilityStats> {
             Ability::Garlic,
             AbilityStats {
                 name: "Garlic".into(),
+
+                description: "Thrown onto a tile, it wards it and its neighbours against the allergic".into(),
                 action: Action::PlaceItem {
                     kind: ItemKind::Garlic,
                 },
@@ -254,6 +529,8 @@ fn init_abilities() -> HashMap<Ability, AbilityStats> {
             Ability::HolyWater,
             AbilityStats {
                 name: "Holy Water".into(),
+
+                description: "A thrown flask that scalds the unholy".into(),
                 action: Action::Attack {
                     damage_kind: DamageKind::Holy,
                     damage: 2,
@@ -265,10 +542,29 @@ fn init_abilities() -> HashMap<Ability, AbilityStats> {
                 persistent: true,
             },
         ),
+        (
+            Ability::FireFlask,
+            AbilityStats {
+                name: "Fire Flask".into(),
+
+                description: "Lamp oil that bursts into flame wherever it breaks".into(),
+                action: Action::Attack {
+                    damage_kind: DamageKind::Fire,
+                    damage: 1,
+                    aoe: true,
+                },
+                range: 4,
+                acquirable: true,
+                consumable: true,
+                persistent: true,
+            },
+        ),
         (
             Ability::BatBite,
             AbilityStats {
                 name: "Bat Bite".into(),
+
+                description: "A small but persistent nip".into(),
                 action: Action::Attack {
                     damage_kind: DamageKind::Normal,
                     damage: 1,
@@ -284,6 +580,8 @@ fn init_abilities() -> HashMap<Ability, AbilityStats> {
             Ability::VampireScratch,
             AbilityStats {
                 name: "Vampire Scratch".into(),
+
+                description: "A swipe of long nails".into(),
                 action: Action::Attack {
                     damage_kind: DamageKind::Normal,
                     damage: 2,
@@ -299,6 +597,8 @@ fn init_abilities() -> HashMap<Ability, AbilityStats> {
             Ability::BigBatBite,
             AbilityStats {
                 name: "Big Bat Bite".into(),
+
+                description: "A bite from something far too large to be a bat".into(),
                 action: Action::Attack {
                     damage_kind: DamageKind::Normal,
                     damage: 2,
@@ -314,6 +614,8 @@ fn init_abilities() -> HashMap<Ability, AbilityStats> {
             Ability::SpawnBat,
             AbilityStats {
                 name: "Spawn Bat".into(),
+
+                description: "Calls a bat out of the dark onto an empty tile nearby".into(),
                 action: Action::Spawn {
                     enemy_kind: EnemyKind::Bat,
                     cooldown: 3,
@@ -324,6 +626,307 @@ fn init_abilities() -> HashMap<Ability, AbilityStats> {
                 persistent: false,
             },
         ),
+        (
+            Ability::DrainLife,
+            AbilityStats {
+                name: "Drain Life".into(),
+
+                description: "Pulls the life out of a foe at a distance, healing as much as it drains".into(),
+                action: Action::Attack {
+                    damage_kind: DamageKind::LifeSteal,
+                    damage: 1,
+                    aoe: false,
+                },
+                range: 3,
+                acquirable: false,
+                consumable: false,
+                persistent: false,
+            },
+        ),
+        (
+            Ability::Resurrect,
+            AbilityStats {
+                name: "Resurrect".into(),
+
+                description: "Raises a fallen bloodsucker from its grave".into(),
+                action: Action::Resurrect { cooldown: 4 },
+                range: 6,
+                acquirable: false,
+                consumable: false,
+                persistent: false,
+            },
+        ),
+        (
+            Ability::Claw,
+            AbilityStats {
+                name: "Claw".into(),
+
+                description: "A rake of claws".into(),
+                action: Action::Attack {
+                    damage_kind: DamageKind::Normal,
+                    damage: 2,
+                    aoe: false,
+                },
+                range: 1,
+                acquirable: false,
+                consumable: false,
+                persistent: false,
+            },
+        ),
+        (
+            Ability::ChillingTouch,
+            AbilityStats {
+                name: "Chilling Touch".into(),
+
+                description: "A grave-cold hand that saps the living".into(),
+                action: Action::Attack {
+                    damage_kind: DamageKind::Normal,
+                    damage: 2,
+                    aoe: false,
+                },
+                range: 1,
+                acquirable: false,
+                consumable: false,
+                persistent: false,
+            },
+        ),
+        (
+            Ability::Charge,
+            AbilityStats {
+                name: "Charge".into(),
+
+                description: "Rushes at a foe in a straight line and bowls it over".into(),
+                action: Action::Charge {
+                    damage_kind: DamageKind::Normal,
+                    damage: 2,
+                    distance: 2,
+                },
+                range: 5,
+                acquirable: false,
+                consumable: false,
+                persistent: false,
+            },
+        ),
+        (
+            Ability::Key,
+            AbilityStats {
+                name: "Key".into(),
+
+                description: "Turns the lock of an adjacent door, and stays in it".into(),
+                action: Action::Unlock { turns: 1 },
+                range: 1,
+                acquirable: true,
+                consumable: true,
+                persistent: true,
+            },
+        ),
+        (
+            Ability::Lockpick,
+            AbilityStats {
+                name: "Lockpick".into(),
+
+                description: "Slowly works the lock of an adjacent door open without a key".into(),
+                action: Action::Unlock { turns: 3 },
+                range: 1,
+                acquirable: false,
+                consumable: false,
+                persistent: false,
+            },
+        ),
+        (
+            Ability::Search,
+            AbilityStats {
+                name: "Search".into(),
+
+                description: "Rummages through an adjacent chest or bookshelf for anything useful".into(),
+                action: Action::Search,
+                range: 1,
+                acquirable: false,
+                consumable: false,
+                persistent: false,
+            },
+        ),
+        (
+            Ability::Pull,
+            AbilityStats {
+                name: "Pull".into(),
+
+                description: "Throws an adjacent lever to see what it works".into(),
+                action: Action::Pull,
+                range: 1,
+                acquirable: false,
+                consumable: false,
+                persistent: false,
+            },
+        ),
+        (
+            Ability::Spoil,
+            AbilityStats {
+                name: "Spoil".into(),
+
+                description: "Fouls anything the hunters left lying about so it's no use to them".into(),
+                action: Action::Spoil,
+                range: 2,
+                acquirable: false,
+                consumable: false,
+                persistent: false,
+            },
+        ),
+        (
+            Ability::HolyFlash,
+            AbilityStats {
+                name: "Holy Flash".into(),
+
+                description: "A burst of holy light that leaves a foe groping in the dark".into(),
+                action: Action::Inflict {
+                    effect: Effect::Blinded,
+                    stats: EffectStats {
+                        magnitude: 0,
+                        duration: 2,
+                    },
+                },
+                range: 3,
+                acquirable: false,
+                consumable: true,
+                persistent: false,
+            },
+        ),
+        (
+            Ability::Darkness,
+            AbilityStats {
+                name: "Darkness".into(),
+
+                description: "Draws a shroud over a hunter's eyes".into(),
+                action: Action::Inflict {
+                    effect: Effect::Darkness,
+                    stats: EffectStats {
+                        magnitude: 3,
+                        duration: 2,
+                    },
+                },
+                range: 4,
+                acquirable: false,
+                consumable: false,
+                persistent: false,
+            },
+        ),
+        (
+            Ability::LungingStake,
+            AbilityStats {
+                name: "Lunging Stake".into(),
+
+                description: "A rush across the room that ends with a stake through the heart".into(),
+                action: Action::DashAttack {
+                    damage_kind: DamageKind::Stake,
+                    damage: 1,
+                    distance: 3,
+                },
+                range: 1,
+                acquirable: false,
+                consumable: true,
+                persistent: false,
+            },
+        ),
+        (
+            Ability::Pounce,
+            AbilityStats {
+                name: "Pounce".into(),
+
+                description: "Bounds after its prey and tears into it".into(),
+                action: Action::DashAttack {
+                    damage_kind: DamageKind::Normal,
+                    damage: 2,
+                    distance: 7,
+                },
+                range: 1,
+                acquirable: false,
+                consumable: false,
+                persistent: false,
+            },
+        ),
+        (
+            Ability::Guard,
+            AbilityStats {
+                name: "Guard".into(),
+
+                description: "Stands ready to step in front of the next blow aimed at an ally beside you".into(),
+                action: Action::Effect {
+                    effect: Effect::Guarding,
+                    stats: EffectStats {
+                        magnitude: 1,
+                        duration: 1,
+                    },
+                },
+                range: 0,
+                acquirable: false,
+                consumable: false,
+                persistent: false,
+            },
+        ),
+        (
+            Ability::Taunt,
+            AbilityStats {
+                name: "Taunt".into(),
+
+                description: "Bellows a challenge that no bloodsucker nearby can ignore".into(),
+                action: Action::Effect {
+                    effect: Effect::Taunting,
+                    stats: EffectStats {
+                        magnitude: 4,
+                        duration: 1,
+                    },
+                },
+                range: 0,
+                acquirable: false,
+                consumable: false,
+                persistent: false,
+            },
+        ),
     ]
     .into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_consumables_change_hands() {
+        assert!(can_hand_over(Ability::HolyWater, false));
+        assert!(can_hand_over(Ability::Key, false));
+        // Bolts need a crossbow to go with them
+        assert!(!can_hand_over(Ability::CrossbowSilverBolt, false));
+        assert!(can_hand_over(Ability::CrossbowSilverBolt, true));
+        assert!(!can_hand_over(Ability::Whip, true));
+        assert!(!can_hand_over(Ability::Guard, false));
+    }
+
+    #[test]
+    fn full_hands_only_turn_away_picked_up_items() {
+        let uses = HashMap::from([
+            (Ability::HolyWater, 2),
+            (Ability::Garlic, 1),
+            (Ability::CrossbowIronBolt, 5),
+        ]);
+        assert_eq!(items_carried(&uses), 3);
+        assert!(has_room_for(&uses, 4, Ability::WoodenStake));
+        assert!(!has_room_for(&uses, 3, Ability::WoodenStake));
+        assert!(!has_room_for(&uses, 3, Ability::HolyWater));
+        // Bolts are ammunition and never take up room
+        assert!(has_room_for(&uses, 3, Ability::CrossbowIronBolt));
+    }
+
+    #[test]
+    fn scene_loadouts_are_read_by_ability_name() {
+        let entries = vec![
+            ("WoodenStake".to_string(), 1),
+            ("Crowbar".to_string(), 2),
+            ("CrossbowSilverBolt".to_string(), 4),
+        ];
+        assert_eq!(
+            parse_loadout(&entries),
+            vec![(Ability::WoodenStake, 1), (Ability::CrossbowSilverBolt, 4)]
+        );
+        assert!(parse_loadout(&[]).is_empty());
+    }
+}
@@ -0,0 +1,288 @@
+use crate::ability::DamageKind;
+use crate::core::UnitId;
+use crate::dialogue::Room;
+use crate::event::GameEvent;
+use crate::level::{EnemyKind, Level};
+use crate::save;
+
+use godot::engine::file_access::ModeFlags;
+use godot::engine::FileAccess;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const PATH: &str = "user://achievements.json";
+// Seconds an unlock stays on the HUD
+const ANNOUNCE_TIME: f64 = 5.0;
+
+// Counted over the player's lifetime and over each level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Stat {
+    EnemiesSlain,
+    VampiresStaked,
+    DamageTaken,
+    AbilitiesUsed,
+    ItemsPickedUp,
+    RoomsCleared,
+}
+
+impl Stat {
+    pub const ALL: [Stat; 6] = [
+        Stat::EnemiesSlain,
+        Stat::VampiresStaked,
+        Stat::DamageTaken,
+        Stat::AbilitiesUsed,
+        Stat::ItemsPickedUp,
+        Stat::RoomsCleared,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::EnemiesSlain => "Enemies slain",
+            Self::VampiresStaked => "Vampires staked",
+            Self::DamageTaken => "Damage taken",
+            Self::AbilitiesUsed => "Abilities used",
+            Self::ItemsPickedUp => "Items picked up",
+            Self::RoomsCleared => "Rooms cleared",
+        }
+    }
+
+    // What an event adds to each statistic, if anything
+    pub fn of(event: GameEvent, level: &Level) -> Vec<(Stat, u32)> {
+        match event {
+            GameEvent::UnitKilled {
+                unit: UnitId::Enemy(enemy_id),
+                source: Some(UnitId::Ally(_)),
+                damage_kind,
+                ..
+            } => {
+                let mut stats = vec![(Stat::EnemiesSlain, 1)];
+                let staked = damage_kind == DamageKind::Stake
+                    && level.enemies.contains_key(&enemy_id)
+                    && level
+                        .try_get_enemy(enemy_id)
+                        .is_some_and(|enemy| enemy.bind().kind == EnemyKind::Vampire);
+                if staked {
+                    stats.push((Stat::VampiresStaked, 1));
+                }
+                stats
+            }
+            GameEvent::UnitDamaged {
+                unit: UnitId::Ally(_),
+                damage,
+                ..
+            } if damage > 0 => vec![(Stat::DamageTaken, damage as u32)],
+            GameEvent::AbilityUsed {
+                unit: UnitId::Ally(_),
+                ..
+            } => vec![(Stat::AbilitiesUsed, 1)],
+            GameEvent::ItemPickedUp { .. } => vec![(Stat::ItemsPickedUp, 1)],
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Achievement {
+    FirstBlood,
+    Untouchable,
+    StakeOut,
+    PackRat,
+    Exterminator,
+    Sunrise,
+}
+
+impl Achievement {
+    pub const ALL: [Achievement; 6] = [
+        Achievement::FirstBlood,
+        Achievement::Untouchable,
+        Achievement::StakeOut,
+        Achievement::PackRat,
+        Achievement::Exterminator,
+        Achievement::Sunrise,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Condition {
+    // A statistic reaches a total over every run
+    Lifetime(Stat, u32),
+    // A statistic reaches a total within one level
+    InLevel(Stat, u32),
+    Clear(Room),
+    // A room is cleared with a statistic no higher than a limit
+    ClearWithin {
+        room: Room,
+        stat: Stat,
+        at_most: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AchievementDef {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub condition: Condition,
+}
+
+// New achievements only need a variant and an entry here
+pub fn definition(achievement: Achievement) -> AchievementDef {
+    match achievement {
+        Achievement::FirstBlood => AchievementDef {
+            name: "First Blood",
+            description: "Slay an enemy",
+            condition: Condition::Lifetime(Stat::EnemiesSlain, 1),
+        },
+        Achievement::Untouchable => AchievementDef {
+            name: "Untouchable",
+            description: "Clear the Entrance Hall without taking damage",
+            condition: Condition::ClearWithin {
+                room: Room::EntranceHall,
+                stat: Stat::DamageTaken,
+                at_most: 0,
+            },
+        },
+        Achievement::StakeOut => AchievementDef {
+            name: "Stake Out",
+            description: "Stake 3 vampires in one level",
+            condition: Condition::InLevel(Stat::VampiresStaked, 3),
+        },
+        Achievement::PackRat => AchievementDef {
+            name: "Pack Rat",
+            description: "Pick up 20 items",
+            condition: Condition::Lifetime(Stat::ItemsPickedUp, 20),
+        },
+        Achievement::Exterminator => AchievementDef {
+            name: "Exterminator",
+            description: "Slay 100 enemies",
+            condition: Condition::Lifetime(Stat::EnemiesSlain, 100),
+        },
+        Achievement::Sunrise => AchievementDef {
+            name: "Sunrise",
+            description: "Make it out of the Great Hall",
+            condition: Condition::Clear(Room::GreatHall),
+        },
+    }
+}
+
+// Unlocks and lifetime statistics, kept across runs, along with the current level's statistics
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Achievements {
+    #[serde(default)]
+    pub lifetime: HashMap<Stat, u32>,
+    #[serde(default)]
+    pub unlocked: Vec<Achievement>,
+    #[serde(skip)]
+    pub level: HashMap<Stat, u32>,
+    // Unlocked this level and when, for the HUD
+    #[serde(skip)]
+    announced: Vec<(Achievement, f64)>,
+}
+
+impl Achievements {
+    pub fn load() -> Self {
+        FileAccess::open(PATH.into(), ModeFlags::READ)
+            .and_then(|file| serde_json::from_str(&file.get_as_text().to_string()).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        if let (Some(mut file), Ok(json)) = (
+            FileAccess::open(PATH.into(), ModeFlags::WRITE),
+            serde_json::to_string(self),
+        ) {
+            file.store_string(json.into());
+        }
+    }
+
+    pub fn get(&self, stat: Stat) -> u32 {
+        self.lifetime.get(&stat).copied().unwrap_or(0)
+    }
+
+    // Returns whatever the new total unlocks
+    pub fn tally(&mut self, stat: Stat, amount: u32) -> Vec<Achievement> {
+        *self.lifetime.entry(stat).or_insert(0) += amount;
+        *self.level.entry(stat).or_insert(0) += amount;
+
+        let (lifetime, level) = (self.get(stat), self.level[&stat]);
+        self.unlock_where(|condition| match condition {
+            Condition::Lifetime(of, total) => of == stat && lifetime >= total,
+            Condition::InLevel(of, total) => of == stat && level >= total,
+            _ => false,
+        })
+    }
+
+    pub fn clear_room(&mut self, room: Room) -> Vec<Achievement> {
+        let mut unlocked = self.tally(Stat::RoomsCleared, 1);
+        let level = self.level.clone();
+        unlocked.extend(self.unlock_where(|condition| match condition {
+            Condition::Clear(cleared) => cleared == room,
+            Condition::ClearWithin {
+                room: cleared,
+                stat,
+                at_most,
+            } => cleared == room && level.get(&stat).copied().unwrap_or(0) <= at_most,
+            _ => false,
+        }));
+        unlocked
+    }
+
+    fn unlock_where(&mut self, met: impl Fn(Condition) -> bool) -> Vec<Achievement> {
+        let unlocked: Vec<Achievement> = Achievement::ALL
+            .into_iter()
+            .filter(|achievement| !self.unlocked.contains(achievement))
+            .filter(|achievement| met(definition(*achievement).condition))
+            .collect();
+        self.unlocked.extend(&unlocked);
+        unlocked
+    }
+
+    pub fn announce(&mut self, unlocked: &[Achievement]) {
+        let now = save::now();
+        self.announced
+            .extend(unlocked.iter().map(|achievement| (*achievement, now)));
+    }
+
+    // Unlocks recent enough to still be shown
+    pub fn announcements(&self) -> Vec<Achievement> {
+        let now = save::now();
+        self.announced
+            .iter()
+            .filter(|(_, at)| now - at < ANNOUNCE_TIME)
+            .map(|(achievement, _)| *achievement)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn achievements_unlock_once_from_lifetime_and_level_stats() {
+        let mut achievements = Achievements::default();
+        assert_eq!(
+            achievements.tally(Stat::EnemiesSlain, 1),
+            vec![Achievement::FirstBlood]
+        );
+        assert!(achievements.tally(Stat::EnemiesSlain, 1).is_empty());
+
+        achievements.tally(Stat::VampiresStaked, 2);
+        assert_eq!(
+            achievements.tally(Stat::VampiresStaked, 1),
+            vec![Achievement::StakeOut]
+        );
+
+        // Taking a hit in the entrance hall rules out clearing it untouched
+        achievements.tally(Stat::DamageTaken, 2);
+        assert!(achievements.clear_room(Room::EntranceHall).is_empty());
+
+        // Level counts start over, lifetime ones carry on
+        achievements.level.clear();
+        assert_eq!(
+            achievements.clear_room(Room::EntranceHall),
+            vec![Achievement::Untouchable]
+        );
+        assert_eq!(achievements.get(Stat::RoomsCleared), 2);
+        assert_eq!(achievements.get(Stat::DamageTaken), 2);
+    }
+}
@@ -0,0 +1,596 @@
+use crate::ability::{abilities, Ability, Action};
+use crate::combat::{damage_taken, ActionResult};
+use crate::core::{Effect, Occupancy, Terrain, Tile, UnitId};
+use crate::grid::Grid;
+use crate::level::{AllyId, EnemyAction, EnemyKind, NpcId};
+use crate::math::{
+    attack_positions, has_room, line_to, path_cost, Movement, Mover, Pathfinder, Position,
+};
+use crate::traits::Trait;
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+pub type Plan = (Option<Vec<Position>>, Option<(Ability, EnemyAction)>);
+
+// Plain copy of the level taken before planning so no nodes are bound mid-plan. Ordered
+// collections keep plans the same from run to run so replays play out as recorded
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub grid: Grid<Occupancy>,
+    pub allies: BTreeMap<AllyId, Target>,
+    // Attacked like allies, though they never fight back
+    pub npcs: BTreeMap<NpcId, Target>,
+    pub coffin_tiles: BTreeSet<Position>,
+    // Obstacles that don't block sight, which some attacks pass over
+    pub low_obstacles: BTreeSet<Position>,
+    pub diagonal_movement: bool,
+    pub graves: Vec<(EnemyKind, Position)>,
+}
+
+impl Snapshot {
+    pub fn target(&self, tile: Tile) -> Option<&Target> {
+        match tile {
+            Tile::Ally(ally_id) => self.allies.get(&ally_id),
+            Tile::Npc(npc_id) => self.npcs.get(&npc_id),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub position: Position,
+    pub traits: Vec<Trait>,
+    // How far off a taunt reaches, while the target is taunting
+    pub taunting: Option<u16>,
+    pub effects: Vec<Effect>,
+}
+
+// How a swarm member takes the death of its leader
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Morale {
+    Holds,
+    // Runs for the edge of the map for a few turns
+    Flees,
+    // Leaves the level altogether
+    Scatters,
+}
+
+impl Morale {
+    // A roll out of 100 under the unit's courage holds, the worst half of the rest scatter
+    pub fn check(courage: u16, roll: u16) -> Self {
+        let courage = courage.min(100);
+        if roll < courage {
+            Self::Holds
+        } else if roll < courage + (100 - courage) / 2 {
+            Self::Flees
+        } else {
+            Self::Scatters
+        }
+    }
+}
+
+// The unit doing the planning
+#[derive(Debug, Clone)]
+pub struct Unit {
+    pub tile: Tile,
+    pub position: Position,
+    pub health: u16,
+    pub max_health: u16,
+    pub speed: u16,
+    pub width: u16,
+    pub height: u16,
+    pub abilities: Vec<Ability>,
+    pub cooldowns: HashMap<Ability, u16>,
+    pub traits: Vec<Trait>,
+    pub effects: Vec<Effect>,
+    pub last_result: Option<ActionResult>,
+    // Lost its nerve, so it runs instead of fighting
+    pub fleeing: bool,
+    pub visible: HashSet<Position>,
+    pub last_known_positions: HashMap<AllyId, Position>,
+    pub retreats_to_coffin: bool,
+}
+
+impl Unit {
+    pub fn mover(&self, diagonal: bool) -> Mover {
+        let movement = if self.traits.contains(&Trait::Incorporeal) {
+            Movement::Phase
+        } else {
+            Movement::Walk
+        };
+        Mover {
+            tile: self.tile,
+            dimensions: (self.width as usize, self.height as usize),
+            movement,
+            diagonal,
+            avoids: self
+                .traits
+                .contains(&Trait::GarlicAllergy)
+                .then_some(Terrain::Garlic),
+        }
+    }
+
+    // The unit's last ability did nothing to whoever stands on the tile, so it is worth nothing
+    // more until the unit has reason to think otherwise
+    fn foiled_by(&self, tile: Tile) -> bool {
+        let unit = match tile {
+            Tile::Ally(ally_id) => UnitId::Ally(ally_id),
+            Tile::Npc(npc_id) => UnitId::Npc(npc_id),
+            _ => return false,
+        };
+        self.last_result
+            .as_ref()
+            .is_some_and(|result| result.foiled(unit))
+    }
+
+    // Hurt enough to look to its own safety first
+    fn is_wounded(&self) -> bool {
+        self.health * 2 <= self.max_health
+    }
+
+    pub fn tiles(&self) -> Vec<Position> {
+        let mut tiles = Vec::new();
+        for i in 0..self.width as usize {
+            for j in 0..self.height as usize {
+                tiles.push(Position {
+                    x: self.position.x + i,
+                    y: self.position.y + j,
+                });
+            }
+        }
+        tiles
+    }
+
+    // Cut a path down to the unit's speed without stopping inside anything it passes through
+    fn truncate(&self, path: &[Position], grid: &Grid<Occupancy>) -> Option<Vec<Position>> {
+        let mut len = 0;
+        while len < path.len() && path_cost(self.position, &path[0..=len]) <= self.speed {
+            len += 1;
+        }
+        while len > 0 && !grid[path[len - 1]].tile.is_empty() {
+            len -= 1;
+        }
+
+        if len > 0 {
+            Some(path[0..len].to_vec())
+        } else {
+            None
+        }
+    }
+
+    // Charges dash their whole path in one go and dash attacks run as far as they reach,
+    // everything else walks at the unit's speed
+    fn within_reach(&self, action: &EnemyAction, cost: u16) -> bool {
+        match action {
+            EnemyAction::Charge { .. } => true,
+            EnemyAction::DashAttack { distance, .. } => cost <= *distance,
+            _ => cost <= self.speed,
+        }
+    }
+}
+
+/*
+ * Enemies make decisions by creating a list of all possible positions they can use an ability on an
+ * ally from and picking the most preferential one based on the following, in order:
+ * - Whether or not they can move to that space in a single turn
+ * - The highest damage the ability can cause to the player
+ * - The farthest range they can use the ability from
+ * - The cheapest the position is to move to from their current position
+ */
+pub fn plan(unit: &mut Unit, snapshot: &Snapshot, pathfinder: &mut Pathfinder) -> Plan {
+    // A taunting ally in range is the only target the unit will consider, wherever it has got to
+    let taunter = snapshot.allies.iter().find(|(_, ally)| {
+        ally.taunting
+            .is_some_and(|range| unit.position.distance(ally.position) <= range)
+    });
+    if let Some((ally_id, ally)) = taunter {
+        let mut taunted = snapshot.clone();
+        taunted.allies = BTreeMap::from([(
+            *ally_id,
+            Target {
+                taunting: None,
+                ..ally.clone()
+            },
+        )]);
+        taunted.npcs.clear();
+        unit.last_known_positions.insert(*ally_id, ally.position);
+        return plan(unit, &taunted, pathfinder);
+    }
+
+    let mover = unit.mover(snapshot.diagonal_movement);
+
+    let grid = &snapshot.grid;
+    let any_visible = snapshot
+        .allies
+        .values()
+        .any(|ally| unit.visible.contains(&ally.position));
+
+    // Wounded units put up whatever protects them while anyone can see them, the way an ally in
+    // trouble would turn to mist
+    let defence = unit
+        .abilities
+        .iter()
+        .find_map(|ability| match abilities().get(ability).unwrap().action {
+            Action::Effect { effect, stats } if !unit.effects.contains(&effect) => {
+                Some((*ability, EnemyAction::Effect { effect, stats }))
+            }
+            _ => None,
+        })
+        .filter(|_| unit.is_wounded() && any_visible);
+
+    // Anything that has lost its nerve makes for the open floor nearest the edge of the map, and
+    // stops there
+    if unit.fleeing {
+        let edge = |position: Position| {
+            position
+                .x
+                .min(position.y)
+                .min(grid.width() - 1 - position.x)
+                .min(grid.height() - 1 - position.y)
+        };
+        let open: Vec<Position> = (0..grid.height())
+            .flat_map(|y| (0..grid.width()).map(move |x| Position { x, y }))
+            .filter(|position| grid[*position].tile.is_empty())
+            .collect();
+        let Some(nearest) = open.iter().map(|position| edge(*position)).min() else {
+            return (None, None);
+        };
+        if edge(unit.position) <= nearest {
+            return (None, None);
+        }
+
+        let goals: Vec<Position> = open
+            .into_iter()
+            .filter(|position| edge(*position) == nearest)
+            .collect();
+        let path = pathfinder
+            .pathfind_to_any(unit.position, &goals, grid, mover, None)
+            .and_then(|(path, _)| unit.truncate(&path, grid));
+        return (path, None);
+    }
+
+    // Wounded vampires fall back to a coffin to regenerate instead of fighting
+    if unit.retreats_to_coffin && unit.is_wounded() {
+        if unit
+            .tiles()
+            .iter()
+            .any(|tile| snapshot.coffin_tiles.contains(tile))
+        {
+            return (None, defence);
+        }
+
+        let coffin_tiles: Vec<Position> = snapshot
+            .coffin_tiles
+            .iter()
+            .filter(|position| grid[**position].tile.is_empty())
+            .copied()
+            .collect();
+        if let Some((path, _)) =
+            pathfinder.pathfind_to_any(unit.position, &coffin_tiles, grid, mover, None)
+        {
+            return (unit.truncate(&path, grid), defence);
+        }
+    }
+    if defence.is_some() {
+        return (None, defence);
+    }
+
+    // Nothing gets used in mist form, though the unit can still move
+    let misted = unit.effects.contains(&Effect::Mist);
+
+    let mut actions = Vec::new();
+    for ability in &unit.abilities {
+        let stats = abilities().get(ability).unwrap();
+        match stats.action {
+            Action::Attack {
+                damage_kind,
+                damage,
+                ..
+            }
+            | Action::Push {
+                damage_kind,
+                damage,
+                ..
+            }
+            | Action::DashAttack {
+                damage_kind,
+                damage,
+                ..
+            } => {
+                // Garlic only keeps the unit from standing nearby, it doesn't block the attack
+                let clears_low_obstacles = stats.action.clears_low_obstacles();
+                let unit_tile = unit.tile;
+                let passes = |position: Position| match snapshot.grid[position].tile {
+                    Tile::Empty => true,
+                    Tile::Obstacle(_) => {
+                        clears_low_obstacles && snapshot.low_obstacles.contains(&position)
+                    }
+                    tile => tile == unit_tile,
+                };
+
+                for (ally_id, ally) in &snapshot.allies {
+                    let action = match stats.action {
+                        Action::Push { distance, .. } => EnemyAction::Push {
+                            ally_id: *ally_id,
+                            damage_kind,
+                            damage,
+                            distance,
+                        },
+                        Action::DashAttack { distance, .. } => EnemyAction::DashAttack {
+                            ally_id: *ally_id,
+                            damage_kind,
+                            damage,
+                            distance,
+                        },
+                        _ => EnemyAction::Attack {
+                            ally_id: *ally_id,
+                            damage_kind,
+                            damage,
+                        },
+                    };
+
+                    if unit.visible.contains(&ally.position) {
+                        unit.last_known_positions.insert(*ally_id, ally.position);
+
+                        // Only the cheapest position to reach at each range is worth weighing
+                        let mut goals: BTreeMap<u16, Vec<Position>> = BTreeMap::new();
+                        for (position, range) in
+                            attack_positions(ally.position, stats.range, grid, mover, passes)
+                        {
+                            goals.entry(range).or_default().push(position);
+                        }
+
+                        for (range, goals) in goals {
+                            if let Some((path, cost)) =
+                                pathfinder.pathfind_to_any(unit.position, &goals, grid, mover, None)
+                            {
+                                actions.push((Some(*ability), action, range, path, cost));
+                            }
+                        }
+                    } else if let Some(last_known_position) = unit.last_known_positions.get(ally_id)
+                    {
+                        if let Some((path, cost)) = pathfinder.pathfind_to_any(
+                            unit.position,
+                            &[*last_known_position],
+                            grid,
+                            mover,
+                            None,
+                        ) {
+                            actions.push((None, action, 1, path, cost));
+                        }
+                    }
+                }
+
+                // Anyone else in sight is fair game, but isn't tracked once out of it
+                for (npc_id, npc) in &snapshot.npcs {
+                    if unit.visible.contains(&npc.position) {
+                        let action = EnemyAction::AttackNpc {
+                            npc_id: *npc_id,
+                            damage_kind,
+                            damage,
+                        };
+
+                        let mut goals: BTreeMap<u16, Vec<Position>> = BTreeMap::new();
+                        for (position, range) in
+                            attack_positions(npc.position, stats.range, grid, mover, passes)
+                        {
+                            goals.entry(range).or_default().push(position);
+                        }
+
+                        for (range, goals) in goals {
+                            if let Some((path, cost)) =
+                                pathfinder.pathfind_to_any(unit.position, &goals, grid, mover, None)
+                            {
+                                actions.push((Some(*ability), action, range, path, cost));
+                            }
+                        }
+                    }
+                }
+            }
+            Action::Spawn { enemy_kind, .. } => {
+                let cooldown_finished = *unit.cooldowns.get(ability).unwrap_or(&0) == 0;
+
+                if cooldown_finished && any_visible {
+                    for position in unit.tiles() {
+                        for adjacent in snapshot.grid.adjacent(position) {
+                            if has_room(adjacent, enemy_kind.dimensions(), &snapshot.grid) {
+                                actions.push((
+                                    Some(*ability),
+                                    EnemyAction::Spawn {
+                                        enemy_kind,
+                                        position: adjacent,
+                                    },
+                                    stats.range,
+                                    vec![unit.position],
+                                    0,
+                                ));
+                            }
+                        }
+                    }
+                }
+            }
+            Action::Charge {
+                damage_kind,
+                damage,
+                distance,
+            } => {
+                for (ally_id, ally) in &snapshot.allies {
+                    // Charges run in a straight line and stop in front of the first ally hit
+                    if unit.visible.contains(&ally.position) {
+                        match line_to(unit.position, ally.position, grid) {
+                            Some(line) if line.len() as u16 <= stats.range + 1 => {
+                                let path = if line.len() > 1 {
+                                    line[0..line.len() - 1].to_vec()
+                                } else {
+                                    vec![unit.position]
+                                };
+                                let cost = path.len() as u16;
+                                actions.push((
+                                    Some(*ability),
+                                    EnemyAction::Charge {
+                                        ally_id: *ally_id,
+                                        damage_kind,
+                                        damage,
+                                        distance,
+                                    },
+                                    stats.range,
+                                    path,
+                                    cost,
+                                ));
+                            }
+                            _ => (),
+                        }
+                    }
+                }
+            }
+            Action::Resurrect { .. } => {
+                let cooldown_finished = *unit.cooldowns.get(ability).unwrap_or(&0) == 0;
+
+                if cooldown_finished {
+                    for (enemy_kind, position) in &snapshot.graves {
+                        if unit.visible.contains(position)
+                            && unit.position.distance(*position) <= stats.range
+                            && has_room(*position, enemy_kind.dimensions(), &snapshot.grid)
+                        {
+                            actions.push((
+                                Some(*ability),
+                                EnemyAction::Resurrect {
+                                    enemy_kind: *enemy_kind,
+                                    position: *position,
+                                },
+                                stats.range,
+                                vec![unit.position],
+                                0,
+                            ));
+                        }
+                    }
+                }
+            }
+            // Cast from where the unit stands on anyone in reach who isn't already suffering it
+            Action::Inflict {
+                effect,
+                stats: effect_stats,
+            } => {
+                for (ally_id, ally) in &snapshot.allies {
+                    if unit.visible.contains(&ally.position)
+                        && unit.position.distance(ally.position) <= stats.range
+                        && !ally.effects.contains(&effect)
+                    {
+                        actions.push((
+                            Some(*ability),
+                            EnemyAction::Inflict {
+                                ally_id: *ally_id,
+                                effect,
+                                stats: effect_stats,
+                            },
+                            stats.range,
+                            vec![unit.position],
+                            0,
+                        ));
+                    }
+                }
+            }
+            // Left to the start of planning, where the unit decides whether it needs it
+            Action::Effect { .. } => (),
+            // With no one to fight, whatever the hunters left lying about is ruined before they
+            // come back for it
+            Action::Spoil if !any_visible => {
+                let unit_tile = unit.tile;
+                let passes = |position: Position| match grid[position].tile {
+                    Tile::Empty => true,
+                    tile => tile == unit_tile,
+                };
+
+                for position in grid.positions() {
+                    if grid[position].item.is_none() || !unit.visible.contains(&position) {
+                        continue;
+                    }
+
+                    let goals: Vec<Position> =
+                        attack_positions(position, stats.range, grid, mover, passes)
+                            .into_iter()
+                            .map(|(goal, _)| goal)
+                            .collect();
+                    if let Some((path, cost)) =
+                        pathfinder.pathfind_to_any(unit.position, &goals, grid, mover, None)
+                    {
+                        if cost <= unit.speed {
+                            actions.push((
+                                Some(*ability),
+                                EnemyAction::Spoil { position },
+                                stats.range,
+                                path,
+                                cost,
+                            ));
+                        }
+                    }
+                }
+            }
+            Action::Spoil => (),
+            // Mod packs are kept from handing these to enemies, but one that slips through is
+            // only ever passed over
+            Action::PlaceItem { .. }
+            | Action::Teleport
+            | Action::Carry
+            | Action::Unlock { .. }
+            | Action::Search
+            | Action::Pull => (),
+        }
+    }
+
+    if actions.is_empty() {
+        (None, None)
+    } else {
+        actions.sort_by(
+            |(_, a_action, a_range, _, a_cost), (_, b_action, b_range, _, b_cost)| match (
+                a_action.target(),
+                b_action.target(),
+            ) {
+                (
+                    Some((a_tile, a_damage_kind, a_damage)),
+                    Some((b_tile, b_damage_kind, b_damage)),
+                ) => {
+                    let a_target = snapshot.target(a_tile).unwrap();
+                    let b_target = snapshot.target(b_tile).unwrap();
+
+                    let damage = |tile, damage, damage_kind, traits: &[Trait]| {
+                        if unit.foiled_by(tile) {
+                            0
+                        } else {
+                            damage_taken(damage, damage_kind, traits)
+                        }
+                    };
+                    let a_damage = damage(a_tile, a_damage, a_damage_kind, &a_target.traits);
+                    let b_damage = damage(b_tile, b_damage, b_damage_kind, &b_target.traits);
+                    let a_within = unit.within_reach(a_action, *a_cost);
+                    let b_within = unit.within_reach(b_action, *b_cost);
+
+                    a_within
+                        .cmp(&b_within)
+                        .reverse()
+                        .then(a_damage.cmp(&b_damage).reverse())
+                        .then(a_range.cmp(b_range).reverse())
+                        .then(a_cost.cmp(b_cost))
+                }
+                (Some(_), None) => Ordering::Greater,
+                (None, Some(_)) => Ordering::Less,
+                (None, None) => Ordering::Equal,
+            },
+        );
+
+        let (ability, action, _, path, cost) = actions.first().unwrap();
+
+        if unit.within_reach(action, *cost) {
+            (
+                Some(path.clone()),
+                ability
+                    .filter(|_| !misted)
+                    .map(|ability| (ability, *action)),
+            )
+        } else {
+            (unit.truncate(path, grid), None)
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use crate::math::Direction;
+
+// Which of the sprite sheet's three rows a unit is drawn from, flipped for left
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Facing {
+    Side,
+    Back,
+    #[default]
+    Front,
+}
+
+impl Facing {
+    pub fn of(direction: Direction) -> Self {
+        match direction {
+            Direction::Left | Direction::Right => Self::Side,
+            Direction::Up => Self::Back,
+            Direction::Down => Self::Front,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Side => "side",
+            Self::Back => "back",
+            Self::Front => "front",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Pose {
+    #[default]
+    Idle,
+    Walk,
+    // Using an ability, named after the animation that plays for it
+    Action(&'static str),
+    Hit,
+    Death,
+    // Arriving mid-level, for enemies whose scene has the animation
+    Spawn,
+}
+
+impl Pose {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Idle => "idle",
+            Self::Walk => "walk",
+            Self::Action(name) => name,
+            Self::Hit => "hit",
+            Self::Death => "death",
+            Self::Spawn => "spawn",
+        }
+    }
+}
+
+// The animation a unit is playing. Damage and death can land in any pose: a hit only flinches an
+// idle unit, and a death during a walk or an action waits for it to finish before playing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Animator {
+    facing: Facing,
+    pose: Pose,
+    death_queued: bool,
+}
+
+impl Animator {
+    pub fn name(&self) -> String {
+        format!("{}_{}", self.facing.name(), self.pose.name())
+    }
+
+    pub fn facing(&self) -> Facing {
+        self.facing
+    }
+
+    pub fn pose(&self) -> Pose {
+        self.pose
+    }
+
+    // Dead or about to be, so the unit should not be given anything else to do
+    pub fn is_dying(&self) -> bool {
+        self.pose == Pose::Death || self.death_queued
+    }
+
+    pub fn walk(&mut self, facing: Facing) {
+        if self.pose != Pose::Death {
+            self.facing = facing;
+            self.pose = Pose::Walk;
+        }
+    }
+
+    pub fn act(&mut self, facing: Facing, action: &'static str) {
+        if !self.is_dying() {
+            self.facing = facing;
+            self.pose = Pose::Action(action);
+        }
+    }
+
+    pub fn hit(&mut self, killed: bool) {
+        match self.pose {
+            Pose::Death => (),
+            Pose::Walk | Pose::Action(_) | Pose::Spawn if killed => self.death_queued = true,
+            Pose::Walk | Pose::Action(_) | Pose::Spawn => (),
+            Pose::Idle | Pose::Hit if killed => self.pose = Pose::Death,
+            Pose::Idle | Pose::Hit => self.pose = Pose::Hit,
+        }
+    }
+
+    pub fn spawn(&mut self) {
+        self.pose = Pose::Spawn;
+    }
+
+    // Back on its feet after a death that didn't take
+    pub fn revive(&mut self) {
+        self.pose = Pose::Idle;
+        self.death_queued = false;
+    }
+
+    // Ends a walk, going into the queued death if one is owed
+    pub fn stop(&mut self) {
+        if self.pose == Pose::Walk {
+            self.settle();
+        }
+    }
+
+    // Called when the player finishes an animation, returning the pose that ended. Signals for an
+    // animation the unit has already moved on from are ignored
+    pub fn finish(&mut self, name: &str) -> Option<Pose> {
+        if name != self.name() {
+            return None;
+        }
+
+        let pose = self.pose;
+        if let Pose::Action(_) | Pose::Hit | Pose::Spawn = pose {
+            self.settle();
+        }
+        Some(pose)
+    }
+
+    fn settle(&mut self) {
+        self.pose = if self.death_queued {
+            Pose::Death
+        } else {
+            Pose::Idle
+        };
+        self.death_queued = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deaths_wait_for_walks_and_actions_to_finish() {
+        let mut animator = Animator::default();
+        animator.walk(Facing::Side);
+        animator.hit(false);
+        assert_eq!(animator.name(), "side_walk");
+        animator.hit(true);
+        assert!(animator.is_dying());
+        assert_eq!(animator.name(), "side_walk");
+        animator.stop();
+        assert_eq!(animator.name(), "side_death");
+
+        let mut animator = Animator::default();
+        animator.act(Facing::Back, "whip");
+        animator.hit(true);
+        // A stale signal from an earlier animation changes nothing
+        assert_eq!(animator.finish("back_hit"), None);
+        assert_eq!(animator.finish("back_whip"), Some(Pose::Action("whip")));
+        assert_eq!(animator.name(), "back_death");
+        animator.hit(false);
+        assert_eq!(animator.finish("back_death"), Some(Pose::Death));
+        assert_eq!(animator.pose(), Pose::Death);
+
+        let mut animator = Animator::default();
+        animator.hit(false);
+        assert_eq!(animator.name(), "front_hit");
+        animator.hit(true);
+        assert_eq!(animator.name(), "front_death");
+    }
+}
@@ -0,0 +1,183 @@
+use crate::ability::{abilities, Action, DamageKind};
+use crate::event::{GameEvent, Listener};
+use crate::level::Level;
+
+use godot::engine::{AudioServer, AudioStream, AudioStreamPlayer, Engine, INode, SceneTree};
+use godot::prelude::*;
+use std::collections::HashMap;
+
+// Enough players for an attack, a hit and a death to overlap with footsteps
+const NUM_PLAYERS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Sfx {
+    Attack(DamageKind),
+    Cast,
+    Hit,
+    Death,
+    Footstep,
+    Navigate,
+    Select,
+    Blip,
+}
+
+impl Sfx {
+    fn path(&self) -> &'static str {
+        match self {
+            Self::Attack(DamageKind::Normal | DamageKind::Pierce) => {
+                "res://assets/audio/sfx/attack.wav"
+            }
+            Self::Attack(DamageKind::Silver) => "res://assets/audio/sfx/attack-silver.wav",
+            Self::Attack(DamageKind::Holy | DamageKind::Sunlight) => {
+                "res://assets/audio/sfx/attack-holy.wav"
+            }
+            Self::Attack(DamageKind::Fire) => "res://assets/audio/sfx/attack-fire.wav",
+            Self::Attack(DamageKind::LifeSteal) => "res://assets/audio/sfx/attack-bite.wav",
+            Self::Attack(DamageKind::Stake) => "res://assets/audio/sfx/attack-stake.wav",
+            Self::Cast => "res://assets/audio/sfx/cast.wav",
+            Self::Hit => "res://assets/audio/sfx/hit.wav",
+            Self::Death => "res://assets/audio/sfx/death.wav",
+            Self::Footstep => "res://assets/audio/sfx/footstep.wav",
+            Self::Navigate => "res://assets/audio/sfx/navigate.wav",
+            Self::Select => "res://assets/audio/sfx/select.wav",
+            Self::Blip => "res://assets/audio/sfx/blip.wav",
+        }
+    }
+
+    fn bus(&self) -> Bus {
+        match self {
+            Self::Navigate | Self::Select | Self::Blip => Bus::Ui,
+            _ => Bus::Sfx,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Bus {
+    Master,
+    Music,
+    Sfx,
+    Ui,
+}
+
+impl Bus {
+    // Names of the buses in default_bus_layout.tres
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Master => "Master",
+            Self::Music => "Music",
+            Self::Sfx => "Sfx",
+            Self::Ui => "Ui",
+        }
+    }
+}
+
+// Volume from 0 to 1, silent buses are muted
+pub fn set_volume(bus: Bus, volume: f32) {
+    let mut audio_server = AudioServer::singleton();
+    let index = audio_server.get_bus_index(bus.name().into());
+    audio_server.set_bus_mute(index, volume <= 0.0);
+    if volume > 0.0 {
+        audio_server.set_bus_volume_db(index, 20.0 * volume.log10());
+    }
+}
+
+// Sounds that are not game events, such as UI and footsteps, go straight to the autoload
+pub fn play(sfx: Sfx) {
+    let tree: Gd<SceneTree> = Engine::singleton().get_main_loop().unwrap().cast();
+    let mut sound_effects = tree
+        .get_root()
+        .unwrap()
+        .get_node_as::<SoundEffects>("SoundEffects");
+    let mut sound_effects = sound_effects.bind_mut();
+    sound_effects.play(sfx);
+}
+
+#[derive(GodotClass)]
+#[class(init, base=Node)]
+pub struct SoundEffects {
+    #[export]
+    #[init(default = 1.0)]
+    pub music_volume: f32,
+    #[export]
+    #[init(default = 1.0)]
+    pub sfx_volume: f32,
+    #[export]
+    #[init(default = 1.0)]
+    pub ui_volume: f32,
+    streams: HashMap<Sfx, Gd<AudioStream>>,
+    players: Vec<Gd<AudioStreamPlayer>>,
+    next_player: usize,
+    base: Base<Node>,
+}
+
+#[godot_api]
+impl INode for SoundEffects {
+    fn ready(&mut self) {
+        for _ in 0..NUM_PLAYERS {
+            let player = AudioStreamPlayer::new_alloc();
+            self.base_mut().add_child(player.clone().upcast());
+            self.players.push(player);
+        }
+
+        set_volume(Bus::Music, self.music_volume);
+        set_volume(Bus::Sfx, self.sfx_volume);
+        set_volume(Bus::Ui, self.ui_volume);
+    }
+}
+
+impl SoundEffects {
+    // Streams are loaded the first time they are played, missing files are skipped
+    pub fn play(&mut self, sfx: Sfx) {
+        let stream = match self.streams.get(&sfx) {
+            Some(stream) => stream.clone(),
+            None => match try_load::<AudioStream>(sfx.path()) {
+                Ok(stream) => {
+                    self.streams.insert(sfx, stream.clone());
+                    stream
+                }
+                Err(_) => return,
+            },
+        };
+
+        let player = &mut self.players[self.next_player];
+        player.set_stream(stream);
+        player.set_bus(sfx.bus().name().into());
+        player.play();
+        self.next_player = (self.next_player + 1) % NUM_PLAYERS;
+    }
+
+    pub fn set_volume(&mut self, bus: Bus, volume: f32) {
+        match bus {
+            Bus::Music => self.music_volume = volume,
+            Bus::Sfx => self.sfx_volume = volume,
+            Bus::Ui => self.ui_volume = volume,
+            Bus::Master => (),
+        }
+        set_volume(bus, volume);
+    }
+}
+
+impl Listener for SoundEffects {
+    fn on_event(&mut self, event: GameEvent, _level: &Level) {
+        match event {
+            GameEvent::AbilityUsed { ability, .. } => {
+                let stats = abilities().get(&ability).unwrap();
+                match stats.action {
+                    Action::Attack { damage_kind, .. }
+                    | Action::Push { damage_kind, .. }
+                    | Action::Charge { damage_kind, .. }
+                    | Action::DashAttack { damage_kind, .. } => self.play(Sfx::Attack(damage_kind)),
+                    _ => self.play(Sfx::Cast),
+                }
+            }
+            GameEvent::UnitDamaged { damage, .. } if damage > 0 => self.play(Sfx::Hit),
+            GameEvent::AllyDied(_) | GameEvent::EnemyDied(..) => self.play(Sfx::Death),
+            GameEvent::ItemPickedUp { .. } => self.play(Sfx::Select),
+            GameEvent::EffectExpired { .. } | GameEvent::EffectRemoved { .. } => {
+                self.play(Sfx::Blip)
+            }
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use crate::core::UnitId;
+use crate::level::TILE_SIZE;
+use crate::math::Position;
+use crate::traits::{trait_stats, AuraKind, Trait};
+
+use godot::prelude::*;
+use std::collections::{BTreeSet, HashMap};
+
+// Every tile a unit's aura reaches, with whose aura it is. Rebuilt whenever units move
+#[derive(Debug, Clone, Default)]
+pub struct AuraLayer {
+    tiles: HashMap<Position, Vec<(UnitId, AuraKind)>>,
+}
+
+impl AuraLayer {
+    // Each source is a unit with the tiles it stands on and its traits. An aura doesn't cover its
+    // own unit
+    pub fn build(
+        sources: &[(UnitId, Vec<Position>, Vec<Trait>)],
+        dimensions: (usize, usize),
+    ) -> Self {
+        let (width, height) = dimensions;
+        let mut layer = Self::default();
+        for (unit, tiles, traits) in sources {
+            for trait_ in traits {
+                let Some(aura) = trait_stats().get(trait_).and_then(|stats| stats.aura) else {
+                    continue;
+                };
+                let radius = aura.radius as usize;
+                let mut reached = BTreeSet::new();
+                for tile in tiles {
+                    for x in tile.x.saturating_sub(radius)..(tile.x + radius + 1).min(width) {
+                        for y in tile.y.saturating_sub(radius)..(tile.y + radius + 1).min(height) {
+                            let position = Position { x, y };
+                            if position.distance(*tile) <= aura.radius && !tiles.contains(&position)
+                            {
+                                reached.insert(position);
+                            }
+                        }
+                    }
+                }
+                for position in reached {
+                    layer
+                        .tiles
+                        .entry(position)
+                        .or_default()
+                        .push((*unit, aura.kind));
+                }
+            }
+        }
+        layer
+    }
+
+    // Auras of a kind don't stack, only the strongest over a tile counts
+    pub fn fear(&self, position: Position) -> u16 {
+        self.strongest(position, |kind| match kind {
+            AuraKind::Fear(amount) => Some(amount),
+            _ => None,
+        })
+        .map_or(0, |(_, amount)| amount)
+    }
+
+    // Along with whose aura it is, to credit the damage to
+    pub fn holy(&self, position: Position) -> Option<(UnitId, u16)> {
+        self.strongest(position, |kind| match kind {
+            AuraKind::Holy(amount) => Some(amount),
+            _ => None,
+        })
+    }
+
+    fn strongest(
+        &self,
+        position: Position,
+        amount: impl Fn(AuraKind) -> Option<u16>,
+    ) -> Option<(UnitId, u16)> {
+        self.tiles
+            .get(&position)
+            .into_iter()
+            .flatten()
+            .filter_map(|(unit, kind)| amount(*kind).map(|amount| (*unit, amount)))
+            .max_by_key(|(_, amount)| *amount)
+    }
+
+    // Tiles covered by the auras of these units, for drawing
+    pub fn covered(&self, units: &[UnitId]) -> Vec<(Position, AuraKind)> {
+        let mut covered: Vec<(Position, AuraKind)> = self
+            .tiles
+            .iter()
+            .flat_map(|(position, auras)| {
+                auras
+                    .iter()
+                    .filter(|(unit, _)| units.contains(unit))
+                    .map(|(_, kind)| (*position, *kind))
+            })
+            .collect();
+        covered.sort_by_key(|(position, _)| *position);
+        covered.dedup();
+        covered
+    }
+}
+
+// Fear takes the edge off a hit but never stops one landing at all
+pub fn frightened(damage: u16, fear: u16) -> u16 {
+    if damage == 0 {
+        0
+    } else {
+        damage.saturating_sub(fear).max(1)
+    }
+}
+
+// Tints every tile an aura the allies can see reaches
+#[derive(GodotClass)]
+#[class(init, base=Node2D)]
+pub struct AuraOverlay {
+    pub tiles: Vec<(Position, AuraKind)>,
+    base: Base<Node2D>,
+}
+
+#[godot_api]
+impl INode2D for AuraOverlay {
+    fn draw(&mut self) {
+        let tiles = self.tiles.clone();
+        for (position, kind) in tiles {
+            let color = match kind {
+                AuraKind::Fear(_) => Color::from_rgba(0.5, 0.1, 0.6, 0.25),
+                AuraKind::Holy(_) => Color::from_rgba(1.0, 0.9, 0.4, 0.25),
+            };
+            let rect = Rect2::new(position.to_vector(), Vector2::new(TILE_SIZE, TILE_SIZE));
+            self.base_mut().draw_rect(rect, color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::AllyId;
+    use crate::traits::trait_lists;
+
+    #[test]
+    fn fear_reaches_two_tiles_out_and_holy_only_next_door() {
+        let batty = UnitId::Enemy(0);
+        let priest = UnitId::Ally(AllyId::Priest);
+        let batty_tiles = vec![
+            Position { x: 0, y: 0 },
+            Position { x: 1, y: 0 },
+            Position { x: 0, y: 1 },
+            Position { x: 1, y: 1 },
+        ];
+        let sources = [
+            (batty, batty_tiles, trait_lists()[6].clone()),
+            (
+                priest,
+                vec![Position { x: 6, y: 6 }],
+                trait_lists()[5].clone(),
+            ),
+        ];
+        let layer = AuraLayer::build(&sources, (10, 10));
+
+        assert_eq!(layer.fear(Position { x: 0, y: 0 }), 0);
+        assert_eq!(layer.fear(Position { x: 3, y: 1 }), 1);
+        assert_eq!(layer.fear(Position { x: 4, y: 1 }), 0);
+        assert_eq!(frightened(3, 1), 2);
+        assert_eq!(frightened(1, 1), 1);
+        assert_eq!(frightened(0, 1), 0);
+
+        assert_eq!(layer.holy(Position { x: 7, y: 7 }), Some((priest, 1)));
+        assert_eq!(layer.holy(Position { x: 6, y: 6 }), None);
+        assert_eq!(layer.holy(Position { x: 8, y: 6 }), None);
+        assert_eq!(layer.covered(&[priest]).len(), 8);
+    }
+}
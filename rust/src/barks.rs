@@ -0,0 +1,181 @@
+use crate::ability::DamageKind;
+use crate::core::{Terrain, UnitId};
+use crate::event::GameEvent;
+use crate::level::{AllyId, EnemyKind, Level};
+use crate::settings;
+
+use godot::engine::Label;
+use godot::global::HorizontalAlignment;
+use godot::prelude::*;
+use std::collections::HashMap;
+
+// Seconds before the same unit says anything again, and before anyone repeats the same bark
+const UNIT_COOLDOWN: f64 = 6.0;
+const BARK_COOLDOWN: f64 = 12.0;
+// Seconds a bubble stays up while it drifts upwards and fades, at normal text speed
+const BUBBLE_TIME: f64 = 2.5;
+const BUBBLE_RISE: f32 = 8.0;
+// Share of max health an ally calls out below, in percent
+const WOUNDED: u32 = 25;
+
+// One-liners over a unit's head that play out without stopping the game, unlike timelines
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Bark {
+    Wounded,
+    GarlicSpotted,
+    StakeKill,
+    // Said on stepping up beside an ally
+    Banter,
+}
+
+impl Bark {
+    pub fn line(&self, unit: UnitId) -> Option<&'static str> {
+        let line = match (self, unit) {
+            (Self::Wounded, UnitId::Ally(AllyId::AshMagnum)) => "Just a flesh wound!",
+            (Self::Wounded, UnitId::Ally(AllyId::Alukrod)) => "Not the face!",
+            (Self::Wounded, UnitId::Ally(AllyId::Priest)) => "Lord, give me strength...",
+            (Self::GarlicSpotted, UnitId::Enemy(_)) => "Ugh! Garlic!",
+            (Self::StakeKill, UnitId::Ally(AllyId::AshMagnum)) => "Straight through the heart!",
+            (Self::Banter, UnitId::Ally(AllyId::Alukrod)) => "Must you wave that rosary about?",
+            (Self::Banter, UnitId::Ally(AllyId::Priest)) => "You smell of blood, my son.",
+            _ => return None,
+        };
+        Some(line)
+    }
+
+    // What, if anything, a unit has to say about an event, checked once it has been applied
+    pub fn of(event: GameEvent, level: &Level) -> Option<(Self, UnitId)> {
+        match event {
+            GameEvent::UnitDamaged {
+                unit: UnitId::Ally(ally_id),
+                damage,
+                ..
+            } if damage > 0 => {
+                let ally = level.try_get_ally(ally_id)?;
+                let ally = ally.bind();
+                let health = ally.vitals.health as u32;
+                (health > 0 && health * 100 < ally.max_health as u32 * WOUNDED)
+                    .then_some((Self::Wounded, UnitId::Ally(ally_id)))
+            }
+            GameEvent::UnitKilled {
+                source: Some(UnitId::Ally(AllyId::AshMagnum)),
+                damage_kind: DamageKind::Stake,
+                ..
+            } => Some((Self::StakeKill, UnitId::Ally(AllyId::AshMagnum))),
+            GameEvent::EnemyMoved(enemy_id, EnemyKind::Vampire) => {
+                let enemy = level.try_get_enemy(enemy_id)?;
+                let enemy = enemy.bind();
+                level
+                    .enemy_sight(&enemy)
+                    .into_iter()
+                    .any(|position| {
+                        level
+                            .grid
+                            .get(position)
+                            .is_some_and(|cell| cell.terrain.contains(&Terrain::Garlic))
+                    })
+                    .then_some((Self::GarlicSpotted, UnitId::Enemy(enemy_id)))
+            }
+            GameEvent::AllyMoved(ally_id) => {
+                let ally = level.try_get_ally(ally_id)?;
+                let position = ally.bind().position;
+                let beside = level.allies.keys().any(|other| {
+                    *other != ally_id
+                        && level.try_get_ally(*other).is_some_and(|other| {
+                            position.adjacent().contains(&other.bind().position)
+                        })
+                });
+                beside.then_some((Self::Banter, UnitId::Ally(ally_id)))
+            }
+            _ => None,
+        }
+    }
+}
+
+// Keeps barks from piling up, timed in seconds
+#[derive(Debug, Clone, Default)]
+pub struct Barks {
+    units: HashMap<UnitId, f64>,
+    barks: HashMap<Bark, f64>,
+}
+
+impl Barks {
+    // Starts both cooldowns when the bark is let through
+    pub fn allow(&mut self, bark: Bark, unit: UnitId, now: f64) -> bool {
+        let ready =
+            |last: Option<&f64>, cooldown: f64| last.map_or(true, |last| now - last >= cooldown);
+        if !ready(self.units.get(&unit), UNIT_COOLDOWN)
+            || !ready(self.barks.get(&bark), BARK_COOLDOWN)
+        {
+            return false;
+        }
+
+        self.units.insert(unit, now);
+        self.barks.insert(bark, now);
+        true
+    }
+}
+
+// A bubble is a label parented to the unit so it follows it around
+pub fn show(mut unit: Gd<Node2D>, line: &str) {
+    // Wider with the text, kept centred over the tile
+    let size = settings::text_size();
+    let width = 96.0 * size;
+    let start = Vector2::new(8.0 - width / 2.0, -14.0);
+    let time = BUBBLE_TIME * settings::text_speed().linger();
+
+    let mut label = Label::new_alloc();
+    label.set_text(line.into());
+    label.set_horizontal_alignment(HorizontalAlignment::CENTER);
+    label.add_theme_font_size_override("font_size".into(), (8.0 * size).round() as i32);
+    label.set_custom_minimum_size(Vector2::new(width, 0.0));
+    label.set_position(start);
+    label.set_z_index(10);
+    unit.add_child(label.clone().upcast());
+
+    let Some(mut tween) = label.create_tween() else {
+        return;
+    };
+    tween.set_parallel();
+    tween.tween_property(
+        label.clone().upcast(),
+        "position".into(),
+        Variant::from(start - Vector2::new(0.0, BUBBLE_RISE)),
+        time,
+    );
+    tween.tween_property(
+        label.clone().upcast(),
+        "modulate".into(),
+        Variant::from(Color::from_rgba(1.0, 1.0, 1.0, 0.0)),
+        time,
+    );
+    if let Some(mut free) = tween.tween_callback(Callable::from_object_method(&label, "queue_free"))
+    {
+        free.set_delay(time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn barks_wait_out_their_cooldowns() {
+        let ash = UnitId::Ally(AllyId::AshMagnum);
+        let priest = UnitId::Ally(AllyId::Priest);
+        let mut barks = Barks::default();
+
+        assert!(barks.allow(Bark::Wounded, ash, 0.0));
+        // Ash has just spoken, and nobody else can call out being wounded yet
+        assert!(!barks.allow(Bark::StakeKill, ash, 1.0));
+        assert!(!barks.allow(Bark::Wounded, priest, 1.0));
+        assert!(barks.allow(Bark::Banter, priest, 1.0));
+
+        assert!(barks.allow(Bark::StakeKill, ash, 6.0));
+        assert!(!barks.allow(Bark::Wounded, priest, 11.0));
+        assert!(barks.allow(Bark::Wounded, priest, 12.0));
+
+        assert_eq!(Bark::StakeKill.line(priest), None);
+        assert!(Bark::GarlicSpotted.line(UnitId::Enemy(3)).is_some());
+    }
+}
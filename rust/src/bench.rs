@@ -0,0 +1,133 @@
+use std::cell::RefCell;
+use std::env;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+// Bats let loose in every room on top of what it already holds
+pub const BENCH_BATS: usize = 60;
+// Frames averaged over between reports
+pub const REPORT_FRAMES: u32 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    // Includes the pathfinding and field of view the plan needed
+    Planning,
+    Pathfinding,
+    Fov,
+    Shadows,
+}
+
+impl Stage {
+    const ALL: [Self; 4] = [Self::Planning, Self::Pathfinding, Self::Fov, Self::Shadows];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Planning => "Planning",
+            Self::Pathfinding => "Pathfinding",
+            Self::Fov => "FOV",
+            Self::Shadows => "Shadows",
+        }
+    }
+}
+
+// Time spent in each stage this frame, and over the frames since the last report
+#[derive(Debug, Default)]
+pub struct Timings {
+    frames: u32,
+    frame: [Duration; 4],
+    total: [Duration; 4],
+    worst: [Duration; 4],
+}
+
+impl Timings {
+    pub fn record(&mut self, stage: Stage, elapsed: Duration) {
+        self.frame[stage as usize] += elapsed;
+    }
+
+    // Closes the frame, and once enough have gone by gives the averages and worst frames
+    pub fn end_frame(&mut self) -> Option<String> {
+        for (i, elapsed) in self.frame.iter_mut().enumerate() {
+            self.total[i] += *elapsed;
+            self.worst[i] = self.worst[i].max(*elapsed);
+            *elapsed = Duration::ZERO;
+        }
+        self.frames += 1;
+        if self.frames < REPORT_FRAMES {
+            return None;
+        }
+
+        let report = Stage::ALL
+            .iter()
+            .map(|stage| {
+                let i = *stage as usize;
+                format!(
+                    "{}: {:.2}ms avg, {:.2}ms worst",
+                    stage.name(),
+                    self.total[i].as_secs_f64() * 1000.0 / self.frames as f64,
+                    self.worst[i].as_secs_f64() * 1000.0,
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        *self = Self::default();
+        Some(report)
+    }
+}
+
+thread_local! {
+    static TIMINGS: RefCell<Timings> = RefCell::default();
+}
+
+// Turned on by running the game with `-- --benchmark`, and `--overlay` as well to see the
+// reports on screen and not only in the console
+pub fn enabled() -> bool {
+    flag("--benchmark")
+}
+
+pub fn overlay() -> bool {
+    enabled() && flag("--overlay")
+}
+
+fn flag(name: &str) -> bool {
+    static ARGS: OnceLock<Vec<String>> = OnceLock::new();
+    ARGS.get_or_init(|| env::args().collect())
+        .iter()
+        .any(|arg| arg == name)
+}
+
+// Runs the work, adding how long it took to the frame when benchmarking
+pub fn time<T>(stage: Stage, work: impl FnOnce() -> T) -> T {
+    if !enabled() {
+        return work();
+    }
+
+    let start = Instant::now();
+    let result = work();
+    TIMINGS.with(|timings| timings.borrow_mut().record(stage, start.elapsed()));
+    result
+}
+
+pub fn end_frame() -> Option<String> {
+    TIMINGS.with(|timings| timings.borrow_mut().end_frame())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn benchmark_reports_average_and_worst_frames() {
+        let mut timings = Timings::default();
+        for frame in 1..REPORT_FRAMES {
+            let elapsed = if frame == 1 { 31 } else { 1 };
+            timings.record(Stage::Fov, Duration::from_millis(elapsed));
+            assert_eq!(timings.end_frame(), None);
+        }
+        timings.record(Stage::Fov, Duration::from_millis(1));
+
+        let report = timings.end_frame().unwrap();
+        assert!(report.contains("FOV: 1.50ms avg, 31.00ms worst"));
+        assert!(report.contains("Planning: 0.00ms avg, 0.00ms worst"));
+        assert_eq!(timings.end_frame(), None);
+    }
+}
@@ -0,0 +1,90 @@
+use crate::level::{Enemy, EnemyKind};
+use crate::traits::trait_lists;
+use crate::ui;
+
+use godot::engine::file_access::ModeFlags;
+use godot::engine::FileAccess;
+use godot::prelude::*;
+use serde::{Deserialize, Serialize};
+
+const PATH: &str = "user://bestiary.json";
+
+// What the player has learned about each kind of enemy, kept across runs. Sighting one reveals
+// its name, stats and lore, and killing one reveals its traits and weaknesses
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Bestiary {
+    pub sighted: Vec<EnemyKind>,
+    pub slain: Vec<EnemyKind>,
+}
+
+impl Bestiary {
+    pub fn load() -> Self {
+        FileAccess::open(PATH.into(), ModeFlags::READ)
+            .and_then(|file| serde_json::from_str(&file.get_as_text().to_string()).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) {
+        if let (Some(mut file), Ok(json)) = (
+            FileAccess::open(PATH.into(), ModeFlags::WRITE),
+            serde_json::to_string(self),
+        ) {
+            file.store_string(json.into());
+        }
+    }
+
+    pub fn sight(&mut self, kind: EnemyKind) {
+        if !self.sighted.contains(&kind) {
+            self.sighted.push(kind);
+            self.save();
+        }
+    }
+
+    pub fn slay(&mut self, kind: EnemyKind) {
+        if !self.slain.contains(&kind) {
+            self.slain.push(kind);
+            self.sight(kind);
+            self.save();
+        }
+    }
+
+    pub fn title(&self, kind: EnemyKind) -> String {
+        if self.sighted.contains(&kind) {
+            kind.name()
+        } else {
+            "???".into()
+        }
+    }
+
+    // Stats are read from the enemy's scene, so they always match the game
+    pub fn entry(&self, kind: EnemyKind) -> String {
+        if !self.sighted.contains(&kind) {
+            return "Not yet encountered".into();
+        }
+
+        let enemy: Gd<Enemy> = load::<PackedScene>(kind.scene())
+            .instantiate()
+            .unwrap()
+            .cast();
+        let (stats, traits) = {
+            let enemy = enemy.bind();
+            (
+                format!(
+                    "{} health, {} speed, {} view distance",
+                    enemy.max_health, enemy.speed, enemy.view_distance
+                ),
+                trait_lists()[enemy.trait_list as usize].clone(),
+            )
+        };
+        enemy.free();
+
+        let mut sections = vec![kind.name(), kind.lore().into(), stats];
+        if self.slain.contains(&kind) {
+            sections.extend(traits.iter().map(|trait_| ui::trait_tooltip(*trait_)));
+            sections.push(ui::resistances(&traits));
+        } else {
+            sections.push("Kill one to learn its weaknesses".into());
+        }
+        sections.join("\n\n")
+    }
+}
@@ -0,0 +1,113 @@
+use crate::level::TILE_SIZE;
+
+use godot::prelude::*;
+
+// How far from the middle of the view the target can wander before the camera follows, in pixels
+pub const DEADZONE: Vector2 = Vector2::new(2.0 * TILE_SIZE, 1.5 * TILE_SIZE);
+// How far ahead of the way the cursor last went the camera looks
+pub const LOOK_AHEAD: f32 = 2.0 * TILE_SIZE;
+// Share of the way to its goal the camera covers per second
+const FOLLOW_SPEED: f32 = 6.0;
+
+// Eases the camera after a target, taking its own position apart from whatever it is parented to
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Follow {
+    center: Option<Vector2>,
+    goal: Option<Vector2>,
+}
+
+impl Follow {
+    // Whatever placed the camera last is taken as where it is next time
+    pub fn reset(&mut self) {
+        self.center = None;
+        self.goal = None;
+    }
+
+    // The next middle of the view, where it is is only used when nothing has been followed yet
+    pub fn step(
+        &mut self,
+        current: Vector2,
+        target: Vector2,
+        heading: Vector2,
+        (min, max): (Vector2, Vector2),
+        delta: f64,
+    ) -> Vector2 {
+        let center = self.center.unwrap_or(current);
+        let goal = drag(
+            self.goal.unwrap_or(center),
+            target + heading * LOOK_AHEAD,
+            DEADZONE,
+        );
+        let goal = clamp(goal, min, max);
+        let center = center.lerp(goal, (FOLLOW_SPEED * delta as f32).min(1.0));
+
+        self.goal = Some(goal);
+        self.center = Some(center);
+        center
+    }
+}
+
+// Moves the middle of the view only as far as it takes to bring the target back inside the deadzone
+pub fn drag(center: Vector2, target: Vector2, deadzone: Vector2) -> Vector2 {
+    let axis = |center: f32, target: f32, half: f32| {
+        if target > center + half {
+            target - half
+        } else if target < center - half {
+            target + half
+        } else {
+            center
+        }
+    };
+    Vector2::new(
+        axis(center.x, target.x, deadzone.x),
+        axis(center.y, target.y, deadzone.y),
+    )
+}
+
+// Within the limits, or halfway between them when the level is smaller than the view
+pub fn clamp(point: Vector2, min: Vector2, max: Vector2) -> Vector2 {
+    let axis = |point: f32, min: f32, max: f32| {
+        if min > max {
+            (min + max) / 2.0
+        } else {
+            point.max(min).min(max)
+        }
+    };
+    Vector2::new(axis(point.x, min.x, max.x), axis(point.y, min.y, max.y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_camera_waits_for_the_cursor_to_leave_the_deadzone() {
+        let center = Vector2::new(100.0, 100.0);
+        let inside = center + DEADZONE * 0.5;
+        assert_eq!(drag(center, inside, DEADZONE), center);
+
+        let right = center + Vector2::new(DEADZONE.x + 16.0, 0.0);
+        assert_eq!(drag(center, right, DEADZONE), Vector2::new(116.0, 100.0));
+
+        let (min, max) = (Vector2::new(0.0, 0.0), Vector2::new(50.0, 200.0));
+        assert_eq!(
+            clamp(Vector2::new(80.0, 80.0), min, max),
+            Vector2::new(50.0, 80.0)
+        );
+        // A level narrower than the view is kept in the middle
+        let narrow = (Vector2::new(60.0, 0.0), Vector2::new(20.0, 200.0));
+        assert_eq!(
+            clamp(Vector2::new(0.0, 80.0), narrow.0, narrow.1),
+            Vector2::new(40.0, 80.0)
+        );
+
+        // Easing gets closer each frame without overshooting
+        let mut follow = Follow::default();
+        let limits = (Vector2::ZERO, Vector2::new(1000.0, 1000.0));
+        let far = Vector2::new(400.0, 100.0);
+        let first = follow.step(center, far, Vector2::ZERO, limits, 0.05);
+        let second = follow.step(center, far, Vector2::ZERO, limits, 0.05);
+        assert!(first.x > center.x && second.x > first.x);
+        assert!(second.x <= far.x - DEADZONE.x);
+    }
+}
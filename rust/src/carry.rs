@@ -0,0 +1,74 @@
+use crate::core::{Occupancy, Tile, Vitals};
+use crate::grid::Grid;
+use crate::level::AllyId;
+use crate::math::Position;
+
+use std::collections::HashMap;
+
+// Only allies who can't get about on their own are picked up, those down to a quarter of their
+// health or less and those held where they stand
+pub fn can_be_carried(vitals: &Vitals, speed: u16) -> bool {
+    vitals.health * 4 <= vitals.max_health || speed == 0
+}
+
+// Carriers mapped to who they carry
+pub fn is_carried(carried: &HashMap<AllyId, AllyId>, ally_id: AllyId) -> bool {
+    carried.values().any(|carried_id| *carried_id == ally_id)
+}
+
+// A carrier who falls lets go of whoever they had, who is put back on the carrier's tile or the
+// nearest empty one to it. The carrier's tile should already be cleared. Returns who was set down
+// and where, they take orders again from the next ally phase like anyone dropped
+pub fn set_down(
+    carried: &mut HashMap<AllyId, AllyId>,
+    carrier_id: AllyId,
+    tile: Position,
+    grid: &mut Grid<Occupancy>,
+) -> Option<(AllyId, Position)> {
+    let carried_id = carried.remove(&carrier_id)?;
+    let position = grid
+        .positions()
+        .filter(|position| grid[*position].tile.is_empty())
+        .min_by_key(|position| (position.distance(tile), *position))?;
+    grid[position].tile = Tile::Ally(carried_id);
+    Some((carried_id, position))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_downed_and_the_held_are_picked_up() {
+        let mut priest = Vitals::new(4, Vec::new());
+        assert!(!can_be_carried(&priest, 3));
+        assert!(can_be_carried(&priest, 0));
+        priest.health = 1;
+        assert!(can_be_carried(&priest, 3));
+    }
+
+    #[test]
+    fn a_fallen_carrier_sets_down_who_they_carried() {
+        let mut grid = Grid::new(3, 3, Occupancy::default());
+        let mut carried = HashMap::new();
+        carried.insert(AllyId::AshMagnum, AllyId::Priest);
+        let fell_on = Position { x: 1, y: 1 };
+
+        // The carrier's tile is free once they are gone
+        let set_down_at = set_down(&mut carried, AllyId::AshMagnum, fell_on, &mut grid);
+        assert_eq!(set_down_at, Some((AllyId::Priest, fell_on)));
+        assert_eq!(grid[fell_on].tile, Tile::Ally(AllyId::Priest));
+        // No longer sitting out, so the next ally phase gives them their move and action back
+        assert!(!is_carried(&carried, AllyId::Priest));
+        assert_eq!(
+            set_down(&mut carried, AllyId::AshMagnum, fell_on, &mut grid),
+            None
+        );
+
+        // Someone already standing there pushes them to the nearest empty tile
+        carried.insert(AllyId::Alukrod, AllyId::AshMagnum);
+        let (_, position) = set_down(&mut carried, AllyId::Alukrod, fell_on, &mut grid).unwrap();
+        assert_eq!(position.distance(fell_on), 1);
+        assert_eq!(grid[position].tile, Tile::Ally(AllyId::AshMagnum));
+    }
+}
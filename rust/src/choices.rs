@@ -0,0 +1,116 @@
+use crate::level::ItemKind;
+
+use godot::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// Dialogic variables that timelines set to make a choice, read back into the level
+pub const LOYALTY: &str = "alukrod_loyalty";
+pub const GIFT: &str = "gift";
+pub const APPROACH: &str = "great_hall_approach";
+
+// Enemies in this group are only placed when the party comes in through the front
+pub const ALERTED_GROUP: &str = "alerted";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Approach {
+    #[default]
+    Front,
+    Sneak,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Choice {
+    // Given to Ash on the spot
+    Gift(ItemKind),
+    Loyalty(i8),
+    // How the party goes into the great hall, which decides who is waiting there
+    Approach(Approach),
+}
+
+impl Choice {
+    pub fn from_variable(name: &str, value: &str) -> Option<Self> {
+        match (name, value) {
+            (LOYALTY, value) => value.parse().ok().map(Self::Loyalty),
+            (GIFT, "HolyWater") => Some(Self::Gift(ItemKind::HolyWater)),
+            (GIFT, "Garlic") => Some(Self::Gift(ItemKind::Garlic)),
+            (GIFT, "WoodenStake") => Some(Self::Gift(ItemKind::WoodenStake)),
+            (APPROACH, "front") => Some(Self::Approach(Approach::Front)),
+            (APPROACH, "sneak") => Some(Self::Approach(Approach::Sneak)),
+            _ => None,
+        }
+    }
+}
+
+// What the party has decided in dialogue so far, carried from room to room
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Choices {
+    pub alukrod_loyalty: i8,
+    pub approach: Approach,
+}
+
+impl Choices {
+    // Gifts are handed out by the level, they leave nothing to remember
+    pub fn make(&mut self, choice: Choice) {
+        match choice {
+            Choice::Gift(_) => (),
+            Choice::Loyalty(loyalty) => self.alukrod_loyalty = loyalty,
+            Choice::Approach(approach) => self.approach = approach,
+        }
+    }
+
+    // Whether making the choice would change anything, as Dialogic reports values set to what
+    // they already were
+    pub fn changed_by(&self, choice: Choice) -> bool {
+        let mut choices = *self;
+        choices.make(choice);
+        matches!(choice, Choice::Gift(_)) || choices != *self
+    }
+
+    // Handed to Dialogic when a room loads, so timelines can branch on earlier choices
+    pub fn variables(&self) -> Vec<(&'static str, Variant)> {
+        vec![
+            (LOYALTY, (self.alukrod_loyalty as i64).to_variant()),
+            (
+                APPROACH,
+                match self.approach {
+                    Approach::Front => "front",
+                    Approach::Sneak => "sneak",
+                }
+                .to_variant(),
+            ),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dialogue_choices_come_back_from_dialogic_variables() {
+        assert_eq!(
+            Choice::from_variable(LOYALTY, "-2"),
+            Some(Choice::Loyalty(-2))
+        );
+        assert_eq!(
+            Choice::from_variable(GIFT, "HolyWater"),
+            Some(Choice::Gift(ItemKind::HolyWater))
+        );
+        assert_eq!(
+            Choice::from_variable(APPROACH, "sneak"),
+            Some(Choice::Approach(Approach::Sneak))
+        );
+        assert_eq!(Choice::from_variable(GIFT, ""), None);
+        assert_eq!(Choice::from_variable("unrelated", "1"), None);
+
+        // Values echoed back unchanged are not made again, gifts always are
+        let mut made = Choices::default();
+        assert!(!made.changed_by(Choice::Loyalty(0)));
+        assert!(made.changed_by(Choice::Loyalty(1)));
+        made.make(Choice::Loyalty(1));
+        assert!(!made.changed_by(Choice::Loyalty(1)));
+        assert!(made.changed_by(Choice::Gift(ItemKind::HolyWater)));
+        made.make(Choice::Approach(Approach::Sneak));
+        assert_eq!(made.approach, Approach::Sneak);
+    }
+}
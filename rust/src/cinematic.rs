@@ -0,0 +1,72 @@
+use crate::math::Position;
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+// Seconds the camera takes to come back to the cursor once the last shot is done
+pub const RETURN_TIME: f64 = 0.8;
+
+// Where Alukrod sits waiting in the great hall
+const THRONE: Position = Position { x: 9, y: 30 };
+
+// One leg of a scripted camera move, taken from wherever the previous one left the camera
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Shot {
+    pub target: Position,
+    // Multiplies the camera's zoom, 1 leaves it as it is
+    pub zoom: f32,
+    // Seconds spent getting there and then lingering
+    pub travel: f64,
+    pub hold: f64,
+}
+
+// How long a set of shots keeps the player waiting, including the trip back
+pub fn duration(shots: &[Shot]) -> f64 {
+    if shots.is_empty() {
+        return 0.0;
+    }
+    shots
+        .iter()
+        .map(|shot| shot.travel + shot.hold)
+        .sum::<f64>()
+        + RETURN_TIME
+}
+
+// Played as a timeline starts, looking over what it is about before the camera comes back
+pub fn timeline_pans() -> &'static HashMap<&'static str, Vec<Shot>> {
+    static TIMELINE_PANS: OnceLock<HashMap<&'static str, Vec<Shot>>> = OnceLock::new();
+    TIMELINE_PANS.get_or_init(|| {
+        [(
+            "great-hall-alukrod-intro",
+            vec![Shot {
+                target: THRONE,
+                zoom: 1.5,
+                travel: 1.5,
+                hold: 2.0,
+            }],
+        )]
+        .into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pans_hold_the_player_until_the_camera_is_back() {
+        assert_eq!(duration(&[]), 0.0);
+
+        let shot = Shot {
+            target: Position { x: 4, y: 4 },
+            zoom: 1.0,
+            travel: 1.0,
+            hold: 0.5,
+        };
+        assert_eq!(duration(&[shot]), 1.5 + RETURN_TIME);
+        assert_eq!(duration(&[shot, shot]), 3.0 + RETURN_TIME);
+
+        let intro = &timeline_pans()["great-hall-alukrod-intro"];
+        assert!(duration(intro) > RETURN_TIME);
+    }
+}
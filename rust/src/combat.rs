@@ -0,0 +1,357 @@
+use crate::ability::{Ability, DamageKind};
+use crate::core::{Effect, EffectStats, UnitId, Vitals};
+use crate::event::{EventBus, GameEvent};
+use crate::level::AllyId;
+use crate::math::Position;
+use crate::traits::{trait_stats, DamageRule, Trait};
+
+// Every hit on a unit goes through resolve_damage, whichever side it is on and whatever dealt it
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Damage {
+    pub amount: u16,
+    pub kind: DamageKind,
+    // Credited with the hit and any effects it leaves behind
+    pub source: Option<UnitId>,
+    // None for effects ticking
+    pub ability: Option<Ability>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DamageResult {
+    // After traits, however little health the unit had left
+    pub dealt: u16,
+    // Health the unit actually lost, after any shield and never more than it had
+    pub applied: u16,
+    pub effects: Vec<Effect>,
+    pub killed: bool,
+    // Mist form let the hit pass straight through
+    pub evaded: bool,
+    // Health the source drains back for itself
+    pub life_stolen: u16,
+}
+
+// Everything one use of an ability did, for the UI to narrate and for enemies to learn from
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ActionResult {
+    pub hits: Vec<(UnitId, DamageResult)>,
+    // Including whatever a hit's damage kind left behind
+    pub effects: Vec<(UnitId, Effect)>,
+}
+
+impl ActionResult {
+    pub fn hit(unit: UnitId, result: DamageResult) -> Self {
+        let mut action = Self::default();
+        action.add_hit(unit, result);
+        action
+    }
+
+    pub fn add_hit(&mut self, unit: UnitId, result: DamageResult) {
+        self.effects
+            .extend(result.effects.iter().map(|effect| (unit, *effect)));
+        self.hits.push((unit, result));
+    }
+
+    pub fn effect(unit: UnitId, effect: Effect) -> Self {
+        Self {
+            hits: Vec::new(),
+            effects: vec![(unit, effect)],
+        }
+    }
+
+    // Hit and left no worse off, through mist or a shield
+    pub fn foiled(&self, unit: UnitId) -> bool {
+        self.hits
+            .iter()
+            .any(|(hit, result)| *hit == unit && result.applied == 0)
+    }
+
+    // One line for the history, such as "Ash Magnum used Whip: Bat took 2 and fell"
+    pub fn narrate(&self, actor: &str, ability: &str, name: impl Fn(UnitId) -> String) -> String {
+        let mut parts = Vec::new();
+        for (unit, result) in &self.hits {
+            parts.push(if result.evaded {
+                format!("it passed through {}", name(*unit))
+            } else if result.killed {
+                format!("{} took {} and fell", name(*unit), result.applied)
+            } else {
+                format!("{} took {}", name(*unit), result.applied)
+            });
+        }
+        for (unit, effect) in &self.effects {
+            parts.push(format!("{} has {:?}", name(*unit), effect));
+        }
+
+        if parts.is_empty() {
+            format!("{} used {}", actor, ability)
+        } else {
+            format!("{} used {}: {}", actor, ability, parts.join(", "))
+        }
+    }
+}
+
+// Mist is checked first, then traits, then the effects the damage kind leaves on the target
+pub fn resolve_damage(
+    unit: UnitId,
+    vitals: &mut Vitals,
+    damage: Damage,
+    events: &mut EventBus,
+) -> DamageResult {
+    let mut result = DamageResult::default();
+    if vitals.effects.contains(Effect::Mist) {
+        result.evaded = true;
+        events.publish(GameEvent::UnitDamaged {
+            unit,
+            damage: 0,
+            damage_kind: damage.kind,
+        });
+        return result;
+    }
+
+    // A vampire's bite drinks from the undead all the same
+    let traits: Vec<Trait> = vitals
+        .traits()
+        .into_iter()
+        .filter(|trait_| *trait_ != Trait::Undead || damage.ability != Some(Ability::VampireBite))
+        .collect();
+    let was_alive = vitals.health > 0;
+    result.dealt = damage_taken(damage.amount, damage.kind, &traits);
+    let absorbed = result.dealt.min(vitals.shield);
+    vitals.shield -= absorbed;
+    result.applied = (result.dealt - absorbed).min(vitals.health);
+    vitals.health -= result.applied;
+    if result.dealt > 0 {
+        *vitals.round_damage.entry(damage.kind).or_insert(0) += result.dealt;
+    }
+
+    if damage.kind == DamageKind::Fire && !is_immune(damage.kind, &traits) {
+        vitals.effects.apply(
+            Effect::Burn,
+            EffectStats {
+                magnitude: 1,
+                duration: 3,
+            },
+            damage.source,
+        );
+        result.effects.push(Effect::Burn);
+    }
+
+    // Nothing is drained from a shield, or from health the unit no longer had
+    if damage.kind == DamageKind::LifeSteal {
+        result.life_stolen = result.applied;
+    }
+    result.killed = vitals.health == 0;
+
+    events.publish(GameEvent::UnitDamaged {
+        unit,
+        damage: result.dealt,
+        damage_kind: damage.kind,
+    });
+    if was_alive && result.killed {
+        events.publish(GameEvent::UnitKilled {
+            unit,
+            source: damage.source,
+            ability: damage.ability,
+            damage: result.dealt,
+            damage_kind: damage.kind,
+        });
+    }
+    result
+}
+
+// An ally on guard beside the target steps into the blow, taking it less the guard's reduction
+pub fn intercept(
+    target: AllyId,
+    position: Position,
+    damage: u16,
+    guards: &[(AllyId, Position, u16)],
+) -> Option<(AllyId, u16)> {
+    guards
+        .iter()
+        .find(|(guard_id, guard_position, _)| {
+            *guard_id != target && position.adjacent().contains(guard_position)
+        })
+        .map(|(guard_id, _, reduction)| (*guard_id, damage.saturating_sub(*reduction)))
+}
+
+// Immunities are checked first, then vulnerabilities and resistances are summed
+pub fn damage_taken(damage: u16, damage_kind: DamageKind, traits: &[Trait]) -> u16 {
+    if is_immune(damage_kind, traits) {
+        0
+    } else {
+        (damage + damage_bonus(damage_kind, traits))
+            .saturating_sub(damage_reduction(damage_kind, traits))
+    }
+}
+
+// Read from each trait's entry in the registry, so modded traits are resolved the same way
+fn damage_rules(traits: &[Trait]) -> impl Iterator<Item = &'static DamageRule> + '_ {
+    traits
+        .iter()
+        .filter_map(|trait_| trait_stats().get(trait_))
+        .flat_map(|stats| stats.damage.iter())
+}
+
+pub fn is_immune(damage_kind: DamageKind, traits: &[Trait]) -> bool {
+    damage_rules(traits).any(|rule| match rule {
+        DamageRule::Immune(kind) => *kind == damage_kind,
+        DamageRule::OnlyHarmedBy(kinds) => !kinds.contains(&damage_kind),
+        _ => false,
+    })
+}
+
+fn damage_bonus(damage_kind: DamageKind, traits: &[Trait]) -> u16 {
+    damage_rules(traits)
+        .map(|rule| match rule {
+            DamageRule::Extra(kind, amount) if *kind == damage_kind => *amount,
+            _ => 0,
+        })
+        .sum()
+}
+
+fn damage_reduction(damage_kind: DamageKind, traits: &[Trait]) -> u16 {
+    damage_rules(traits)
+        .map(|rule| match rule {
+            DamageRule::Reduced(kind, amount) if *kind == damage_kind => *amount,
+            _ => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::trait_lists;
+
+    #[test]
+    fn life_steal_drains_only_what_was_there_and_gorging_shields() {
+        let mut events = EventBus::default();
+        let mut bat = Vitals::new(2, Vec::new());
+        bat.health = 1;
+        let bite = Damage {
+            amount: 3,
+            kind: DamageKind::LifeSteal,
+            source: Some(UnitId::Ally(AllyId::Alukrod)),
+            ability: Some(Ability::VampireBite),
+        };
+        let result = resolve_damage(UnitId::Enemy(0), &mut bat, bite, &mut events);
+        assert_eq!(result.dealt, 3);
+        assert_eq!(result.applied, 1);
+        assert_eq!(result.life_stolen, 1);
+
+        let mut alukrod = Vitals::new(6, vec![Trait::BloodShield]);
+        alukrod.health = 5;
+        alukrod.drain(3);
+        assert_eq!((alukrod.health, alukrod.shield), (6, 2));
+        assert_eq!(alukrod.health_text(), "6/6 health, 2 shield");
+
+        let claw = Damage {
+            amount: 3,
+            kind: DamageKind::Normal,
+            source: None,
+            ability: None,
+        };
+        let result = resolve_damage(
+            UnitId::Ally(AllyId::Alukrod),
+            &mut alukrod,
+            claw,
+            &mut events,
+        );
+        assert_eq!(result.applied, 1);
+        assert_eq!((alukrod.health, alukrod.shield), (5, 0));
+
+        // Without the trait the excess is simply lost, and shields never outlast the phase
+        let mut ash = Vitals::new(4, Vec::new());
+        ash.drain(2);
+        assert_eq!(ash.shield, 0);
+        alukrod.drain(4);
+        alukrod.start_phase();
+        assert_eq!(alukrod.shield, 0);
+    }
+
+    #[test]
+    fn action_results_narrate_hits_and_remember_what_was_foiled() {
+        let mut events = EventBus::default();
+        let bat = UnitId::Enemy(0);
+        let mut vitals = Vitals::new(2, Vec::new());
+        let fire = Damage {
+            amount: 3,
+            kind: DamageKind::Fire,
+            source: Some(UnitId::Ally(AllyId::AshMagnum)),
+            ability: None,
+        };
+        let result = resolve_damage(bat, &mut vitals, fire, &mut events);
+        let action = ActionResult::hit(bat, result.clone());
+        assert_eq!(action.hits, vec![(bat, result.clone())]);
+        assert_eq!(action.effects, vec![(bat, Effect::Burn)]);
+        assert!(!action.foiled(bat));
+
+        let name = |unit| match unit {
+            UnitId::Ally(ally_id) => ally_id.name(),
+            _ => "Bat".to_string(),
+        };
+        assert_eq!(
+            action.narrate("Ash Magnum", "Fireball", name),
+            "Ash Magnum used Fireball: Bat took 2 and fell, Bat has Burn"
+        );
+
+        // A misted target comes out untouched, and the attacker remembers as much
+        let alukrod = UnitId::Ally(AllyId::Alukrod);
+        let mut misted = Vitals::new(6, Vec::new());
+        let mist = EffectStats {
+            magnitude: 0,
+            duration: 1,
+        };
+        misted.effects.apply(Effect::Mist, mist, None);
+        let result = resolve_damage(alukrod, &mut misted, fire, &mut events);
+        let action = ActionResult::hit(alukrod, result);
+        assert!(action.foiled(alukrod));
+        assert!(!action.foiled(bat));
+        assert_eq!(
+            action.narrate("Bat", "Bite", name),
+            format!(
+                "Bat used Bite: it passed through {}",
+                AllyId::Alukrod.name()
+            )
+        );
+        assert_eq!(
+            ActionResult::default().narrate("Bat", "Spoil", name),
+            "Bat used Spoil"
+        );
+    }
+
+    #[test]
+    fn vampires_bite_the_undead_and_alukrod_never_burns() {
+        let mut events = EventBus::default();
+        let mut vampire = Vitals::new(6, trait_lists()[2].clone());
+        let mut drain = Damage {
+            amount: 1,
+            kind: DamageKind::LifeSteal,
+            source: Some(UnitId::Enemy(1)),
+            ability: Some(Ability::DrainLife),
+        };
+        let result = resolve_damage(UnitId::Enemy(0), &mut vampire, drain, &mut events);
+        assert_eq!(result.dealt, 0);
+
+        drain.source = Some(UnitId::Ally(AllyId::Alukrod));
+        drain.ability = Some(Ability::VampireBite);
+        let result = resolve_damage(UnitId::Enemy(0), &mut vampire, drain, &mut events);
+        assert_eq!(result.life_stolen, 1);
+
+        let mut alukrod = Vitals::new(6, trait_lists()[1].clone());
+        let flask = Damage {
+            amount: 2,
+            kind: DamageKind::Fire,
+            source: Some(UnitId::Ally(AllyId::AshMagnum)),
+            ability: Some(Ability::FireFlask),
+        };
+        let result = resolve_damage(
+            UnitId::Ally(AllyId::Alukrod),
+            &mut alukrod,
+            flask,
+            &mut events,
+        );
+        assert_eq!(result.dealt, 0);
+        assert!(result.effects.is_empty());
+    }
+}
@@ -0,0 +1,227 @@
+use crate::ability::Ability;
+use crate::choices::Choice;
+use crate::level::{AllyId, EnemyAction, EnemyId, EnemyKind, ItemId};
+use crate::math::{Direction, Position};
+
+use serde::{Deserialize, Serialize};
+
+// Every change to the state of a level goes through Level::apply as one of these
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Command {
+    Move {
+        ally_id: AllyId,
+        position: Position,
+    },
+    UseAbility {
+        ally_id: AllyId,
+        ability: Ability,
+        position: Position,
+    },
+    Drop {
+        ally_id: AllyId,
+        position: Position,
+    },
+    PickUp {
+        ally_id: AllyId,
+        item_id: ItemId,
+    },
+    // Puts one use of a picked up ability down as an item, on the ally's tile or beside them
+    DropItem {
+        ally_id: AllyId,
+        ability: Ability,
+        position: Position,
+    },
+    // Answers the prompt for an item walked onto with no room for it, leaving one use of the
+    // ability behind in its place or, with none, the item where it lies
+    Swap {
+        ally_id: AllyId,
+        item_id: ItemId,
+        ability: Option<Ability>,
+    },
+    // A free action, once a turn for the ally handing it over
+    Trade {
+        from: AllyId,
+        to: AllyId,
+        ability: Ability,
+    },
+    /*
+     * An attack arriving: a bolt or flask at the end of its flight, or a dash attack's strike once
+     * the runner gets there. The host decides when, and it is recorded and shared like an order,
+     * so whoever has stepped into a bolt's line by then takes it the same way on every machine
+     */
+    Impact {
+        ally_id: AllyId,
+        ability: Ability,
+        position: Position,
+    },
+    EnemyAct {
+        enemy_id: EnemyId,
+        ability: Ability,
+        action: EnemyAction,
+    },
+    // An enemy steered by the second player in versus mode
+    ControlEnemy {
+        enemy_id: EnemyId,
+        position: Position,
+        action: Option<(Ability, EnemyAction)>,
+    },
+    Spawn {
+        enemy_kind: EnemyKind,
+        position: Position,
+    },
+    Push {
+        ally_id: AllyId,
+        direction: Direction,
+        distance: u16,
+    },
+    // Made in dialogue by whoever is hosting
+    Choose {
+        choice: Choice,
+    },
+    EndTurn,
+}
+
+impl Command {
+    // The ally a player orders about with the command, online only its owner may give it
+    pub fn ordered_ally(&self) -> Option<AllyId> {
+        match *self {
+            Command::Move { ally_id, .. }
+            | Command::UseAbility { ally_id, .. }
+            | Command::Drop { ally_id, .. }
+            | Command::DropItem { ally_id, .. }
+            | Command::Swap { ally_id, .. }
+            | Command::Trade { from: ally_id, .. } => Some(ally_id),
+            _ => None,
+        }
+    }
+
+    // Tile the command acts on, for following it with the camera
+    pub fn position(&self) -> Option<Position> {
+        match self {
+            Command::Move { position, .. }
+            | Command::UseAbility { position, .. }
+            | Command::Drop { position, .. }
+            | Command::DropItem { position, .. }
+            | Command::ControlEnemy { position, .. }
+            | Command::Spawn { position, .. } => Some(*position),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ability::DamageKind;
+    use crate::choices::Approach;
+    use crate::core::{Effect, EffectStats};
+
+    #[test]
+    fn every_command_survives_a_round_trip() {
+        let position = Position { x: 3, y: 7 };
+        let commands = [
+            Command::Move {
+                ally_id: AllyId::AshMagnum,
+                position,
+            },
+            Command::UseAbility {
+                ally_id: AllyId::Alukrod,
+                ability: Ability::Hellfire,
+                position,
+            },
+            Command::Drop {
+                ally_id: AllyId::AshMagnum,
+                position,
+            },
+            Command::PickUp {
+                ally_id: AllyId::Priest,
+                item_id: 4,
+            },
+            Command::DropItem {
+                ally_id: AllyId::AshMagnum,
+                ability: Ability::HolyWater,
+                position,
+            },
+            Command::Swap {
+                ally_id: AllyId::AshMagnum,
+                item_id: 2,
+                ability: Some(Ability::Garlic),
+            },
+            Command::Swap {
+                ally_id: AllyId::AshMagnum,
+                item_id: 2,
+                ability: None,
+            },
+            Command::Trade {
+                from: AllyId::AshMagnum,
+                to: AllyId::Priest,
+                ability: Ability::WoodenStake,
+            },
+            Command::Impact {
+                ally_id: AllyId::AshMagnum,
+                ability: Ability::CrossbowIronBolt,
+                position,
+            },
+            Command::EnemyAct {
+                enemy_id: 1,
+                ability: Ability::VampireBite,
+                action: EnemyAction::Attack {
+                    ally_id: AllyId::Alukrod,
+                    damage_kind: DamageKind::Normal,
+                    damage: 2,
+                },
+            },
+            Command::EnemyAct {
+                enemy_id: 5,
+                ability: Ability::Mist,
+                action: EnemyAction::Effect {
+                    effect: Effect::Mist,
+                    stats: EffectStats {
+                        magnitude: 1,
+                        duration: 2,
+                    },
+                },
+            },
+            Command::ControlEnemy {
+                enemy_id: 0,
+                position,
+                action: Some((
+                    Ability::Charge,
+                    EnemyAction::Charge {
+                        ally_id: AllyId::AshMagnum,
+                        damage_kind: DamageKind::Normal,
+                        damage: 1,
+                        distance: 3,
+                    },
+                )),
+            },
+            Command::ControlEnemy {
+                enemy_id: 0,
+                position,
+                action: None,
+            },
+            Command::Spawn {
+                enemy_kind: EnemyKind::Ghost,
+                position,
+            },
+            Command::Push {
+                ally_id: AllyId::Priest,
+                direction: Direction::Left,
+                distance: 2,
+            },
+            Command::Choose {
+                choice: Choice::Approach(Approach::Sneak),
+            },
+            Command::Choose {
+                choice: Choice::Loyalty(-1),
+            },
+            Command::EndTurn,
+        ];
+
+        for command in commands {
+            let json = serde_json::to_string(&command).unwrap();
+            let read: Command = serde_json::from_str(&json).unwrap();
+            assert_eq!(read, command, "{}", json);
+        }
+    }
+}
@@ -0,0 +1,485 @@
+use crate::math::Direction;
+
+use godot::engine::{
+    Input, InputEvent, InputEventJoypadButton, InputEventJoypadMotion, InputEventKey, InputMap, Os,
+};
+use godot::global::{JoyAxis, JoyButton, Key};
+use godot::obj::EngineEnum;
+use godot::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use strum::IntoEnumIterator;
+
+// Past this a stick or trigger counts as pushed
+const DEADZONE: f32 = 0.5;
+// How long a direction is held before the cursor starts repeating, then how often it repeats
+const REPEAT_DELAY: f64 = 0.35;
+const REPEAT_INTERVAL: f64 = 0.08;
+
+// Actions the player can rebind. The diagonals and ability numbers stay on their keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Action {
+    Up,
+    Down,
+    Left,
+    Right,
+    Select,
+    Cancel,
+    UseAbility,
+    Choose,
+    NextAlly,
+    NextAbility,
+    PreviousAbility,
+    Details,
+    Inspect,
+    Center,
+    FreeCamera,
+    FastForward,
+    Skip,
+    Pause,
+    Jump,
+}
+
+impl Action {
+    pub const ALL: [Self; 19] = [
+        Self::Up,
+        Self::Down,
+        Self::Left,
+        Self::Right,
+        Self::Select,
+        Self::Cancel,
+        Self::UseAbility,
+        Self::Choose,
+        Self::NextAlly,
+        Self::NextAbility,
+        Self::PreviousAbility,
+        Self::Details,
+        Self::Inspect,
+        Self::Center,
+        Self::FreeCamera,
+        Self::FastForward,
+        Self::Skip,
+        Self::Pause,
+        Self::Jump,
+    ];
+
+    pub fn of(direction: Direction) -> Self {
+        match direction {
+            Direction::Left => Self::Left,
+            Direction::Right => Self::Right,
+            Direction::Up => Self::Up,
+            Direction::Down => Self::Down,
+        }
+    }
+
+    // The action's name in the input map
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Up => "up",
+            Self::Down => "down",
+            Self::Left => "left",
+            Self::Right => "right",
+            Self::Select => "select",
+            Self::Cancel => "cancel",
+            Self::UseAbility => "use_ability",
+            Self::Choose => "choose",
+            Self::NextAlly => "next_ally",
+            Self::NextAbility => "next_ability",
+            Self::PreviousAbility => "previous_ability",
+            Self::Details => "details",
+            Self::Inspect => "inspect",
+            Self::Center => "center",
+            Self::FreeCamera => "free_camera",
+            Self::FastForward => "fast_forward",
+            Self::Skip => "skip",
+            Self::Pause => "pause",
+            Self::Jump => "jump",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Up => "Up",
+            Self::Down => "Down",
+            Self::Left => "Left",
+            Self::Right => "Right",
+            Self::Select => "Select",
+            Self::Cancel => "Cancel",
+            Self::UseAbility => "Use ability",
+            Self::Choose => "Choose ability",
+            Self::NextAlly => "Next ally",
+            Self::NextAbility => "Next ability",
+            Self::PreviousAbility => "Previous ability",
+            Self::Details => "Details",
+            Self::Inspect => "Inspect",
+            Self::Center => "Center camera",
+            Self::FreeCamera => "Free camera",
+            Self::FastForward => "Fast-forward",
+            Self::Skip => "Skip",
+            Self::Pause => "Pause",
+            Self::Jump => "Jump (hold)",
+        }
+    }
+
+    // The menus' own action that follows this one's bindings
+    fn ui_action(&self) -> Option<&'static str> {
+        match self {
+            Self::Up => Some("ui_up"),
+            Self::Down => Some("ui_down"),
+            Self::Left => Some("ui_left"),
+            Self::Right => Some("ui_right"),
+            Self::Select => Some("ui_accept"),
+            Self::Cancel => Some("ui_cancel"),
+            _ => None,
+        }
+    }
+
+    // The keys match the project's input map, which they take over from once remapped
+    pub fn default_binding(&self, device: Device) -> Option<Binding> {
+        let binding = match device {
+            Device::Keyboard => match self {
+                Self::Up => Binding::key(Key::UP),
+                Self::Down => Binding::key(Key::DOWN),
+                Self::Left => Binding::key(Key::LEFT),
+                Self::Right => Binding::key(Key::RIGHT),
+                Self::Select => Binding::key(Key::Z),
+                Self::Cancel => Binding::key(Key::ESCAPE),
+                Self::UseAbility => Binding::key(Key::X),
+                Self::Choose => Binding::key(Key::C),
+                Self::NextAlly => Binding::key(Key::N),
+                Self::NextAbility => Binding::key(Key::TAB),
+                Self::PreviousAbility => Binding::Key {
+                    code: Key::TAB.ord(),
+                    shift: true,
+                },
+                Self::Details => Binding::key(Key::D),
+                Self::Inspect => Binding::key(Key::I),
+                Self::Center => Binding::key(Key::Q),
+                Self::FreeCamera => Binding::key(Key::V),
+                Self::FastForward => Binding::key(Key::F),
+                Self::Skip => Binding::key(Key::ENTER),
+                Self::Pause => Binding::key(Key::P),
+                Self::Jump => Binding::key(Key::CTRL),
+            },
+            Device::Gamepad => match self {
+                Self::Up => Binding::button(JoyButton::DPAD_UP),
+                Self::Down => Binding::button(JoyButton::DPAD_DOWN),
+                Self::Left => Binding::button(JoyButton::DPAD_LEFT),
+                Self::Right => Binding::button(JoyButton::DPAD_RIGHT),
+                Self::Select => Binding::button(JoyButton::A),
+                Self::Cancel => Binding::button(JoyButton::B),
+                Self::UseAbility => Binding::button(JoyButton::X),
+                Self::Choose => Binding::button(JoyButton::Y),
+                Self::NextAlly => Binding::button(JoyButton::RIGHT_SHOULDER),
+                Self::NextAbility => Binding::axis(JoyAxis::TRIGGER_RIGHT, true),
+                Self::PreviousAbility => Binding::axis(JoyAxis::TRIGGER_LEFT, true),
+                Self::Details => Binding::button(JoyButton::MISC1),
+                Self::Inspect => Binding::button(JoyButton::BACK),
+                Self::Center => Binding::button(JoyButton::RIGHT_STICK),
+                Self::FreeCamera => Binding::button(JoyButton::LEFT_STICK),
+                Self::Pause => Binding::button(JoyButton::START),
+                Self::Jump => Binding::button(JoyButton::LEFT_SHOULDER),
+                // Left free for whoever wants them on a pad
+                Self::FastForward | Self::Skip => return None,
+            },
+        };
+        Some(binding)
+    }
+
+    // The left stick always moves in the four directions on top of whatever they are bound to
+    fn stick(&self) -> Option<Binding> {
+        match self {
+            Self::Up => Some(Binding::axis(JoyAxis::LEFT_Y, false)),
+            Self::Down => Some(Binding::axis(JoyAxis::LEFT_Y, true)),
+            Self::Left => Some(Binding::axis(JoyAxis::LEFT_X, false)),
+            Self::Right => Some(Binding::axis(JoyAxis::LEFT_X, true)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Device {
+    Keyboard,
+    Gamepad,
+}
+
+// Codes are the engine's enum values, kept as numbers so the config can be written out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Binding {
+    // Physical keys, so the layout stays in place on other keyboards
+    Key { code: i32, shift: bool },
+    Button(i32),
+    // A stick or trigger pushed one way past the deadzone
+    Axis { axis: i32, positive: bool },
+}
+
+impl Binding {
+    fn key(key: Key) -> Self {
+        Self::Key {
+            code: key.ord(),
+            shift: false,
+        }
+    }
+
+    fn button(button: JoyButton) -> Self {
+        Self::Button(button.ord())
+    }
+
+    fn axis(axis: JoyAxis, positive: bool) -> Self {
+        Self::Axis {
+            axis: axis.ord(),
+            positive,
+        }
+    }
+
+    pub fn device(&self) -> Device {
+        match self {
+            Self::Key { .. } => Device::Keyboard,
+            Self::Button(_) | Self::Axis { .. } => Device::Gamepad,
+        }
+    }
+
+    // What a key press or a push on a pad would bind to, if anything
+    pub fn from_event(event: &Gd<InputEvent>) -> Option<Self> {
+        if let Ok(key) = event.clone().try_cast::<InputEventKey>() {
+            let code = key.get_physical_keycode();
+            // A lone shift is waited out for the key it goes with
+            if !key.is_pressed() || key.is_echo() || code == Key::NONE || code == Key::SHIFT {
+                return None;
+            }
+            return Some(Self::Key {
+                code: code.ord(),
+                shift: key.is_shift_pressed(),
+            });
+        }
+        if let Ok(button) = event.clone().try_cast::<InputEventJoypadButton>() {
+            return button
+                .is_pressed()
+                .then(|| Self::button(button.get_button_index()));
+        }
+        if let Ok(motion) = event.clone().try_cast::<InputEventJoypadMotion>() {
+            let value = motion.get_axis_value();
+            return (value.abs() >= DEADZONE).then(|| Self::axis(motion.get_axis(), value > 0.0));
+        }
+        None
+    }
+
+    // Any pad can use it, not only the first one plugged in
+    fn event(&self) -> Gd<InputEvent> {
+        let mut event: Gd<InputEvent> = match *self {
+            Self::Key { code, shift } => {
+                let mut event = InputEventKey::new_gd();
+                event.set_physical_keycode(Key::from_ord(code));
+                event.set_shift_pressed(shift);
+                event.upcast()
+            }
+            Self::Button(button) => {
+                let mut event = InputEventJoypadButton::new_gd();
+                event.set_button_index(JoyButton::from_ord(button));
+                event.upcast()
+            }
+            Self::Axis { axis, positive } => {
+                let mut event = InputEventJoypadMotion::new_gd();
+                event.set_axis(JoyAxis::from_ord(axis));
+                event.set_axis_value(if positive { 1.0 } else { -1.0 });
+                event.upcast()
+            }
+        };
+        event.set_device(-1);
+        event
+    }
+
+    pub fn label(&self) -> String {
+        match *self {
+            Self::Key { code, shift } => {
+                let key = Os::singleton().get_keycode_string(Key::from_ord(code));
+                if shift {
+                    format!("Shift+{}", key)
+                } else {
+                    key.to_string()
+                }
+            }
+            Self::Button(button) => match JoyButton::from_ord(button) {
+                JoyButton::A => "A".into(),
+                JoyButton::B => "B".into(),
+                JoyButton::X => "X".into(),
+                JoyButton::Y => "Y".into(),
+                JoyButton::BACK => "Back".into(),
+                JoyButton::GUIDE => "Guide".into(),
+                JoyButton::START => "Start".into(),
+                JoyButton::MISC1 => "Share".into(),
+                JoyButton::LEFT_STICK => "L3".into(),
+                JoyButton::RIGHT_STICK => "R3".into(),
+                JoyButton::LEFT_SHOULDER => "LB".into(),
+                JoyButton::RIGHT_SHOULDER => "RB".into(),
+                JoyButton::DPAD_UP => "D-pad up".into(),
+                JoyButton::DPAD_DOWN => "D-pad down".into(),
+                JoyButton::DPAD_LEFT => "D-pad left".into(),
+                JoyButton::DPAD_RIGHT => "D-pad right".into(),
+                _ => format!("Button {}", button),
+            },
+            Self::Axis { axis, positive } => {
+                let sign = if positive { "+" } else { "-" };
+                match JoyAxis::from_ord(axis) {
+                    JoyAxis::TRIGGER_LEFT => "LT".into(),
+                    JoyAxis::TRIGGER_RIGHT => "RT".into(),
+                    JoyAxis::LEFT_X => format!("Left stick X{}", sign),
+                    JoyAxis::LEFT_Y => format!("Left stick Y{}", sign),
+                    JoyAxis::RIGHT_X => format!("Right stick X{}", sign),
+                    JoyAxis::RIGHT_Y => format!("Right stick Y{}", sign),
+                    _ => format!("Axis {}{}", axis, sign),
+                }
+            }
+        }
+    }
+}
+
+// Bindings the player has changed, everything else keeps its default. None unbinds an action
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Controls {
+    keyboard: BTreeMap<Action, Option<Binding>>,
+    gamepad: BTreeMap<Action, Option<Binding>>,
+}
+
+impl Controls {
+    fn remapped(&self, device: Device) -> &BTreeMap<Action, Option<Binding>> {
+        match device {
+            Device::Keyboard => &self.keyboard,
+            Device::Gamepad => &self.gamepad,
+        }
+    }
+
+    pub fn binding(&self, action: Action, device: Device) -> Option<Binding> {
+        match self.remapped(device).get(&action) {
+            Some(binding) => *binding,
+            None => action.default_binding(device),
+        }
+    }
+
+    // An action already on the binding is handed the one being replaced, so the two swap and
+    // nothing goes unreachable
+    pub fn rebind(&mut self, action: Action, binding: Binding) {
+        let device = binding.device();
+        let replaced = self.binding(action, device);
+        let taken = Action::ALL
+            .into_iter()
+            .find(|other| *other != action && self.binding(*other, device) == Some(binding));
+        if let Some(other) = taken {
+            self.set(other, device, replaced);
+        }
+        self.set(action, device, Some(binding));
+    }
+
+    fn set(&mut self, action: Action, device: Device, binding: Option<Binding>) {
+        let remapped = match device {
+            Device::Keyboard => &mut self.keyboard,
+            Device::Gamepad => &mut self.gamepad,
+        };
+        if binding == action.default_binding(device) {
+            remapped.remove(&action);
+        } else {
+            remapped.insert(action, binding);
+        }
+    }
+
+    // Starts again from the project's input map, then swaps in the bindings. Keys are only
+    // replaced once remapped, pads always get theirs since the project has none
+    pub fn apply(&self) {
+        let mut input_map = InputMap::singleton();
+        input_map.load_from_project_settings();
+
+        for action in Action::ALL {
+            let name = StringName::from(action.name());
+            if self.keyboard.contains_key(&action) {
+                for event in input_map.action_get_events(name.clone()).iter_shared() {
+                    if event.clone().try_cast::<InputEventKey>().is_ok() {
+                        input_map.action_erase_event(name.clone(), event);
+                    }
+                }
+                if let Some(binding) = self.binding(action, Device::Keyboard) {
+                    input_map.action_add_event(name.clone(), binding.event());
+                }
+            }
+
+            let added: Vec<Binding> = [self.binding(action, Device::Gamepad), action.stick()]
+                .into_iter()
+                .flatten()
+                .collect();
+            for binding in &added {
+                input_map.action_add_event(name.clone(), binding.event());
+            }
+            // The menus keep their own keys and take on these as well
+            if let Some(ui_action) = action.ui_action() {
+                let keys = self.binding(action, Device::Keyboard);
+                for binding in keys.iter().chain(&added) {
+                    input_map.action_add_event(ui_action.into(), binding.event());
+                }
+            }
+        }
+    }
+}
+
+// The direction being held down, on keys, the d-pad or the stick
+pub fn held() -> Option<Direction> {
+    let input = Input::singleton();
+    Direction::iter()
+        .find(|direction| input.is_action_pressed(Action::of(*direction).name().into()))
+}
+
+// Steps a held direction again and again. The first step is left to the press itself
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Repeat {
+    held: Option<Direction>,
+    timer: f64,
+}
+
+impl Repeat {
+    pub fn tick(&mut self, held: Option<Direction>, delta: f64) -> Option<Direction> {
+        if held != self.held {
+            self.held = held;
+            self.timer = REPEAT_DELAY;
+            return None;
+        }
+
+        let direction = self.held?;
+        self.timer -= delta;
+        if self.timer <= 0.0 {
+            self.timer += REPEAT_INTERVAL;
+            Some(direction)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_taken_binding_swaps_and_a_held_stick_repeats() {
+        let mut controls = Controls::default();
+        let escape = Action::Cancel.default_binding(Device::Keyboard).unwrap();
+        let z = Action::Select.default_binding(Device::Keyboard).unwrap();
+        controls.rebind(Action::Select, escape);
+        assert_eq!(
+            controls.binding(Action::Select, Device::Keyboard),
+            Some(escape)
+        );
+        assert_eq!(controls.binding(Action::Cancel, Device::Keyboard), Some(z));
+        // Swapping back leaves nothing remapped
+        controls.rebind(Action::Select, z);
+        assert_eq!(controls, Controls::default());
+
+        let up = Some(Direction::Up);
+        let mut repeat = Repeat::default();
+        let ticks: Vec<_> = [0.0, 0.2, 0.2, 0.02, 0.05]
+            .into_iter()
+            .map(|delta| repeat.tick(up, delta))
+            .collect();
+        assert_eq!(ticks, [None, None, up, None, up]);
+        assert_eq!(repeat.tick(None, 0.5), None);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::ability::DamageKind;
+use crate::effects::{Effects, Tick};
+use crate::level::{AllyId, EnemyId, ItemId, NpcId, ObstacleId};
+use crate::math::Position;
+use crate::traits::{active_traits, Trait};
+
+use serde::{Deserialize, Serialize};
+
+use std::cmp;
+use std::collections::HashMap;
+
+// Game rules kept free of Godot nodes, the scene classes in level read and write through these
+
+pub const COFFIN_HEAL: u16 = 2;
+pub const REGENERATION: u16 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Effect {
+    Burn,
+    Mist,
+    Blessing,
+    Guarding,
+    Taunting,
+    Blinded,
+    Darkness,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct EffectStats {
+    pub magnitude: u16,
+    pub duration: u16,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Tile {
+    #[default]
+    Empty,
+    Ally(AllyId),
+    Enemy(EnemyId),
+    // Someone on neither side, who can be hurt but takes no orders
+    Npc(NpcId),
+    Obstacle(ObstacleId),
+}
+
+impl Tile {
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Self::Empty => true,
+            _ => false,
+        }
+    }
+}
+
+// Lingering effects on the ground that stay put whatever stands on them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Terrain {
+    // Within reach of garlic, which allergic enemies keep out of
+    Garlic,
+    // Let in by an open shutter, or the dawn, burning enemies that stand in it
+    Sunlight,
+    // Left by a fire flask, setting alight anyone still standing in it when a round starts
+    Fire,
+}
+
+// Everything on one tile: the unit or obstacle standing there, an item on the ground beneath it
+// and any terrain effects, so none of them overwrites another
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Occupancy {
+    pub tile: Tile,
+    pub item: Option<ItemId>,
+    // Kept once per source so overlapping effects can be removed one at a time
+    pub terrain: Vec<Terrain>,
+    // The tile on another floor that stairs here lead to
+    pub stairs: Option<Position>,
+    // Held for an enemy about to appear, so nothing moves onto it in the meantime
+    pub arriving: bool,
+}
+
+// A unit on either side, for selection that works the same for both factions, or caught in
+// between
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum UnitId {
+    Ally(AllyId),
+    Enemy(EnemyId),
+    Npc(NpcId),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Turn {
+    #[default]
+    Ally,
+    // Between the allies and the enemies, for anyone on neither side to make their move
+    Npc,
+    Enemy(usize, bool),
+}
+
+// Health, traits and effects shared by allies and enemies
+#[derive(Debug, Clone, Default)]
+pub struct Vitals {
+    pub max_health: u16,
+    pub health: u16,
+    pub base_traits: Vec<Trait>,
+    pub effects: Effects,
+    pub round_damage: HashMap<DamageKind, u16>,
+    // Soaks up damage before health does, gone by the unit's next phase
+    pub shield: u16,
+}
+
+impl Vitals {
+    pub fn new(max_health: u16, base_traits: Vec<Trait>) -> Self {
+        Self {
+            max_health,
+            health: max_health,
+            base_traits,
+            ..Default::default()
+        }
+    }
+
+    pub fn traits(&self) -> Vec<Trait> {
+        active_traits(&self.base_traits, &self.effects)
+    }
+
+    pub fn heal(&mut self, amount: u16) {
+        self.health = cmp::min(self.health + amount, self.max_health);
+    }
+
+    // Life stolen from a hit heals, and whatever is left over at full health shields units that
+    // gorge on it
+    pub fn drain(&mut self, amount: u16) {
+        let overheal = (self.health + amount).saturating_sub(self.max_health);
+        self.heal(amount);
+        if self.traits().contains(&Trait::BloodShield) {
+            self.shield += overheal;
+        }
+    }
+
+    pub fn health_text(&self) -> String {
+        if self.shield > 0 {
+            format!(
+                "{}/{} health, {} shield",
+                self.health, self.max_health, self.shield
+            )
+        } else {
+            format!("{}/{} health", self.health, self.max_health)
+        }
+    }
+
+    // Before effects tick and the damage taken since the last phase is forgotten, see
+    // passives::hooks
+    pub fn regenerate(&mut self) {
+        if regenerates(&self.traits(), &self.round_damage) {
+            self.heal(REGENERATION);
+        }
+    }
+
+    // The damage effects deal as they tick is left to the unit so it goes through mist and the
+    // hit animations
+    pub fn start_phase(&mut self) -> Vec<Tick> {
+        self.shield = 0;
+        self.round_damage.clear();
+        self.effects.tick()
+    }
+}
+
+// Regenerating units heal every phase unless fire or holy damage kept their wounds open
+fn regenerates(traits: &[Trait], round_damage: &HashMap<DamageKind, u16>) -> bool {
+    traits.contains(&Trait::Regeneration)
+        && !round_damage.contains_key(&DamageKind::Fire)
+        && !round_damage.contains_key(&DamageKind::Holy)
+}
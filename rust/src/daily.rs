@@ -0,0 +1,144 @@
+use crate::dialogue::Room;
+use crate::scoring::Score;
+
+use godot::engine::file_access::ModeFlags;
+use godot::engine::{FileAccess, Time};
+use serde::{Deserialize, Serialize};
+
+const PATH: &str = "user://daily.json";
+
+// The day's seed is its UTC date written as YYYYMMDD, so every player gets the same run
+pub fn today() -> u64 {
+    let date = Time::singleton()
+        .get_date_string_from_system_ex()
+        .utc(true)
+        .done();
+    seed_of(&date.to_string())
+}
+
+pub fn seed_of(date: &str) -> u64 {
+    date.chars()
+        .filter(char::is_ascii_digit)
+        .collect::<String>()
+        .parse()
+        .unwrap_or(0)
+}
+
+pub fn date_of(seed: u64) -> String {
+    format!("{}-{:02}-{:02}", seed / 10000, seed / 100 % 100, seed % 100)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DailyScore {
+    pub seed: u64,
+    pub room: Room,
+    pub score: Score,
+}
+
+// Best scores of past daily challenges, kept locally to compare against
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Daily {
+    pub best: Vec<DailyScore>,
+}
+
+impl Daily {
+    pub fn load() -> Self {
+        FileAccess::open(PATH.into(), ModeFlags::READ)
+            .and_then(|file| serde_json::from_str(&file.get_as_text().to_string()).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) {
+        if let (Some(mut file), Ok(json)) = (
+            FileAccess::open(PATH.into(), ModeFlags::WRITE),
+            serde_json::to_string(self),
+        ) {
+            file.store_string(json.into());
+        }
+    }
+
+    // Returns the room's best score that day before this one
+    pub fn record(seed: u64, room: Room, score: Score) -> Option<Score> {
+        let mut daily = Self::load();
+        let best = daily.keep(seed, room, score);
+        daily.save();
+        best
+    }
+
+    pub fn keep(&mut self, seed: u64, room: Room, score: Score) -> Option<Score> {
+        let entry = self
+            .best
+            .iter_mut()
+            .find(|entry| entry.seed == seed && entry.room == room);
+        match entry {
+            Some(entry) => {
+                let best = entry.score;
+                if score.total() > best.total() {
+                    entry.score = score;
+                }
+                Some(best)
+            }
+            None => {
+                self.best.push(DailyScore { seed, room, score });
+                None
+            }
+        }
+    }
+
+    pub fn on(&self, seed: u64) -> Vec<DailyScore> {
+        self.best
+            .iter()
+            .filter(|entry| entry.seed == seed)
+            .copied()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::UnitId;
+    use crate::level::AllyId;
+    use crate::modifiers::Modifiers;
+    use crate::rng::Rng;
+    use crate::stats::RunStats;
+
+    #[test]
+    fn the_same_day_draws_the_same_daily_challenge() {
+        let seed = seed_of("2024-03-09");
+        assert_eq!(seed, 20240309);
+        assert_eq!(date_of(seed), "2024-03-09");
+
+        let mut a = Rng::new(seed);
+        let mut b = Rng::new(seed);
+        let draws: Vec<u64> = (0..8).map(|_| a.below(100)).collect();
+        assert_eq!(draws, (0..8).map(|_| b.below(100)).collect::<Vec<_>>());
+
+        let today = Modifiers::daily(seed);
+        let kept: Vec<bool> = (0..12).map(|index| today.keeps_item(index)).collect();
+        assert_eq!(
+            kept,
+            (0..12)
+                .map(|index| Modifiers::daily(seed).keeps_item(index))
+                .collect::<Vec<_>>()
+        );
+        let tomorrow = Modifiers::daily(seed + 1);
+        assert_ne!(
+            kept,
+            (0..12)
+                .map(|index| tomorrow.keeps_item(index))
+                .collect::<Vec<_>>()
+        );
+
+        // Only a better score replaces the day's best
+        let mut stats = RunStats::default();
+        let mut scores = Daily::default();
+        let slow = Score::new(Room::EntranceHall, 40, &stats, 4);
+        stats.kills.insert(UnitId::Ally(AllyId::AshMagnum), 4);
+        let fast = Score::new(Room::EntranceHall, 10, &stats, 4);
+        assert_eq!(scores.keep(seed, Room::EntranceHall, fast), None);
+        assert_eq!(scores.keep(seed, Room::EntranceHall, slow), Some(fast));
+        assert_eq!(scores.on(seed)[0].score, fast);
+        assert!(scores.on(seed + 1).is_empty());
+    }
+}
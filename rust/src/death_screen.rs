@@ -1,6 +1,12 @@
+use crate::defeat::Defeat;
 use crate::dialogue::Room;
+use crate::level::Level;
+use crate::main_menu::MENU_SCENE;
+use crate::replay::Replay;
+use crate::stats::RunStats;
+use crate::ui;
 
-use godot::engine::CenterContainer;
+use godot::engine::{Button, CenterContainer, ICenterContainer, Label};
 use godot::prelude::*;
 
 #[derive(GodotClass)]
@@ -8,20 +14,108 @@ use godot::prelude::*;
 pub struct DeathScreen {
     #[export]
     pub room: Room,
+    pub defeat: Option<Defeat>,
+    // What killed the fallen ally, or which rule the level was lost by, written out by the level
+    // before the screen is shown
+    pub cause: String,
+    pub round: u16,
+    pub stats: RunStats,
     base: Base<CenterContainer>,
 }
 
+#[godot_api]
+impl ICenterContainer for DeathScreen {
+    fn ready(&mut self) {
+        if let Some(headline) = self.defeat.as_ref().and_then(Defeat::headline) {
+            let mut label = self.base().get_node_as::<Label>("VBoxContainer/Label");
+            label.set_text(headline.into());
+        }
+
+        let mut cause = self.base().get_node_as::<Label>("VBoxContainer/Cause");
+        cause.set_text(self.cause.clone().into());
+
+        let mut stats = vec![
+            format!("{} in the {}", turns(self.round), self.room.name()),
+            format!("{} enemies slain", self.stats.enemies_slain()),
+            format!("{} abilities used", self.stats.abilities_used),
+            format!("{} damage dealt", self.stats.damage_dealt),
+            format!("{} damage taken", self.stats.damage_taken),
+        ];
+        if !self.stats.slain.is_empty() {
+            stats.push(format!("Turned to dust: {}", self.stats.dust()));
+        }
+        let mut label = self.base().get_node_as::<Label>("VBoxContainer/Stats");
+        label.set_text(stats.join("\n").into());
+
+        let checkpoint = Replay::load(self.room).map_or(0, |replay| replay.checkpoint);
+        let mut checkpoint_button = self
+            .base()
+            .get_node_as::<Button>("VBoxContainer/CheckpointButton");
+        checkpoint_button.set_disabled(checkpoint == 0);
+
+        ui::focus_first(self.base().clone().upcast());
+    }
+}
+
 #[godot_api]
 impl DeathScreen {
     #[func]
     fn _on_restart_button_pressed(&self) {
-        let scene = match self.room {
-            Room::EntranceHall => "res://scenes/levels/1-entrance-hall.tscn",
-            Room::GreatHall => "res://scenes/levels/2-great-hall.tscn",
-        };
         self.base()
             .get_tree()
             .unwrap()
-            .change_scene_to_file(scene.into());
+            .change_scene_to_file(self.room.scene().into());
+    }
+
+    // The replay is played back to the checkpoint at speed, then handed over
+    #[func]
+    fn _on_checkpoint_button_pressed(&mut self) {
+        self.play_back(true);
+    }
+
+    #[func]
+    fn _on_menu_button_pressed(&self) {
+        self.base()
+            .get_tree()
+            .unwrap()
+            .change_scene_to_file(MENU_SCENE.into());
+    }
+
+    #[func]
+    fn _on_replay_button_pressed(&mut self) {
+        self.play_back(false);
+    }
+}
+
+impl DeathScreen {
+    fn play_back(&mut self, catching_up: bool) {
+        let scene = load::<PackedScene>(self.room.scene());
+        let mut level: Gd<Level> = scene.instantiate().unwrap().cast();
+
+        {
+            let mut level = level.bind_mut();
+            level.playback = true;
+            level.catching_up = catching_up;
+        }
+
+        self.base()
+            .get_tree()
+            .unwrap()
+            .get_root()
+            .unwrap()
+            .add_child(level.clone().upcast());
+        self.base()
+            .get_tree()
+            .unwrap()
+            .set_current_scene(level.upcast());
+
+        self.base_mut().queue_free();
+    }
+}
+
+fn turns(round: u16) -> String {
+    match round {
+        1 => "Lasted 1 turn".into(),
+        _ => format!("Lasted {} turns", round),
     }
 }
@@ -0,0 +1,87 @@
+// Why a level was lost, shown on the death screen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Defeat {
+    // Ash fell with no lives left, in a level that can't go on without him
+    AshDied,
+    // Nobody left standing
+    PartyDied,
+    // Set by the level when something it needed done can no longer be
+    ObjectiveFailed(String),
+    OutOfTurns(u16),
+}
+
+impl Defeat {
+    // The death screen's own lines are kept for Ash, who is the one they were written for
+    pub fn headline(&self) -> Option<String> {
+        match self {
+            Self::AshDied => None,
+            Self::PartyDied => Some("The whole party has fallen".into()),
+            Self::ObjectiveFailed(_) => Some("The job went wrong".into()),
+            Self::OutOfTurns(_) => Some("Too slow".into()),
+        }
+    }
+
+    // Deaths are described by the level, which knows who did the killing
+    pub fn reason(&self) -> Option<String> {
+        match self {
+            Self::AshDied | Self::PartyDied => None,
+            Self::ObjectiveFailed(reason) => Some(reason.clone()),
+            Self::OutOfTurns(1) => Some("The room had to be cleared in 1 turn".into()),
+            Self::OutOfTurns(limit) => {
+                Some(format!("The room had to be cleared in {} turns", limit))
+            }
+        }
+    }
+}
+
+// What loses a level besides the whole party falling, which always does. Set per level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DefeatRules {
+    pub ash_must_survive: bool,
+    pub turn_limit: Option<u16>,
+}
+
+impl DefeatRules {
+    // Checked whenever an ally falls and whenever a new round starts
+    pub fn check(&self, ash_alive: bool, allies_alive: usize, round: u16) -> Option<Defeat> {
+        if self.ash_must_survive && !ash_alive {
+            Some(Defeat::AshDied)
+        } else if allies_alive == 0 {
+            Some(Defeat::PartyDied)
+        } else {
+            match self.turn_limit {
+                Some(limit) if round > limit => Some(Defeat::OutOfTurns(limit)),
+                _ => None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defeat_rules_lose_with_ash_the_party_or_the_clock() {
+        let classic = DefeatRules {
+            ash_must_survive: true,
+            turn_limit: None,
+        };
+        assert_eq!(classic.check(false, 2, 3), Some(Defeat::AshDied));
+        assert_eq!(classic.check(true, 1, 99), None);
+
+        let timed = DefeatRules {
+            ash_must_survive: false,
+            turn_limit: Some(10),
+        };
+        assert_eq!(timed.check(false, 2, 3), None);
+        assert_eq!(timed.check(false, 0, 3), Some(Defeat::PartyDied));
+        assert_eq!(timed.check(true, 3, 10), None);
+        assert_eq!(timed.check(true, 3, 11), Some(Defeat::OutOfTurns(10)));
+        assert_eq!(Defeat::AshDied.headline(), None);
+        assert_eq!(
+            Defeat::OutOfTurns(10).reason().as_deref(),
+            Some("The room had to be cleared in 10 turns")
+        );
+    }
+}
@@ -1,11 +1,36 @@
+use crate::audio::{self, Sfx};
+use crate::choices::Choice;
+use crate::cinematic;
+use crate::command::Command;
+use crate::dust::Death;
+use crate::event::{GameEvent, Listener};
 use crate::level::{AllyId, EnemyKind, Level};
+use crate::math::Position;
+use crate::mods;
+use crate::nodes::{self, find_level};
+use crate::settings;
 
 use godot::engine::Sprite2D;
 use godot::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::mem;
 use std::sync::OnceLock;
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Var, Export, GodotConvert)]
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    Var,
+    Export,
+    GodotConvert,
+    Serialize,
+    Deserialize,
+)]
 #[godot(via = u8)]
 pub enum Room {
     #[default]
@@ -13,70 +38,310 @@ pub enum Room {
     GreatHall,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+impl Room {
+    // In the order they are played
+    pub const ALL: [Room; 2] = [Room::EntranceHall, Room::GreatHall];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::EntranceHall => "Entrance Hall",
+            Self::GreatHall => "Great Hall",
+        }
+    }
+
+    pub fn scene(&self) -> &'static str {
+        match self {
+            Self::EntranceHall => "res://scenes/levels/1-entrance-hall.tscn",
+            Self::GreatHall => "res://scenes/levels/2-great-hall.tscn",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum DialogueEvent {
     LevelReady,
     EnemyMoved(EnemyKind),
     AllyKilled(AllyId),
     EnemyKilled(EnemyKind),
+    AllyRecruited(AllyId),
+}
+
+// Cells of a room, from its top left corner
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Region {
+    pub fn contains(&self, position: Position) -> bool {
+        (self.x..self.x + self.width).contains(&position.x)
+            && (self.y..self.y + self.height).contains(&position.y)
+    }
 }
 
-pub fn trigger_lists() -> &'static HashMap<Room, Vec<(Vec<DialogueEvent>, String)>> {
-    static TRIGGER_LISTS: OnceLock<HashMap<Room, Vec<(Vec<DialogueEvent>, String)>>> =
-        OnceLock::new();
-    TRIGGER_LISTS.get_or_init(|| init_trigger_lists())
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Condition {
+    // Happened since the trigger last fired
+    Event(DialogueEvent),
+    AllOf(Vec<Condition>),
+    AnyOf(Vec<Condition>),
+    // The level has reached this round
+    Round(u16),
+    // An ally is below this share of their max health, in percent
+    AllyHealthBelow(AllyId, u16),
+    // An ally is standing in the region
+    Entered(AllyId, Region),
+    // Enemies of the kind have died in the region and none are left standing in it
+    Cleared(EnemyKind, Region),
 }
 
-fn init_trigger_lists() -> HashMap<Room, Vec<(Vec<DialogueEvent>, String)>> {
+impl Condition {
+    fn met(&self, events: &[DialogueEvent], situation: &Situation) -> bool {
+        match self {
+            Self::Event(event) => events.contains(event),
+            Self::AllOf(conditions) => conditions
+                .iter()
+                .all(|condition| condition.met(events, situation)),
+            Self::AnyOf(conditions) => conditions
+                .iter()
+                .any(|condition| condition.met(events, situation)),
+            Self::Round(round) => situation.round >= *round,
+            Self::AllyHealthBelow(ally_id, percent) => {
+                situation.health.iter().any(|(id, health, max_health)| {
+                    id == ally_id
+                        && (*health as u32) * 100 < (*max_health as u32) * (*percent as u32)
+                })
+            }
+            Self::Entered(ally_id, region) => situation
+                .positions
+                .iter()
+                .any(|(id, position)| id == ally_id && region.contains(*position)),
+            Self::Cleared(enemy_kind, region) => {
+                situation
+                    .deaths
+                    .iter()
+                    .any(|death| death.enemy_kind == *enemy_kind && region.contains(death.position))
+                    && !situation
+                        .enemies
+                        .iter()
+                        .any(|(kind, position)| kind == enemy_kind && region.contains(*position))
+            }
+        }
+    }
+}
+
+// What conditions are checked against besides events, taken from the level each frame
+#[derive(Debug, Clone, Default)]
+pub struct Situation {
+    pub round: u16,
+    // Health and max health of each ally
+    pub health: Vec<(AllyId, u16, u16)>,
+    pub positions: Vec<(AllyId, Position)>,
+    pub enemies: Vec<(EnemyKind, Position)>,
+    pub deaths: Vec<Death>,
+}
+
+impl Situation {
+    pub fn of(level: &Level) -> Self {
+        let mut situation = Self {
+            round: level.round,
+            deaths: level.deaths.clone(),
+            ..Default::default()
+        };
+        for enemy in level
+            .enemies
+            .keys()
+            .filter_map(|enemy_id| level.try_get_enemy(*enemy_id))
+        {
+            let enemy = enemy.bind();
+            situation.enemies.push((enemy.kind, enemy.position));
+        }
+        for ally in level
+            .allies
+            .keys()
+            .filter_map(|ally_id| level.try_get_ally(*ally_id))
+        {
+            let ally = ally.bind();
+            situation
+                .health
+                .push((ally.id, ally.vitals.health, ally.max_health));
+            situation.positions.push((ally.id, ally.position));
+        }
+        situation
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trigger {
+    pub condition: Condition,
+    pub timeline: &'static str,
+    // Fires again once its condition has stopped holding, where a one-shot only ever fires once
+    pub repeatable: bool,
+}
+
+impl Trigger {
+    pub fn once(condition: Condition, timeline: &'static str) -> Self {
+        Self {
+            condition,
+            timeline,
+            repeatable: false,
+        }
+    }
+
+    pub fn on(event: DialogueEvent, timeline: &'static str) -> Self {
+        Self::once(Condition::Event(event), timeline)
+    }
+}
+
+pub fn trigger_lists() -> &'static HashMap<Room, Vec<Trigger>> {
+    static TRIGGER_LISTS: OnceLock<HashMap<Room, Vec<Trigger>>> = OnceLock::new();
+    TRIGGER_LISTS.get_or_init(|| {
+        let mut triggers = init_trigger_lists();
+        mods::overlay_triggers(&mut triggers);
+        triggers
+    })
+}
+
+fn init_trigger_lists() -> HashMap<Room, Vec<Trigger>> {
     [
         (
             Room::EntranceHall,
             vec![
-                (
-                    vec![DialogueEvent::LevelReady],
-                    "entrance-hall-movement-manual".into(),
-                ),
-                (
-                    vec![DialogueEvent::EnemyMoved(EnemyKind::Bat)],
-                    "entrance-hall-attack-manual".into(),
+                Trigger::on(DialogueEvent::LevelReady, "entrance-hall-movement-manual"),
+                Trigger::on(
+                    DialogueEvent::EnemyMoved(EnemyKind::Bat),
+                    "entrance-hall-attack-manual",
                 ),
-                (
-                    vec![DialogueEvent::EnemyKilled(EnemyKind::Bat)],
-                    "entrance-hall-defeat-bat".into(),
+                Trigger::on(
+                    DialogueEvent::EnemyKilled(EnemyKind::Bat),
+                    "entrance-hall-defeat-bat",
                 ),
-                (
-                    vec![DialogueEvent::EnemyMoved(EnemyKind::Vampire)],
-                    "entrance-hall-vampire-appears".into(),
+                Trigger::on(
+                    DialogueEvent::EnemyMoved(EnemyKind::Vampire),
+                    "entrance-hall-vampire-appears",
                 ),
-                (
-                    vec![DialogueEvent::EnemyMoved(EnemyKind::BigBatty)],
-                    "entrance-hall-big-batty".into(),
+                Trigger::on(
+                    DialogueEvent::EnemyMoved(EnemyKind::BigBatty),
+                    "entrance-hall-big-batty",
                 ),
-                (
-                    vec![DialogueEvent::EnemyKilled(EnemyKind::BigBatty)],
-                    "entrance-hall-big-batty-death".into(),
+                Trigger::on(
+                    DialogueEvent::EnemyKilled(EnemyKind::BigBatty),
+                    "entrance-hall-big-batty-death",
                 ),
             ],
         ),
         (
             Room::GreatHall,
-            vec![(
-                vec![DialogueEvent::LevelReady],
-                "great-hall-alukrod-intro".into(),
-            )],
+            vec![
+                Trigger::on(DialogueEvent::LevelReady, "great-hall-alukrod-intro"),
+                Trigger::on(
+                    DialogueEvent::AllyRecruited(AllyId::Priest),
+                    "great-hall-priest-rescued",
+                ),
+                // Alukrod only remarks on it once he has had a round to notice
+                Trigger::once(
+                    Condition::AllOf(vec![
+                        Condition::Round(2),
+                        Condition::AllyHealthBelow(AllyId::AshMagnum, 50),
+                    ]),
+                    "great-hall-ash-wounded",
+                ),
+            ],
         ),
     ]
     .into()
 }
 
+// A trigger waiting to fire, with the events it has seen since it last did
+#[derive(Debug, Clone)]
+struct Pending {
+    trigger: Trigger,
+    events: Vec<DialogueEvent>,
+    // A repeatable trigger is disarmed after firing until its condition stops holding
+    armed: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Triggers {
+    pending: Vec<Pending>,
+}
+
+impl Triggers {
+    pub fn new(triggers: Vec<Trigger>) -> Self {
+        Self {
+            pending: triggers
+                .into_iter()
+                .map(|trigger| Pending {
+                    trigger,
+                    events: Vec::new(),
+                    armed: true,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn observe(&mut self, event: DialogueEvent) {
+        for pending in &mut self.pending {
+            pending.events.push(event);
+        }
+    }
+
+    // The timeline of the first trigger in the list whose condition holds, one at a time
+    pub fn fire(&mut self, situation: &Situation) -> Option<&'static str> {
+        for index in 0..self.pending.len() {
+            let pending = &mut self.pending[index];
+            if !pending.trigger.condition.met(&pending.events, situation) {
+                pending.armed = true;
+                continue;
+            }
+            if !pending.armed {
+                continue;
+            }
+
+            let timeline = pending.trigger.timeline;
+            if pending.trigger.repeatable {
+                pending.events.clear();
+                pending.armed = false;
+            } else {
+                self.pending.remove(index);
+            }
+            return Some(timeline);
+        }
+        None
+    }
+}
+
+// A line shown this level, from a timeline or a bark, kept for the history in the pause menu
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogLine {
+    // None for narration
+    pub speaker: Option<String>,
+    pub text: String,
+}
+
+impl LogLine {
+    // Dialogic text is BBCode, which the history shows as it is
+    pub fn bbcode(&self) -> String {
+        match &self.speaker {
+            Some(speaker) => format!("[b]{}:[/b] {}", speaker, self.text),
+            None => format!("[i]{}[/i]", self.text),
+        }
+    }
+}
+
 #[derive(GodotClass)]
 #[class(init, base=Node2D)]
 pub struct Dialogue {
     #[export]
     pub room: Room,
     pub active: bool,
-    pub events: Vec<DialogueEvent>,
-    pub triggers: Vec<(Vec<DialogueEvent>, String)>,
+    pub triggers: Triggers,
+    // Made in a timeline and waiting to be handed to the level as commands
+    pub choices: Vec<Choice>,
+    pub log: Vec<LogLine>,
     pub current_timeline: String,
     base: Base<Node2D>,
 }
@@ -84,7 +349,10 @@ pub struct Dialogue {
 #[godot_api]
 impl INode2D for Dialogue {
     fn ready(&mut self) {
-        let mut dialogic = self.base().get_node_as::<Node>("../../Dialogic");
+        let Some(mut dialogic) = find_level(&self.base()).and_then(|level| nodes::dialogic(&level))
+        else {
+            return;
+        };
         dialogic.connect(
             "timeline_started".into(),
             Callable::from_object_method(&self.base(), "on_started"),
@@ -94,24 +362,55 @@ impl INode2D for Dialogue {
             Callable::from_object_method(&self.base(), "on_ended"),
         );
 
-        self.triggers = trigger_lists().get(&self.room).unwrap().clone();
+        let mut variables = dialogic.get("VAR".into()).to::<Gd<Node>>();
+        variables.connect(
+            "variable_changed".into(),
+            Callable::from_object_method(&self.base(), "on_variable_changed"),
+        );
+
+        let mut text = dialogic.get("Text".into()).to::<Gd<Node>>();
+        text.connect(
+            "about_to_show_text".into(),
+            Callable::from_object_method(&self.base(), "on_text"),
+        );
+        settings::set_dialogic_speed(&dialogic, settings::text_speed());
+
+        self.triggers = Triggers::new(trigger_lists().get(&self.room).unwrap().clone());
     }
 
+    // Triggers that come due during a timeline wait for it to end
     fn process(&mut self, _delta: f64) {
-        if let Some(trigger) = self.next_trigger() {
-            for event in self.events.clone() {
-                if event == trigger {
-                    if self.triggered() {
-                        let timeline = self.next_timeline();
-                        let mut dialogic = self.base().get_node_as::<Node>("../../Dialogic");
-                        dialogic.call_deferred("start".into(), &[Variant::from(timeline)]);
-                        self.current_timeline = timeline.into();
-                    }
+        let Some(mut level) = find_level(&self.base()) else {
+            return;
+        };
 
-                    self.next();
+        // Replays have their choices recorded, and online only the host's count
+        let choices = mem::take(&mut self.choices);
+        {
+            let mut level = level.bind_mut();
+            if !level.playback && level.is_host() {
+                for choice in choices {
+                    if level.choices.changed_by(choice) {
+                        level.commands.push(Command::Choose { choice });
+                    }
                 }
             }
-            self.events.clear();
+        }
+
+        if self.active {
+            return;
+        }
+        let situation = Situation::of(&level.bind());
+
+        if let Some(timeline) = self.triggers.fire(&situation) {
+            if let Some(mut dialogic) = nodes::dialogic(&level) {
+                dialogic.call_deferred("start".into(), &[Variant::from(timeline)]);
+            }
+            if let Some(shots) = cinematic::timeline_pans().get(timeline) {
+                level.bind_mut().play_shots(shots);
+            }
+            self.active = true;
+            self.current_timeline = timeline.into();
         }
     }
 }
@@ -123,6 +422,30 @@ impl Dialogue {
         self.active = true;
     }
 
+    // Each line of dialogue starts with a blip and goes into the log
+    #[func]
+    pub fn on_text(&mut self, info: Dictionary) {
+        audio::play(Sfx::Blip);
+
+        let speaker = info
+            .get("character")
+            .and_then(|character| character.try_to::<Gd<Object>>().ok())
+            .map(|character| character.get("display_name".into()).to_string());
+        if let Some(text) = info.get("text") {
+            self.record(speaker, text.to_string());
+        }
+    }
+
+    #[func]
+    pub fn on_variable_changed(&mut self, info: Dictionary) {
+        let (Some(name), Some(value)) = (info.get("variable"), info.get("new_value")) else {
+            return;
+        };
+        if let Some(choice) = Choice::from_variable(&name.to_string(), &value.to_string()) {
+            self.choices.push(choice);
+        }
+    }
+
     #[func]
     pub fn on_ended(&mut self) {
         self.active = false;
@@ -140,27 +463,137 @@ impl Dialogue {
 }
 
 impl Dialogue {
-    pub fn next_trigger(&self) -> Option<DialogueEvent> {
-        self.triggers.get(0).map(|triggers| triggers.0[0])
+    pub fn record(&mut self, speaker: Option<String>, text: String) {
+        self.log.push(LogLine { speaker, text });
     }
+}
 
-    pub fn next_timeline(&self) -> &str {
-        &self.triggers[0].1
+impl Listener for Dialogue {
+    fn on_event(&mut self, event: GameEvent, _level: &Level) {
+        let event = match event {
+            GameEvent::LevelReady => DialogueEvent::LevelReady,
+            GameEvent::EnemyMoved(_, enemy_kind) => DialogueEvent::EnemyMoved(enemy_kind),
+            GameEvent::AllyDied(ally_id) => DialogueEvent::AllyKilled(ally_id),
+            GameEvent::EnemyDied(_, enemy_kind) => DialogueEvent::EnemyKilled(enemy_kind),
+            GameEvent::AllyRecruited(ally_id) => DialogueEvent::AllyRecruited(ally_id),
+            _ => return,
+        };
+        self.triggers.observe(event);
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::RunStats;
+
+    #[test]
+    fn dialogue_triggers_wait_for_every_condition() {
+        let bat_fight = Condition::AllOf(vec![
+            Condition::Event(DialogueEvent::EnemyMoved(EnemyKind::Bat)),
+            Condition::Event(DialogueEvent::EnemyKilled(EnemyKind::Vampire)),
+        ]);
+        let corner = Region {
+            x: 4,
+            y: 4,
+            width: 2,
+            height: 2,
+        };
+        let mut triggers = Triggers::new(vec![
+            Trigger::once(bat_fight, "both"),
+            Trigger::once(
+                Condition::AnyOf(vec![
+                    Condition::Round(5),
+                    Condition::Entered(AllyId::AshMagnum, corner),
+                ]),
+                "corner",
+            ),
+            Trigger {
+                condition: Condition::AllyHealthBelow(AllyId::AshMagnum, 50),
+                timeline: "wounded",
+                repeatable: true,
+            },
+        ]);
+
+        let mut situation = Situation {
+            round: 1,
+            health: vec![(AllyId::AshMagnum, 8, 8)],
+            positions: vec![(AllyId::AshMagnum, Position { x: 0, y: 0 })],
+            ..Default::default()
+        };
+        triggers.observe(DialogueEvent::EnemyMoved(EnemyKind::Bat));
+        assert_eq!(triggers.fire(&situation), None);
+        triggers.observe(DialogueEvent::EnemyKilled(EnemyKind::Vampire));
+        assert_eq!(triggers.fire(&situation), Some("both"));
+        assert_eq!(triggers.fire(&situation), None);
 
-    pub fn triggered(&self) -> bool {
-        self.triggers[0].0.len() == 1
+        situation.positions[0].1 = Position { x: 5, y: 4 };
+        assert_eq!(triggers.fire(&situation), Some("corner"));
+        situation.round = 6;
+        assert_eq!(triggers.fire(&situation), None);
+
+        // A repeatable trigger fires once each time its condition comes to hold
+        situation.health[0].1 = 3;
+        assert_eq!(triggers.fire(&situation), Some("wounded"));
+        assert_eq!(triggers.fire(&situation), None);
+        situation.health[0].1 = 8;
+        assert_eq!(triggers.fire(&situation), None);
+        situation.health[0].1 = 2;
+        assert_eq!(triggers.fire(&situation), Some("wounded"));
     }
 
-    pub fn next(&mut self) {
-        if self.triggered() {
-            self.triggers.remove(0);
-        } else {
-            self.triggers[0].0.remove(0);
-        }
+    #[test]
+    fn a_wing_is_cleared_once_its_bats_are_dust() {
+        let west_wing = Region {
+            x: 0,
+            y: 0,
+            width: 4,
+            height: 8,
+        };
+        let mut triggers = Triggers::new(vec![Trigger::once(
+            Condition::Cleared(EnemyKind::Bat, west_wing),
+            "cleared",
+        )]);
+        let death = |x, y| Death {
+            enemy_kind: EnemyKind::Bat,
+            position: Position { x, y },
+            round: 2,
+        };
+
+        // Nothing has died there yet, so there is nothing to have cleared
+        let mut situation = Situation::default();
+        assert_eq!(triggers.fire(&situation), None);
+
+        situation.deaths.push(death(1, 1));
+        situation.enemies = vec![
+            (EnemyKind::Bat, Position { x: 2, y: 5 }),
+            (EnemyKind::Vampire, Position { x: 3, y: 3 }),
+        ];
+        assert_eq!(triggers.fire(&situation), None);
+
+        // Bats elsewhere and other kinds in the wing don't hold it up
+        situation.deaths.push(death(2, 5));
+        situation.enemies[0].1 = Position { x: 9, y: 5 };
+        assert_eq!(triggers.fire(&situation), Some("cleared"));
+
+        let mut stats = RunStats::default();
+        stats.record(GameEvent::EnemyDied(0, EnemyKind::Vampire));
+        stats.record(GameEvent::EnemyDied(1, EnemyKind::Bat));
+        stats.record(GameEvent::EnemyDied(2, EnemyKind::Bat));
+        assert_eq!(stats.dust(), "2 Bat, 1 Vampire");
     }
 
-    pub fn push_event(&mut self, event: DialogueEvent) {
-        self.events.push(event);
+    #[test]
+    fn the_dialogue_history_marks_who_said_what() {
+        let said = LogLine {
+            speaker: Some("Ash Magnum".into()),
+            text: "Halt, fiend!".into(),
+        };
+        assert_eq!(said.bbcode(), "[b]Ash Magnum:[/b] Halt, fiend!");
+        let narrated = LogLine {
+            speaker: None,
+            text: "The figure turns.".into(),
+        };
+        assert_eq!(narrated.bbcode(), "[i]The figure turns.[/i]");
     }
 }
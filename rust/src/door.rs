@@ -0,0 +1,42 @@
+use crate::math::Position;
+
+use godot::prelude::*;
+
+// A way out of a room, placed in the level's DoorLayer at its top left tile. Stepping onto any
+// of its tiles ends the room and goes through to the destination
+#[derive(GodotClass)]
+#[class(init, base=Node2D)]
+pub struct Door {
+    pub position: Position,
+    // Tiles across, from the left
+    #[export]
+    #[init(default = 1)]
+    pub width: u16,
+    // Scene of the room it leads to, left empty for the way out of the castle
+    #[export]
+    pub destination: GString,
+    // Node in the destination whose children mark where the party comes in, one per ally.
+    // Left empty, the party starts wherever that room puts it
+    #[export]
+    pub spawn: GString,
+    // Can be walked onto but not through
+    #[export]
+    pub locked: bool,
+    base: Base<Node2D>,
+}
+
+impl Door {
+    pub fn tiles(&self) -> Vec<Position> {
+        (0..self.width as usize)
+            .map(|i| self.position.offset(i, 0))
+            .collect()
+    }
+
+    pub fn destination(&self) -> Option<String> {
+        Some(self.destination.to_string()).filter(|destination| !destination.is_empty())
+    }
+
+    pub fn spawn(&self) -> Option<String> {
+        Some(self.spawn.to_string()).filter(|spawn| !spawn.is_empty())
+    }
+}
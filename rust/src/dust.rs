@@ -0,0 +1,41 @@
+use crate::level::{EnemyKind, TILE_SIZE};
+use crate::math::Position;
+
+use godot::prelude::*;
+
+// An enemy that died in the level, what it was and where it fell
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Death {
+    pub enemy_kind: EnemyKind,
+    pub position: Position,
+    pub round: u16,
+}
+
+// What an enemy leaves where it fell. Units walk over it, and it stays for the rest of the level
+// unless a necromancer raises it again
+#[derive(GodotClass)]
+#[class(init, base=Node2D)]
+pub struct DustPile {
+    pub enemy_kind: EnemyKind,
+    #[init(default = 1)]
+    pub width: u16,
+    #[init(default = 1)]
+    pub height: u16,
+    base: Base<Node2D>,
+}
+
+#[godot_api]
+impl INode2D for DustPile {
+    fn draw(&mut self) {
+        let size = Vector2::new(self.width as f32, self.height as f32) * TILE_SIZE;
+        let centre = size / 2.0 + Vector2::new(0.0, size.y / 4.0);
+        let radius = size.x.min(size.y) / 4.0;
+        self.base_mut()
+            .draw_circle(centre, radius, Color::from_rgba(0.5, 0.47, 0.43, 0.8));
+        self.base_mut().draw_circle(
+            centre - Vector2::new(0.0, radius / 3.0),
+            radius / 2.0,
+            Color::from_rgba(0.65, 0.62, 0.58, 0.8),
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use crate::ability::DamageKind;
+use crate::core::{Effect, EffectStats, UnitId};
+
+use std::collections::BTreeMap;
+use std::mem;
+
+// How reapplying an effect a unit already has combines with the one in place
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stacking {
+    // Magnitudes add up on the running duration, so fire on a burning unit burns hotter
+    Intensify,
+    // The stronger magnitude and the longer duration are kept
+    Refresh,
+    // The effect in place is left as it is
+    Ignore,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EffectDef {
+    pub description: &'static str,
+    pub stacking: Stacking,
+    // Harmful effects are stripped by a cleanse, helpful ones by a dispel
+    pub harmful: bool,
+    pub dispellable: bool,
+    // Effects tick from lowest to highest at the start of a phase
+    pub tick_order: u8,
+}
+
+pub fn definition(effect: Effect) -> EffectDef {
+    match effect {
+        Effect::Burn => EffectDef {
+            description: "Takes damage at the start of each phase, more with every new burn",
+            stacking: Stacking::Intensify,
+            harmful: true,
+            dispellable: true,
+            tick_order: 0,
+        },
+        Effect::Blessing => EffectDef {
+            description: "Holy damage hits harder",
+            stacking: Stacking::Refresh,
+            harmful: true,
+            dispellable: true,
+            tick_order: 1,
+        },
+        Effect::Mist => EffectDef {
+            description: "Every hit passes straight through",
+            stacking: Stacking::Refresh,
+            harmful: false,
+            dispellable: false,
+            tick_order: 2,
+        },
+        Effect::Guarding => EffectDef {
+            description: "Takes the next blow meant for an adjacent ally",
+            stacking: Stacking::Refresh,
+            harmful: false,
+            dispellable: true,
+            tick_order: 3,
+        },
+        Effect::Taunting => EffectDef {
+            description: "Draws the attacks of every enemy nearby",
+            stacking: Stacking::Refresh,
+            harmful: false,
+            dispellable: true,
+            tick_order: 4,
+        },
+        Effect::Blinded => EffectDef {
+            description: "Sees nothing past the tiles right beside it",
+            stacking: Stacking::Refresh,
+            harmful: true,
+            dispellable: true,
+            tick_order: 5,
+        },
+        Effect::Darkness => EffectDef {
+            description: "Sees less far, by the effect's magnitude",
+            stacking: Stacking::Refresh,
+            harmful: true,
+            dispellable: true,
+            tick_order: 6,
+        },
+    }
+}
+
+// How far a unit sees with what is on it, never less than the tiles right beside it
+pub fn view_distance(base: u16, effects: &Effects) -> u16 {
+    if effects.contains(Effect::Blinded) {
+        return 1;
+    }
+    match effects.get(Effect::Darkness) {
+        Some(active) => base.saturating_sub(active.stats.magnitude).max(1),
+        None => base,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ActiveEffect {
+    pub stats: EffectStats,
+    // The unit that applied it, credited with whatever the effect goes on to do
+    pub source: Option<UnitId>,
+}
+
+// Kept on the unit until the level turns them into events
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EffectChange {
+    Applied(Effect),
+    Expired(Effect),
+    Removed(Effect),
+}
+
+// Work an effect does when it ticks, handed back for the unit to carry out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tick {
+    Damage {
+        damage: u16,
+        damage_kind: DamageKind,
+        source: Option<UnitId>,
+    },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Effects {
+    active: BTreeMap<Effect, ActiveEffect>,
+    changes: Vec<EffectChange>,
+}
+
+impl Effects {
+    pub fn contains(&self, effect: Effect) -> bool {
+        self.active.contains_key(&effect)
+    }
+
+    pub fn get(&self, effect: Effect) -> Option<&ActiveEffect> {
+        self.active.get(&effect)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Effect, &ActiveEffect)> {
+        self.active.iter().map(|(effect, active)| (*effect, active))
+    }
+
+    pub fn apply(&mut self, effect: Effect, stats: EffectStats, source: Option<UnitId>) {
+        match self.active.get_mut(&effect) {
+            Some(active) => match definition(effect).stacking {
+                Stacking::Intensify => {
+                    active.stats.magnitude += stats.magnitude;
+                    active.source = source.or(active.source);
+                }
+                Stacking::Refresh => {
+                    active.stats.magnitude = active.stats.magnitude.max(stats.magnitude);
+                    active.stats.duration = active.stats.duration.max(stats.duration);
+                    active.source = source.or(active.source);
+                }
+                Stacking::Ignore => return,
+            },
+            None => {
+                self.active.insert(effect, ActiveEffect { stats, source });
+            }
+        }
+        self.changes.push(EffectChange::Applied(effect));
+    }
+
+    pub fn remove(&mut self, effect: Effect) -> bool {
+        let removed = self.active.remove(&effect).is_some();
+        if removed {
+            self.changes.push(EffectChange::Removed(effect));
+        }
+        removed
+    }
+
+    // Strips every harmful effect that can be dispelled
+    pub fn cleanse(&mut self) -> Vec<Effect> {
+        self.strip(true)
+    }
+
+    // Strips every helpful effect that can be dispelled
+    pub fn dispel(&mut self) -> Vec<Effect> {
+        self.strip(false)
+    }
+
+    fn strip(&mut self, harmful: bool) -> Vec<Effect> {
+        let stripped: Vec<Effect> = self
+            .active
+            .keys()
+            .copied()
+            .filter(|effect| {
+                let def = definition(*effect);
+                def.dispellable && def.harmful == harmful
+            })
+            .collect();
+        for effect in &stripped {
+            self.remove(*effect);
+        }
+        stripped
+    }
+
+    // Each effect does its work before its duration runs down
+    pub fn tick(&mut self) -> Vec<Tick> {
+        let mut order: Vec<Effect> = self.active.keys().copied().collect();
+        order.sort_by_key(|effect| definition(*effect).tick_order);
+
+        let mut ticks = Vec::new();
+        for effect in order {
+            let active = self.active.get_mut(&effect).unwrap();
+            if effect == Effect::Burn {
+                ticks.push(Tick::Damage {
+                    damage: active.stats.magnitude,
+                    damage_kind: DamageKind::Normal,
+                    source: active.source,
+                });
+            }
+
+            active.stats.duration = active.stats.duration.saturating_sub(1);
+            if active.stats.duration == 0 {
+                self.active.remove(&effect);
+                self.changes.push(EffectChange::Expired(effect));
+            }
+        }
+        ticks
+    }
+
+    pub fn take_changes(&mut self) -> Vec<EffectChange> {
+        mem::take(&mut self.changes)
+    }
+}
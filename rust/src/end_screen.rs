@@ -0,0 +1,215 @@
+use crate::achievements::{Achievement, Achievements, Stat};
+use crate::bestiary::Bestiary;
+use crate::dialogue::Room;
+use crate::level::{EnemyKind, Level};
+use crate::main_menu::MENU_SCENE;
+use crate::modifiers::Modifiers;
+use crate::progress::Progress;
+use crate::ui;
+
+use godot::engine::{CenterContainer, Control, ICenterContainer, Label};
+use godot::prelude::*;
+
+pub const END_SCENE: &str = "res://scenes/end.tscn";
+
+// Seconds the credits take to roll past
+const CREDITS_TIME: f64 = 20.0;
+
+const CREDITS: &str = "Ash Magnum, the Vampire Slayer
+
+Design, code and art
+giraffekey
+
+Dialogue
+Dialogic
+
+Engine
+Godot, with godot-rust
+
+Thanks for playing";
+
+// Best score in every room and what they add up to
+pub fn campaign_lines(progress: &Progress) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut total = 0;
+    for room in Room::ALL {
+        match progress.best.get(&room) {
+            Some(score) => {
+                total += score.total();
+                lines.push(format!(
+                    "{}: {} ({})",
+                    room.name(),
+                    score.total(),
+                    score.rank.letter()
+                ));
+            }
+            None => lines.push(format!("{}: -", room.name())),
+        }
+    }
+    lines.push(format!("Campaign total: {}", total));
+    lines
+}
+
+// How much of the bestiary and the achievements the player has filled in so far
+pub fn codex_lines(bestiary: &Bestiary, achievements: &Achievements) -> Vec<String> {
+    let sighted = EnemyKind::ALL
+        .iter()
+        .filter(|kind| bestiary.sighted.contains(kind))
+        .count();
+    let slain = EnemyKind::ALL
+        .iter()
+        .filter(|kind| bestiary.slain.contains(kind))
+        .count();
+    let unlocked = Achievement::ALL
+        .iter()
+        .filter(|achievement| achievements.unlocked.contains(achievement))
+        .count();
+    vec![
+        format!(
+            "Bestiary: {} of {} sighted, {} fully known",
+            sighted,
+            EnemyKind::ALL.len(),
+            slain
+        ),
+        format!("Achievements: {} of {}", unlocked, Achievement::ALL.len()),
+    ]
+}
+
+// Shown once the last room is left, with the whole campaign's results, then the credits
+#[derive(GodotClass)]
+#[class(init, base=CenterContainer)]
+pub struct EndScreen {
+    progress: Progress,
+    base: Base<CenterContainer>,
+}
+
+#[godot_api]
+impl ICenterContainer for EndScreen {
+    fn ready(&mut self) {
+        self.progress = Progress::load();
+        let achievements = Achievements::load();
+
+        let mut lines = campaign_lines(&self.progress);
+        lines.push(String::new());
+        lines.extend(
+            [Stat::EnemiesSlain, Stat::VampiresStaked, Stat::DamageTaken]
+                .iter()
+                .map(|stat| format!("{}: {}", stat.name(), achievements.get(*stat))),
+        );
+        let mut stats = self.base().get_node_as::<Label>("Results/Stats");
+        stats.set_text(lines.join("\n").into());
+
+        let lines = codex_lines(&Bestiary::load(), &achievements);
+        let mut codex = self.base().get_node_as::<Label>("Results/Codex");
+        codex.set_text(lines.join("\n").into());
+
+        let unlocked = self.progress.new_game_plus_unlocked();
+        let mut prompt = self
+            .base()
+            .get_node_as::<Label>("Results/NewGamePlusPrompt");
+        prompt.set_text(
+            format!(
+                "New Game+ cycle {} awaits, with what the party carried out",
+                self.progress.cycles_finished
+            )
+            .into(),
+        );
+        prompt.set_visible(unlocked);
+        self.base()
+            .get_node_as::<Control>("Results/NewGamePlusButton")
+            .set_visible(unlocked);
+
+        let mut credits = self.base().get_node_as::<Label>("Credits/Text");
+        credits.set_text(CREDITS.into());
+
+        ui::focus_first(self.base().get_node_as::<Node>("Results"));
+    }
+}
+
+#[godot_api]
+impl EndScreen {
+    #[func]
+    fn _on_new_game_plus_button_pressed(&mut self) {
+        let scene = load::<PackedScene>(Room::EntranceHall.scene());
+        let mut level: Gd<Level> = scene.instantiate().unwrap().cast();
+
+        {
+            let mut level = level.bind_mut();
+            level.modifiers = Modifiers::new_game_plus(self.progress.cycles_finished);
+            level.inventory = self.progress.carried.clone();
+        }
+
+        let mut tree = self.base().get_tree().unwrap();
+        tree.get_root().unwrap().add_child(level.clone().upcast());
+        tree.set_current_scene(level.upcast());
+        self.base_mut().queue_free();
+    }
+
+    // The results make way for the credits, which roll up and then go back to the menu
+    #[func]
+    fn _on_credits_button_pressed(&mut self) {
+        self.base()
+            .get_node_as::<Control>("Results")
+            .set_visible(false);
+        let mut credits = self.base().get_node_as::<Control>("Credits");
+        credits.set_visible(true);
+
+        let mut text = credits.get_node_as::<Label>("Text");
+        let height = credits.get_size().y;
+        text.set_position(Vector2::new(0.0, height));
+        let Some(mut tween) = self.base_mut().create_tween() else {
+            return;
+        };
+        tween.tween_property(
+            text.clone().upcast(),
+            "position".into(),
+            Variant::from(Vector2::new(0.0, -text.get_size().y)),
+            CREDITS_TIME,
+        );
+        tween.tween_callback(Callable::from_object_method(
+            &self.base(),
+            "_on_menu_button_pressed",
+        ));
+    }
+
+    #[func]
+    fn _on_menu_button_pressed(&mut self) {
+        self.base()
+            .get_tree()
+            .unwrap()
+            .change_scene_to_file(MENU_SCENE.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scoring::Score;
+    use crate::stats::RunStats;
+
+    #[test]
+    fn the_end_screen_adds_up_the_whole_campaign() {
+        let mut progress = Progress::default();
+        let stats = RunStats::default();
+        let score = Score::new(Room::EntranceHall, 1, &stats, 0);
+        progress.best.insert(Room::EntranceHall, score);
+
+        let lines = campaign_lines(&progress);
+        assert_eq!(lines.len(), Room::ALL.len() + 1);
+        assert_eq!(lines[1], "Great Hall: -");
+        assert_eq!(
+            lines.last().unwrap(),
+            &format!("Campaign total: {}", score.total())
+        );
+
+        let bestiary = Bestiary {
+            sighted: vec![EnemyKind::Bat, EnemyKind::Vampire],
+            slain: vec![EnemyKind::Bat],
+        };
+        let mut achievements = Achievements::default();
+        achievements.unlocked.push(Achievement::FirstBlood);
+        let lines = codex_lines(&bestiary, &achievements);
+        assert_eq!(lines[0], "Bestiary: 2 of 6 sighted, 1 fully known");
+        assert_eq!(lines[1], "Achievements: 1 of 6");
+    }
+}
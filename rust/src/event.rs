@@ -0,0 +1,252 @@
+use crate::ability::{Ability, DamageKind};
+use crate::achievements::Stat;
+use crate::core::{Effect, UnitId};
+use crate::level::{AllyId, EnemyId, EnemyKind, ItemKind, Level, NpcId};
+use crate::run_log::Entry;
+
+use godot::obj::{bounds, Bounds};
+use godot::prelude::*;
+use std::mem;
+
+// Something that happened in a level, seen by every listener once the level next processes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GameEvent {
+    LevelReady,
+    AllyTurnStarted,
+    EnemyTurnStarted,
+    AllyMoved(AllyId),
+    EnemyMoved(EnemyId, EnemyKind),
+    // The first time an enemy of a kind comes into view in the level
+    EnemySighted(EnemyKind),
+    AbilityUsed {
+        unit: UnitId,
+        ability: Ability,
+    },
+    // An ally finished the animation of the ability they used
+    AllyActed(AllyId),
+    UnitDamaged {
+        unit: UnitId,
+        damage: u16,
+        damage_kind: DamageKind,
+    },
+    EffectApplied {
+        unit: UnitId,
+        effect: Effect,
+    },
+    // Ran out on its own, as opposed to being removed
+    EffectExpired {
+        unit: UnitId,
+        effect: Effect,
+    },
+    EffectRemoved {
+        unit: UnitId,
+        effect: Effect,
+    },
+    // Credited to the source the moment the hit lands, before any death animation
+    UnitKilled {
+        unit: UnitId,
+        source: Option<UnitId>,
+        ability: Option<Ability>,
+        damage: u16,
+        damage_kind: DamageKind,
+    },
+    AllyDied(AllyId),
+    EnemyDied(EnemyId, EnemyKind),
+    ItemPickedUp {
+        ally_id: AllyId,
+        item_kind: ItemKind,
+    },
+    ItemDropped {
+        ally_id: AllyId,
+        item_kind: ItemKind,
+    },
+    // One use passed between adjacent allies
+    ItemTraded {
+        from: AllyId,
+        to: AllyId,
+        ability: Ability,
+    },
+    AllyRecruited(AllyId),
+    // Got out through a door alive
+    NpcEscaped(NpcId),
+}
+
+pub trait Listener {
+    fn on_event(&mut self, event: GameEvent, level: &Level);
+}
+
+#[derive(Default)]
+pub struct EventBus {
+    events: Vec<GameEvent>,
+    listeners: Vec<Box<dyn FnMut(GameEvent, &Level)>>,
+}
+
+impl EventBus {
+    pub fn publish(&mut self, event: GameEvent) {
+        self.events.push(event);
+    }
+
+    // Published but not yet dispatched
+    pub fn pending(&self) -> &[GameEvent] {
+        &self.events
+    }
+
+    pub fn subscribe<T>(&mut self, mut listener: Gd<T>)
+    where
+        T: GodotClass + Bounds<Declarer = bounds::DeclUser> + Listener,
+    {
+        self.listeners.push(Box::new(move |event, level| {
+            let mut listener = listener.bind_mut();
+            listener.on_event(event, level);
+        }));
+    }
+}
+
+impl Level {
+    // Listeners are bound one at a time while the level is, so they only read it. The level keeps
+    // its own tallies first
+    pub fn dispatch_events(&mut self) {
+        let mut listeners = mem::take(&mut self.events.listeners);
+        for event in mem::take(&mut self.events.events) {
+            self.stats.record(event);
+            if !self.playback {
+                let mut unlocked = Vec::new();
+                for (stat, amount) in Stat::of(event, self) {
+                    unlocked.extend(self.achievements.tally(stat, amount));
+                }
+                if !unlocked.is_empty() {
+                    self.achievements.announce(&unlocked);
+                    self.achievements.save();
+                }
+            }
+            self.bark(event);
+            match event {
+                GameEvent::EnemySighted(kind) => self.bestiary.sight(kind),
+                GameEvent::EnemyDied(_, kind) => self.bestiary.slay(kind),
+                GameEvent::UnitDamaged {
+                    unit,
+                    damage,
+                    damage_kind,
+                } => self.log(Entry::Damage {
+                    unit: format!("{:?}", unit),
+                    damage,
+                    damage_kind,
+                }),
+                GameEvent::UnitKilled { unit, source, .. } => self.log(Entry::Killed {
+                    unit: format!("{:?}", unit),
+                    source: source.map(|source| format!("{:?}", source)),
+                }),
+                _ => (),
+            }
+
+            self.emit_signals(event);
+
+            for listener in &mut listeners {
+                listener(event, self);
+            }
+        }
+        self.events.listeners = listeners;
+    }
+
+    // The same events again as Godot signals, for scripts that would rather not be listeners.
+    // The level emits every one of them, allies and enemies only their own
+    fn emit_signals(&mut self, event: GameEvent) {
+        let level = self.base().clone().upcast::<Object>();
+        match event {
+            GameEvent::AllyTurnStarted => emit(
+                level,
+                "turn_changed",
+                &[self.round.to_variant(), "ally".to_variant()],
+            ),
+            GameEvent::EnemyTurnStarted => emit(
+                level,
+                "turn_changed",
+                &[self.round.to_variant(), "enemy".to_variant()],
+            ),
+            GameEvent::UnitDamaged {
+                unit,
+                damage,
+                damage_kind,
+            } => {
+                let Some(node) = self.unit_node(unit) else {
+                    return;
+                };
+                let damage_kind = format!("{:?}", damage_kind);
+                emit(
+                    level,
+                    "unit_damaged",
+                    &[
+                        node.to_variant(),
+                        damage.to_variant(),
+                        damage_kind.to_variant(),
+                    ],
+                );
+                if !matches!(unit, UnitId::Npc(_)) {
+                    emit(
+                        node.upcast(),
+                        "unit_damaged",
+                        &[damage.to_variant(), damage_kind.to_variant()],
+                    );
+                }
+            }
+            GameEvent::UnitKilled { unit, .. } => {
+                let Some(node) = self.unit_node(unit) else {
+                    return;
+                };
+                emit(level, "unit_died", &[node.to_variant()]);
+                if !matches!(unit, UnitId::Npc(_)) {
+                    emit(node.upcast(), "unit_died", &[]);
+                }
+            }
+            GameEvent::ItemPickedUp { ally_id, item_kind } => {
+                let Some(node) = self.unit_node(UnitId::Ally(ally_id)) else {
+                    return;
+                };
+                emit(
+                    level,
+                    "item_picked_up",
+                    &[node.to_variant(), item_kind.to_variant()],
+                );
+                emit(node.upcast(), "item_picked_up", &[item_kind.to_variant()]);
+            }
+            GameEvent::AbilityUsed { unit, ability } => {
+                let Some(node) = self.unit_node(unit) else {
+                    return;
+                };
+                let ability = format!("{:?}", ability);
+                emit(
+                    level,
+                    "ability_used",
+                    &[node.to_variant(), ability.to_variant()],
+                );
+                if !matches!(unit, UnitId::Npc(_)) {
+                    emit(node.upcast(), "ability_used", &[ability.to_variant()]);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // Escorted units that were killed are gone from the level by the time events go out
+    pub fn unit_node(&self, unit: UnitId) -> Option<Gd<Node2D>> {
+        match unit {
+            UnitId::Ally(ally_id) if self.allies.contains_key(&ally_id) => {
+                Some(self.get_ally(ally_id).upcast())
+            }
+            UnitId::Enemy(enemy_id) if self.enemies.contains_key(&enemy_id) => {
+                Some(self.get_enemy(enemy_id).upcast())
+            }
+            UnitId::Npc(npc_id) if self.npcs.contains_key(&npc_id) => {
+                Some(self.get_npc(npc_id).upcast())
+            }
+            _ => None,
+        }
+    }
+}
+
+// Deferred, so anything connected is free to call back into the level once it is unbound
+fn emit(mut object: Gd<Object>, signal: &str, args: &[Variant]) {
+    let mut call = vec![signal.to_variant()];
+    call.extend_from_slice(args);
+    object.call_deferred("emit_signal".into(), &call);
+}
@@ -0,0 +1,86 @@
+use crate::ability::DamageKind;
+use crate::grid::Grid;
+use crate::level::TILE_SIZE;
+use crate::math::Position;
+
+use godot::prelude::*;
+
+// Rounds the ground keeps burning where a fire flask broke
+pub const FIRE_ROUNDS: u16 = 2;
+const BLAST_TIME: f64 = 0.35;
+
+// The tiles beside the one a flask breaks on and that one last, as far as the room goes
+pub fn burst<T>(position: Position, grid: &Grid<T>) -> Vec<Position> {
+    let mut area = grid.adjacent(position);
+    area.push(position);
+    area
+}
+
+// A flash over a thrown flask as it bursts, growing and fading before freeing itself
+#[derive(GodotClass)]
+#[class(init, base=Node2D)]
+pub struct Explosion {
+    pub damage_kind: Option<DamageKind>,
+    age: f64,
+    base: Base<Node2D>,
+}
+
+#[godot_api]
+impl INode2D for Explosion {
+    fn process(&mut self, delta: f64) {
+        self.age += delta;
+        if self.age >= BLAST_TIME {
+            self.base_mut().queue_free();
+        } else {
+            self.base_mut().queue_redraw();
+        }
+    }
+
+    fn draw(&mut self) {
+        let t = (self.age / BLAST_TIME) as f32;
+        let color = match self.damage_kind {
+            Some(DamageKind::Fire) => Color::from_rgba(1.0, 0.5, 0.15, 0.8 * (1.0 - t)),
+            _ => Color::from_rgba(1.0, 0.95, 0.6, 0.8 * (1.0 - t)),
+        };
+        // Out to the middle of the tiles around the one it burst on
+        let radius = TILE_SIZE * (0.5 + t);
+        self.base_mut().draw_circle(Vector2::ZERO, radius, color);
+    }
+}
+
+// Burning oil over one tile until it goes out
+#[derive(GodotClass)]
+#[class(init, base=Node2D)]
+pub struct Flames {
+    base: Base<Node2D>,
+}
+
+#[godot_api]
+impl INode2D for Flames {
+    fn draw(&mut self) {
+        let size = Vector2::new(TILE_SIZE, TILE_SIZE);
+        self.base_mut().draw_rect(
+            Rect2::new(Vector2::ZERO, size),
+            Color::from_rgba(1.0, 0.45, 0.1, 0.45),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flasks_burst_over_their_tile_and_the_ones_beside_it() {
+        let grid = Grid::new(5, 5, ());
+        let middle = Position { x: 2, y: 2 };
+        let area = burst(middle, &grid);
+        assert_eq!(area.len(), 5);
+        assert_eq!(area.last(), Some(&middle));
+        assert!(area.iter().all(|tile| tile.distance(middle) <= 1));
+
+        // Nothing past the walls of the room
+        assert_eq!(burst(Position { x: 0, y: 0 }, &grid).len(), 3);
+        assert_eq!(burst(Position { x: 4, y: 2 }, &grid).len(), 4);
+    }
+}
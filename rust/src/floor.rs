@@ -0,0 +1,47 @@
+use crate::math::Position;
+
+use godot::prelude::*;
+
+// One storey of a level with several, placed in the level's FloorLayer at its top left tile.
+// Floors share the level's grid side by side without overlapping, and nothing on one can see
+// or be seen from another
+#[derive(GodotClass)]
+#[class(init, base=Node2D)]
+pub struct Floor {
+    #[export]
+    pub display_name: GString,
+    // Tiles across and down
+    #[export]
+    #[init(default = 1)]
+    pub width: u16,
+    #[export]
+    #[init(default = 1)]
+    pub height: u16,
+    base: Base<Node2D>,
+}
+
+// A stairway between two floors, placed in the level's StairLayer on its tile. Stepping onto it
+// comes out on the tile of the stairs it leads to, and the way back is the same pair
+#[derive(GodotClass)]
+#[class(init, base=Node2D)]
+pub struct Stairs {
+    // Name of the other end in the same layer
+    #[export]
+    pub leads_to: GString,
+    base: Base<Node2D>,
+}
+
+// Where a floor lies on the level's grid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Area {
+    pub origin: Position,
+    pub dimensions: (usize, usize),
+}
+
+impl Area {
+    pub fn contains(&self, position: Position) -> bool {
+        let (width, height) = self.dimensions;
+        (self.origin.x..self.origin.x + width).contains(&position.x)
+            && (self.origin.y..self.origin.y + height).contains(&position.y)
+    }
+}
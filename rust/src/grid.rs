@@ -0,0 +1,105 @@
+use crate::math::{Direction, Position};
+
+use std::ops::{Index, IndexMut};
+
+// Cells of a level stored column by column on the heap, sized to the level's tile map
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grid<T> {
+    width: usize,
+    height: usize,
+    cells: Vec<T>,
+}
+
+impl<T> Default for Grid<T> {
+    fn default() -> Self {
+        Self {
+            width: 0,
+            height: 0,
+            cells: Vec::new(),
+        }
+    }
+}
+
+impl<T: Clone> Grid<T> {
+    pub fn new(width: usize, height: usize, fill: T) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![fill; width * height],
+        }
+    }
+}
+
+impl<T> Grid<T> {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    pub fn contains(&self, position: Position) -> bool {
+        position.x < self.width && position.y < self.height
+    }
+
+    pub fn get(&self, position: Position) -> Option<&T> {
+        if self.contains(position) {
+            Some(&self.cells[position.x * self.height + position.y])
+        } else {
+            None
+        }
+    }
+
+    pub fn positions(&self) -> impl Iterator<Item = Position> + '_ {
+        (0..self.width).flat_map(move |x| (0..self.height).map(move |y| Position { x, y }))
+    }
+
+    // Neighbours of a position that lie inside the grid
+    pub fn adjacent(&self, position: Position) -> Vec<Position> {
+        position
+            .adjacent()
+            .into_iter()
+            .filter(|adjacent| self.contains(*adjacent))
+            .collect()
+    }
+
+    pub fn in_direction(
+        &self,
+        position: Position,
+        direction: Direction,
+        dist: usize,
+    ) -> Option<Position> {
+        position
+            .in_direction(direction, dist)
+            .filter(|position| self.contains(*position))
+    }
+}
+
+impl<T> Index<Position> for Grid<T> {
+    type Output = T;
+
+    fn index(&self, position: Position) -> &T {
+        assert!(
+            self.contains(position),
+            "{:?} is outside the grid",
+            position
+        );
+        &self.cells[position.x * self.height + position.y]
+    }
+}
+
+impl<T> IndexMut<Position> for Grid<T> {
+    fn index_mut(&mut self, position: Position) -> &mut T {
+        assert!(
+            self.contains(position),
+            "{:?} is outside the grid",
+            position
+        );
+        &mut self.cells[position.x * self.height + position.y]
+    }
+}
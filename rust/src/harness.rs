@@ -0,0 +1,1206 @@
+use crate::ability::{ability_lists, Ability};
+use crate::ai::{self, Plan, Snapshot, Target};
+use crate::combat::{self, Damage};
+use crate::core::{Effect, Occupancy, Tile, UnitId, Vitals};
+use crate::effects;
+use crate::event::EventBus;
+use crate::grid::Grid;
+use crate::level::{AllyId, EnemyAction, EnemyId, EnemyKind};
+use crate::math::{FovCache, Movement, Mover, Pathfinder, Position};
+use crate::traits::{trait_lists, Trait};
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/*
+ * A level built from an ASCII map that plays out turns on plain data, without the scene tree.
+ * The grid is as wide as the longest line and anything outside the map is wall. Legend:
+ * - `.` floor
+ * - `#` wall, blocks sight
+ * - `_` low wall, blocks movement but not sight
+ * - `A` Ash Magnum, `L` Alukrod, `P` the priest
+ * - `b` bat, `v` vampire, `B` big batty (top left corner), `n` necromancer, `w` werewolf, `g` ghost
+ */
+pub struct Sim {
+    pub grid: Grid<Occupancy>,
+    pub walls: HashSet<Position>,
+    pub allies: BTreeMap<AllyId, SimAlly>,
+    pub enemies: BTreeMap<EnemyId, SimEnemy>,
+    pub fov_cache: RefCell<FovCache>,
+    pub pathfinder: RefCell<Pathfinder>,
+    pub diagonal_movement: bool,
+    // Events the real level would dispatch, kept so tests can check them
+    pub events: EventBus,
+}
+
+#[derive(Debug, Clone)]
+pub struct SimAlly {
+    pub position: Position,
+    pub vitals: Vitals,
+}
+
+#[derive(Debug, Clone)]
+pub struct SimEnemy {
+    pub kind: EnemyKind,
+    pub position: Position,
+    pub vitals: Vitals,
+    pub speed: u16,
+    pub view_distance: u16,
+    pub size: u16,
+    pub abilities: Vec<Ability>,
+    pub cooldowns: HashMap<Ability, u16>,
+    pub last_known_positions: HashMap<AllyId, Position>,
+    pub fleeing: u16,
+}
+
+// Stats the scenes export for each unit: max health, speed, view distance, ability list, trait list
+fn ally_stats(ally_id: AllyId) -> (u16, u16, u16, usize, usize) {
+    match ally_id {
+        AllyId::AshMagnum => (4, 4, 8, 0, 0),
+        AllyId::Alukrod => (6, 4, 10, 1, 1),
+        AllyId::Priest => (4, 3, 8, 8, 5),
+    }
+}
+
+fn enemy_stats(kind: EnemyKind) -> (u16, u16, u16, usize, usize) {
+    match kind {
+        EnemyKind::Bat => (2, 5, 6, 2, 0),
+        EnemyKind::Vampire => (6, 4, 10, 3, 2),
+        EnemyKind::BigBatty => (6, 3, 8, 4, 6),
+        EnemyKind::Necromancer => (4, 3, 8, 5, 2),
+        EnemyKind::Werewolf => (6, 5, 8, 6, 3),
+        EnemyKind::Ghost => (3, 4, 8, 7, 4),
+    }
+}
+
+impl Sim {
+    pub fn parse(map: &str) -> Self {
+        let lines: Vec<&str> = map.trim().lines().map(|line| line.trim()).collect();
+        let width = lines.iter().map(|line| line.len()).max().unwrap_or(0);
+        let height = lines.len();
+
+        let mut sim = Sim {
+            grid: Grid::new(
+                width,
+                height,
+                Occupancy {
+                    tile: Tile::Obstacle(0),
+                    ..Default::default()
+                },
+            ),
+            walls: HashSet::new(),
+            allies: BTreeMap::new(),
+            enemies: BTreeMap::new(),
+            fov_cache: RefCell::new(FovCache::default()),
+            pathfinder: RefCell::new(Pathfinder::default()),
+            diagonal_movement: false,
+            events: EventBus::default(),
+        };
+        sim.walls.extend(sim.grid.positions());
+
+        for (y, line) in lines.iter().enumerate() {
+            for (x, c) in line.chars().enumerate() {
+                let position = Position { x, y };
+                if c != '#' {
+                    sim.walls.remove(&position);
+                    sim.grid[position].tile = Tile::Empty;
+                }
+
+                match c {
+                    '.' | '#' => (),
+                    '_' => sim.grid[position].tile = Tile::Obstacle(0),
+                    'A' => sim.add_ally(AllyId::AshMagnum, position),
+                    'L' => sim.add_ally(AllyId::Alukrod, position),
+                    'P' => sim.add_ally(AllyId::Priest, position),
+                    'b' => sim.add_enemy(EnemyKind::Bat, position),
+                    'v' => sim.add_enemy(EnemyKind::Vampire, position),
+                    'B' => sim.add_enemy(EnemyKind::BigBatty, position),
+                    'n' => sim.add_enemy(EnemyKind::Necromancer, position),
+                    'w' => sim.add_enemy(EnemyKind::Werewolf, position),
+                    'g' => sim.add_enemy(EnemyKind::Ghost, position),
+                    _ => panic!("Unknown tile {:?} at {:?}", c, position),
+                }
+            }
+        }
+
+        // Big batty takes up the tiles right of and below its corner
+        for (enemy_id, enemy) in &sim.enemies {
+            for tile in enemy.tiles() {
+                sim.walls.remove(&tile);
+                sim.grid[tile].tile = Tile::Enemy(*enemy_id);
+            }
+        }
+
+        sim
+    }
+
+    fn add_ally(&mut self, ally_id: AllyId, position: Position) {
+        let (max_health, _, _, _, trait_list) = ally_stats(ally_id);
+        self.grid[position].tile = Tile::Ally(ally_id);
+        self.allies.insert(
+            ally_id,
+            SimAlly {
+                position,
+                vitals: Vitals::new(max_health, trait_lists()[trait_list].clone()),
+            },
+        );
+    }
+
+    fn add_enemy(&mut self, kind: EnemyKind, position: Position) {
+        let (max_health, speed, view_distance, ability_list, trait_list) = enemy_stats(kind);
+        let enemy_id = self.enemies.len() as EnemyId;
+        self.enemies.insert(
+            enemy_id,
+            SimEnemy {
+                kind,
+                position,
+                vitals: Vitals::new(max_health, trait_lists()[trait_list].clone()),
+                speed,
+                view_distance,
+                size: kind.dimensions().0 as u16,
+                abilities: ability_lists()[ability_list]
+                    .iter()
+                    .map(|(ability, _)| *ability)
+                    .collect(),
+                cooldowns: HashMap::new(),
+                last_known_positions: HashMap::new(),
+                fleeing: 0,
+            },
+        );
+    }
+
+    // Walls never change during a simulation, so the cache is never invalidated
+    pub fn fov(&self, origin: Position, distance: u16) -> HashSet<Position> {
+        self.fov_cache
+            .borrow_mut()
+            .get(origin, distance, self.grid.dimensions(), &|position| {
+                self.walls.contains(&position)
+            })
+            .clone()
+    }
+
+    pub fn pathfind(&self, start: Position, goal: Position) -> Option<Vec<Position>> {
+        self.pathfinder.borrow_mut().a_star(
+            start,
+            goal,
+            &self.grid,
+            Mover {
+                tile: self.grid[start].tile,
+                dimensions: (1, 1),
+                movement: Movement::Walk,
+                diagonal: self.diagonal_movement,
+                avoids: None,
+            },
+            None,
+        )
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            grid: self.grid.clone(),
+            allies: self
+                .allies
+                .iter()
+                .map(|(ally_id, ally)| {
+                    (
+                        *ally_id,
+                        Target {
+                            position: ally.position,
+                            traits: ally.vitals.traits(),
+                            taunting: ally
+                                .vitals
+                                .effects
+                                .get(Effect::Taunting)
+                                .map(|active| active.stats.magnitude),
+                            effects: ally
+                                .vitals
+                                .effects
+                                .iter()
+                                .map(|(effect, _)| effect)
+                                .collect(),
+                        },
+                    )
+                })
+                .collect(),
+            coffin_tiles: Default::default(),
+            npcs: BTreeMap::new(),
+            low_obstacles: self
+                .grid
+                .positions()
+                .filter(|position| {
+                    matches!(self.grid[*position].tile, Tile::Obstacle(_))
+                        && !self.walls.contains(position)
+                })
+                .collect(),
+            graves: Vec::new(),
+            diagonal_movement: self.diagonal_movement,
+        }
+    }
+
+    pub fn plan(&mut self, enemy_id: EnemyId) -> Plan {
+        let snapshot = self.snapshot();
+        let visible = {
+            let enemy = &self.enemies[&enemy_id];
+            let view_distance = effects::view_distance(enemy.view_distance, &enemy.vitals.effects);
+            self.fov(enemy.position, view_distance)
+        };
+
+        let enemy = self.enemies.get_mut(&enemy_id).unwrap();
+        let mut unit = ai::Unit {
+            tile: Tile::Enemy(enemy_id),
+            position: enemy.position,
+            health: enemy.vitals.health,
+            max_health: enemy.vitals.max_health,
+            speed: enemy.speed,
+            width: enemy.size,
+            height: enemy.size,
+            abilities: enemy.abilities.clone(),
+            cooldowns: enemy.cooldowns.clone(),
+            traits: enemy.vitals.traits(),
+            effects: enemy
+                .vitals
+                .effects
+                .iter()
+                .map(|(effect, _)| effect)
+                .collect(),
+            last_result: None,
+            fleeing: enemy.fleeing > 0,
+            visible,
+            last_known_positions: enemy.last_known_positions.clone(),
+            retreats_to_coffin: enemy.kind == EnemyKind::Vampire,
+        };
+
+        let plan = ai::plan(&mut unit, &snapshot, &mut self.pathfinder.borrow_mut());
+        enemy.last_known_positions = unit.last_known_positions;
+        plan
+    }
+
+    // Every enemy plans and acts in speed order, the same way the level runs its enemy phase
+    pub fn enemy_turn(&mut self) -> Vec<(EnemyId, Plan)> {
+        let mut turn_order: Vec<(EnemyId, u16)> = self
+            .enemies
+            .iter()
+            .map(|(enemy_id, enemy)| (*enemy_id, enemy.speed))
+            .collect();
+        turn_order.sort_by(|(_, a_speed), (_, b_speed)| a_speed.cmp(b_speed).reverse());
+
+        let mut plans = Vec::new();
+        for (enemy_id, _) in turn_order {
+            let plan = self.plan(enemy_id);
+            let (path, ability) = &plan;
+
+            if let Some(path) = path {
+                self.move_enemy(enemy_id, *path.last().unwrap());
+            }
+
+            match ability {
+                Some((
+                    used_ability,
+                    EnemyAction::Attack {
+                        ally_id,
+                        damage_kind,
+                        damage,
+                    },
+                ))
+                | Some((
+                    used_ability,
+                    EnemyAction::Charge {
+                        ally_id,
+                        damage_kind,
+                        damage,
+                        ..
+                    },
+                ))
+                | Some((
+                    used_ability,
+                    EnemyAction::DashAttack {
+                        ally_id,
+                        damage_kind,
+                        damage,
+                        ..
+                    },
+                ))
+                | Some((
+                    used_ability,
+                    EnemyAction::Push {
+                        ally_id,
+                        damage_kind,
+                        damage,
+                        ..
+                    },
+                )) => {
+                    if let Some(ally) = self.allies.get_mut(ally_id) {
+                        combat::resolve_damage(
+                            UnitId::Ally(*ally_id),
+                            &mut ally.vitals,
+                            Damage {
+                                amount: *damage,
+                                kind: *damage_kind,
+                                source: Some(UnitId::Enemy(enemy_id)),
+                                ability: Some(*used_ability),
+                            },
+                            &mut self.events,
+                        );
+                    }
+                }
+                _ => (),
+            }
+
+            plans.push((enemy_id, plan));
+        }
+
+        for ally in self.allies.values() {
+            if ally.vitals.health == 0 {
+                self.grid[ally.position].tile = Tile::Empty;
+            }
+        }
+        self.allies.retain(|_, ally| ally.vitals.health > 0);
+
+        plans
+    }
+
+    fn move_enemy(&mut self, enemy_id: EnemyId, position: Position) {
+        let enemy = self.enemies.get_mut(&enemy_id).unwrap();
+        for tile in enemy.tiles() {
+            self.grid[tile].tile = Tile::Empty;
+        }
+        enemy.position = position;
+        for tile in enemy.tiles() {
+            self.grid[tile].tile = Tile::Enemy(enemy_id);
+        }
+    }
+}
+
+impl SimEnemy {
+    pub fn tiles(&self) -> Vec<Position> {
+        let mut tiles = Vec::new();
+        for i in 0..self.size as usize {
+            for j in 0..self.size as usize {
+                tiles.push(Position {
+                    x: self.position.x + i,
+                    y: self.position.y + j,
+                });
+            }
+        }
+        tiles
+    }
+
+    pub fn has_trait(&self, trait_: Trait) -> bool {
+        self.vitals.traits().contains(&trait_)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ability::DamageKind;
+    use crate::ai::Morale;
+    use crate::animation::{Animator, Pose};
+    use crate::combat::DamageResult;
+    use crate::core::{Effect, EffectStats, Terrain};
+    use crate::effects::{EffectChange, Effects, Tick};
+    use crate::event::GameEvent;
+    use crate::math::{cone, dash_path, has_room, in_cone, line_of_sight, path_cost, Direction};
+    use crate::stats::{DeathCause, RunStats};
+
+    #[test]
+    fn pathfind_goes_around_walls() {
+        let sim = Sim::parse(
+            "
+            A.#..
+            ..#..
+            .....
+            ",
+        );
+
+        let path = sim
+            .pathfind(Position { x: 0, y: 0 }, Position { x: 4, y: 0 })
+            .unwrap();
+        assert_eq!(path.len(), 8);
+        assert!(path.iter().all(|position| !sim.walls.contains(position)));
+    }
+
+    #[test]
+    fn flood_agrees_with_a_star_within_max_cost() {
+        let sim = Sim::parse(
+            "
+            A.#..
+            ..#..
+            .....
+            ",
+        );
+        let start = Position { x: 0, y: 0 };
+        let mover = Mover {
+            tile: sim.grid[start].tile,
+            dimensions: (1, 1),
+            movement: Movement::Walk,
+            diagonal: false,
+            avoids: None,
+        };
+
+        let mut pathfinder = Pathfinder::default();
+        pathfinder.flood(start, &sim.grid, mover, Some(6));
+        for goal in sim.grid.positions() {
+            let a_star = sim.pathfind(start, goal).filter(|path| path.len() <= 6);
+            assert_eq!(
+                pathfinder.path_to(goal).map(|path| path.len()),
+                a_star.map(|path| path.len())
+            );
+        }
+        assert_eq!(pathfinder.cost_to(Position { x: 4, y: 0 }), None);
+        assert_eq!(pathfinder.cost_to(Position { x: 4, y: 2 }), Some(6));
+    }
+
+    #[test]
+    fn stairs_join_floors_that_walls_keep_apart() {
+        let mut sim = Sim::parse(
+            "
+            A..#...
+            ...#...
+            ...#...
+            ",
+        );
+        let start = Position { x: 0, y: 0 };
+        let upstairs = Position { x: 2, y: 2 };
+        let downstairs = Position { x: 6, y: 0 };
+        let goal = Position { x: 4, y: 0 };
+        assert_eq!(sim.pathfind(start, goal), None);
+
+        sim.grid[upstairs].stairs = Some(downstairs);
+        sim.grid[downstairs].stairs = Some(upstairs);
+        let path = sim.pathfind(start, goal).unwrap();
+        assert_eq!(path.len(), 7);
+        let up = path.iter().position(|tile| *tile == upstairs).unwrap();
+        assert_eq!(path[up + 1], downstairs);
+    }
+
+    #[test]
+    fn pathfind_to_any_takes_the_cheapest_goal() {
+        let sim = Sim::parse(
+            "
+            A.#..
+            ..#..
+            .....
+            ",
+        );
+        let start = Position { x: 0, y: 0 };
+        let mover = Mover {
+            tile: sim.grid[start].tile,
+            dimensions: (1, 1),
+            movement: Movement::Walk,
+            diagonal: false,
+            avoids: None,
+        };
+        let goals = [Position { x: 4, y: 0 }, Position { x: 3, y: 2 }];
+
+        let (path, cost) = Pathfinder::default()
+            .pathfind_to_any(start, &goals, &sim.grid, mover, None)
+            .unwrap();
+        assert_eq!(path.last(), Some(&Position { x: 3, y: 2 }));
+        assert_eq!(cost, 5);
+    }
+
+    #[test]
+    fn diagonal_paths_are_cheaper_but_do_not_cut_corners() {
+        let mut open = Sim::parse(
+            "
+            A....
+            .....
+            .....
+            ",
+        );
+        open.diagonal_movement = true;
+
+        let path = open
+            .pathfind(Position { x: 0, y: 0 }, Position { x: 4, y: 2 })
+            .unwrap();
+        assert_eq!(path.len(), 4);
+        assert_eq!(path_cost(Position { x: 0, y: 0 }, &path), 5);
+
+        let mut walled = Sim::parse(
+            "
+            .....
+            ..#..
+            .....
+            ",
+        );
+        walled.diagonal_movement = true;
+
+        // Going straight up and right would clip the corner of the wall
+        let path = walled
+            .pathfind(Position { x: 1, y: 1 }, Position { x: 2, y: 0 })
+            .unwrap();
+        assert_eq!(path.len(), 2);
+    }
+
+    #[test]
+    fn wide_enemies_do_not_squeeze_through_narrow_gaps() {
+        let path = |map: &str| {
+            let sim = Sim::parse(map);
+            let mover = Mover {
+                tile: Tile::Enemy(0),
+                dimensions: (2, 2),
+                movement: Movement::Walk,
+                diagonal: false,
+                avoids: None,
+            };
+            Pathfinder::default().a_star(
+                Position { x: 0, y: 0 },
+                Position { x: 0, y: 3 },
+                &sim.grid,
+                mover,
+                None,
+            )
+        };
+
+        let narrow = path(
+            "
+            B....
+            .....
+            ##.##
+            .....
+            .....
+            ",
+        );
+        assert!(narrow.is_none());
+
+        let wide = path(
+            "
+            B....
+            .....
+            ##..#
+            .....
+            .....
+            ",
+        )
+        .unwrap();
+        assert!(wide.iter().all(|position| position.x <= 2));
+    }
+
+    #[test]
+    fn allergic_enemies_walk_around_garlic_without_hiding_items() {
+        let mut sim = Sim::parse(
+            "
+            v....
+            .....
+            .....
+            ",
+        );
+        let garlic = Position { x: 2, y: 0 };
+        sim.grid[garlic].item = Some(0);
+        sim.grid[garlic].terrain.push(Terrain::Garlic);
+        for position in sim.grid.adjacent(garlic) {
+            sim.grid[position].terrain.push(Terrain::Garlic);
+        }
+
+        let start = Position { x: 0, y: 0 };
+        let goal = Position { x: 4, y: 0 };
+        let mut mover = Mover {
+            tile: sim.grid[start].tile,
+            dimensions: (1, 1),
+            movement: Movement::Walk,
+            diagonal: false,
+            avoids: None,
+        };
+        let mut pathfinder = Pathfinder::default();
+
+        let path = pathfinder
+            .a_star(start, goal, &sim.grid, mover, None)
+            .unwrap();
+        assert_eq!(path.len(), 4);
+
+        mover.avoids = Some(Terrain::Garlic);
+        let path = pathfinder
+            .a_star(start, goal, &sim.grid, mover, None)
+            .unwrap();
+        assert_eq!(path.len(), 8);
+        assert!(path
+            .iter()
+            .all(|position| position.y == 2 || position.x != 2));
+        assert_eq!(sim.grid[garlic].tile, Tile::Empty);
+    }
+
+    #[test]
+    fn walls_block_sight_and_low_walls_do_not() {
+        let sim = Sim::parse(
+            "
+            A.#..
+            .....
+            L._..
+            ",
+        );
+
+        let behind_wall = sim.fov(Position { x: 0, y: 0 }, 8);
+        assert!(!behind_wall.contains(&Position { x: 3, y: 0 }));
+
+        let behind_low_wall = sim.fov(Position { x: 0, y: 2 }, 8);
+        assert!(behind_low_wall.contains(&Position { x: 3, y: 2 }));
+    }
+
+    #[test]
+    fn lines_of_sight_reach_diagonals_but_not_between_walls() {
+        let open = Sim::parse(
+            "
+            A....
+            .....
+            ....L
+            ",
+        );
+        let line = line_of_sight(
+            Position { x: 0, y: 0 },
+            Position { x: 4, y: 2 },
+            |position| open.grid[position].tile.is_empty(),
+        )
+        .unwrap();
+        assert_eq!(line.last(), Some(&Position { x: 4, y: 2 }));
+
+        let closed = Sim::parse(
+            "
+            A#.
+            #..
+            ..L
+            ",
+        );
+        assert!(line_of_sight(
+            Position { x: 0, y: 0 },
+            Position { x: 2, y: 2 },
+            |position| closed.grid[position].tile.is_empty(),
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn only_some_attacks_clear_low_walls() {
+        let sim = Sim::parse("A_L");
+        let line = |clears_low_obstacles: bool| {
+            line_of_sight(
+                Position { x: 0, y: 0 },
+                Position { x: 2, y: 0 },
+                |position| {
+                    sim.grid[position].tile.is_empty()
+                        || (clears_low_obstacles && !sim.walls.contains(&position))
+                },
+            )
+        };
+
+        assert!(line(true).is_some());
+        assert!(line(false).is_none());
+    }
+
+    fn hit(
+        vitals: &mut Vitals,
+        amount: u16,
+        kind: DamageKind,
+        source: Option<UnitId>,
+    ) -> DamageResult {
+        let damage = Damage {
+            amount,
+            kind,
+            source,
+            ability: None,
+        };
+        combat::resolve_damage(UnitId::Enemy(0), vitals, damage, &mut EventBus::default())
+    }
+
+    #[test]
+    fn werewolves_resist_piercing_but_not_silver() {
+        let sim = Sim::parse("w");
+        let mut werewolf = sim.enemies[&0].clone();
+        assert!(werewolf.has_trait(Trait::PierceResistant));
+
+        assert_eq!(
+            hit(&mut werewolf.vitals, 2, DamageKind::Pierce, None).dealt,
+            1
+        );
+        assert!(hit(&mut werewolf.vitals, 2, DamageKind::Silver, None).dealt > 2);
+    }
+
+    #[test]
+    fn ghosts_ignore_normal_damage() {
+        let sim = Sim::parse("g");
+        let mut ghost = sim.enemies[&0].clone();
+
+        assert_eq!(hit(&mut ghost.vitals, 2, DamageKind::Normal, None).dealt, 0);
+        assert_eq!(hit(&mut ghost.vitals, 2, DamageKind::Holy, None).dealt, 2);
+    }
+
+    #[test]
+    fn damage_results_report_burns_kills_and_mist() {
+        let sim = Sim::parse("b");
+        let mut bat = sim.enemies[&0].clone();
+        let mut events = EventBus::default();
+        let damage = Damage {
+            amount: 1,
+            kind: DamageKind::Fire,
+            source: None,
+            ability: None,
+        };
+
+        bat.vitals.effects.apply(
+            Effect::Mist,
+            EffectStats {
+                magnitude: 0,
+                duration: 1,
+            },
+            None,
+        );
+        let result = combat::resolve_damage(UnitId::Enemy(0), &mut bat.vitals, damage, &mut events);
+        assert!(result.evaded);
+        assert_eq!(result.dealt, 0);
+
+        bat.vitals.effects.remove(Effect::Mist);
+        let result = combat::resolve_damage(UnitId::Enemy(0), &mut bat.vitals, damage, &mut events);
+        assert_eq!(result.effects, vec![Effect::Burn]);
+
+        let damage = Damage {
+            amount: bat.vitals.health,
+            kind: DamageKind::LifeSteal,
+            source: Some(UnitId::Ally(AllyId::Alukrod)),
+            ability: Some(Ability::VampireBite),
+        };
+        let result = combat::resolve_damage(UnitId::Enemy(0), &mut bat.vitals, damage, &mut events);
+        assert!(result.killed);
+        assert_eq!(result.life_stolen, result.dealt);
+        assert_eq!(events.pending().len(), 4);
+        assert_eq!(
+            events.pending().last(),
+            Some(&GameEvent::UnitKilled {
+                unit: UnitId::Enemy(0),
+                source: Some(UnitId::Ally(AllyId::Alukrod)),
+                ability: Some(Ability::VampireBite),
+                damage: result.dealt,
+                damage_kind: DamageKind::LifeSteal,
+            })
+        );
+
+        // Hitting the dead again credits nobody
+        combat::resolve_damage(UnitId::Enemy(0), &mut bat.vitals, damage, &mut events);
+        assert_eq!(events.pending().len(), 5);
+    }
+
+    #[test]
+    fn burns_stack_and_cleansing_keeps_mist() {
+        let sim = Sim::parse("v");
+        let mut vampire = sim.enemies[&0].clone();
+        let ash = Some(UnitId::Ally(AllyId::AshMagnum));
+
+        hit(&mut vampire.vitals, 1, DamageKind::Fire, ash);
+        hit(&mut vampire.vitals, 1, DamageKind::Fire, None);
+        let burn = vampire.vitals.effects.get(Effect::Burn).unwrap();
+        assert_eq!(burn.stats.magnitude, 2);
+        assert_eq!(burn.source, ash);
+
+        let mist = EffectStats {
+            magnitude: 0,
+            duration: 1,
+        };
+        vampire.vitals.effects.apply(Effect::Mist, mist, None);
+        let ticks = vampire.vitals.start_phase();
+        assert_eq!(
+            ticks,
+            vec![Tick::Damage {
+                damage: 2,
+                damage_kind: DamageKind::Normal,
+                source: ash,
+            }]
+        );
+        assert!(!vampire.vitals.effects.contains(Effect::Mist));
+
+        vampire.vitals.effects.apply(Effect::Mist, mist, None);
+        assert_eq!(vampire.vitals.effects.cleanse(), vec![Effect::Burn]);
+        assert!(vampire.vitals.effects.contains(Effect::Mist));
+        assert!(vampire
+            .vitals
+            .effects
+            .take_changes()
+            .contains(&EffectChange::Expired(Effect::Mist)));
+    }
+
+    #[test]
+    fn bats_bite_adjacent_allies() {
+        let mut sim = Sim::parse(
+            "
+            A.
+            b.
+            ",
+        );
+
+        let plans = sim.enemy_turn();
+        let (_, (_, ability)) = &plans[0];
+        assert!(matches!(
+            ability,
+            Some((
+                Ability::BatBite,
+                EnemyAction::Attack {
+                    ally_id: AllyId::AshMagnum,
+                    ..
+                }
+            ))
+        ));
+        assert_eq!(sim.allies[&AllyId::AshMagnum].vitals.health, 3);
+    }
+
+    #[test]
+    fn enemies_close_in_on_visible_allies() {
+        let mut sim = Sim::parse(
+            "
+            A.........
+            ..........
+            .........v
+            ",
+        );
+
+        let start = sim.enemies[&0].position;
+        let ash = sim.allies[&AllyId::AshMagnum].position;
+        sim.enemy_turn();
+        assert!(sim.enemies[&0].position.distance(ash) < start.distance(ash));
+    }
+
+    #[test]
+    fn planning_is_deterministic() {
+        let map = "
+            A...L
+            .....
+            ..b..
+            .v...
+            ";
+
+        let mut first = Sim::parse(map);
+        let mut second = Sim::parse(map);
+        for _ in 0..3 {
+            assert_eq!(first.enemy_turn(), second.enemy_turn());
+        }
+    }
+
+    // Bats mostly hover in place between turns, so the same fields of view come up again
+    #[test]
+    fn the_fov_cache_hands_back_what_shadowcasting_would() {
+        use crate::math::shadowcast;
+
+        let map = "
+            ................
+            .b.b.b.#..b.b.b.
+            ................
+            .b.b.#....#.b.b.
+            ................
+            .b.b.b.##.b.b.b.
+            ................
+            .b.#.b....b.#.b.
+            ................
+            .b.b.b.AL.b.b.b.
+            ................
+            ";
+        let sim = Sim::parse(map);
+
+        for _ in 0..3 {
+            for enemy in sim.enemies.values() {
+                assert_eq!(
+                    sim.fov(enemy.position, enemy.view_distance),
+                    shadowcast(
+                        enemy.position,
+                        enemy.view_distance,
+                        sim.grid.dimensions(),
+                        &|position| sim.walls.contains(&position),
+                    )
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn wounded_vampires_turn_to_mist_and_hold_off_while_in_it() {
+        let mut sim = Sim::parse("A...v");
+        let vampire = 0;
+        sim.enemies.get_mut(&vampire).unwrap().vitals.health = 2;
+
+        let (path, action) = sim.plan(vampire);
+        assert_eq!(path, None);
+        assert!(matches!(
+            action,
+            Some((
+                Ability::Mist,
+                EnemyAction::Effect {
+                    effect: Effect::Mist,
+                    ..
+                }
+            ))
+        ));
+
+        let enemy = sim.enemies.get_mut(&vampire).unwrap();
+        enemy.vitals.health = 6;
+        enemy.vitals.effects.apply(
+            Effect::Mist,
+            EffectStats {
+                magnitude: 0,
+                duration: 2,
+            },
+            None,
+        );
+        let (path, action) = sim.plan(vampire);
+        assert!(path.is_some());
+        assert_eq!(action, None);
+    }
+
+    #[test]
+    fn bats_that_lose_their_nerve_run_for_the_edge() {
+        assert_eq!(Morale::check(40, 39), Morale::Holds);
+        assert_eq!(Morale::check(40, 40), Morale::Flees);
+        assert_eq!(Morale::check(40, 69), Morale::Flees);
+        assert_eq!(Morale::check(40, 70), Morale::Scatters);
+        assert_eq!(Morale::check(100, 99), Morale::Holds);
+
+        let mut sim = Sim::parse(
+            "
+            .......
+            ...A...
+            ...b...
+            .......
+            .......
+            ",
+        );
+        let bat = 0;
+        let (_, action) = sim.plan(bat);
+        assert_eq!(action.map(|(ability, _)| ability), Some(Ability::BatBite));
+
+        sim.enemies.get_mut(&bat).unwrap().fleeing = 2;
+        let (path, action) = sim.plan(bat);
+        assert_eq!(action, None);
+        assert_eq!(path.unwrap().last(), Some(&Position { x: 3, y: 4 }));
+    }
+
+    #[test]
+    fn ash_draws_the_bite_meant_for_the_priest() {
+        let ash = Position { x: 3, y: 2 };
+        let priest = Position { x: 4, y: 3 };
+        assert_eq!(
+            combat::intercept(AllyId::Priest, priest, 2, &[(AllyId::AshMagnum, ash, 1)]),
+            None
+        );
+        let beside = Position { x: 4, y: 2 };
+        assert_eq!(
+            combat::intercept(AllyId::Priest, priest, 2, &[(AllyId::AshMagnum, beside, 1)]),
+            Some((AllyId::AshMagnum, 1))
+        );
+        assert_eq!(
+            combat::intercept(
+                AllyId::AshMagnum,
+                beside,
+                2,
+                &[(AllyId::AshMagnum, beside, 1)]
+            ),
+            None
+        );
+
+        let mut sim = Sim::parse(
+            "
+            .......
+            A......
+            .......
+            ...bP..
+            ",
+        );
+        let bat = 0;
+        let target = |plan: Plan| match plan.1 {
+            Some((_, EnemyAction::Attack { ally_id, .. })) => Some(ally_id),
+            _ => None,
+        };
+        let plan = sim.plan(bat);
+        assert_eq!(target(plan), Some(AllyId::Priest));
+
+        let taunt = EffectStats {
+            magnitude: 4,
+            duration: 1,
+        };
+        let ash = sim.allies.get_mut(&AllyId::AshMagnum).unwrap();
+        ash.vitals.effects.apply(Effect::Taunting, taunt, None);
+        let plan = sim.plan(bat);
+        assert_eq!(target(plan), Some(AllyId::AshMagnum));
+    }
+
+    #[test]
+    fn tiles_held_for_a_spawn_are_kept_clear() {
+        let mut sim = Sim::parse(
+            "
+            A..
+            ...
+            ",
+        );
+        let held = Position { x: 1, y: 0 };
+        sim.grid[held].arriving = true;
+        assert!(!has_room(held, (1, 1), &sim.grid));
+
+        let path = sim
+            .pathfind(Position { x: 0, y: 0 }, Position { x: 2, y: 0 })
+            .unwrap();
+        assert!(!path.contains(&held));
+        assert_eq!(path.len(), 4);
+
+        // Killed while still coming in, it dies once the spawn animation ends
+        let mut animator = Animator::default();
+        animator.spawn();
+        animator.hit(true);
+        assert_eq!(animator.name(), "front_spawn");
+        assert_eq!(animator.finish("front_spawn"), Some(Pose::Spawn));
+        assert_eq!(animator.name(), "front_death");
+    }
+
+    #[test]
+    fn run_stats_keep_the_blow_that_killed_ash() {
+        let mut sim = Sim::parse("Av");
+        let ash = sim.allies.get_mut(&AllyId::AshMagnum).unwrap();
+        let mut events = EventBus::default();
+        let damage = Damage {
+            amount: 1,
+            kind: DamageKind::Normal,
+            source: Some(UnitId::Enemy(0)),
+            ability: Some(Ability::VampireScratch),
+        };
+        combat::resolve_damage(
+            UnitId::Ally(AllyId::AshMagnum),
+            &mut ash.vitals,
+            damage,
+            &mut events,
+        );
+        let killing_blow = Damage {
+            amount: ash.vitals.health,
+            ..damage
+        };
+        combat::resolve_damage(
+            UnitId::Ally(AllyId::AshMagnum),
+            &mut ash.vitals,
+            killing_blow,
+            &mut events,
+        );
+
+        let mut stats = RunStats::default();
+        for event in events.pending() {
+            stats.record(*event);
+        }
+        assert_eq!(stats.damage_taken, 1 + killing_blow.amount as u32);
+        assert_eq!(stats.kills.get(&UnitId::Enemy(0)), Some(&1));
+        assert_eq!(stats.enemies_slain(), 0);
+        assert_eq!(
+            stats.deaths.get(&UnitId::Ally(AllyId::AshMagnum)),
+            Some(&DeathCause {
+                source: Some(UnitId::Enemy(0)),
+                ability: Some(Ability::VampireScratch),
+                damage: killing_blow.amount,
+                damage_kind: DamageKind::Normal,
+            })
+        );
+    }
+
+    #[test]
+    fn blinded_bats_lose_sight_of_all_but_their_neighbours() {
+        let mut sim = Sim::parse("A...b");
+        let bat = 0;
+        let flash = EffectStats {
+            magnitude: 0,
+            duration: 2,
+        };
+        let darkness = EffectStats {
+            magnitude: 3,
+            duration: 2,
+        };
+
+        let mut effects = Effects::default();
+        assert_eq!(effects::view_distance(6, &effects), 6);
+        effects.apply(Effect::Darkness, darkness, None);
+        assert_eq!(effects::view_distance(6, &effects), 3);
+        assert_eq!(effects::view_distance(2, &effects), 1);
+        effects.apply(Effect::Blinded, flash, None);
+        assert_eq!(effects::view_distance(6, &effects), 1);
+
+        let enemy = &sim.enemies[&bat];
+        let ally = Position { x: 0, y: 0 };
+        assert!(sim.fov(enemy.position, enemy.view_distance).contains(&ally));
+
+        let enemy = sim.enemies.get_mut(&bat).unwrap();
+        enemy.vitals.effects.apply(Effect::Blinded, flash, None);
+        let enemy = &sim.enemies[&bat];
+        let view_distance = effects::view_distance(enemy.view_distance, &enemy.vitals.effects);
+        assert!(!sim.fov(enemy.position, view_distance).contains(&ally));
+    }
+
+    #[test]
+    fn vampires_facing_away_miss_whoever_creeps_up_behind() {
+        let sim = Sim::parse(
+            "
+            .....
+            ..v..
+            .....
+            ",
+        );
+        let vampire = Position { x: 2, y: 1 };
+        let behind = Position { x: 1, y: 1 };
+        let ahead = Position { x: 4, y: 1 };
+        let diagonal = Position { x: 3, y: 0 };
+        let beside = Position { x: 2, y: 0 };
+
+        let visible = cone(sim.fov(vampire, 8), vampire, Direction::Right);
+        assert!(visible.contains(&vampire));
+        assert!(visible.contains(&ahead));
+        assert!(visible.contains(&diagonal));
+        assert!(!visible.contains(&beside));
+        assert!(!visible.contains(&behind));
+
+        let turned = cone(sim.fov(vampire, 8), vampire, Direction::Left);
+        assert!(turned.contains(&behind));
+        assert!(!turned.contains(&ahead));
+        assert!(in_cone(vampire, Direction::Up, beside));
+    }
+
+    #[test]
+    fn dash_attacks_run_around_walls_and_strike_at_the_end() {
+        let sim = Sim::parse(
+            "
+            A.#.v
+            .....
+            ",
+        );
+        let start = Position { x: 0, y: 0 };
+        let target = Position { x: 4, y: 0 };
+        let mover = Mover {
+            tile: sim.grid[start].tile,
+            dimensions: (1, 1),
+            movement: Movement::Walk,
+            diagonal: false,
+            avoids: None,
+        };
+        let mut pathfinder = Pathfinder::default();
+        let mut dash = |distance| {
+            dash_path(
+                &mut pathfinder,
+                start,
+                target,
+                (1, distance),
+                &sim.grid,
+                mover,
+            )
+        };
+        assert_eq!(dash(3), None);
+        let path = dash(4).unwrap();
+        assert_eq!(path.len(), 4);
+        assert_eq!(path.last(), Some(&Position { x: 3, y: 1 }));
+        assert_eq!(
+            dash_path(
+                &mut pathfinder,
+                Position { x: 3, y: 0 },
+                target,
+                (1, 4),
+                &sim.grid,
+                mover
+            ),
+            Some(Vec::new())
+        );
+
+        // A werewolf pounces on prey further off than it could walk to in a turn
+        let mut sim = Sim::parse("w......A");
+        let (path, ability) = sim.plan(0);
+        assert_eq!(path.unwrap().last(), Some(&Position { x: 6, y: 0 }));
+        assert!(matches!(
+            ability,
+            Some((Ability::Pounce, EnemyAction::DashAttack { .. }))
+        ));
+    }
+}
@@ -0,0 +1,131 @@
+use crate::ability::{abilities, Action};
+use crate::event::{GameEvent, Listener};
+use crate::level::Level;
+
+use godot::engine::{Engine, INode, Time};
+use godot::global::randf_range;
+use godot::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// Furthest the camera is offset at full trauma, in pixels
+const MAX_OFFSET: f64 = 6.0;
+// Trauma lost per second
+const DECAY: f64 = 2.0;
+const HIT_TRAUMA: f64 = 0.3;
+const HEAVY_TRAUMA: f64 = 0.7;
+// Real time the game freezes for on heavy impacts, in milliseconds
+const HIT_STOP: u64 = 80;
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    GodotConvert,
+    Var,
+    Export,
+    Serialize,
+    Deserialize,
+)]
+#[godot(via = u8)]
+pub enum Intensity {
+    Off,
+    Reduced,
+    #[default]
+    Full,
+}
+
+impl Intensity {
+    fn scale(&self) -> f64 {
+        match self {
+            Self::Off => 0.0,
+            Self::Reduced => 0.5,
+            Self::Full => 1.0,
+        }
+    }
+}
+
+// Camera shake and hit-stop, fed by damage and deaths in the level
+#[derive(GodotClass)]
+#[class(init, base=Node)]
+pub struct Juice {
+    #[export]
+    pub intensity: Intensity,
+    trauma: f64,
+    // When the hit-stop ends and the time scale to go back to, which fast-forward may have raised
+    hit_stop_until: Option<(u64, f64)>,
+    base: Base<Node>,
+}
+
+#[godot_api]
+impl INode for Juice {
+    fn process(&mut self, delta: f64) {
+        // Time is frozen during hit-stop, so it is measured on the wall clock
+        if let Some((until, time_scale)) = self.hit_stop_until {
+            if Time::singleton().get_ticks_msec() >= until {
+                Engine::singleton().set_time_scale(time_scale);
+                self.hit_stop_until = None;
+            }
+        }
+
+        self.trauma = (self.trauma - DECAY * delta).max(0.0);
+
+        let Some(mut camera) = self
+            .base()
+            .get_viewport()
+            .and_then(|viewport| viewport.get_camera_2d())
+        else {
+            return;
+        };
+
+        let shake = MAX_OFFSET * self.trauma * self.trauma * self.intensity.scale();
+        camera.set_offset(Vector2::new(
+            (randf_range(-1.0, 1.0) * shake) as f32,
+            (randf_range(-1.0, 1.0) * shake) as f32,
+        ));
+    }
+}
+
+impl Juice {
+    pub fn shake(&mut self, trauma: f64) {
+        self.trauma = (self.trauma + trauma).min(1.0);
+    }
+
+    pub fn hit_stop(&mut self) {
+        if self.intensity == Intensity::Off {
+            return;
+        }
+
+        let time_scale = match self.hit_stop_until {
+            Some((_, time_scale)) => time_scale,
+            None => Engine::singleton().get_time_scale(),
+        };
+        let duration = (HIT_STOP as f64 * self.intensity.scale()) as u64;
+        self.hit_stop_until = Some((Time::singleton().get_ticks_msec() + duration, time_scale));
+        Engine::singleton().set_time_scale(0.0);
+    }
+}
+
+impl Listener for Juice {
+    fn on_event(&mut self, event: GameEvent, _level: &Level) {
+        match event {
+            GameEvent::UnitDamaged { damage, .. } if damage > 0 => self.shake(HIT_TRAUMA),
+            GameEvent::AllyDied(_) | GameEvent::EnemyDied(..) => {
+                self.shake(HEAVY_TRAUMA);
+                self.hit_stop();
+            }
+            // Area attacks are the closest thing to explosions
+            GameEvent::AbilityUsed { ability, .. } => {
+                let stats = abilities().get(&ability).unwrap();
+                if let Action::Attack { aoe: true, .. } = stats.action {
+                    self.shake(HEAVY_TRAUMA);
+                    self.hit_stop();
+                }
+            }
+            _ => (),
+        }
+    }
+}
@@ -1,41 +1,144 @@
-use crate::ability::{abilities, ability_lists, Ability, Action, DamageKind};
+use crate::ability::{
+    abilities, ability_lists, can_hand_over, has_room_for, parse_loadout, Ability, AbilityStats,
+    Action, DamageKind,
+};
+use crate::achievements::Achievements;
+use crate::ai::{self, Morale, Plan, Snapshot, Target};
+use crate::animation::{Animator, Facing, Pose};
+use crate::audio::{self, Sfx, SoundEffects};
+use crate::auras::{self, AuraLayer, AuraOverlay};
+use crate::barks::{self, Bark, Barks};
+use crate::bench::{self, Stage, BENCH_BATS};
+use crate::bestiary::Bestiary;
+use crate::camera::{self, Follow};
+use crate::carry;
+use crate::choices::{Approach, Choice, Choices, ALERTED_GROUP};
+use crate::cinematic::{Shot, RETURN_TIME};
+use crate::combat::{self, ActionResult, Damage, DamageResult};
+use crate::command::Command;
+use crate::controls::{self, Repeat};
+use crate::core::{
+    Effect, EffectStats, Occupancy, Terrain, Tile, Turn, UnitId, Vitals, COFFIN_HEAL,
+};
+use crate::daily::Daily;
 use crate::death_screen::DeathScreen;
-use crate::dialogue::{Dialogue, DialogueEvent, Room};
-use crate::math::{attack_positions, compute_fov, line_to, pathfind, Direction, Position};
+use crate::defeat::{Defeat, DefeatRules};
+use crate::dialogue::{Dialogue, Room};
+use crate::door::Door;
+use crate::dust::{Death, DustPile};
+use crate::effects::{self, EffectChange, Tick};
+use crate::end_screen::END_SCENE;
+use crate::event::{EventBus, GameEvent, Listener};
+use crate::explosion::{self, Explosion, Flames, FIRE_ROUNDS};
+use crate::floor::{Area, Floor, Stairs};
+use crate::grid::Grid;
+use crate::juice::Juice;
+use crate::loot::{self, Loot, Supply, LOOT_STREAM};
+use crate::math::{
+    self, compute_fov, cone, dash_path, has_room, line_of_sight, line_to, path_cost, slide,
+    Direction, FovCache, Movement, Mover, Pathfinder, Position,
+};
+use crate::modifiers::Modifiers;
+use crate::mods;
+use crate::music::Music;
+use crate::network::{rpc_config, HOST};
+use crate::nodes::{self, find_level};
+use crate::noise::{self, NoiseRing, EXPLOSION_NOISE};
+use crate::passives::{self, Boundary, Hook, Passive};
+use crate::progress::Progress;
+use crate::replay::Replay;
+use crate::rng::Rng;
+use crate::run_log::{Entry, RunLog};
+use crate::save::{self, Save};
+use crate::scoring::Score;
+use crate::settings;
+use crate::stats::RunStats;
+use crate::summary::Summary;
+use crate::survival::{self, PendingSpawn, SpawnMarker, Sunlight, Wave, SUNLIGHT_DAMAGE};
 use crate::traits::{trait_lists, Trait};
-use crate::ui::{AbilityBar, InfoPanel};
+use crate::ui::{self, AbilityBar, InfoPanel};
+use crate::weather::{Weather, WEATHER_STREAM};
 
+use godot::engine::multiplayer_api::RpcMode;
 use godot::engine::{
-    AnimationPlayer, AtlasTexture, CanvasLayer, ISprite2D, Sprite2D, Texture2D, TileMap,
+    AnimationPlayer, AtlasTexture, Button, CanvasLayer, Engine, ISprite2D, Label, PanelContainer,
+    Sprite2D, Texture2D, TileMap, VBoxContainer,
 };
-use godot::global::instance_from_id;
+use godot::global::{randi, Side};
 use godot::prelude::*;
-use std::cmp::{self, Ordering};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::cmp;
 use std::collections::{HashMap, HashSet};
+use std::f64::consts::SQRT_2;
+use std::mem;
+use strum::IntoEnumIterator;
 
-pub const LEVEL_WIDTH: usize = 16;
-pub const LEVEL_HEIGHT: usize = 32;
 pub const TILE_SIZE: f32 = 16.0;
-pub const DOOR_TILES: [Position; 2] = [Position { x: 7, y: 0 }, Position { x: 8, y: 0 }];
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum Effect {
-    Burn,
-    Mist,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub struct EffectStats {
-    pub magnitude: u16,
-    pub duration: u16,
+// Seconds between commands during playback
+pub const PLAYBACK_STEP: f64 = 0.5;
+// Time scale while a restored level catches up to its checkpoint
+pub const CATCH_UP_SPEED: f64 = 8.0;
+// Rounds between checkpoints, saved as the ally phase begins
+pub const CHECKPOINT_ROUNDS: u16 = 3;
+// Speeds the enemy phase can be played at, cycled with the fast forward key
+pub const FAST_FORWARD: [f64; 3] = [1.0, 2.0, 4.0];
+// Pixels per second the free camera pans at
+pub const PAN_SPEED: f32 = 160.0;
+// Seconds a unit takes to walk from one tile to the next
+pub const STEP_TIME: f64 = 0.3;
+// Dealt to everyone beneath a chandelier when it comes down
+pub const CHANDELIER_DAMAGE: u16 = 4;
+// Seconds an enemy without a spawn animation takes to fade in
+const SPAWN_FADE_TIME: f64 = 0.4;
+// How near a fallen leader the swarm has to be to lose its nerve, and for how many turns
+const MORALE_RADIUS: u16 = 5;
+const FLEE_TURNS: u16 = 3;
+const MORALE_STREAM: u64 = 0x300;
+// Keys that move the cursor diagonally when the level allows diagonal movement
+// Tiles a jump covers when there is no unit ahead to snap to
+const JUMP_TILES: usize = 5;
+
+const DIAGONAL_KEYS: [(&str, Direction, Direction); 4] = [
+    ("up_left", Direction::Left, Direction::Up),
+    ("up_right", Direction::Right, Direction::Up),
+    ("down_left", Direction::Left, Direction::Down),
+    ("down_right", Direction::Right, Direction::Down),
+];
+
+// Taking the stairs is over in an instant
+fn step_time(from: Position, to: Position) -> f64 {
+    if from.distance(to) > 1 {
+        0.0
+    } else if from.is_diagonal_to(to) {
+        STEP_TIME * SQRT_2
+    } else {
+        STEP_TIME
+    }
 }
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, GodotConvert, Var, Export)]
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    GodotConvert,
+    Var,
+    Export,
+    Serialize,
+    Deserialize,
+)]
 #[godot(via = u8)]
 pub enum AllyId {
     #[default]
     AshMagnum,
     Alukrod,
+    Priest,
 }
 
 impl AllyId {
@@ -43,6 +146,15 @@ impl AllyId {
         match self {
             Self::AshMagnum => "Ash Magnum".into(),
             Self::Alukrod => "Alukrod".into(),
+            Self::Priest => "Father Ignatius".into(),
+        }
+    }
+
+    // Allies who join partway through a level, the rest are placed in the level scenes
+    pub fn scene(&self) -> Option<&'static str> {
+        match self {
+            Self::Priest => Some("res://scenes/allies/priest.tscn"),
+            Self::AshMagnum | Self::Alukrod => None,
         }
     }
 }
@@ -55,7 +167,6 @@ pub struct Ally {
     pub position: Position,
     #[export]
     pub max_health: u16,
-    pub health: u16,
     #[export]
     pub speed: u16,
     #[export]
@@ -66,17 +177,26 @@ pub struct Ally {
     pub uses: HashMap<Ability, u16>,
     #[export]
     pub trait_list: u8,
-    pub traits: Vec<Trait>,
     pub selected_ability: usize,
     pub has_moved: bool,
     pub has_acted: bool,
-    pub effects: HashMap<Effect, EffectStats>,
+    pub has_traded: bool,
+    // Iron bolts shot this phase, one of which may be picked back up at its end
+    pub bolts_fired: u16,
+    // A dash attack under way, struck at its target once the ally gets to the end of the run
+    pub lunge: Option<(Ability, Position)>,
+    // Picked up items carried at once, past which something has to be left behind
+    #[export]
+    #[init(default = 6)]
+    pub capacity: u16,
+    pub vitals: Vitals,
     path: Option<Vec<Position>>,
     index: usize,
-    #[init(default = "front_idle".into())]
-    animation: String,
+    animator: Animator,
     whip_animation: Option<String>,
     sword_animation: Option<String>,
+    // Shown a marker for still having to act this turn
+    marked: bool,
     base: Base<Node2D>,
 }
 
@@ -91,8 +211,6 @@ impl INode2D for Ally {
             Callable::from_object_method(&self.base(), "animation_end"),
         );
 
-        self.health = self.max_health;
-
         let ability_list = ability_lists()[self.ability_list as usize].clone();
         for (ability, uses) in &ability_list {
             self.uses.insert(*ability, *uses);
@@ -103,7 +221,10 @@ impl INode2D for Ally {
             .copied()
             .collect();
 
-        self.traits = trait_lists()[self.trait_list as usize].clone();
+        self.vitals = Vitals::new(
+            self.max_health,
+            trait_lists()[self.trait_list as usize].clone(),
+        );
     }
 
     fn process(&mut self, _delta: f64) {
@@ -112,7 +233,7 @@ impl INode2D for Ally {
             .get_node_as::<AnimationPlayer>("AnimationPlayer");
         animation_player
             .play_ex()
-            .name(self.animation.clone().into())
+            .name(self.animator.name().into())
             .done();
 
         if let Some(whip_animation) = &self.whip_animation {
@@ -135,86 +256,86 @@ impl INode2D for Ally {
                 .done();
         }
     }
+
+    fn draw(&mut self) {
+        if self.marked {
+            let points = PackedVector2Array::from(
+                &[
+                    Vector2::new(5.0, -10.0),
+                    Vector2::new(11.0, -10.0),
+                    Vector2::new(8.0, -7.0),
+                ][..],
+            );
+            self.base_mut()
+                .draw_colored_polygon(points, Color::from_rgb(0.95, 0.8, 0.35));
+        }
+    }
 }
 
 #[godot_api]
 impl Ally {
-    #[func]
-    pub fn animation_end(&mut self, name: StringName) {
-        let name = name.to_string();
-
-        match name.as_str() {
-            "side_whip" | "side_crossbow" | "side_sword" | "side_hellfire" | "side_bite"
-            | "side_mist" | "side_stake" | "side_hit" => self.animation = "side_idle".into(),
-            "back_whip" | "back_crossbow" | "back_sword" | "back_hellfire" | "back_bite"
-            | "back_mist" | "back_stake" | "back_hit" => self.animation = "back_idle".into(),
-            "front_whip" | "front_crossbow" | "front_sword" | "front_hellfire" | "front_bite"
-            | "front_mist" | "front_stake" | "front_hit" => self.animation = "front_idle".into(),
-            "side_death" | "back_death" | "front_death" => {
-                let mut level_node = self.base().get_node_as::<Level>("../../..");
-                let mut level = level_node.bind_mut();
+    #[signal]
+    fn unit_damaged(damage: u16, damage_kind: GString);
 
-                match self.id {
-                    AllyId::AshMagnum => {
-                        let scene = load::<PackedScene>("res://scenes/death.tscn");
-                        let mut scene: Gd<DeathScreen> = scene.instantiate().unwrap().cast();
+    #[signal]
+    fn unit_died();
 
-                        {
-                            let mut scene = scene.bind_mut();
-                            scene.room = level.room;
-                        }
+    #[signal]
+    fn item_picked_up(item_kind: ItemKind);
 
-                        self.base()
-                            .get_tree()
-                            .unwrap()
-                            .get_root()
-                            .unwrap()
-                            .add_child(scene.clone().upcast());
-                        self.base()
-                            .get_tree()
-                            .unwrap()
-                            .set_current_scene(scene.upcast());
-
-                        drop(level);
-                        level_node.queue_free();
-                    }
-                    _ => {
-                        level.grid[self.position.x][self.position.y] = Tile::Empty;
-                        level.allies.remove(&self.id);
+    #[signal]
+    fn ability_used(ability: GString);
 
-                        let mut dialogue = self.base().get_node_as::<Dialogue>("../../../Dialogue");
-                        let mut dialogue = dialogue.bind_mut();
-                        dialogue.push_event(DialogueEvent::AllyKilled(self.id));
+    #[func]
+    pub fn animation_end(&mut self, name: StringName) {
+        match self.animator.finish(&name.to_string()) {
+            Some(Pose::Action(_)) => {
+                self.has_acted = true;
+                self.whip_animation = None;
+                self.sword_animation = None;
 
-                        self.base_mut().queue_free();
-                    }
+                let Some(mut level) = find_level(&self.base()) else {
+                    return;
+                };
+                let mut level = level.bind_mut();
+                // A shooter is done once their bolt lands
+                if !level.in_flight.contains(&self.id) {
+                    level.events.publish(GameEvent::AllyActed(self.id));
                 }
             }
-            _ => (),
-        }
+            Some(Pose::Death) => {
+                let Some(mut level) = find_level(&self.base()) else {
+                    return;
+                };
+                let mut level = level.bind_mut();
 
-        match name.as_str() {
-            "side_whip" | "side_crossbow" | "side_sword" | "side_hellfire" | "side_bite"
-            | "side_mist" | "side_stake" | "back_whip" | "back_crossbow" | "back_sword"
-            | "back_hellfire" | "back_bite" | "back_mist" | "back_stake" | "front_whip"
-            | "front_crossbow" | "front_sword" | "front_hellfire" | "front_bite" | "front_mist"
-            | "front_stake" => {
-                self.has_acted = true;
-                self.whip_animation = None;
-                self.sword_animation = None;
+                if self.id == AllyId::AshMagnum && level.ash_lives > 0 {
+                    level.ash_lives -= 1;
+                    self.vitals.heal(self.max_health);
+                    self.animator.revive();
+                    return;
+                }
 
-                let mut cursor = self
-                    .base()
-                    .get_node_as::<Cursor>("../../../CursorLayer/Cursor");
-                let mut cursor = cursor.bind_mut();
-                cursor.can_interact = true;
-                cursor.selected = None;
+                let level = &mut *level;
+                if level.is_carried(self.id) {
+                    level.carried.retain(|_, carried_id| *carried_id != self.id);
+                } else {
+                    level.grid[self.position].tile = Tile::Empty;
+                }
+                // Whoever they were carrying is let go where they fell
+                if let Some((carried_id, position)) =
+                    carry::set_down(&mut level.carried, self.id, self.position, &mut level.grid)
+                {
+                    if let Some(mut ally) = level.try_get_ally(carried_id) {
+                        ally.bind_mut().teleport(position);
+                    }
+                    level.shadows_cast = false;
+                }
+                level.allies.remove(&self.id);
+                level.events.publish(GameEvent::AllyDied(self.id));
+                level.check_defeat(Some(self.id));
 
-                let mut ability_bar = self
-                    .base()
-                    .get_node_as::<AbilityBar>("../../../UILayer/AbilityBar");
-                let mut ability_bar = ability_bar.bind_mut();
-                ability_bar.select_none();
+                self.base_mut().queue_free();
             }
             _ => (),
         }
@@ -223,7 +344,9 @@ impl Ally {
     #[func]
     pub fn next_position(&mut self) {
         if self.index > 0 {
-            let mut level = self.base().get_node_as::<Level>("../../..");
+            let Some(mut level) = find_level(&self.base()) else {
+                return;
+            };
             let mut level = level.bind_mut();
             level.shadows_cast = false;
         }
@@ -236,31 +359,33 @@ impl Ally {
                     self.base().clone().upcast(),
                     "position".into(),
                     Variant::from(position.to_vector()),
-                    0.3,
+                    step_time(self.position, position),
                 );
                 tween.tween_callback(Callable::from_object_method(&self.base(), "next_position"));
 
                 match self.position.direction_to(position) {
                     Direction::Left => {
-                        self.animation = "side_walk".into();
+                        self.animator.walk(Facing::Side);
                         self.flip_h(true);
                     }
                     Direction::Right => {
-                        self.animation = "side_walk".into();
+                        self.animator.walk(Facing::Side);
                         self.flip_h(false);
                     }
                     Direction::Up => {
-                        self.animation = "back_walk".into();
+                        self.animator.walk(Facing::Back);
                         self.flip_h(false);
                     }
                     Direction::Down => {
-                        self.animation = "front_walk".into();
+                        self.animator.walk(Facing::Front);
                         self.flip_h(false);
                     }
                 }
 
                 self.position = position;
                 self.index += 1;
+
+                audio::play(Sfx::Footstep);
             }
             Some(path) => {
                 self.position = *path.last().unwrap();
@@ -268,108 +393,147 @@ impl Ally {
                 self.index = 0;
                 self.has_moved = true;
 
-                let mut level_node = self.base().get_node_as::<Level>("../../..");
+                let Some(mut level_node) = find_level(&self.base()) else {
+                    return;
+                };
                 let mut level = level_node.bind_mut();
 
-                if DOOR_TILES.contains(&self.position) {
-                    let scene = match level.room {
-                        Room::EntranceHall => "res://scenes/levels/2-great-hall.tscn",
-                        Room::GreatHall => {
-                            self.base()
-                                .get_tree()
-                                .unwrap()
-                                .change_scene_to_file("res://scenes/end.tscn".into());
-                            return;
-                        }
+                let door = level
+                    .door_at(self.position)
+                    .filter(|door| !door.bind().locked);
+                if let Some(door) = door {
+                    let room = level.room;
+                    let mut best = None;
+                    let score = Score::new(
+                        room,
+                        level.round,
+                        &level.stats,
+                        level.stats.enemies_slain() + level.enemies_remaining() as u16,
+                    );
+                    level.log(Entry::RoomCleared { room, score });
+                    if !level.playback {
+                        let unlocked = level.achievements.clear_room(room);
+                        level.achievements.announce(&unlocked);
+                        level.achievements.save();
+                        // Daily challenges are scored against the same day's runs only
+                        best = match level.modifiers.seed {
+                            Some(seed) => Daily::record(seed, room, score),
+                            None => Progress::complete(room, score),
+                        };
+                    }
+
+                    let (destination, spawn) = {
+                        let door = door.bind();
+                        (door.destination(), door.spawn())
                     };
+                    let next_level = destination.map(|destination| {
+                        let scene = load::<PackedScene>(destination.as_str());
+                        scene.instantiate().unwrap().cast::<Level>()
+                    });
 
-                    let scene = load::<PackedScene>(scene);
-                    let mut next_level: Gd<Level> = scene.instantiate().unwrap().cast();
+                    let mut inventory = HashMap::new();
+                    for ally_id in level.allies.keys() {
+                        let (abilities, uses) = if self.id == *ally_id {
+                            (self.abilities.clone(), self.uses.clone())
+                        } else {
+                            let ally = level.get_ally(*ally_id);
+                            let ally = ally.bind();
+                            (ally.abilities.clone(), ally.uses.clone())
+                        };
+                        let abilities: Vec<(Ability, u16)> = abilities
+                            .iter()
+                            .map(|ability| (*ability, uses[ability]))
+                            .collect();
+                        inventory.insert(*ally_id, abilities);
+                    }
 
-                    {
-                        let mut next_level = next_level.bind_mut();
-                        for ally_id in level.allies.keys() {
-                            let (abilities, uses) = if self.id == *ally_id {
-                                (self.abilities.clone(), self.uses.clone())
-                            } else {
-                                let ally = level.get_ally(*ally_id);
-                                let ally = ally.bind();
-                                (ally.abilities.clone(), ally.uses.clone())
-                            };
-                            let inventory = abilities
-                                .iter()
-                                .map(|ability| (*ability, uses[ability]))
-                                .collect();
-                            next_level.inventory.insert(*ally_id, inventory);
+                    match next_level.clone() {
+                        Some(mut next_level) => {
+                            let mut next_level = next_level.bind_mut();
+                            next_level.versus = level.versus;
+                            next_level.online = level.online;
+                            next_level.guest = level.guest;
+                            next_level.modifiers = level.modifiers;
+                            next_level.choices = level.choices;
+                            next_level.play_time_before = level.play_time();
+                            next_level.inventory = inventory;
+                            next_level.entrance = spawn;
+                        }
+                        // Whatever the party carries out of the last room is kept for New Game+
+                        None if !level.playback && level.modifiers.seed.is_none() => {
+                            Progress::finish_run(level.modifiers, inventory)
                         }
+                        None => (),
                     }
 
-                    self.base()
-                        .get_tree()
-                        .unwrap()
-                        .get_root()
-                        .unwrap()
-                        .add_child(next_level.clone().upcast());
-                    self.base()
-                        .get_tree()
-                        .unwrap()
-                        .set_current_scene(next_level.upcast());
+                    // Replays and online games go straight through, the summary waits on the player
+                    let mut tree = self.base().get_tree().unwrap();
+                    if level.playback || level.online {
+                        match next_level {
+                            Some(next_level) => {
+                                tree.get_root()
+                                    .unwrap()
+                                    .add_child(next_level.clone().upcast());
+                                tree.set_current_scene(next_level.upcast());
+                            }
+                            None => {
+                                tree.change_scene_to_file(END_SCENE.into());
+                                return;
+                            }
+                        }
+                    } else {
+                        let scene = load::<PackedScene>("res://scenes/summary.tscn");
+                        let mut summary: Gd<Summary> = scene.instantiate().unwrap().cast();
+
+                        {
+                            let mut summary = summary.bind_mut();
+                            summary.room = room;
+                            summary.round = level.round;
+                            summary.score = Some(score);
+                            summary.best = best;
+                            summary.seed = level.modifiers.seed;
+                            summary.next = next_level;
+                        }
+
+                        tree.get_root().unwrap().add_child(summary.clone().upcast());
+                        tree.set_current_scene(summary.upcast());
+                    }
 
                     drop(level);
                     level_node.queue_free();
                 } else {
-                    match self.animation.as_str() {
-                        "side_walk" => self.animation = "side_idle".into(),
-                        "back_walk" => self.animation = "back_idle".into(),
-                        "front_walk" => self.animation = "front_idle".into(),
-                        _ => unreachable!(),
-                    }
-
-                    match level.item_at(self.position) {
-                        Some(id) => {
-                            let mut item = level.get_item(id);
-
-                            let picked_up = {
-                                let item = item.bind();
-                                let ability = item.ability();
-                                let stats = abilities().get(&ability).unwrap();
-
-                                if stats.acquirable || self.abilities.contains(&ability) {
-                                    match self.uses.get_mut(&ability) {
-                                        Some(n) => *n += 1,
-                                        None => {
-                                            self.abilities.push(ability);
-                                            self.uses.insert(ability, 1);
-                                        }
-                                    }
-                                    level.items.remove(&id);
-                                    true
-                                } else {
-                                    false
-                                }
-                            };
-
-                            if picked_up {
-                                item.queue_free();
-                            }
+                    self.animator.stop();
+
+                    if let Some(item_id) = level.item_at(self.position) {
+                        let ability = level.get_item(item_id).bind().ability();
+                        if has_room_for(&self.uses, self.capacity, ability) {
+                            level.commands.push(Command::PickUp {
+                                ally_id: self.id,
+                                item_id,
+                            });
+                        } else {
+                            let held = self
+                                .abilities
+                                .iter()
+                                .filter(|ability| abilities().get(ability).unwrap().acquirable)
+                                .copied()
+                                .collect();
+                            level.prompt_swap(self.id, item_id, held);
                         }
-                        None => (),
                     }
 
-                    level.grid[self.position.x][self.position.y] = Tile::Ally(self.id);
-
-                    let mut cursor = self
-                        .base()
-                        .get_node_as::<Cursor>("../../../CursorLayer/Cursor");
-                    let mut cursor = cursor.bind_mut();
-                    cursor.can_interact = true;
-                    cursor.acting = true;
+                    level.grid[self.position].tile = Tile::Ally(self.id);
+                    level.free_captives(self.position);
+                    level.events.publish(GameEvent::AllyMoved(self.id));
 
-                    let mut ability_bar = self
-                        .base()
-                        .get_node_as::<AbilityBar>("../../../UILayer/AbilityBar");
-                    let mut ability_bar = ability_bar.bind_mut();
-                    ability_bar.select_ally(&self);
+                    let lunge = self.lunge.take().filter(|_| level.lands_impacts());
+                    if let Some((ability, position)) = lunge {
+                        level.commands.push(Command::Impact {
+                            ally_id: self.id,
+                            ability,
+                            position,
+                        });
+                    }
                 }
             }
             None => (),
@@ -382,6 +546,10 @@ impl Ally {
         self.id.name()
     }
 
+    pub fn traits(&self) -> Vec<Trait> {
+        self.vitals.traits()
+    }
+
     pub fn current_ability(&self) -> &Ability {
         &self.abilities[self.selected_ability]
     }
@@ -391,15 +559,61 @@ impl Ally {
         sprite.set_flip_h(flip_h);
     }
 
+    pub fn set_marked(&mut self, marked: bool) {
+        if self.marked != marked {
+            self.marked = marked;
+            self.base_mut().queue_redraw();
+        }
+    }
+
     pub fn follow_path(&mut self, path: Vec<Position>) {
         self.path = Some(path);
         self.index = 0;
         self.next_position();
     }
 
+    // Takes one more use of an ability, if it is one that can be picked up or is already known
+    pub fn acquire(&mut self, ability: Ability) -> bool {
+        let stats = abilities().get(&ability).unwrap();
+        if !stats.acquirable && !self.abilities.contains(&ability) {
+            return false;
+        }
+
+        match self.uses.get_mut(&ability) {
+            Some(n) => *n += 1,
+            None => {
+                self.abilities.push(ability);
+                self.uses.insert(ability, 1);
+            }
+        }
+        true
+    }
+
+    // Gives up one use to another ally, a picked up ability leaving the bar with its last use
+    pub fn hand_over(&mut self, ability: Ability) -> bool {
+        let Some(uses) = self.uses.get_mut(&ability).filter(|uses| **uses > 0) else {
+            return false;
+        };
+        *uses -= 1;
+
+        if *uses == 0 && abilities().get(&ability).unwrap().acquirable {
+            self.uses.remove(&ability);
+            if let Some(i) = self.abilities.iter().position(|a| *a == ability) {
+                self.abilities.remove(i);
+            }
+            self.selected_ability = self
+                .selected_ability
+                .min(self.abilities.len().saturating_sub(1));
+        }
+        true
+    }
+
     pub fn use_ability(&mut self, position: Position) -> Option<Gd<Projectile>> {
         let ability = *self.current_ability();
         let stats = abilities().get(&ability).unwrap();
+        if ability == Ability::CrossbowIronBolt {
+            self.bolts_fired += 1;
+        }
         if stats.consumable {
             let uses = self.uses.get_mut(&ability).unwrap();
             *uses -= 1;
@@ -417,7 +631,7 @@ impl Ally {
         match ability {
             Ability::Whip | Ability::Thwack => match self.position.direction_to(position) {
                 Direction::Left => {
-                    self.animation = "side_whip".into();
+                    self.animator.act(Facing::Side, "whip");
                     self.flip_h(true);
 
                     let whip = self.base().get_node_as::<Node2D>("Whip");
@@ -429,7 +643,7 @@ impl Ally {
                     whip.get_node_as::<Sprite2D>("Sprite").set_flip_h(true);
                 }
                 Direction::Right => {
-                    self.animation = "side_whip".into();
+                    self.animator.act(Facing::Side, "whip");
                     self.flip_h(false);
 
                     let whip = self.base().get_node_as::<Node2D>("Whip");
@@ -441,7 +655,7 @@ impl Ally {
                     whip.get_node_as::<Sprite2D>("Sprite").set_flip_h(false);
                 }
                 Direction::Up => {
-                    self.animation = "back_whip".into();
+                    self.animator.act(Facing::Back, "whip");
                     self.flip_h(false);
 
                     let whip = self.base().get_node_as::<Node2D>("Whip");
@@ -453,7 +667,7 @@ impl Ally {
                     whip.get_node_as::<Sprite2D>("Sprite").set_flip_h(false);
                 }
                 Direction::Down => {
-                    self.animation = "front_whip".into();
+                    self.animator.act(Facing::Front, "whip");
                     self.flip_h(false);
 
                     let whip = self.base().get_node_as::<Node2D>("Whip");
@@ -468,26 +682,26 @@ impl Ally {
             Ability::CrossbowIronBolt | Ability::CrossbowSilverBolt => {
                 match self.position.direction_to(position) {
                     Direction::Left => {
-                        self.animation = "side_crossbow".into();
+                        self.animator.act(Facing::Side, "crossbow");
                         self.flip_h(true);
                     }
                     Direction::Right => {
-                        self.animation = "side_crossbow".into();
+                        self.animator.act(Facing::Side, "crossbow");
                         self.flip_h(false);
                     }
                     Direction::Up => {
-                        self.animation = "back_crossbow".into();
+                        self.animator.act(Facing::Back, "crossbow");
                         self.flip_h(false);
                     }
                     Direction::Down => {
-                        self.animation = "front_crossbow".into();
+                        self.animator.act(Facing::Front, "crossbow");
                         self.flip_h(false);
                     }
                 }
             }
             Ability::Sword => match self.position.direction_to(position) {
                 Direction::Left => {
-                    self.animation = "side_sword".into();
+                    self.animator.act(Facing::Side, "sword");
                     self.flip_h(true);
 
                     let sword = self.base().get_node_as::<Node2D>("Sword");
@@ -500,7 +714,7 @@ impl Ally {
                     sword.get_node_as::<Sprite2D>("Sprite").set_flip_h(true);
                 }
                 Direction::Right => {
-                    self.animation = "side_sword".into();
+                    self.animator.act(Facing::Side, "sword");
                     self.flip_h(false);
 
                     let sword = self.base().get_node_as::<Node2D>("Sword");
@@ -513,7 +727,7 @@ impl Ally {
                     sword.get_node_as::<Sprite2D>("Sprite").set_flip_h(false);
                 }
                 Direction::Up => {
-                    self.animation = "back_sword".into();
+                    self.animator.act(Facing::Back, "sword");
                     self.flip_h(false);
 
                     let sword = self.base().get_node_as::<Node2D>("Sword");
@@ -526,7 +740,7 @@ impl Ally {
                     sword.get_node_as::<Sprite2D>("Sprite").set_flip_h(false);
                 }
                 Direction::Down => {
-                    self.animation = "front_sword".into();
+                    self.animator.act(Facing::Front, "sword");
                     self.flip_h(false);
 
                     let sword = self.base().get_node_as::<Node2D>("Sword");
@@ -541,67 +755,79 @@ impl Ally {
             },
             Ability::Hellfire => match self.position.direction_to(position) {
                 Direction::Left => {
-                    self.animation = "side_hellfire".into();
+                    self.animator.act(Facing::Side, "hellfire");
                     self.flip_h(true);
                 }
                 Direction::Right => {
-                    self.animation = "side_hellfire".into();
+                    self.animator.act(Facing::Side, "hellfire");
                     self.flip_h(false);
                 }
                 Direction::Up => {
-                    self.animation = "back_hellfire".into();
+                    self.animator.act(Facing::Back, "hellfire");
                     self.flip_h(false);
                 }
                 Direction::Down => {
-                    self.animation = "front_hellfire".into();
+                    self.animator.act(Facing::Front, "hellfire");
                     self.flip_h(false);
                 }
             },
             Ability::VampireBite => match self.position.direction_to(position) {
                 Direction::Left => {
-                    self.animation = "side_bite".into();
+                    self.animator.act(Facing::Side, "bite");
                     self.flip_h(true);
                 }
                 Direction::Right => {
-                    self.animation = "side_bite".into();
+                    self.animator.act(Facing::Side, "bite");
                     self.flip_h(false);
                 }
                 Direction::Up => {
-                    self.animation = "back_bite".into();
+                    self.animator.act(Facing::Back, "bite");
                     self.flip_h(false);
                 }
                 Direction::Down => {
-                    self.animation = "front_bite".into();
+                    self.animator.act(Facing::Front, "bite");
                     self.flip_h(false);
                 }
             },
-            Ability::Mist => match self.animation.as_str() {
-                "side_idle" => self.animation = "side_mist".into(),
-                "back_idle" => self.animation = "back_mist".into(),
-                "front_idle" => self.animation = "front_mist".into(),
-                _ => unreachable!(),
-            },
-            Ability::WoodenStake | Ability::Garlic | Ability::HolyWater => {
-                match self.position.direction_to(position) {
-                    Direction::Left => {
-                        self.animation = "side_stake".into();
-                        self.flip_h(true);
-                    }
-                    Direction::Right => {
-                        self.animation = "side_stake".into();
-                        self.flip_h(false);
-                    }
-                    Direction::Up => {
-                        self.animation = "back_stake".into();
-                        self.flip_h(false);
-                    }
-                    Direction::Down => {
-                        self.animation = "front_stake".into();
-                        self.flip_h(false);
-                    }
-                }
+            Ability::Mist | Ability::Teleport => {
+                self.animator.act(self.animator.facing(), "mist");
             }
-            _ => unreachable!(),
+            Ability::Guard | Ability::Taunt => {
+                self.animator.act(self.animator.facing(), "stake");
+            }
+            Ability::WoodenStake
+            | Ability::LungingStake
+            | Ability::Garlic
+            | Ability::HolyWater
+            | Ability::FireFlask
+            | Ability::Blessing
+            | Ability::HolyFlash
+            | Ability::Carry
+            | Ability::Smite
+            | Ability::Key
+            | Ability::Lockpick
+            | Ability::Search
+            | Ability::Pull => match self.position.direction_to(position) {
+                Direction::Left => {
+                    self.animator.act(Facing::Side, "stake");
+                    self.flip_h(true);
+                }
+                Direction::Right => {
+                    self.animator.act(Facing::Side, "stake");
+                    self.flip_h(false);
+                }
+                Direction::Up => {
+                    self.animator.act(Facing::Back, "stake");
+                    self.flip_h(false);
+                }
+                Direction::Down => {
+                    self.animator.act(Facing::Front, "stake");
+                    self.flip_h(false);
+                }
+            },
+            // Whatever has no animation of its own, a mod's abilities among them, is a stake
+            // thrust the way the ally already faces
+            _ => self.animator.act(self.animator.facing(), "stake"),
         }
 
         match ability {
@@ -618,54 +844,84 @@ impl Ally {
                 let projectile = Projectile::new(ProjectileKind::Fireball, self.position, position);
                 Some(projectile)
             }
+            Ability::HolyWater => {
+                let projectile = Projectile::new(ProjectileKind::HolyBomb, self.position, position);
+                Some(projectile)
+            }
+            Ability::FireFlask => {
+                let projectile =
+                    Projectile::new(ProjectileKind::FireFlask, self.position, position);
+                Some(projectile)
+            }
             _ => None,
         }
     }
 
-    pub fn heal(&mut self, amount: u16) {
-        self.health = cmp::min(self.health + amount, self.max_health);
+    pub fn teleport(&mut self, position: Position) {
+        self.position = position;
+        self.base_mut().set_position(position.to_vector());
     }
 
-    pub fn hit(&mut self, damage: u16, damage_kind: DamageKind) {
-        if !self.effects.contains_key(&Effect::Mist) {
-            let damage = damage + damage_bonus(damage_kind, &self.traits);
-            self.health = self.health.checked_sub(damage).unwrap_or(0);
+    pub fn push(&mut self, level: &mut Level, direction: Direction, distance: u16) {
+        let mover = Mover {
+            tile: Tile::Ally(self.id),
+            dimensions: (1, 1),
+            movement: Movement::Walk,
+            diagonal: false,
+            avoids: None,
+        };
+        let position = slide(self.position, direction, distance, &level.grid, mover);
+        level.grid[self.position].tile = Tile::Empty;
+        level.grid[position].tile = Tile::Ally(self.id);
+        self.position = position;
 
-            if damage_kind == DamageKind::Fire {
-                match self.effects.get_mut(&Effect::Burn) {
-                    Some(stats) => stats.magnitude += 1,
-                    None => {
-                        self.effects.insert(
-                            Effect::Burn,
-                            EffectStats {
-                                magnitude: 1,
-                                duration: 3,
-                            },
-                        );
-                    }
-                }
-            }
+        let mut tween = self.base_mut().create_tween().unwrap();
+        tween.tween_property(
+            self.base().clone().upcast(),
+            "position".into(),
+            Variant::from(position.to_vector()),
+            0.3,
+        );
+    }
 
-            if self.health == 0 {
-                match self.animation.as_str() {
-                    "side_idle" => self.animation = "side_death".into(),
-                    "back_idle" => self.animation = "back_death".into(),
-                    "front_idle" => self.animation = "front_death".into(),
-                    _ => unreachable!(),
-                }
-            } else {
-                match self.animation.as_str() {
-                    "side_idle" => self.animation = "side_hit".into(),
-                    "back_idle" => self.animation = "back_hit".into(),
-                    "front_idle" => self.animation = "front_hit".into(),
-                    _ => unreachable!(),
+    pub fn heal(&mut self, amount: u16) {
+        self.vitals.heal(amount);
+    }
+
+    pub fn hit(&mut self, damage: Damage, events: &mut EventBus) -> DamageResult {
+        let result =
+            combat::resolve_damage(UnitId::Ally(self.id), &mut self.vitals, damage, events);
+        if !result.evaded {
+            self.animator.hit(result.killed);
+        }
+        result
+    }
+
+    // Whatever the unit's effects do as they tick down
+    pub fn start_phase(&mut self, events: &mut EventBus) {
+        for tick in self.vitals.start_phase() {
+            match tick {
+                Tick::Damage {
+                    damage,
+                    damage_kind,
+                    source,
+                } => {
+                    self.hit(
+                        Damage {
+                            amount: damage,
+                            kind: damage_kind,
+                            source,
+                            ability: None,
+                        },
+                        events,
+                    );
                 }
             }
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum EnemyAction {
     Attack {
         ally_id: AllyId,
@@ -676,27 +932,190 @@ pub enum EnemyAction {
         enemy_kind: EnemyKind,
         position: Position,
     },
+    Resurrect {
+        enemy_kind: EnemyKind,
+        position: Position,
+    },
+    Charge {
+        ally_id: AllyId,
+        damage_kind: DamageKind,
+        damage: u16,
+        distance: u16,
+    },
+    // Struck at the end of a run of up to distance, instead of a walk at the enemy's speed
+    DashAttack {
+        ally_id: AllyId,
+        damage_kind: DamageKind,
+        damage: u16,
+        distance: u16,
+    },
+    AttackNpc {
+        npc_id: NpcId,
+        damage_kind: DamageKind,
+        damage: u16,
+    },
+    Push {
+        ally_id: AllyId,
+        damage_kind: DamageKind,
+        damage: u16,
+        distance: u16,
+    },
+    // On the enemy itself
+    Effect {
+        effect: Effect,
+        stats: EffectStats,
+    },
+    Inflict {
+        ally_id: AllyId,
+        effect: Effect,
+        stats: EffectStats,
+    },
+    Spoil {
+        position: Position,
+    },
+}
+
+impl EnemyAction {
+    // The unit targeted by an action that deals damage, by the tile it stands on
+    pub fn target(&self) -> Option<(Tile, DamageKind, u16)> {
+        match *self {
+            Self::Attack {
+                ally_id,
+                damage_kind,
+                damage,
+            }
+            | Self::Charge {
+                ally_id,
+                damage_kind,
+                damage,
+                ..
+            }
+            | Self::DashAttack {
+                ally_id,
+                damage_kind,
+                damage,
+                ..
+            }
+            | Self::Push {
+                ally_id,
+                damage_kind,
+                damage,
+                ..
+            } => Some((Tile::Ally(ally_id), damage_kind, damage)),
+            Self::AttackNpc {
+                npc_id,
+                damage_kind,
+                damage,
+            } => Some((Tile::Npc(npc_id), damage_kind, damage)),
+            _ => None,
+        }
+    }
 }
 
 pub type EnemyId = u16;
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, Var, Export, GodotConvert)]
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    Var,
+    Export,
+    GodotConvert,
+    Serialize,
+    Deserialize,
+)]
 #[godot(via = u8)]
 pub enum EnemyKind {
     #[default]
     Bat,
     Vampire,
     BigBatty,
+    Necromancer,
+    Werewolf,
+    Ghost,
 }
 
 impl EnemyKind {
+    pub const ALL: [EnemyKind; 6] = [
+        EnemyKind::Bat,
+        EnemyKind::Vampire,
+        EnemyKind::BigBatty,
+        EnemyKind::Necromancer,
+        EnemyKind::Werewolf,
+        EnemyKind::Ghost,
+    ];
+
     pub fn name(&self) -> String {
         match self {
             Self::Bat => "Bat".into(),
             Self::Vampire => "Vampire".into(),
             Self::BigBatty => "BigBatty".into(),
+            Self::Necromancer => "Necromancer".into(),
+            Self::Werewolf => "Werewolf".into(),
+            Self::Ghost => "Ghost".into(),
+        }
+    }
+
+    pub fn scene(&self) -> &'static str {
+        match self {
+            Self::Bat => "res://scenes/enemies/bat.tscn",
+            Self::Vampire => "res://scenes/enemies/vampire.tscn",
+            Self::BigBatty => "res://scenes/enemies/big-batty.tscn",
+            Self::Necromancer => "res://scenes/enemies/necromancer.tscn",
+            Self::Werewolf => "res://scenes/enemies/werewolf.tscn",
+            Self::Ghost => "res://scenes/enemies/ghost.tscn",
+        }
+    }
+
+    // Bestiary flavour text
+    pub fn lore(&self) -> &'static str {
+        match self {
+            Self::Bat => {
+                "A rat with wings and a drinking problem. Comes in swarms, leaves in pieces"
+            }
+            Self::Vampire => {
+                "Pale, brooding and centuries out of fashion. Feeds on the living and sulks in \
+                 coffins to mend"
+            }
+            Self::BigBatty => {
+                "What happens when a bat never stops eating. Too big for doorways, calls its \
+                 little siblings to do the work"
+            }
+            Self::Necromancer => {
+                "A scholar who took the wrong electives. Raises whatever you leave lying around"
+            }
+            Self::Werewolf => "Fast, furry and fond of ankles. Shrugs off iron like it's nothing",
+            Self::Ghost => {
+                "Someone who died and refused to leave. Ordinary blows pass right through it"
+            }
+        }
+    }
+
+    // Matches the width and height exported by each enemy's scene
+    pub fn dimensions(&self) -> (usize, usize) {
+        match self {
+            Self::BigBatty => (2, 2),
+            _ => (1, 1),
+        }
+    }
+
+    // Out of 100, the chance of standing firm when a leader falls nearby. Anything at 100 never
+    // checks at all
+    pub fn courage(&self) -> u16 {
+        match self {
+            Self::Bat => 40,
+            _ => 100,
         }
     }
+
+    // Whose death shakes the swarm around it
+    pub fn leads_swarm(&self) -> bool {
+        matches!(self, Self::BigBatty | Self::Vampire)
+    }
 }
 
 #[derive(GodotClass)]
@@ -708,7 +1127,6 @@ pub struct Enemy {
     pub kind: EnemyKind,
     #[export]
     pub max_health: u16,
-    pub health: u16,
     #[export]
     pub speed: u16,
     #[export]
@@ -724,14 +1142,20 @@ pub struct Enemy {
     pub cooldowns: HashMap<Ability, u16>,
     #[export]
     pub trait_list: u8,
-    pub traits: Vec<Trait>,
-    pub effects: HashMap<Effect, EffectStats>,
+    pub vitals: Vitals,
+    pub selected_ability: usize,
     path: Option<Vec<Position>>,
     index: usize,
     current_ability: Option<(Ability, EnemyAction)>,
+    // What its last ability did, so it doesn't keep wasting them on the same target
+    last_result: Option<ActionResult>,
+    // Turns left running for the edge of the map after its nerve broke
+    pub fleeing: u16,
     last_known_positions: HashMap<AllyId, Position>,
-    #[init(default = "front_idle".into())]
-    animation: String,
+    // Which way it is looking, set by where it last went or struck
+    #[init(default = Direction::Down)]
+    pub facing: Direction,
+    animator: Animator,
     base: Base<Node2D>,
 }
 
@@ -746,8 +1170,7 @@ impl INode2D for Enemy {
             Callable::from_object_method(&self.base(), "animation_end"),
         );
 
-        self.health = self.max_health;
-
+        mods::overlay_enemy(self);
         let ability_list = ability_lists()[self.ability_list as usize].clone();
         for (ability, uses) in &ability_list {
             self.uses.insert(*ability, *uses);
@@ -758,7 +1181,10 @@ impl INode2D for Enemy {
             .copied()
             .collect();
 
-        self.traits = trait_lists()[self.trait_list as usize].clone();
+        self.vitals = Vitals::new(
+            self.max_health,
+            trait_lists()[self.trait_list as usize].clone(),
+        );
     }
 
     fn process(&mut self, _delta: f64) {
@@ -767,56 +1193,84 @@ impl INode2D for Enemy {
             .get_node_as::<AnimationPlayer>("AnimationPlayer");
         animation_player
             .play_ex()
-            .name(self.animation.clone().into())
+            .name(self.animator.name().into())
             .done();
     }
 }
 
 #[godot_api]
 impl Enemy {
-    #[func]
-    pub fn animation_end(&mut self, name: StringName) {
-        let name = name.to_string();
-
-        match name.as_str() {
-            "side_attack" | "side_hit" => self.animation = "side_idle".into(),
-            "back_attack" | "back_hit" => self.animation = "back_idle".into(),
-            "front_attack" | "front_hit" => self.animation = "front_idle".into(),
-            "side_death" | "back_death" | "front_death" => {
-                let mut level = self.base().get_node_as::<Level>("../../..");
-                let mut level = level.bind_mut();
+    #[signal]
+    fn unit_damaged(damage: u16, damage_kind: GString);
 
-                for i in 0..self.width as usize {
-                    for j in 0..self.height as usize {
-                        level.grid[self.position.x + i][self.position.y + j] = Tile::Empty;
-                    }
-                }
-
-                level.enemies.remove(&self.id);
-                if let Some(i) = level.turn_order.iter().position(|(id, _)| *id == self.id) {
-                    level.turn_order.remove(i);
-                }
+    #[signal]
+    fn unit_died();
 
-                let mut dialogue = self.base().get_node_as::<Dialogue>("../../../Dialogue");
-                let mut dialogue = dialogue.bind_mut();
-                dialogue.push_event(DialogueEvent::EnemyKilled(self.kind));
+    #[signal]
+    fn ability_used(ability: GString);
 
-                self.base_mut().queue_free();
-            }
-            _ => (),
+    // Plays the scene's spawn animation when it has one, and fades the enemy in otherwise
+    pub fn appear(&mut self) {
+        let mut spawning = self.animator;
+        spawning.spawn();
+        let animation_player = self
+            .base()
+            .get_node_as::<AnimationPlayer>("AnimationPlayer");
+        if animation_player.has_animation(spawning.name().into()) {
+            self.animator = spawning;
+            return;
         }
+
+        self.base_mut()
+            .set_modulate(Color::from_rgba(1.0, 1.0, 1.0, 0.0));
+        let mut tween = self.base_mut().create_tween().unwrap();
+        tween.tween_property(
+            self.base().clone().upcast(),
+            "modulate".into(),
+            Variant::from(Color::from_rgb(1.0, 1.0, 1.0)),
+            SPAWN_FADE_TIME,
+        );
     }
 
     #[func]
-    pub fn next_position(&mut self) {
-        if self.index > 0 {
-            let shadow_map = self
-                .base()
-                .get_node_as::<ShadowMap>("../../../ShadowLayer/ShadowMap");
-            let shadow_map = shadow_map.bind();
+    pub fn animation_end(&mut self, name: StringName) {
+        if let Some(Pose::Death) = self.animator.finish(&name.to_string()) {
+            let Some(mut level) = find_level(&self.base()) else {
+                return;
+            };
+            let mut level = level.bind_mut();
+
+            for i in 0..self.width as usize {
+                for j in 0..self.height as usize {
+                    level.grid[self.position.offset(i, j)].tile = Tile::Empty;
+                }
+            }
 
-            let visible = shadow_map.visible.contains(&self.position);
-            self.base_mut().set_visible(visible);
+            level.enemies.remove(&self.id);
+            level.graves.push((self.kind, self.position));
+            level.leave_dust(self.kind, self.position, (self.width, self.height));
+            if self.kind.leads_swarm() {
+                level.break_morale(self.position);
+            }
+            if let Some(i) = level.turn_order.iter().position(|(id, _)| *id == self.id) {
+                level.turn_order.remove(i);
+            }
+            level
+                .events
+                .publish(GameEvent::EnemyDied(self.id, self.kind));
+
+            self.base_mut().queue_free();
+        }
+    }
+
+    #[func]
+    pub fn next_position(&mut self) {
+        if self.index > 0 {
+            let shadow_map = find_level(&self.base()).and_then(|level| nodes::shadow_map(&level));
+            if let Some(shadow_map) = shadow_map {
+                let visible = shadow_map.bind().visible.contains(&self.position);
+                self.base_mut().set_visible(visible);
+            }
         }
 
         match &self.path {
@@ -827,31 +1281,36 @@ impl Enemy {
                     self.base().clone().upcast(),
                     "position".into(),
                     Variant::from(position.to_vector()),
-                    0.3,
+                    step_time(self.position, position),
                 );
                 tween.tween_callback(Callable::from_object_method(&self.base(), "next_position"));
 
                 if self.position != position {
                     match self.position.direction_to(position) {
                         Direction::Left => {
-                            self.animation = "side_walk".into();
+                            self.animator.walk(Facing::Side);
                             self.flip_h(true);
                         }
                         Direction::Right => {
-                            self.animation = "side_walk".into();
+                            self.animator.walk(Facing::Side);
                             self.flip_h(false);
                         }
                         Direction::Up => {
-                            self.animation = "back_walk".into();
+                            self.animator.walk(Facing::Back);
                             self.flip_h(false);
                         }
                         Direction::Down => {
-                            self.animation = "front_walk".into();
+                            self.animator.walk(Facing::Front);
                             self.flip_h(false);
                         }
                     }
                 }
 
+                // Only enemies the allies can see are heard
+                if self.base().is_visible() {
+                    audio::play(Sfx::Footstep);
+                }
+
                 self.position = position;
                 self.index += 1;
             }
@@ -860,69 +1319,13 @@ impl Enemy {
                 self.path = None;
                 self.index = 0;
 
-                match self.animation.as_str() {
-                    "side_walk" => self.animation = "side_idle".into(),
-                    "back_walk" => self.animation = "back_idle".into(),
-                    "front_walk" => self.animation = "front_idle".into(),
-                    "side_idle" | "back_idle" | "front_idle" => (),
-                    _ => unreachable!(),
-                }
+                self.animator.stop();
 
-                let mut level = self.base().get_node_as::<Level>("../../..");
-                let mut level = level.bind_mut();
-                let Turn::Enemy(i, _) = level.turn else {
-                    unreachable!()
+                let Some(mut level) = find_level(&self.base()) else {
+                    return;
                 };
-                level.turn = Turn::Enemy(i + 1, false);
-
-                for (_, cooldown) in &mut self.cooldowns {
-                    if *cooldown > 0 {
-                        *cooldown -= 1;
-                    }
-                }
-
-                if let Some((ability, action)) = self.current_ability {
-                    match action {
-                        EnemyAction::Attack {
-                            ally_id,
-                            damage_kind,
-                            damage,
-                        } => {
-                            let mut ally = level.get_ally(ally_id);
-                            let mut ally = ally.bind_mut();
-                            ally.hit(damage, damage_kind);
-
-                            match damage_kind {
-                                DamageKind::LifeSteal => self.heal(damage),
-                                _ => (),
-                            }
-
-                            self.use_ability(ability, ally.position);
-                            self.current_ability = None;
-                        }
-                        EnemyAction::Spawn {
-                            enemy_kind,
-                            position,
-                        } => {
-                            let stats = abilities().get(&ability).unwrap();
-                            match stats.action {
-                                Action::Spawn { cooldown, .. } => {
-                                    self.cooldowns.insert(ability, cooldown);
-                                }
-                                _ => (),
-                            }
-
-                            level.spawn_enemy(enemy_kind, position);
-
-                            self.use_ability(ability, position);
-                            self.current_ability = None;
-                        }
-                    }
-                }
-
-                let mut dialogue = self.base().get_node_as::<Dialogue>("../../../Dialogue");
-                let mut dialogue = dialogue.bind_mut();
-                dialogue.push_event(DialogueEvent::EnemyMoved(self.kind));
+                let mut level = level.bind_mut();
+                self.end_move(&mut level);
             }
             None => (),
         }
@@ -930,207 +1333,107 @@ impl Enemy {
 }
 
 impl Enemy {
-    pub fn name(&self) -> String {
-        self.kind.name()
+    // Moves without tweening, for enemies no ally can see
+    pub fn move_immediately(&mut self, position: Position, level: &mut Level) {
+        self.face(position);
+        self.position = position;
+        self.path = None;
+        self.index = 0;
+        self.base_mut().set_position(position.to_vector());
+        self.end_move(level);
     }
 
-    /*
-     * Enemies make decisions by creating a list of all possible positions they can use an ability on an
-     * ally from and picking the most preferential one based on the following, in order:
-     * - Whether or not they can move to that space in a single turn
-     * - The highest damage the ability can cause to the player
-     * - The farthest range they can use the ability from
-     * - The closest the position is to their current position
-     */
-    pub fn plan(
-        &mut self,
-        level: &Level,
-    ) -> (Option<Vec<Position>>, Option<(Ability, EnemyAction)>) {
-        let visible = compute_fov(self.position, self.view_distance, level);
-        let dimensions = (self.width as usize, self.height as usize);
-
-        let mut grid = level.grid;
-        if self.traits.contains(&Trait::GarlicAllergy) {
-            for item_id in level.items.keys() {
-                let item = level.get_item(*item_id);
-                let item = item.bind();
-                match item.kind {
-                    ItemKind::Garlic => {
-                        grid[item.position.x][item.position.y] = Tile::Obstacle(0);
-
-                        for position in item.position.adjacent() {
-                            grid[position.x][position.y] = Tile::Obstacle(0);
-                        }
-                    }
-                    _ => (),
-                }
+    // Hands the turn to the next enemy, the ability is used once nothing is bound
+    fn end_move(&mut self, level: &mut Level) {
+        let Turn::Enemy(i, _) = level.turn else {
+            return;
+        };
+        level.turn = Turn::Enemy(i + 1, false);
+        self.fleeing = self.fleeing.saturating_sub(1);
+
+        for (_, cooldown) in &mut self.cooldowns {
+            if *cooldown > 0 {
+                *cooldown -= 1;
             }
         }
 
-        let mut actions = Vec::new();
-        for ability in &self.abilities {
-            let stats = abilities().get(ability).unwrap();
-            match stats.action {
-                Action::Attack {
-                    damage_kind,
-                    damage,
-                    ..
-                } => {
-                    for (ally_id, instance_id) in &level.allies {
-                        let ally: Gd<Ally> = instance_from_id(*instance_id).unwrap().cast();
-                        let ally = ally.bind();
-
-                        if visible.contains(&ally.position) {
-                            self.last_known_positions.insert(*ally_id, ally.position);
-                            actions.extend(
-                                attack_positions(ally.position, stats.range, grid, dimensions)
-                                    .iter()
-                                    .map(|(position, range)| {
-                                        (
-                                            Some(*ability),
-                                            *ally_id,
-                                            *range,
-                                            pathfind(
-                                                self.position,
-                                                *position,
-                                                grid,
-                                                Tile::Enemy(self.id),
-                                                dimensions,
-                                            ),
-                                        )
-                                    })
-                                    .filter_map(|(ability, ally_id, range, path)| {
-                                        path.map(|path| {
-                                            (
-                                                ability,
-                                                EnemyAction::Attack {
-                                                    ally_id,
-                                                    damage_kind,
-                                                    damage,
-                                                },
-                                                range,
-                                                path,
-                                            )
-                                        })
-                                    }),
-                            );
-                        } else if let Some(last_known_position) =
-                            self.last_known_positions.get(&ally_id)
-                        {
-                            if let Some(path) = pathfind(
-                                self.position,
-                                *last_known_position,
-                                grid,
-                                Tile::Enemy(self.id),
-                                dimensions,
-                            ) {
-                                actions.push((
-                                    None,
-                                    EnemyAction::Attack {
-                                        ally_id: *ally_id,
-                                        damage_kind,
-                                        damage,
-                                    },
-                                    1,
-                                    path,
-                                ));
-                            }
-                        }
-                    }
-                }
-                Action::Spawn { enemy_kind, .. } => {
-                    let cooldown_finished = *self.cooldowns.get(&ability).unwrap_or(&0) == 0;
-                    let any_visible = level.allies.values().any(|instance_id| {
-                        let ally: Gd<Ally> = instance_from_id(*instance_id).unwrap().cast();
-                        let ally = ally.bind();
-                        visible.contains(&ally.position)
-                    });
+        if let Some((ability, action)) = self.current_ability {
+            level.commands.push(Command::EnemyAct {
+                enemy_id: self.id,
+                ability,
+                action,
+            });
+        }
 
-                    if cooldown_finished && any_visible {
-                        for i in 0..self.width as usize {
-                            for j in 0..self.height as usize {
-                                let position = Position {
-                                    x: self.position.x + i,
-                                    y: self.position.y + j,
-                                };
-                                for adjacent in position.adjacent() {
-                                    if level.grid[adjacent.x][adjacent.y].is_empty() {
-                                        actions.push((
-                                            Some(*ability),
-                                            EnemyAction::Spawn {
-                                                enemy_kind,
-                                                position: adjacent,
-                                            },
-                                            stats.range,
-                                            vec![self.position],
-                                        ));
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-                _ => unreachable!(),
-            }
+        level
+            .events
+            .publish(GameEvent::EnemyMoved(self.id, self.kind));
+    }
+
+    pub fn name(&self) -> String {
+        self.kind.name()
+    }
+
+    pub fn traits(&self) -> Vec<Trait> {
+        self.vitals.traits()
+    }
+
+    pub fn face(&mut self, position: Position) {
+        if position != self.position {
+            self.facing = self.position.direction_to(position);
         }
+    }
 
-        if actions.is_empty() {
-            (None, None)
-        } else {
-            actions.sort_by(
-                |(_, a_action, a_range, a_path), (_, b_action, b_range, b_path)| match (
-                    a_action, b_action,
-                ) {
-                    (
-                        EnemyAction::Attack {
-                            ally_id: a_ally_id,
-                            damage_kind: a_damage_kind,
-                            damage: a_damage,
-                        },
-                        EnemyAction::Attack {
-                            ally_id: b_ally_id,
-                            damage_kind: b_damage_kind,
-                            damage: b_damage,
-                        },
-                    ) => {
-                        let a_ally: Gd<Ally> =
-                            instance_from_id(level.allies[a_ally_id]).unwrap().cast();
-                        let a_ally = a_ally.bind();
-                        let b_ally: Gd<Ally> =
-                            instance_from_id(level.allies[b_ally_id]).unwrap().cast();
-                        let b_ally = b_ally.bind();
-
-                        let a_damage = a_damage + damage_bonus(*a_damage_kind, &a_ally.traits);
-                        let b_damage = b_damage + damage_bonus(*b_damage_kind, &b_ally.traits);
-                        let a_cost = a_path.len() as u16;
-                        let b_cost = b_path.len() as u16;
-                        let a_within = a_cost <= self.speed;
-                        let b_within = b_cost <= self.speed;
-
-                        a_within
-                            .cmp(&b_within)
-                            .reverse()
-                            .then(a_damage.cmp(&b_damage).reverse())
-                            .then(a_range.cmp(b_range).reverse())
-                            .then(a_cost.cmp(&b_cost))
-                    }
-                    (EnemyAction::Attack { .. }, EnemyAction::Spawn { .. }) => Ordering::Greater,
-                    (EnemyAction::Spawn { .. }, EnemyAction::Attack { .. }) => Ordering::Less,
-                    (EnemyAction::Spawn { .. }, EnemyAction::Spawn { .. }) => Ordering::Equal,
-                },
-            );
+    // Whoever it spotted, or who struck or made a noise nearby, is looked for there
+    pub fn notice(&mut self, ally_id: AllyId, position: Position) {
+        self.last_known_positions.insert(ally_id, position);
+        self.face(position);
+    }
 
-            let (ability, action, _, path) = actions.first().unwrap();
+    pub fn plan(&mut self, level: &Level) -> Plan {
+        let snapshot = level.snapshot();
+        let mut unit = ai::Unit {
+            tile: Tile::Enemy(self.id),
+            position: self.position,
+            health: self.vitals.health,
+            max_health: self.max_health,
+            speed: self.speed,
+            width: self.width,
+            height: self.height,
+            abilities: self.abilities.clone(),
+            cooldowns: self.cooldowns.clone(),
+            traits: self.traits(),
+            effects: self
+                .vitals
+                .effects
+                .iter()
+                .map(|(effect, _)| effect)
+                .collect(),
+            last_result: self.last_result.clone(),
+            fleeing: self.fleeing > 0,
+            visible: level.enemy_sight(self),
+            last_known_positions: self.last_known_positions.clone(),
+            retreats_to_coffin: self.kind == EnemyKind::Vampire,
+        };
 
-            if path.len() as u16 <= self.speed {
-                (
-                    Some(path.clone()),
-                    ability.map(|ability| (ability, *action)),
-                )
-            } else {
-                (Some(path[0..self.speed as usize].to_vec()), None)
+        let plan = bench::time(Stage::Planning, || {
+            ai::plan(&mut unit, &snapshot, &mut level.pathfinder.borrow_mut())
+        });
+        self.last_known_positions = unit.last_known_positions;
+        plan
+    }
+
+    pub fn tiles(&self) -> Vec<Position> {
+        let mut tiles = Vec::new();
+        for i in 0..self.width as usize {
+            for j in 0..self.height as usize {
+                tiles.push(Position {
+                    x: self.position.x + i,
+                    y: self.position.y + j,
+                });
             }
         }
+        tiles
     }
 
     pub fn flip_h(&mut self, flip_h: bool) {
@@ -1138,13 +1441,56 @@ impl Enemy {
         sprite.set_flip_h(flip_h);
     }
 
+    // Facing is settled up front, so enemies moved without tweening end up looking the same way
     pub fn follow_path(&mut self, path: Vec<Position>) {
+        if let Some(last) = path.last() {
+            self.face(*last);
+        }
         self.path = Some(path);
         self.index = 0;
         self.next_position();
     }
 
+    pub fn dash(&mut self, position: Position) {
+        self.face(position);
+        if self.position != position {
+            match self.position.direction_to(position) {
+                Direction::Left => {
+                    self.animator.walk(Facing::Side);
+                    self.flip_h(true);
+                }
+                Direction::Right => {
+                    self.animator.walk(Facing::Side);
+                    self.flip_h(false);
+                }
+                Direction::Up => {
+                    self.animator.walk(Facing::Back);
+                    self.flip_h(false);
+                }
+                Direction::Down => {
+                    self.animator.walk(Facing::Front);
+                    self.flip_h(false);
+                }
+            }
+        }
+
+        let distance = self.position.distance(position);
+        self.path = Some(vec![position]);
+        self.index = 1;
+        self.position = position;
+
+        let mut tween = self.base_mut().create_tween().unwrap();
+        tween.tween_property(
+            self.base().clone().upcast(),
+            "position".into(),
+            Variant::from(position.to_vector()),
+            0.08 * distance as f64,
+        );
+        tween.tween_callback(Callable::from_object_method(&self.base(), "next_position"));
+    }
+
     pub fn use_ability(&mut self, ability: Ability, position: Position) {
+        self.face(position);
         let stats = abilities().get(&ability).unwrap();
         if stats.consumable {
             let uses = self.uses.get_mut(&ability).unwrap();
@@ -1162,93 +1508,92 @@ impl Enemy {
             Ability::BatBite
             | Ability::VampireScratch
             | Ability::VampireBite
-            | Ability::BigBatBite => match self.position.direction_to(position) {
+            | Ability::BigBatBite
+            | Ability::DrainLife
+            | Ability::Claw
+            | Ability::Charge
+            | Ability::Pounce
+            | Ability::ChillingTouch
+            | Ability::Thwack
+            | Ability::Darkness
+            | Ability::Spoil => match self.position.direction_to(position) {
                 Direction::Left => {
-                    self.animation = "side_attack".into();
+                    self.animator.act(Facing::Side, "attack");
                     self.flip_h(true);
                 }
                 Direction::Right => {
-                    self.animation = "side_attack".into();
+                    self.animator.act(Facing::Side, "attack");
                     self.flip_h(false);
                 }
                 Direction::Up => {
-                    self.animation = "back_attack".into();
+                    self.animator.act(Facing::Back, "attack");
                     self.flip_h(false);
                 }
                 Direction::Down => {
-                    self.animation = "front_attack".into();
+                    self.animator.act(Facing::Front, "attack");
                     self.flip_h(false);
                 }
             },
-            Ability::SpawnBat => (),
-            _ => unreachable!(),
+            Ability::SpawnBat | Ability::Resurrect | Ability::Mist => (),
+            // Likewise an attack the way the enemy already faces
+            _ => self.animator.act(self.animator.facing(), "attack"),
         }
     }
 
     pub fn heal(&mut self, amount: u16) {
-        self.health = cmp::min(self.health + amount, self.max_health);
+        self.vitals.heal(amount);
     }
 
-    pub fn hit(&mut self, damage: u16, damage_kind: DamageKind) {
-        if !self.effects.contains_key(&Effect::Mist) {
-            let damage = damage + damage_bonus(damage_kind, &self.traits);
-            self.health = self.health.checked_sub(damage).unwrap_or(0);
-
-            if damage_kind == DamageKind::Fire {
-                match self.effects.get_mut(&Effect::Burn) {
-                    Some(stats) => stats.magnitude += 1,
-                    None => {
-                        self.effects.insert(
-                            Effect::Burn,
-                            EffectStats {
-                                magnitude: 1,
-                                duration: 3,
-                            },
-                        );
-                    }
-                }
-            }
+    pub fn hit(&mut self, damage: Damage, events: &mut EventBus) -> DamageResult {
+        let result =
+            combat::resolve_damage(UnitId::Enemy(self.id), &mut self.vitals, damage, events);
+        if !result.evaded {
+            self.animator.hit(result.killed);
+        }
+        result
+    }
 
-            if self.health == 0 {
-                match self.animation.as_str() {
-                    "side_idle" => self.animation = "side_death".into(),
-                    "back_idle" => self.animation = "back_death".into(),
-                    "front_idle" => self.animation = "front_death".into(),
-                    _ => unreachable!(),
-                }
-            } else {
-                match self.animation.as_str() {
-                    "side_idle" => self.animation = "side_hit".into(),
-                    "back_idle" => self.animation = "back_hit".into(),
-                    "front_idle" => self.animation = "front_hit".into(),
-                    _ => unreachable!(),
+    // Whatever the unit's effects do as they tick down
+    pub fn start_phase(&mut self, events: &mut EventBus) {
+        for tick in self.vitals.start_phase() {
+            match tick {
+                Tick::Damage {
+                    damage,
+                    damage_kind,
+                    source,
+                } => {
+                    self.hit(
+                        Damage {
+                            amount: damage,
+                            kind: damage_kind,
+                            source,
+                            ability: None,
+                        },
+                        events,
+                    );
                 }
             }
         }
     }
 
     pub fn push(&mut self, level: &mut Level, direction: Direction, distance: u16) {
-        let mut position = self.position;
-        for dist in 1..=distance {
-            let pos = match self.position.in_direction(direction, dist as usize) {
-                Some(pos) => pos,
-                None => break,
-            };
-
-            if level.grid[pos.x][pos.y].is_empty() {
-                position = pos;
-            } else {
-                break;
-            }
-        }
+        let mover = Mover {
+            tile: Tile::Enemy(self.id),
+            dimensions: (self.width as usize, self.height as usize),
+            movement: Movement::Walk,
+            diagonal: false,
+            avoids: None,
+        };
+        let position = slide(self.position, direction, distance, &level.grid, mover);
 
-        for i in 0..self.width as usize {
-            for j in 0..self.height as usize {
-                level.grid[self.position.x + i][self.position.y + j] = Tile::Empty;
-                level.grid[position.x + i][position.y + j] = Tile::Enemy(self.id);
-            }
+        // The old and new footprints can overlap, so all of the old one is cleared first
+        for tile in self.tiles() {
+            level.grid[tile].tile = Tile::Empty;
         }
         self.position = position;
+        for tile in self.tiles() {
+            level.grid[tile].tile = Tile::Enemy(self.id);
+        }
 
         let mut tween = self.base_mut().create_tween().unwrap();
         tween.tween_property(
@@ -1260,18 +1605,66 @@ impl Enemy {
     }
 }
 
-fn damage_bonus(damage_kind: DamageKind, traits: &[Trait]) -> u16 {
-    traits
-        .iter()
-        .map(|trait_| match (damage_kind, trait_) {
-            (DamageKind::Silver, Trait::SilverVulnerable) => 1,
-            (DamageKind::Holy, Trait::HolyVulnerable) => 2,
-            (DamageKind::Stake, Trait::StakeVulnerable) => 1_000,
-            (DamageKind::Sunlight, Trait::SunlightVulnerable) => 1_000,
-            (DamageKind::Sunlight, Trait::HolyFromSunlight) => 2,
-            _ => 0,
-        })
-        .sum()
+pub type NpcId = u16;
+
+// Someone caught up in the fighting, such as a fleeing villager, who takes no orders and makes
+// for the nearest open door every round. The level is lost if they die
+#[derive(GodotClass)]
+#[class(init, base=Node2D)]
+pub struct Npc {
+    pub id: NpcId,
+    pub position: Position,
+    // Node names are taken by Godot
+    #[export]
+    pub display_name: GString,
+    #[export]
+    #[init(default = 2)]
+    pub max_health: u16,
+    #[export]
+    #[init(default = 3)]
+    pub speed: u16,
+    // Handed to the party as thanks once they get out
+    #[export]
+    pub reward: ItemKind,
+    pub vitals: Vitals,
+    base: Base<Node2D>,
+}
+
+#[godot_api]
+impl INode2D for Npc {
+    fn ready(&mut self) {
+        self.vitals = Vitals::new(self.max_health, Vec::new());
+    }
+}
+
+impl Npc {
+    pub fn name(&self) -> String {
+        self.display_name.to_string()
+    }
+
+    pub fn hit(&mut self, damage: Damage, events: &mut EventBus) -> DamageResult {
+        combat::resolve_damage(UnitId::Npc(self.id), &mut self.vitals, damage, events)
+    }
+
+    // The level has already moved them on the grid, this only walks the node there. Anyone
+    // walking out of the room is gone once they arrive
+    pub fn walk(&mut self, path: Vec<Position>, escaping: bool) {
+        let mut tween = self.base_mut().create_tween().unwrap();
+        let mut from = self.position;
+        for position in &path {
+            tween.tween_property(
+                self.base().clone().upcast(),
+                "position".into(),
+                Variant::from(position.to_vector()),
+                step_time(from, *position),
+            );
+            from = *position;
+        }
+        if escaping {
+            tween.tween_callback(Callable::from_object_method(&self.base(), "queue_free"));
+        }
+        self.position = from;
+    }
 }
 
 pub type ObstacleId = u16;
@@ -1283,6 +1676,48 @@ pub enum ObstacleKind {
     Wall,
     LowWall,
     Barrel,
+    Coffin,
+    Cell,
+    LockedDoor,
+    Chest,
+    Bookshelf,
+    Lever,
+    Portcullis,
+    Shutter,
+    // Hangs over its tiles until shot down or dropped by a lever, then lies where it fell
+    Chandelier,
+}
+
+impl ObstacleKind {
+    pub fn blocks_sight(&self) -> bool {
+        match self {
+            Self::Wall | Self::Barrel | Self::LockedDoor | Self::Bookshelf | Self::Shutter => true,
+            Self::LowWall
+            | Self::Coffin
+            | Self::Cell
+            | Self::Chest
+            | Self::Lever
+            | Self::Portcullis
+            | Self::Chandelier => false,
+        }
+    }
+
+    pub fn searchable(&self) -> bool {
+        matches!(self, Self::Chest | Self::Bookshelf)
+    }
+
+    // Opened and closed by a lever on its switch
+    pub fn switched(&self) -> bool {
+        matches!(self, Self::Portcullis | Self::Shutter)
+    }
+
+    // The ally held captive inside, if any
+    pub fn captive(&self) -> Option<AllyId> {
+        match self {
+            Self::Cell => Some(AllyId::Priest),
+            _ => None,
+        }
+    }
 }
 
 #[derive(GodotClass)]
@@ -1296,12 +1731,39 @@ pub struct Obstacle {
     pub width: u16,
     #[export]
     pub height: u16,
+    #[export]
+    pub health: u16,
+    pub blessed: bool,
+    // Turns spent working at its lock so far
+    pub picked: u16,
+    // What searching it can turn up, see Loot::from_entry
+    #[export]
+    pub loot: PackedStringArray,
+    pub searched: bool,
+    // Named by a lever to throw it, and by whatever a lever works to answer to it
+    #[export]
+    pub switch: GString,
+    // A pulled lever, raised portcullis or open shutter
+    pub open: bool,
     base: Base<Node2D>,
 }
 
 pub type ItemId = u16;
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, GodotConvert, Var, Export)]
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    GodotConvert,
+    Var,
+    Export,
+    Serialize,
+    Deserialize,
+)]
 #[godot(via = u8)]
 pub enum ItemKind {
     #[default]
@@ -1310,6 +1772,8 @@ pub enum ItemKind {
     WoodenStake,
     Garlic,
     HolyWater,
+    Key,
+    FireFlask,
 }
 
 impl ItemKind {
@@ -1320,6 +1784,46 @@ impl ItemKind {
             Self::WoodenStake => "Wooden Stake".into(),
             Self::Garlic => "Garlic".into(),
             Self::HolyWater => "Holy Water".into(),
+            Self::Key => "Key".into(),
+            Self::FireFlask => "Fire Flask".into(),
+        }
+    }
+
+    pub fn scene(&self) -> &'static str {
+        match self {
+            Self::IronBolt => "res://scenes/items/iron_bolt.tscn",
+            Self::SilverBolt => "res://scenes/items/silver_bolt.tscn",
+            Self::WoodenStake => "res://scenes/items/wooden_stake.tscn",
+            Self::Garlic => "res://scenes/items/garlic.tscn",
+            Self::HolyWater => "res://scenes/items/holy_water.tscn",
+            Self::Key => "res://scenes/items/key.tscn",
+            Self::FireFlask => "res://scenes/items/fire_flask.tscn",
+        }
+    }
+
+    // What a picked up ability is put down as
+    pub fn from_ability(ability: Ability) -> Option<Self> {
+        match ability {
+            Ability::CrossbowIronBolt => Some(Self::IronBolt),
+            Ability::CrossbowSilverBolt => Some(Self::SilverBolt),
+            Ability::WoodenStake => Some(Self::WoodenStake),
+            Ability::Garlic => Some(Self::Garlic),
+            Ability::HolyWater => Some(Self::HolyWater),
+            Ability::Key => Some(Self::Key),
+            Ability::FireFlask => Some(Self::FireFlask),
+            _ => None,
+        }
+    }
+
+    pub fn ability(&self) -> Ability {
+        match self {
+            Self::IronBolt => Ability::CrossbowIronBolt,
+            Self::SilverBolt => Ability::CrossbowSilverBolt,
+            Self::WoodenStake => Ability::WoodenStake,
+            Self::Garlic => Ability::Garlic,
+            Self::HolyWater => Ability::HolyWater,
+            Self::Key => Ability::Key,
+            Self::FireFlask => Ability::FireFlask,
         }
     }
 }
@@ -1340,13 +1844,7 @@ impl Item {
     }
 
     pub fn ability(&self) -> Ability {
-        match self.kind {
-            ItemKind::IronBolt => Ability::CrossbowIronBolt,
-            ItemKind::SilverBolt => Ability::CrossbowSilverBolt,
-            ItemKind::WoodenStake => Ability::WoodenStake,
-            ItemKind::Garlic => Ability::Garlic,
-            ItemKind::HolyWater => Ability::HolyWater,
-        }
+        self.kind.ability()
     }
 }
 
@@ -1356,6 +1854,9 @@ pub enum ProjectileKind {
     IronBolt,
     SilverBolt,
     Fireball,
+    // Thrown flasks, bursting over the tiles around where they land
+    HolyBomb,
+    FireFlask,
 }
 
 #[derive(GodotClass)]
@@ -1364,6 +1865,8 @@ pub struct Projectile {
     pub kind: ProjectileKind,
     pub start: Position,
     pub end: Position,
+    // Who fired it and with what, the hit is resolved once it arrives at the end tile
+    pub shooter: Option<(AllyId, Ability)>,
     base: Base<Sprite2D>,
 }
 
@@ -1384,6 +1887,8 @@ impl ISprite2D for Projectile {
             ProjectileKind::IronBolt => 0.0,
             ProjectileKind::SilverBolt => 16.0,
             ProjectileKind::Fireball => 32.0,
+            ProjectileKind::HolyBomb => 48.0,
+            ProjectileKind::FireFlask => 64.0,
         };
         atlas.set_region(Rect2::new(Vector2::new(x, y), Vector2::new(16.0, 16.0)));
 
@@ -1398,7 +1903,27 @@ impl ISprite2D for Projectile {
             Variant::from(end),
             0.05 * self.start.distance(self.end) as f64,
         );
-        tween.tween_callback(Callable::from_object_method(&self.base(), "queue_free"));
+        tween.tween_callback(Callable::from_object_method(&self.base(), "arrive"));
+    }
+}
+
+#[godot_api]
+impl Projectile {
+    #[func]
+    fn arrive(&mut self) {
+        if let Some((ally_id, ability)) = self.shooter.take() {
+            if let Some(mut level) = find_level(&self.base()) {
+                let mut level = level.bind_mut();
+                if level.lands_impacts() {
+                    level.commands.push(Command::Impact {
+                        ally_id,
+                        ability,
+                        position: self.end,
+                    });
+                }
+            }
+        }
+        self.base_mut().queue_free();
     }
 }
 
@@ -1418,29 +1943,12 @@ impl Projectile {
     }
 }
 
-#[derive(Debug, Clone, Copy, Default, PartialEq)]
-pub enum Tile {
-    #[default]
-    Empty,
-    Ally(AllyId),
-    Enemy(EnemyId),
-    Obstacle(ObstacleId),
-}
-
-impl Tile {
-    pub fn is_empty(&self) -> bool {
-        match self {
-            Self::Empty => true,
-            _ => false,
-        }
-    }
-}
-
-#[derive(Debug, Clone, Copy, Default, PartialEq)]
-pub enum Turn {
-    #[default]
-    Ally,
-    Enemy(usize, bool),
+pub struct PendingSwap {
+    pub ally_id: AllyId,
+    pub item_id: ItemId,
+    // Leaving the item itself first, then one use of each picked up ability the ally has
+    pub options: Vec<Option<Ability>>,
+    pub prompt: Option<Gd<CanvasLayer>>,
 }
 
 #[derive(GodotClass)]
@@ -1448,29 +1956,221 @@ pub enum Turn {
 pub struct Level {
     #[export]
     pub room: Room,
-    pub grid: [[Tile; LEVEL_HEIGHT]; LEVEL_WIDTH],
-    pub item_grid: [[Option<ItemId>; LEVEL_HEIGHT]; LEVEL_WIDTH],
+    // A second player controls the enemies on the same machine
+    #[export]
+    pub versus: bool,
+    // Whether the level is lost with Ash, rather than only once the whole party has fallen
+    #[export]
+    #[init(default = true)]
+    pub ash_must_survive: bool,
+    // Times Ash gets back up before he falls for good
+    #[export]
+    pub ash_lives: u16,
+    // Rounds the room has to be cleared in, 0 for no limit
+    #[export]
+    pub turn_limit: u16,
+    // Round the sun comes up on, burning enemies caught in the light and opening the doors. 0 for
+    // a level that ends before dawn
+    #[export]
+    pub sunrise: u16,
+    // Changes how far everyone sees or how true shots fly, for the whole level
+    #[export]
+    pub weather: Weather,
+    // Starting abilities and their uses for some allies, in place of their usual lists, as
+    // { "AshMagnum": { "WoodenStake": 1 } }. What the party carried in is added on top
+    #[export]
+    pub loadouts: Dictionary,
+    // Enemies still to arrive, by the round they are due
+    waves: Vec<(u16, EnemyKind, Position)>,
+    // Turns the allies get to see an enemy coming before it appears, 0 for at once
+    #[export]
+    #[init(default = 1)]
+    pub spawn_warning: u16,
+    pending_spawns: Vec<PendingSpawn>,
+    sunlit: HashSet<Position>,
+    // Set once lost, so a second fall in the same frame doesn't lose it again
+    defeated: bool,
+    pub modifiers: Modifiers,
+    pub choices: Choices,
+    // Name of the node marking where the party came in through a door
+    pub entrance: Option<String>,
+    // Units may step diagonally, without cutting corners
+    #[export]
+    pub diagonal_movement: bool,
+    // Sized to the tile map when the level is ready
+    pub grid: Grid<Occupancy>,
     pub turn: Turn,
+    // Ally phases begun so far, the first one included
+    pub round: u16,
     pub turn_order: Vec<(EnemyId, u16)>,
     pub spawn_queue: Vec<EnemyId>,
+    pub graves: Vec<(EnemyKind, Position)>,
+    // Every enemy that has died in the level, raised again or not
+    pub deaths: Vec<Death>,
+    dust_piles: HashMap<Position, Gd<DustPile>>,
     pub allies: HashMap<AllyId, i64>,
+    // Carrier and the ally they are carrying
+    pub carried: HashMap<AllyId, AllyId>,
+    // Shooters whose bolts haven't landed yet, they aren't done acting until they do
+    pub in_flight: HashSet<AllyId>,
+    // An item walked onto with no room for it, the turn waits until the player picks what to
+    // leave behind
+    pub swap: Option<PendingSwap>,
+    // A scripted camera move is playing and the player waits for it to finish
+    pub cinematic: bool,
+    // Burning tiles, with the round each goes out
+    pub fires: HashMap<Position, (u16, Gd<Flames>)>,
+    // Where every unit's aura reaches, rebuilt whenever units move
+    pub auras: AuraLayer,
+    aura_overlay: Option<Gd<AuraOverlay>>,
     pub inventory: HashMap<AllyId, Vec<(Ability, u16)>>,
     pub enemy_id: EnemyId,
     pub enemies: HashMap<EnemyId, i64>,
+    pub npc_id: NpcId,
+    pub npcs: HashMap<NpcId, i64>,
     pub obstacle_id: ObstacleId,
     pub obstacles: HashMap<ObstacleId, i64>,
     pub item_id: ItemId,
     pub items: HashMap<ItemId, i64>,
+    // Every tile of every door, to the door's instance
+    pub doors: HashMap<Position, i64>,
+    log: RunLog,
+    // Tiles a script has lit up for good, on top of whatever the allies can see
+    revealed: HashSet<Position>,
+    // Set by the level's script, shown under the room's own objective
+    scripted_objective: Option<String>,
+    // Benchmark reports drawn over the level, see bench::overlay
+    timings: Option<Gd<Label>>,
+    // Where each floor lies on the grid, empty for a level on one floor
+    pub floors: Vec<Area>,
+    // Every tile with a chandelier still hanging over it
+    chandeliers: HashMap<Position, ObstacleId>,
+    // Cut down during a command and landing once it has been applied, so whoever is under it is
+    // free to be hit
+    falling: Vec<ObstacleId>,
+    pub loot_seed: u64,
     pub shadows_cast: bool,
+    pub fov_cache: RefCell<FovCache>,
+    pub pathfinder: RefCell<Pathfinder>,
+    pub commands: Vec<Command>,
+    // Enemy waiting on the second player's orders
+    pub awaiting: Option<EnemyId>,
+    // Alukrod is played by a guest over the network, whose peer id is kept here
+    pub online: bool,
+    pub guest: i32,
+    pub remote_commands: Vec<Command>,
+    remote: bool,
+    pub events: EventBus,
+    pub stats: RunStats,
+    pub bestiary: Bestiary,
+    barks: Barks,
+    pub achievements: Achievements,
+    // Kinds of enemy seen so far in the level
+    sighted: HashSet<EnemyKind>,
+    pub replay: Replay,
+    pub playback: bool,
+    pub playback_step: usize,
+    pub playback_timer: f64,
+    // Playing back to the last checkpoint without pauses, after which the player takes over
+    pub catching_up: bool,
+    // Handed in by a save to catch up on, instead of the room's last replay
+    pub restored: Option<Replay>,
+    // Seconds played in earlier rooms, and when this one was entered
+    pub play_time_before: f64,
+    entered_at: f64,
+    // Index into FAST_FORWARD, kept between enemy phases
+    pub fast_forward: usize,
     base: Base<Node2D>,
 }
 
 #[godot_api]
 impl INode2D for Level {
     fn ready(&mut self) {
+        if self.playback {
+            match self.restored.take().or_else(|| Replay::load(self.room)) {
+                Some(mut replay) => {
+                    if self.catching_up {
+                        replay.commands.truncate(replay.checkpoint);
+                    }
+                    self.inventory = replay.inventory.clone();
+                    self.versus = replay.versus;
+                    self.modifiers = replay.modifiers;
+                    self.choices = replay.choices;
+                    self.entrance = replay.entrance.clone();
+                    self.loot_seed = replay.loot_seed;
+                    self.replay = replay;
+                }
+                None => {
+                    self.playback = false;
+                    self.catching_up = false;
+                }
+            }
+        } else {
+            self.replay.inventory = self.inventory.clone();
+            self.replay.versus = self.versus;
+            self.replay.modifiers = self.modifiers;
+            self.replay.choices = self.choices;
+            self.replay.entrance = self.entrance.clone();
+            self.replay.impacts = true;
+
+            // Both ends of an online game have to roll the same loot
+            self.loot_seed = match self.modifiers.seed {
+                Some(seed) => seed,
+                None if self.online => 0,
+                None => randi() as u64,
+            };
+            self.replay.loot_seed = self.loot_seed;
+        }
+
+        if self.online {
+            self.base_mut().rpc_config(
+                "receive_command".into(),
+                rpc_config(RpcMode::ANY_PEER, false),
+            );
+            self.base_mut()
+                .rpc_config("sync_command".into(), rpc_config(RpcMode::AUTHORITY, false));
+            self.base_mut().rpc_config(
+                "command_rejected".into(),
+                rpc_config(RpcMode::AUTHORITY, false),
+            );
+        }
+
+        let tile_map = self.base().get_node_as::<TileMap>("MapLayer/TileMap");
+        let used = tile_map.get_used_rect();
+        let width = (used.position.x + used.size.x).max(0) as usize;
+        let height = (used.position.y + used.size.y).max(0) as usize;
+        self.grid = Grid::new(width, height, Occupancy::default());
+        self.round = 1;
+        self.entered_at = save::now();
+        self.bestiary = Bestiary::load();
+        self.achievements = Achievements::load();
+        settings::set_time_scale(self.phase_speed());
+
+        let mut cursor = self.base().get_node_as::<Cursor>("CursorLayer/Cursor");
+        cursor.bind_mut().bounds = (width, height);
+
+        // Coming in through a door puts the party on the markers it names, in order
+        let arrival: Vec<Vector2> = self
+            .entrance
+            .as_ref()
+            .and_then(|name| nodes::try_node::<Node2D>(&self.base(), name))
+            .map(|spawn| {
+                spawn
+                    .get_children()
+                    .iter_shared()
+                    .filter_map(|child| child.try_cast::<Node2D>().ok())
+                    .map(|marker| spawn.get_position() + marker.get_position())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let loadouts = self.loadouts();
         let allies = self.base().get_node_as::<Node2D>("UnitLayer/Allies");
-        for child in allies.get_children().iter_shared() {
+        for (index, child) in allies.get_children().iter_shared().enumerate() {
             let mut ally_node: Gd<Ally> = child.cast();
+            if let Some(arrival) = arrival.get(index) {
+                ally_node.set_position(*arrival);
+            }
             let instance_id = ally_node.instance_id();
             let position = Position::from_vector(ally_node.get_position());
 
@@ -1478,8 +2178,14 @@ impl INode2D for Level {
             self.allies.insert(ally.id, instance_id.to_i64());
 
             ally.position = position;
-            self.grid[position.x][position.y] = Tile::Ally(ally.id);
+            self.grid[position].tile = Tile::Ally(ally.id);
 
+            if let Some(loadout) = loadouts.get(&ally.id) {
+                ally.abilities = loadout.iter().map(|(ability, _)| *ability).collect();
+                ally.uses = loadout.iter().copied().collect();
+                // The scene may have picked an ability further down the usual list
+                ally.selected_ability = ally.selected_ability.min(ally.abilities.len() - 1);
+            }
             for (ability, uses) in self.inventory.get(&ally.id).unwrap_or(&Vec::new()) {
                 let stats = abilities().get(&ability).unwrap();
                 if stats.persistent {
@@ -1515,13 +2221,19 @@ impl INode2D for Level {
                             .set_visible(false);
                     }
                 }
+                AllyId::Priest => (),
             }
         }
         self.inventory.clear();
 
         let enemies = self.base().get_node_as::<Node2D>("UnitLayer/Enemies");
         let mut turn_order = Vec::new();
-        for child in enemies.get_children().iter_shared() {
+        for mut child in enemies.get_children().iter_shared() {
+            if self.choices.approach == Approach::Sneak && child.is_in_group(ALERTED_GROUP.into()) {
+                child.queue_free();
+                continue;
+            }
+
             let mut enemy: Gd<Enemy> = child.cast();
             let position = enemy.get_position();
             let position = Position::from_vector(position);
@@ -1530,10 +2242,11 @@ impl INode2D for Level {
 
             let mut enemy = enemy.bind_mut();
             enemy.position = position;
+            self.modifiers.upgrade(&mut enemy);
 
             for i in 0..enemy.width as usize {
                 for j in 0..enemy.height as usize {
-                    self.grid[position.x + i][position.y + j] = Tile::Enemy(self.enemy_id);
+                    self.grid[position.offset(i, j)].tile = Tile::Enemy(self.enemy_id);
                 }
             }
 
@@ -1546,6 +2259,21 @@ impl INode2D for Level {
         turn_order.sort_by(|(_, a_speed), (_, b_speed)| a_speed.cmp(b_speed).reverse());
         self.turn_order = turn_order;
 
+        // Only levels with someone to escort have the layer
+        if let Some(npcs) = self.base().try_get_node_as::<Node2D>("UnitLayer/Npcs") {
+            for child in npcs.get_children().iter_shared() {
+                let mut npc: Gd<Npc> = child.cast();
+                let position = Position::from_vector(npc.get_position());
+                self.npcs.insert(self.npc_id, npc.instance_id().to_i64());
+
+                let mut npc = npc.bind_mut();
+                npc.position = position;
+                npc.id = self.npc_id;
+                self.grid[position].tile = Tile::Npc(self.npc_id);
+                self.npc_id += 1;
+            }
+        }
+
         let obstacles = self.base().get_node_as::<CanvasLayer>("ObstacleLayer");
         for child in obstacles.get_children().iter_shared() {
             let mut obstacle: Gd<Obstacle> = child.cast();
@@ -1558,9 +2286,15 @@ impl INode2D for Level {
 
             for i in 0..obstacle.width as usize {
                 for j in 0..obstacle.height as usize {
-                    if position.x + i < LEVEL_WIDTH && position.y + j < LEVEL_HEIGHT {
-                        self.grid[position.x + i][position.y + j] =
-                            Tile::Obstacle(self.obstacle_id);
+                    if !self.grid.contains(position.offset(i, j)) {
+                        continue;
+                    }
+                    // Anyone can walk beneath a chandelier until it falls
+                    if obstacle.kind == ObstacleKind::Chandelier {
+                        self.chandeliers
+                            .insert(position.offset(i, j), self.obstacle_id);
+                    } else {
+                        self.grid[position.offset(i, j)].tile = Tile::Obstacle(self.obstacle_id);
                     }
                 }
             }
@@ -1569,141 +2303,287 @@ impl INode2D for Level {
             self.obstacle_id += 1;
         }
 
+        let doors = self.base().get_node_as::<CanvasLayer>("DoorLayer");
+        for child in doors.get_children().iter_shared() {
+            // The layer also holds the art around the doors
+            let Ok(mut door) = child.try_cast::<Door>() else {
+                continue;
+            };
+            let position = Position::from_vector(door.get_position());
+            let instance_id = door.instance_id().to_i64();
+
+            let mut door = door.bind_mut();
+            door.position = position;
+            for tile in door.tiles() {
+                self.doors.insert(tile, instance_id);
+            }
+        }
+
+        // Only levels on several floors have the layers
+        if let Some(floors) = self.base().try_get_node_as::<CanvasLayer>("FloorLayer") {
+            for child in floors.get_children().iter_shared() {
+                let floor: Gd<Floor> = child.cast();
+                let origin = Position::from_vector(floor.get_position());
+                let floor = floor.bind();
+                self.floors.push(Area {
+                    origin,
+                    dimensions: (floor.width as usize, floor.height as usize),
+                });
+            }
+        }
+        if let Some(stairs) = self.base().try_get_node_as::<CanvasLayer>("StairLayer") {
+            for child in stairs.get_children().iter_shared() {
+                let from: Gd<Stairs> = child.cast();
+                let leads_to = from.bind().leads_to.to_string();
+                // Missing ends are reported by try_node
+                let Some(to) = nodes::try_node::<Stairs>(&stairs, &leads_to) else {
+                    continue;
+                };
+
+                let from = Position::from_vector(from.get_position());
+                let to = Position::from_vector(to.get_position());
+                if self.grid.contains(from) && self.grid.contains(to) {
+                    self.grid[from].stairs = Some(to);
+                    self.grid[to].stairs = Some(from);
+                }
+            }
+        }
+
+        // Only levels to be survived have the layers
+        if let Some(waves) = self.base().try_get_node_as::<CanvasLayer>("WaveLayer") {
+            for child in waves.get_children().iter_shared() {
+                let wave: Gd<Wave> = child.cast();
+                let position = Position::from_vector(wave.get_position());
+                let wave = wave.bind();
+                self.waves.push((wave.round, wave.kind, position));
+            }
+        }
+        if let Some(mut sun) = self.base().try_get_node_as::<CanvasLayer>("SunLayer") {
+            for child in sun.get_children().iter_shared() {
+                let sunlight: Gd<Sunlight> = child.cast();
+                let position = Position::from_vector(sunlight.get_position());
+                self.sunlit.extend(sunlight.bind().tiles(position));
+            }
+            sun.set_visible(false);
+        }
+        if let Some(lights) = self.base().try_get_node_as::<CanvasLayer>("LightLayer") {
+            for child in lights.get_children().iter_shared() {
+                child.cast::<Sunlight>().set_visible(false);
+            }
+        }
+
         let items = self.base().get_node_as::<CanvasLayer>("ItemLayer");
-        for child in items.get_children().iter_shared() {
+        for (index, mut child) in items.get_children().iter_shared().enumerate() {
+            if !self.modifiers.keeps_item(index) {
+                child.queue_free();
+                continue;
+            }
+
             let mut item: Gd<Item> = child.cast();
             let position = Position::from_vector(item.get_position());
             self.items.insert(self.item_id, item.instance_id().to_i64());
 
             let mut item = item.bind_mut();
             item.position = position;
-            self.item_grid[position.x][position.y] = Some(self.item_id);
+            self.grid[position].item = Some(self.item_id);
+            if item.kind == ItemKind::Garlic {
+                self.add_terrain(position, Terrain::Garlic);
+            }
 
             item.id = self.item_id;
             self.item_id += 1;
         }
 
-        let mut dialogue = self.base().get_node_as::<Dialogue>("Dialogue");
-        let mut dialogue = dialogue.bind_mut();
-        dialogue.push_event(DialogueEvent::LevelReady);
+        let cursor = self.base().get_node_as::<Cursor>("CursorLayer/Cursor");
+        let ability_bar = self.base().get_node_as::<AbilityBar>("UILayer/AbilityBar");
+        let dialogue = self.base().get_node_as::<Dialogue>("Dialogue");
+        let sound_effects = self
+            .base()
+            .get_node_as::<SoundEffects>("/root/SoundEffects");
+        let music = self.base().get_node_as::<Music>("/root/Music");
+        let juice = self.base().get_node_as::<Juice>("/root/Juice");
+        self.events.subscribe(cursor);
+        self.events.subscribe(ability_bar);
+        self.events.subscribe(dialogue);
+        self.events.subscribe(sound_effects);
+        self.events.subscribe(music);
+        self.events.subscribe(juice);
+
+        // Timelines in this room can branch on what was chosen in earlier ones
+        if let Some(dialogic) = nodes::dialogic(&self.base()) {
+            let mut variables = dialogic.get("VAR".into()).to::<Gd<Object>>();
+            for (name, value) in self.choices.variables() {
+                variables.call("set_variable".into(), &[name.to_variant(), value]);
+            }
+        }
+
+        if let Some(mut end_turn) = nodes::end_turn_button(&self.base()) {
+            end_turn.connect(
+                "pressed".into(),
+                Callable::from_object_method(&self.base(), "end_turn_pressed"),
+            );
+        }
+
+        // A run starts with no time played, and replays are never logged
+        if !self.playback {
+            self.log = RunLog::open(save::slot(), self.play_time_before == 0.0);
+        }
+        self.log(Entry::RoomEntered { room: self.room });
+
+        // Entering a room is autosaved, as is the start of each ally phase
+        self.autosave();
+        if bench::enabled() {
+            self.start_benchmark();
+        }
+        self.events.publish(GameEvent::LevelReady);
     }
 
-    fn process(&mut self, _delta: f64) {
-        let dialogue = self.base().get_node_as::<Dialogue>("Dialogue");
+    fn exit_tree(&mut self) {
+        Engine::singleton().set_time_scale(1.0);
+
+        // Benchmark bats were never in the room to be replayed
+        if !self.playback && !bench::enabled() {
+            self.replay.save(self.room);
+            self.achievements.save();
+        }
+    }
+
+    fn process(&mut self, delta: f64) {
+        if let Some(report) = bench::end_frame() {
+            godot_print!("{}", report);
+            if let Some(timings) = &mut self.timings {
+                timings.set_text(report.into());
+            }
+        }
+
+        // Commands queued by units mid-animation are applied once nothing else is bound
+        for command in mem::take(&mut self.commands) {
+            self.apply(command);
+        }
+
+        // Commands from the other player. The host sends the guest's orders back once applied,
+        // or tells the guest they were turned down so its cursor isn't left waiting on them
+        self.remote = true;
+        for command in mem::take(&mut self.remote_commands) {
+            if !self.apply(command) && self.is_host() {
+                let guest = self.guest as i64;
+                self.base_mut()
+                    .rpc_id(guest, "command_rejected".into(), &[]);
+            }
+        }
+        self.remote = false;
+
+        self.publish_effect_changes();
+        self.dispatch_events();
+        self.mark_unacted_allies();
+
+        let Some(dialogue) = nodes::dialogue(&self.base()) else {
+            return;
+        };
         let dialogue = dialogue.bind();
 
+        if let Some(mut end_turn) = nodes::end_turn_button(&self.base()) {
+            end_turn.set_disabled(dialogue.active || !self.can_end_turn());
+        }
+
         if !dialogue.active {
             match self.turn {
                 Turn::Ally => {
                     if !self.shadows_cast {
-                        self.cast_shadows();
+                        bench::time(Stage::Shadows, || self.cast_shadows());
                         self.shadows_cast = true;
                     }
 
                     let input = Input::singleton();
-                    if input.is_action_just_pressed("skip".into()) {
-                        self.turn = Turn::Enemy(0, false);
-                    } else {
+                    if self.playback {
+                        self.play_next(delta);
+                    } else if !self.can_end_turn() {
+                        // The host decides when the turn ends
+                    } else if input.is_action_just_pressed("skip".into()) {
+                        self.apply(Command::EndTurn);
+                    } else if settings::auto_end_turn() {
+                        // Allies that can't be found are counted as done so the turn still ends
                         let all_acted = self.allies.keys().all(|ally_id| {
-                            let ally = self.get_ally(*ally_id);
-                            let ally = ally.bind();
-                            ally.has_acted
+                            self.try_get_ally(*ally_id)
+                                .map_or(true, |ally| ally.bind().has_acted)
                         });
                         if all_acted {
-                            self.turn = Turn::Enemy(0, false);
+                            self.apply(Command::EndTurn);
                         }
                     }
                 }
+                Turn::Npc => {
+                    self.run_hooks(Boundary::AllyEnd);
+                    self.move_npcs();
+                    self.turn = Turn::Enemy(0, false);
+                }
                 Turn::Enemy(i, waiting) => {
+                    // Speeding up the engine covers tweens, animations and projectiles alike
+                    if Input::singleton().is_action_just_pressed("fast_forward".into()) {
+                        self.fast_forward = (self.fast_forward + 1) % FAST_FORWARD.len();
+                        settings::set_time_scale(self.phase_speed());
+                    }
+
                     if waiting {
                         if i < self.turn_order.len() {
-                            let cursor = self.base().get_node_as::<Cursor>("CursorLayer/Cursor");
-                            let mut camera = cursor.get_node_as::<Camera2D>("Camera");
+                            let Some(mut cursor) = nodes::cursor(&self.base()) else {
+                                return;
+                            };
 
                             let (enemy_id, _) = self.turn_order[i];
-                            let enemy = self.get_enemy(enemy_id);
+                            let Some(enemy) = self.try_get_enemy(enemy_id) else {
+                                self.turn = Turn::Enemy(i + 1, false);
+                                return;
+                            };
 
-                            camera.set_position_smoothing_enabled(true);
-                            camera.set_position_smoothing_speed(8.0);
-                            camera.set_position(enemy.get_position() - cursor.get_position());
+                            cursor.bind_mut().focus = Some(enemy.get_position());
                         }
                     } else {
-                        if i == 0 {
-                            let mut cursor =
-                                self.base().get_node_as::<Cursor>("CursorLayer/Cursor");
-                            let mut cursor = cursor.bind_mut();
-                            cursor.acting = false;
-                            cursor.selected = None;
-
-                            let path = self.base().get_node_as::<Path>("PathLayer/Path");
-                            let path = path.bind();
-                            path.clear_path();
-
-                            let mut info_panel =
-                                self.base().get_node_as::<InfoPanel>("UILayer/InfoPanel");
-                            let mut info_panel = info_panel.bind_mut();
-                            info_panel.deselect_tile();
-                            info_panel.deselect_ability(self);
-
-                            let mut ability_bar =
-                                self.base().get_node_as::<AbilityBar>("UILayer/AbilityBar");
-                            let mut ability_bar = ability_bar.bind_mut();
-                            ability_bar.select_none();
-
-                            for enemy_id in self.enemies.keys() {
-                                let mut enemy = self.get_enemy(*enemy_id);
-                                let mut enemy = enemy.bind_mut();
-                                for (effect, mut stats) in enemy.effects.clone() {
-                                    match effect {
-                                        Effect::Burn => {
-                                            enemy.hit(stats.magnitude, DamageKind::Normal)
-                                        }
-                                        _ => (),
-                                    }
-                                    stats.duration -= 1;
-                                    if stats.duration == 0 {
-                                        enemy.effects.remove(&effect);
-                                    } else {
-                                        enemy.effects.insert(effect, stats);
-                                    }
-                                }
+                        if i == 0 && self.awaiting.is_none() {
+                            // The turn still changes hands if any of the UI is missing
+                            if let Some(mut cursor) = nodes::cursor(&self.base()) {
+                                cursor.bind_mut().mode = CursorMode::Browse;
+                            }
+
+                            if let Some(path) = nodes::path_node(&self.base()) {
+                                path.bind().clear_path();
+                            }
+
+                            if let Some(mut info_panel) = nodes::info_panel(&self.base()) {
+                                let mut info_panel = info_panel.bind_mut();
+                                info_panel.deselect_tile();
+                                info_panel.deselect_ability(self);
+                            }
+
+                            if let Some(mut ability_bar) = nodes::ability_bar(&self.base()) {
+                                ability_bar.bind_mut().select_none();
                             }
+
+                            self.run_hooks(Boundary::EnemyStart);
+
+                            settings::set_time_scale(self.phase_speed());
+                            self.events.publish(GameEvent::EnemyTurnStarted);
                         }
 
                         if i < self.turn_order.len() {
+                            // Whoever moved last may have carried an aura with them
+                            self.refresh_auras();
+
                             let (enemy_id, _) = self.turn_order[i];
-                            let mut enemy = self.get_enemy(enemy_id);
+                            // A missing enemy forfeits its turn rather than stalling the round
+                            let Some(mut enemy) = self.try_get_enemy(enemy_id) else {
+                                self.turn = Turn::Enemy(i + 1, false);
+                                return;
+                            };
                             let mut enemy = enemy.bind_mut();
-                            match enemy.animation.as_str() {
-                                "side_death" | "front_death" | "back_death" => (),
-                                _ => {
-                                    let (path, ability) = enemy.plan(self);
-
-                                    if let Some(path) = path {
-                                        let position = *path.last().unwrap();
-
-                                        // Remove enemy from previous positions and add to new position
-                                        for i in 0..enemy.width as usize {
-                                            for j in 0..enemy.height as usize {
-                                                self.grid[enemy.position.x + i]
-                                                    [enemy.position.y + j] = Tile::Empty;
-                                            }
-                                        }
-
-                                        for i in 0..enemy.width as usize {
-                                            for j in 0..enemy.height as usize {
-                                                self.grid[position.x + i][position.y + j] =
-                                                    Tile::Enemy(enemy_id);
-                                            }
-                                        }
-
-                                        enemy.current_ability = ability;
-                                        enemy.follow_path(path);
-
-                                        self.turn = Turn::Enemy(i, true);
-                                    } else {
-                                        self.turn = Turn::Enemy(i + 1, false);
-                                    }
+                            // A dying enemy is taken out of the turn order once its death plays
+                            if !enemy.animator.is_dying() {
+                                // The second player steers enemies with the cursor in versus mode
+                                if self.versus {
+                                    self.awaiting = Some(enemy_id);
+                                } else {
+                                    let plan = enemy.plan(self);
+                                    self.follow_plan(i, &mut enemy, plan);
                                 }
                             }
                         } else {
@@ -1711,59 +2591,72 @@ impl INode2D for Level {
                             self.shadows_cast = false;
 
                             for ally_id in self.allies.keys() {
-                                let mut ally = self.get_ally(*ally_id);
+                                let Some(mut ally) = self.try_get_ally(*ally_id) else {
+                                    continue;
+                                };
                                 let mut ally = ally.bind_mut();
-                                ally.has_moved = false;
-                                ally.has_acted = false;
-
-                                for (effect, mut stats) in ally.effects.clone() {
-                                    match effect {
-                                        Effect::Burn => {
-                                            ally.hit(stats.magnitude, DamageKind::Normal)
-                                        }
-                                        _ => (),
-                                    }
-                                    stats.duration -= 1;
-                                    if stats.duration == 0 {
-                                        ally.effects.remove(&effect);
-                                    } else {
-                                        ally.effects.insert(effect, stats);
-                                    }
-                                }
+                                // Carried allies sit out until they are dropped
+                                ally.has_moved = self.is_carried(*ally_id);
+                                ally.has_acted = self.is_carried(*ally_id);
+                                ally.has_traded = false;
+                                ally.bolts_fired = 0;
 
                                 match ally.id {
                                     AllyId::AshMagnum => {
-                                        let mut cursor =
-                                            self.base().get_node_as::<Cursor>("CursorLayer/Cursor");
-                                        cursor.set_position(
-                                            ally.position.to_vector() + Vector2::new(8.0, 8.0),
-                                        );
-                                        let mut cursor = cursor.bind_mut();
-                                        cursor.position = ally.position;
+                                        if let Some(mut cursor) = nodes::cursor(&self.base()) {
+                                            cursor.set_position(
+                                                ally.position.to_vector() + Vector2::new(8.0, 8.0),
+                                            );
+                                            cursor.bind_mut().position = ally.position;
+                                        }
                                     }
                                     _ => (),
                                 }
                             }
 
-                            let path = self.base().get_node_as::<Path>("PathLayer/Path");
-                            let path = path.bind();
-                            path.clear_path();
+                            if let Some(path) = nodes::path_node(&self.base()) {
+                                path.bind().clear_path();
+                            }
+
+                            // The camera eases back to the cursor from the last enemy
+                            if let Some(mut cursor) = nodes::cursor(&self.base()) {
+                                cursor.bind_mut().focus = None;
+                            }
 
-                            let mut camera = self
-                                .base()
-                                .get_node_as::<Camera2D>("CursorLayer/Cursor/Camera");
-                            camera.set_position_smoothing_enabled(false);
-                            camera.set_position(Vector2::default());
+                            self.run_hooks(Boundary::EnemyEnd);
 
+                            // Arrivals for the coming round are in its turn order
+                            self.arrive(self.round + 1);
+                            self.spawn_waves(self.round + 1);
                             for enemy_id in &self.spawn_queue {
-                                let enemy = self.get_enemy(*enemy_id);
-                                let enemy = enemy.bind();
-                                self.turn_order.push((*enemy_id, enemy.speed));
+                                if let Some(enemy) = self.try_get_enemy(*enemy_id) {
+                                    self.turn_order.push((*enemy_id, enemy.bind().speed));
+                                }
                             }
                             self.turn_order.sort_by(|(_, a_speed), (_, b_speed)| {
                                 a_speed.cmp(b_speed).reverse()
                             });
                             self.spawn_queue.clear();
+
+                            settings::set_time_scale(self.phase_speed());
+                            self.round += 1;
+                            if self.check_defeat(None) {
+                                return;
+                            }
+                            if self.round == self.sunrise {
+                                self.dawn();
+                            }
+                            self.run_hooks(Boundary::AllyStart);
+                            if self.round % CHECKPOINT_ROUNDS == 0 && !self.playback {
+                                self.replay.checkpoint = self.replay.commands.len();
+                                self.replay.save(self.room);
+                            }
+                            self.autosave();
+                            self.events.publish(GameEvent::AllyTurnStarted);
+                        }
+
+                        if self.playback && self.awaiting.is_some() {
+                            self.play_next(delta);
                         }
                     }
                 }
@@ -1772,243 +2665,3523 @@ impl INode2D for Level {
     }
 }
 
+#[godot_api]
 impl Level {
-    pub fn at(&self, position: Position) -> Tile {
-        self.grid[position.x][position.y]
-    }
+    // Units are the ally, enemy or escorted npc node. Damage kinds and abilities go by the names
+    // they have in mod packs, sides by those query_unit gives
+    #[signal]
+    fn unit_damaged(unit: Gd<Node2D>, damage: u16, damage_kind: GString);
 
-    pub fn item_at(&self, position: Position) -> Option<ItemId> {
-        self.item_grid[position.x][position.y]
-    }
+    #[signal]
+    fn unit_died(unit: Gd<Node2D>);
 
-    pub fn get_ally(&self, ally_id: AllyId) -> Gd<Ally> {
-        let instance_id = *self.allies.get(&ally_id).unwrap();
-        instance_from_id(instance_id).unwrap().cast()
+    #[signal]
+    fn turn_changed(round: u16, side: GString);
+
+    #[signal]
+    fn item_picked_up(ally: Gd<Node2D>, item_kind: ItemKind);
+
+    #[signal]
+    fn ability_used(unit: Gd<Node2D>, ability: GString);
+
+    // Turn ownership is enforced here, clients may only order their own allies
+    #[func]
+    fn receive_command(&mut self, json: GString) {
+        let sender = self
+            .base()
+            .get_multiplayer()
+            .unwrap()
+            .get_remote_sender_id();
+        match serde_json::from_str::<Command>(&json.to_string()) {
+            Ok(command)
+                if command
+                    .ordered_ally()
+                    .is_some_and(|ally_id| self.owner(ally_id) == sender) =>
+            {
+                self.remote_commands.push(command)
+            }
+            _ => godot_warn!("Rejected command from peer {}: {}", sender, json),
+        }
     }
 
-    pub fn get_enemy(&self, enemy_id: EnemyId) -> Gd<Enemy> {
-        let instance_id = *self.enemies.get(&enemy_id).unwrap();
-        instance_from_id(instance_id).unwrap().cast()
+    #[func]
+    fn sync_command(&mut self, json: GString) {
+        if let Ok(command) = serde_json::from_str(&json.to_string()) {
+            self.remote_commands.push(command);
+        }
     }
 
-    pub fn get_obstacle(&self, obstacle_id: ObstacleId) -> Gd<Obstacle> {
-        let instance_id = *self.obstacles.get(&obstacle_id).unwrap();
-        instance_from_id(instance_id).unwrap().cast()
+    #[func]
+    fn command_rejected(&mut self) {
+        godot_warn!("The host turned down the last order");
+        if let Some(mut cursor) = nodes::cursor(&self.base()) {
+            cursor.bind_mut().can_interact = true;
+        }
     }
 
-    pub fn get_item(&self, item_id: ItemId) -> Gd<Item> {
-        let instance_id = *self.items.get(&item_id).unwrap();
-        instance_from_id(instance_id).unwrap().cast()
+    // For GDScript and timelines, a single shot of a tile before the camera comes back
+    #[func]
+    pub fn pan_camera_to(&mut self, x: i64, y: i64, zoom: f32, travel: f64, hold: f64) {
+        self.play_shots(&[Shot {
+            target: Position {
+                x: x as usize,
+                y: y as usize,
+            },
+            zoom,
+            travel,
+            hold,
+        }]);
     }
 
-    pub fn cast_shadows(&self) {
-        let mut visible = HashSet::new();
-        for ally_id in self.allies.keys() {
-            let ally = self.get_ally(*ally_id);
-            let ally = ally.bind();
-            visible.extend(compute_fov(ally.position, ally.view_distance, self));
+    // Tweens the cursor's camera through the shots and back, input waits until it is done
+    pub fn play_shots(&mut self, shots: &[Shot]) {
+        if shots.is_empty() || self.catching_up {
+            return;
         }
+        let (Some(cursor), Some(mut camera)) = (
+            nodes::cursor(&self.base()),
+            nodes::try_node::<Camera2D>(&self.base(), "CursorLayer/Cursor/Camera"),
+        ) else {
+            return;
+        };
+        let Some(mut tween) = self.base_mut().create_tween() else {
+            return;
+        };
 
-        for ally_id in self.allies.keys() {
-            let mut ally = self.get_ally(*ally_id);
-            let position = ally.bind().position;
-            ally.set_visible(visible.contains(&position));
+        let zoom = camera.get_zoom();
+        // The camera sits under the cursor, so tiles are looked at relative to it
+        let offset = cursor.get_position() - Vector2::new(TILE_SIZE, TILE_SIZE) / 2.0;
+        for shot in shots {
+            tween.tween_property(
+                camera.clone().upcast(),
+                "position".into(),
+                Variant::from(shot.target.to_vector() - offset),
+                shot.travel,
+            );
+            tween.parallel();
+            tween.tween_property(
+                camera.clone().upcast(),
+                "zoom".into(),
+                Variant::from(zoom * shot.zoom),
+                shot.travel,
+            );
+            tween.tween_interval(shot.hold);
         }
+        tween.tween_property(
+            camera.clone().upcast(),
+            "position".into(),
+            Variant::from(Vector2::ZERO),
+            RETURN_TIME,
+        );
+        tween.parallel();
+        tween.tween_property(
+            camera.clone().upcast(),
+            "zoom".into(),
+            Variant::from(zoom),
+            RETURN_TIME,
+        );
+        tween.tween_callback(Callable::from_object_method(
+            &self.base(),
+            "_on_cinematic_finished",
+        ));
+        self.cinematic = true;
+    }
 
-        for enemy_id in self.enemies.keys() {
-            let mut enemy = self.get_enemy(*enemy_id);
-            let position = enemy.bind().position;
-            enemy.set_visible(visible.contains(&position));
+    #[func]
+    fn _on_cinematic_finished(&mut self) {
+        self.cinematic = false;
+    }
+
+    #[func]
+    fn _on_swap_chosen(&mut self, index: i64) {
+        let Some(swap) = &self.swap else {
+            return;
+        };
+        let command = Command::Swap {
+            ally_id: swap.ally_id,
+            item_id: swap.item_id,
+            ability: swap.options[index as usize],
+        };
+        self.commands.push(command);
+    }
+
+    // The button does what the skip key does, the turn ends with the next frame's commands
+    #[func]
+    fn end_turn_pressed(&mut self) {
+        let dialogue_active = nodes::dialogue(&self.base()).is_some_and(|d| d.bind().active);
+        if self.can_end_turn() && !dialogue_active {
+            self.commands.push(Command::EndTurn);
         }
+    }
 
-        for obstacle_id in self.obstacles.keys() {
-            let mut obstacle = self.get_obstacle(*obstacle_id);
-            let position = obstacle.bind().position;
-            obstacle.set_visible(visible.contains(&position));
+    /*
+     * What a script attached to a level scene can do for its set pieces, by tile. Scripts run
+     * again when the level is replayed, so they should only act on what the level tells them and
+     * never on a timer or the player's input.
+     */
+
+    // Whether there was room for it
+    #[func(rename = spawn_enemy)]
+    fn script_spawn_enemy(&mut self, kind: EnemyKind, tile: Vector2i) -> bool {
+        match self.tile(tile) {
+            Some(position) if has_room(position, kind.dimensions(), &self.grid) => {
+                self.apply(Command::Spawn {
+                    enemy_kind: kind,
+                    position,
+                })
+            }
+            _ => false,
         }
+    }
 
-        for item_id in self.items.keys() {
-            let mut item = self.get_item(*item_id);
-            let position = item.bind().position;
-            item.set_visible(visible.contains(&position));
+    // Only one item fits on a tile
+    #[func(rename = spawn_item)]
+    fn script_spawn_item(&mut self, kind: ItemKind, tile: Vector2i) -> bool {
+        match self.tile(tile) {
+            Some(position) if self.item_at(position).is_none() => {
+                self.spawn_item(kind, position);
+                true
+            }
+            _ => false,
         }
+    }
 
-        let mut shadow_map = self
-            .base()
-            .get_node_as::<ShadowMap>("ShadowLayer/ShadowMap");
-        let mut shadow_map = shadow_map.bind_mut();
-        shadow_map.cast_shadows(visible);
+    // Damage kinds are named as in ability descriptions' data, such as "Holy". Whether anyone
+    // was there to be hit
+    #[func]
+    fn apply_damage(&mut self, tile: Vector2i, amount: u16, kind: GString) -> bool {
+        let Ok(kind) = serde_json::from_str::<DamageKind>(&format!("\"{}\"", kind)) else {
+            godot_error!("No damage kind {}", kind);
+            return false;
+        };
+        let unit = match self.tile(tile).map(|position| self.at(position)) {
+            Some(Tile::Ally(ally_id)) => UnitId::Ally(ally_id),
+            Some(Tile::Enemy(enemy_id)) => UnitId::Enemy(enemy_id),
+            Some(Tile::Npc(npc_id)) => UnitId::Npc(npc_id),
+            _ => return false,
+        };
+
+        self.hit_unit(
+            unit,
+            Damage {
+                amount,
+                kind,
+                source: None,
+                ability: None,
+            },
+            "didn't make it out",
+        );
+        true
     }
 
-    pub fn move_ally(&mut self, ally_id: AllyId, position: Position) -> bool {
-        let mut ally = self.get_ally(ally_id);
-        let mut ally = ally.bind_mut();
-        if !ally.has_moved {
-            match pathfind(
-                ally.position,
-                position,
-                self.grid,
-                Tile::Ally(ally.id),
-                (1, 1),
-            ) {
-                Some(path) if !path.is_empty() && path.len() as u16 <= ally.speed => {
-                    self.grid[ally.position.x][ally.position.y] = Tile::Empty;
-                    ally.follow_path(path);
-                    return true;
+    // Stays in sight for the rest of the level
+    #[func]
+    fn reveal_region(&mut self, tile: Vector2i, width: u16, height: u16) {
+        let Some(origin) = self.tile(tile) else {
+            return;
+        };
+        for i in 0..width as usize {
+            for j in 0..height as usize {
+                if self.grid.contains(origin.offset(i, j)) {
+                    self.revealed.insert(origin.offset(i, j));
                 }
-                _ => (),
             }
         }
-        false
+        self.shadows_cast = false;
     }
 
-    pub fn use_ability(
-        &mut self,
-        ally_id: AllyId,
-        position: Position,
-        enemy_id: Option<EnemyId>,
-    ) -> bool {
-        let mut ally = self.get_ally(ally_id);
-        let mut ally = ally.bind_mut();
+    // Left empty to take it down again
+    #[func]
+    fn set_objective(&mut self, text: GString) {
+        let text = text.to_string();
+        self.scripted_objective = Some(text).filter(|text| !text.is_empty());
+    }
 
-        // Mist form prevents allies from using abilities
-        if !ally.has_acted && !ally.effects.contains_key(&Effect::Mist) {
-            let stats = abilities().get(ally.current_ability()).unwrap();
-            match stats.action {
-                Action::Attack {
-                    damage_kind,
-                    damage,
-                    ..
-                }
-                | Action::Push {
-                    damage_kind,
-                    damage,
-                    ..
-                } => {
-                    if let Some(enemy_id) = enemy_id {
-                        let mut enemy_ids = HashSet::new();
-                        enemy_ids.insert(enemy_id);
-
-                        // AOE attacks also attack adjacent spaces
-                        match stats.action {
-                            Action::Attack { aoe, .. } if aoe => {
-                                for position in position.adjacent() {
-                                    match self.grid[position.x][position.y] {
-                                        Tile::Enemy(id) => {
-                                            enemy_ids.insert(id);
-                                        }
-                                        _ => (),
-                                    }
-                                }
-                            }
-                            _ => (),
-                        };
+    // Side, id, name, health and max health of whoever is on the tile, or empty for nobody
+    #[func]
+    fn query_unit(&self, tile: Vector2i) -> Dictionary {
+        let mut unit = Dictionary::new();
+        match self.tile(tile).map(|position| self.at(position)) {
+            Some(Tile::Ally(ally_id)) => {
+                let ally = self.get_ally(ally_id);
+                let ally = ally.bind();
+                unit.set("side", "ally");
+                unit.set("id", ally_id);
+                unit.set("name", ally.name());
+                unit.set("health", ally.vitals.health);
+                unit.set("max_health", ally.max_health);
+            }
+            Some(Tile::Enemy(enemy_id)) => {
+                let enemy = self.get_enemy(enemy_id);
+                let enemy = enemy.bind();
+                unit.set("side", "enemy");
+                unit.set("id", enemy_id);
+                unit.set("name", enemy.kind.name());
+                unit.set("health", enemy.vitals.health);
+                unit.set("max_health", enemy.max_health);
+            }
+            Some(Tile::Npc(npc_id)) => {
+                let npc = self.get_npc(npc_id);
+                let npc = npc.bind();
+                unit.set("side", "npc");
+                unit.set("id", npc_id);
+                unit.set("name", npc.name());
+                unit.set("health", npc.vitals.health);
+                unit.set("max_health", npc.max_health);
+            }
+            _ => (),
+        }
+        unit
+    }
+}
 
-                        for enemy_id in enemy_ids {
-                            let mut enemy = self.get_enemy(enemy_id);
-                            let mut enemy = enemy.bind_mut();
-                            for i in 0..enemy.width as usize {
-                                for j in 0..enemy.height as usize {
-                                    let position = Position {
-                                        x: enemy.position.x + i,
-                                        y: enemy.position.y + j,
-                                    };
-                                    // There must not be obstacles obstructing line of sight
-                                    match line_to(ally.position, position, self.grid) {
-                                        Some(path) if path.len() as u16 <= stats.range => {
-                                            if let Some(projectile) = ally.use_ability(position) {
-                                                self.base_mut().add_child(projectile.upcast());
-                                            }
+impl Level {
+    pub fn at(&self, position: Position) -> Tile {
+        self.grid[position].tile
+    }
 
-                                            enemy.hit(damage, damage_kind);
-                                            enemy
-                                                .last_known_positions
-                                                .insert(ally.id, ally.position);
+    // A tile passed in from a script, if it is on the grid
+    fn tile(&self, tile: Vector2i) -> Option<Position> {
+        let position = Position {
+            x: usize::try_from(tile.x).ok()?,
+            y: usize::try_from(tile.y).ok()?,
+        };
+        self.grid.contains(position).then_some(position)
+    }
 
-                                            match damage_kind {
-                                                DamageKind::LifeSteal => ally.heal(damage),
-                                                _ => (),
-                                            }
+    pub fn item_at(&self, position: Position) -> Option<ItemId> {
+        self.grid[position].item
+    }
 
-                                            match stats.action {
-                                                Action::Push { distance, .. } => {
-                                                    let direction =
-                                                        ally.position.direction_to(enemy.position);
-                                                    enemy.push(self, direction, distance);
-                                                }
-                                                _ => (),
-                                            }
+    pub fn door_at(&self, position: Position) -> Option<Gd<Door>> {
+        let instance_id = self.doors.get(&position)?;
+        nodes::try_instance(Some(*instance_id), "door", position)
+    }
 
-                                            return true;
-                                        }
-                                        _ => (),
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-                Action::Effect { effect, stats } => {
-                    let position = ally.position;
-                    ally.use_ability(position);
-                    ally.effects.insert(effect, stats);
-                    return true;
-                }
-                Action::PlaceItem { kind } => {
-                    if self.grid[position.x][position.y] == Tile::Empty {
-                        match line_to(ally.position, position, self.grid) {
-                            Some(path) if path.len() as u16 <= stats.range => {
-                                ally.use_ability(position);
+    // Garlic wards its own tile and the ones next to it
+    fn terrain_area(&self, position: Position, terrain: Terrain) -> Vec<Position> {
+        match terrain {
+            Terrain::Garlic => {
+                let mut area = self.grid.adjacent(position);
+                area.push(position);
+                area
+            }
+            Terrain::Sunlight | Terrain::Fire => vec![position],
+        }
+    }
+
+    pub fn add_terrain(&mut self, position: Position, terrain: Terrain) {
+        for position in self.terrain_area(position, terrain) {
+            self.grid[position].terrain.push(terrain);
+        }
+    }
+
+    pub fn remove_terrain(&mut self, position: Position, terrain: Terrain) {
+        for position in self.terrain_area(position, terrain) {
+            let effects = &mut self.grid[position].terrain;
+            if let Some(i) = effects.iter().position(|effect| *effect == terrain) {
+                effects.remove(i);
+            }
+        }
+    }
+
+    pub fn try_get_ally(&self, ally_id: AllyId) -> Option<Gd<Ally>> {
+        nodes::try_instance(self.allies.get(&ally_id).copied(), "ally", ally_id)
+    }
+
+    pub fn try_get_enemy(&self, enemy_id: EnemyId) -> Option<Gd<Enemy>> {
+        nodes::try_instance(self.enemies.get(&enemy_id).copied(), "enemy", enemy_id)
+    }
+
+    pub fn try_get_npc(&self, npc_id: NpcId) -> Option<Gd<Npc>> {
+        nodes::try_instance(self.npcs.get(&npc_id).copied(), "npc", npc_id)
+    }
+
+    pub fn try_get_obstacle(&self, obstacle_id: ObstacleId) -> Option<Gd<Obstacle>> {
+        nodes::try_instance(
+            self.obstacles.get(&obstacle_id).copied(),
+            "obstacle",
+            obstacle_id,
+        )
+    }
+
+    pub fn try_get_item(&self, item_id: ItemId) -> Option<Gd<Item>> {
+        nodes::try_instance(self.items.get(&item_id).copied(), "item", item_id)
+    }
+
+    // For ids the turn loop has just read off the level itself. Commands, whether from the other
+    // player or a recording, look everything up with the try_ versions and are skipped once an
+    // id no longer resolves
+    pub fn get_ally(&self, ally_id: AllyId) -> Gd<Ally> {
+        self.try_get_ally(ally_id)
+            .expect("ally should be in the level")
+    }
+
+    pub fn get_enemy(&self, enemy_id: EnemyId) -> Gd<Enemy> {
+        self.try_get_enemy(enemy_id)
+            .expect("enemy should be in the level")
+    }
+
+    pub fn get_npc(&self, npc_id: NpcId) -> Gd<Npc> {
+        self.try_get_npc(npc_id)
+            .expect("npc should be in the level")
+    }
+
+    pub fn get_obstacle(&self, obstacle_id: ObstacleId) -> Gd<Obstacle> {
+        self.try_get_obstacle(obstacle_id)
+            .expect("obstacle should be in the level")
+    }
+
+    pub fn get_item(&self, item_id: ItemId) -> Gd<Item> {
+        self.try_get_item(item_id)
+            .expect("item should be in the level")
+    }
+
+    fn publish_effect_changes(&mut self) {
+        let mut changes = Vec::new();
+        for ally_id in self.allies.keys() {
+            if let Some(mut ally) = self.try_get_ally(*ally_id) {
+                let unit = UnitId::Ally(*ally_id);
+                for change in ally.bind_mut().vitals.effects.take_changes() {
+                    changes.push((unit, change));
+                }
+            }
+        }
+        for enemy_id in self.enemies.keys() {
+            if let Some(mut enemy) = self.try_get_enemy(*enemy_id) {
+                let unit = UnitId::Enemy(*enemy_id);
+                for change in enemy.bind_mut().vitals.effects.take_changes() {
+                    changes.push((unit, change));
+                }
+            }
+        }
+
+        for (unit, change) in changes {
+            self.events.publish(match change {
+                EffectChange::Applied(effect) => GameEvent::EffectApplied { unit, effect },
+                EffectChange::Expired(effect) => GameEvent::EffectExpired { unit, effect },
+                EffectChange::Removed(effect) => GameEvent::EffectRemoved { unit, effect },
+            });
+        }
+    }
+
+    // Commands naming units or items that can no longer be found are dropped instead of applied
+    fn resolves(&self, command: &Command) -> bool {
+        match *command {
+            Command::Move { ally_id, .. }
+            | Command::UseAbility { ally_id, .. }
+            | Command::Drop { ally_id, .. }
+            | Command::DropItem { ally_id, .. }
+            | Command::Push { ally_id, .. }
+            | Command::Impact { ally_id, .. } => self.try_get_ally(ally_id).is_some(),
+            Command::PickUp { ally_id, item_id } => {
+                self.try_get_ally(ally_id).is_some() && self.try_get_item(item_id).is_some()
+            }
+            Command::Swap {
+                ally_id, item_id, ..
+            } => self.try_get_ally(ally_id).is_some() && self.try_get_item(item_id).is_some(),
+            Command::Trade { from, to, .. } => {
+                self.try_get_ally(from).is_some() && self.try_get_ally(to).is_some()
+            }
+            Command::EnemyAct { enemy_id, .. } | Command::ControlEnemy { enemy_id, .. } => {
+                self.try_get_enemy(enemy_id).is_some()
+            }
+            _ => true,
+        }
+    }
+
+    pub fn cast_shadows(&mut self) {
+        let mut visible = self.revealed.clone();
+        for ally_id in self.allies.keys() {
+            if !self.is_carried(*ally_id) {
+                let ally = self.get_ally(*ally_id);
+                let ally = ally.bind();
+                visible.extend(compute_fov(ally.position, self.ally_view(&ally), self));
+            }
+        }
+
+        for ally_id in self.allies.keys() {
+            let mut ally = self.get_ally(*ally_id);
+            let position = ally.bind().position;
+            ally.set_visible(visible.contains(&position) && !self.is_carried(*ally_id));
+        }
+
+        let mut sighted = Vec::new();
+        for enemy_id in self.enemies.keys() {
+            let mut enemy = self.get_enemy(*enemy_id);
+            let (position, kind) = {
+                let enemy = enemy.bind();
+                (enemy.position, enemy.kind)
+            };
+            enemy.set_visible(visible.contains(&position));
+            if visible.contains(&position) {
+                sighted.push(kind);
+            }
+        }
+        for kind in sighted {
+            if self.sighted.insert(kind) {
+                self.events.publish(GameEvent::EnemySighted(kind));
+            }
+        }
+
+        for npc_id in self.npcs.keys() {
+            let mut npc = self.get_npc(*npc_id);
+            let position = npc.bind().position;
+            npc.set_visible(visible.contains(&position));
+        }
+
+        for obstacle_id in self.obstacles.keys() {
+            let mut obstacle = self.get_obstacle(*obstacle_id);
+            let position = obstacle.bind().position;
+            obstacle.set_visible(visible.contains(&position));
+        }
+
+        for item_id in self.items.keys() {
+            let mut item = self.get_item(*item_id);
+            let position = item.bind().position;
+            item.set_visible(visible.contains(&position));
+        }
+
+        let mut shadow_map = self
+            .base()
+            .get_node_as::<ShadowMap>("ShadowLayer/ShadowMap");
+        shadow_map
+            .bind_mut()
+            .cast_shadows(visible, self.grid.dimensions());
+
+        self.refresh_auras();
+    }
+
+    // Only the auras of units the allies can see are drawn
+    pub fn refresh_auras(&mut self) {
+        let mut sources = Vec::new();
+        let mut shown = Vec::new();
+        for ally_id in self.allies.keys() {
+            if self.is_carried(*ally_id) {
+                continue;
+            }
+            let Some(ally) = self.try_get_ally(*ally_id) else {
+                continue;
+            };
+            let ally = ally.bind();
+            sources.push((UnitId::Ally(*ally_id), vec![ally.position], ally.traits()));
+            shown.push(UnitId::Ally(*ally_id));
+        }
+        for enemy_id in self.enemies.keys() {
+            let Some(enemy) = self.try_get_enemy(*enemy_id) else {
+                continue;
+            };
+            let visible = enemy.is_visible();
+            let enemy = enemy.bind();
+            if enemy.animator.is_dying() {
+                continue;
+            }
+            sources.push((UnitId::Enemy(*enemy_id), enemy.tiles(), enemy.traits()));
+            if visible {
+                shown.push(UnitId::Enemy(*enemy_id));
+            }
+        }
+        self.auras = AuraLayer::build(&sources, self.grid.dimensions());
+
+        let mut overlay = match &self.aura_overlay {
+            Some(overlay) => overlay.clone(),
+            None => {
+                let overlay = AuraOverlay::new_alloc();
+                let Some(mut layer) = self.base().try_get_node_as::<CanvasLayer>("PathLayer")
+                else {
+                    return;
+                };
+                layer.add_child(overlay.clone().upcast());
+                self.aura_overlay = Some(overlay.clone());
+                overlay
+            }
+        };
+        overlay.bind_mut().tiles = self.auras.covered(&shown);
+        overlay.queue_redraw();
+    }
+
+    // Tiles next to a coffin that has not been destroyed or blessed
+    pub fn coffin_tiles(&self) -> HashSet<Position> {
+        let mut tiles = HashSet::new();
+        for obstacle_id in self.obstacles.keys() {
+            let obstacle = self.get_obstacle(*obstacle_id);
+            let obstacle = obstacle.bind();
+            if obstacle.kind == ObstacleKind::Coffin && !obstacle.blessed {
+                for i in 0..obstacle.width as usize {
+                    for j in 0..obstacle.height as usize {
+                        let position = Position {
+                            x: obstacle.position.x + i,
+                            y: obstacle.position.y + j,
+                        };
+                        for adjacent in self.grid.adjacent(position) {
+                            if self.at(adjacent) != Tile::Obstacle(*obstacle_id) {
+                                tiles.insert(adjacent);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        tiles
+    }
+
+    // Holy damage blesses an obstacle, any damage wears it down until it is destroyed
+    pub fn hit_obstacle(&mut self, obstacle_id: ObstacleId, damage: u16, damage_kind: DamageKind) {
+        let Some(mut obstacle) = self.try_get_obstacle(obstacle_id) else {
+            return;
+        };
+        let destroyed = {
+            let mut obstacle = obstacle.bind_mut();
+            if damage_kind == DamageKind::Holy {
+                obstacle.blessed = true;
+            }
+            obstacle.health = obstacle.health.checked_sub(damage).unwrap_or(0);
+            obstacle.health == 0
+        };
+
+        if destroyed {
+            self.remove_obstacle(obstacle_id);
+        }
+    }
+
+    // Frees up the tiles it stood on and lets sight through them again
+    pub fn remove_obstacle(&mut self, obstacle_id: ObstacleId) {
+        let Some(mut obstacle) = self.try_get_obstacle(obstacle_id) else {
+            return;
+        };
+        let (position, width, height) = {
+            let obstacle = obstacle.bind();
+            (obstacle.position, obstacle.width, obstacle.height)
+        };
+
+        for i in 0..width as usize {
+            for j in 0..height as usize {
+                if self.grid.contains(position.offset(i, j)) {
+                    self.grid[position.offset(i, j)].tile = Tile::Empty;
+                }
+            }
+        }
+
+        self.obstacles.remove(&obstacle_id);
+        self.fov_cache.borrow_mut().invalidate();
+        self.shadows_cast = false;
+        obstacle.queue_free();
+    }
+
+    pub fn floor_at(&self, position: Position) -> Option<usize> {
+        self.floors
+            .iter()
+            .position(|floor| floor.contains(position))
+    }
+
+    // Always true on a level with one floor
+    pub fn same_floor(&self, a: Position, b: Position) -> bool {
+        self.floor_at(a) == self.floor_at(b)
+    }
+
+    // Where stairs on a tile come out, if they are free to be taken
+    pub fn stairs_from(&self, position: Position) -> Option<Position> {
+        self.grid
+            .get(position)
+            .and_then(|occupancy| occupancy.stairs)
+            .filter(|stairs| self.at(*stairs).is_empty())
+    }
+
+    // Damage from no unit's ability, such as the level's own traps and scripts. Allies and
+    // enemies see to their own deaths, anyone escorted is gone at once, and the objective fails
+    // with how it happened
+    pub fn hit_unit(&mut self, unit: UnitId, damage: Damage, fate: &str) -> DamageResult {
+        match unit {
+            UnitId::Ally(ally_id) => match self.try_get_ally(ally_id) {
+                Some(mut ally) => ally.bind_mut().hit(damage, &mut self.events),
+                None => DamageResult::default(),
+            },
+            UnitId::Enemy(enemy_id) => match self.try_get_enemy(enemy_id) {
+                Some(mut enemy) => enemy.bind_mut().hit(damage, &mut self.events),
+                None => DamageResult::default(),
+            },
+            UnitId::Npc(npc_id) => {
+                let Some(mut npc) = self.try_get_npc(npc_id) else {
+                    return DamageResult::default();
+                };
+                let (result, position, name) = {
+                    let mut npc = npc.bind_mut();
+                    (npc.hit(damage, &mut self.events), npc.position, npc.name())
+                };
+                if result.killed {
+                    self.grid[position].tile = Tile::Empty;
+                    self.npcs.remove(&npc_id);
+                    npc.queue_free();
+                    self.fail_objective(format!("{} {}", name, fate));
+                }
+                result
+            }
+        }
+    }
+
+    pub fn chandelier_at(&self, position: Position) -> Option<ObstacleId> {
+        self.chandeliers.get(&position).copied()
+    }
+
+    // Takes the chandelier off its rope, to land once the command cutting it is done
+    pub fn cut_chandelier(&mut self, obstacle_id: ObstacleId) {
+        self.chandeliers.retain(|_, id| *id != obstacle_id);
+        self.falling.push(obstacle_id);
+    }
+
+    // Crushes everyone beneath each chandelier that was cut, then leaves it lying on every tile
+    // nobody survived on
+    fn drop_chandeliers(&mut self) {
+        for obstacle_id in mem::take(&mut self.falling) {
+            let Some(mut obstacle) = self.try_get_obstacle(obstacle_id) else {
+                continue;
+            };
+            let tiles: Vec<Position> = {
+                let obstacle = obstacle.bind();
+                (0..obstacle.width as usize)
+                    .flat_map(|i| (0..obstacle.height as usize).map(move |j| (i, j)))
+                    .map(|(i, j)| obstacle.position.offset(i, j))
+                    .filter(|tile| self.grid.contains(*tile))
+                    .collect()
+            };
+            let damage = Damage {
+                amount: CHANDELIER_DAMAGE,
+                kind: DamageKind::Normal,
+                source: None,
+                ability: None,
+            };
+
+            let mut crushed = HashSet::new();
+            for tile in &tiles {
+                let unit = match self.at(*tile) {
+                    Tile::Ally(ally_id) => UnitId::Ally(ally_id),
+                    Tile::Enemy(enemy_id) => UnitId::Enemy(enemy_id),
+                    Tile::Npc(npc_id) => UnitId::Npc(npc_id),
+                    _ => continue,
+                };
+                // Wide enemies are only hit once however many tiles it lands on
+                if !crushed.insert(unit) {
+                    continue;
+                }
+
+                self.hit_unit(unit, damage, "was crushed");
+            }
+
+            for tile in tiles {
+                if self.at(tile).is_empty() {
+                    self.grid[tile].tile = Tile::Obstacle(obstacle_id);
+                }
+            }
+            if let Some(mut sprite) = obstacle.try_get_node_as::<Sprite2D>("Sprite") {
+                let fallen = sprite.get_position() + Vector2::new(0.0, TILE_SIZE / 2.0);
+                sprite.set_position(fallen);
+            }
+            self.fov_cache.borrow_mut().invalidate();
+            self.shadows_cast = false;
+        }
+    }
+
+    // Teleport targets must be empty and within sight, regardless of any path to them
+    pub fn can_teleport(&self, origin: Position, position: Position, range: u16) -> bool {
+        self.at(position).is_empty()
+            && origin.distance(position) <= range
+            && compute_fov(origin, range, self).contains(&position)
+    }
+
+    // Line a ranged ability takes from a unit to a target, if the target is in range and nothing
+    // the ability can't pass over is in the way. The unit's own tiles never block it
+    pub fn ability_line(
+        &self,
+        origin: Position,
+        target: Position,
+        stats: &AbilityStats,
+        unit_tile: Tile,
+    ) -> Option<Vec<Position>> {
+        if origin.distance(target) > stats.range {
+            return None;
+        }
+
+        let clears_low_obstacles = stats.action.clears_low_obstacles();
+        line_of_sight(origin, target, |position| match self.grid[position].tile {
+            Tile::Empty => true,
+            Tile::Obstacle(obstacle_id) => {
+                let obstacle = self.get_obstacle(obstacle_id);
+                let obstacle = obstacle.bind();
+                clears_low_obstacles && !obstacle.kind.blocks_sight()
+            }
+            tile => tile == unit_tile,
+        })
+    }
+
+    // Single entry point for every change to the level, returns whether the command was valid
+    pub fn apply(&mut self, command: Command) -> bool {
+        if !self.resolves(&command) {
+            return false;
+        }
+
+        // A guest only sends its orders, they are applied once the host has checked them and
+        // sent them back, so both sides play the same commands in the same order
+        if self.online && !self.remote && !self.is_host() {
+            if let Some(ally_id) = command.ordered_ally() {
+                let Some(ally) = self.try_get_ally(ally_id) else {
+                    return false;
+                };
+                // The cursor tries dropping before anything else, so only a drop that can happen
+                // is sent
+                let sent = self.controls(ally_id)
+                    && match command {
+                        Command::Drop { position, .. } => self.can_drop(&ally.bind(), position),
+                        Command::DropItem {
+                            ability, position, ..
+                        } => self.can_drop_item(&ally.bind(), ability, position),
+                        _ => true,
+                    };
+                if sent {
+                    self.share(command);
+                }
+                return sent;
+            }
+        }
+
+        let applied = match command {
+            Command::Move { ally_id, position } => self.move_ally(ally_id, position),
+            Command::UseAbility {
+                ally_id,
+                ability,
+                position,
+            } => {
+                {
+                    let Some(mut ally) = self.try_get_ally(ally_id) else {
+                        return false;
+                    };
+                    let mut ally = ally.bind_mut();
+                    match ally.abilities.iter().position(|a| *a == ability) {
+                        Some(i) => ally.selected_ability = i,
+                        None => return false,
+                    }
+                }
+
+                let enemy_id = match self.at(position) {
+                    Tile::Enemy(enemy_id) => Some(enemy_id),
+                    _ => None,
+                };
+                match self.use_ability(ally_id, position, enemy_id) {
+                    Some(result) => {
+                        // A bolt still in the air is reported once it lands
+                        if !self.in_flight.contains(&ally_id) {
+                            self.report(UnitId::Ally(ally_id), ability, &result);
+                        }
+                        true
+                    }
+                    None => false,
+                }
+            }
+            Command::Impact {
+                ally_id,
+                ability,
+                position,
+            } => {
+                self.in_flight.remove(&ally_id);
+                let Some(mut ally) = self.try_get_ally(ally_id) else {
+                    return false;
+                };
+                let result = {
+                    let mut ally = ally.bind_mut();
+                    // A dash is only swung once the ally has got there, and a bolt is caught by
+                    // whoever has stepped into its line since it was fired
+                    let stats = abilities().get(&ability).unwrap();
+                    let position = if matches!(stats.action, Action::DashAttack { .. }) {
+                        if let Some(i) = ally.abilities.iter().position(|a| *a == ability) {
+                            ally.selected_ability = i;
+                            ally.use_ability(position);
+                        }
+                        position
+                    } else {
+                        self.intercept(ally.position, position)
+                    };
+                    let result = self.land(&mut ally, ability, position);
+                    if ally.has_acted {
+                        self.events.publish(GameEvent::AllyActed(ally_id));
+                    }
+                    result
+                };
+                self.report(UnitId::Ally(ally_id), ability, &result);
+                true
+            }
+            Command::Drop { ally_id, position } => self.drop_ally(ally_id, position),
+            Command::PickUp { ally_id, item_id } => self.pick_up_item(ally_id, item_id),
+            Command::DropItem {
+                ally_id,
+                ability,
+                position,
+            } => self.drop_item(ally_id, ability, position),
+            Command::Swap {
+                ally_id,
+                item_id,
+                ability,
+            } => self.swap_item(ally_id, item_id, ability),
+            Command::Trade { from, to, ability } => self.trade(from, to, ability),
+            Command::EnemyAct {
+                enemy_id,
+                ability,
+                action,
+            } => match self.enemy_act(enemy_id, ability, action) {
+                Some(result) => {
+                    self.report(UnitId::Enemy(enemy_id), ability, &result);
+                    true
+                }
+                None => false,
+            },
+            Command::ControlEnemy {
+                enemy_id,
+                position,
+                action,
+            } => self.control_enemy(enemy_id, position, action),
+            Command::Spawn {
+                enemy_kind,
+                position,
+            } => {
+                if self.spawn_warning == 0 {
+                    self.spawn_enemy(enemy_kind, position);
+                } else {
+                    let round = self.round + 1 + self.spawn_warning;
+                    self.telegraph_spawn(enemy_kind, position, round);
+                }
+                true
+            }
+            Command::Push {
+                ally_id,
+                direction,
+                distance,
+            } => match self.try_get_ally(ally_id) {
+                Some(mut ally) => {
+                    ally.bind_mut().push(self, direction, distance);
+                    true
+                }
+                None => false,
+            },
+            Command::Choose { choice } => {
+                self.choices.make(choice);
+                if let Choice::Gift(item_kind) = choice {
+                    if let Some(mut ash) = self.try_get_ally(AllyId::AshMagnum) {
+                        ash.bind_mut().acquire(item_kind.ability());
+                    }
+                }
+                true
+            }
+            Command::EndTurn => {
+                if self.turn == Turn::Ally {
+                    self.turn = Turn::Npc;
+                    true
+                } else {
+                    false
+                }
+            }
+        };
+
+        if applied {
+            self.log(Entry::Command { command });
+            match command {
+                Command::UseAbility {
+                    ally_id, ability, ..
+                } => self.events.publish(GameEvent::AbilityUsed {
+                    unit: UnitId::Ally(ally_id),
+                    ability,
+                }),
+                Command::EnemyAct {
+                    enemy_id, ability, ..
+                } => self.events.publish(GameEvent::AbilityUsed {
+                    unit: UnitId::Enemy(enemy_id),
+                    ability,
+                }),
+                _ => (),
+            }
+        }
+        self.drop_chandeliers();
+
+        // Everything else follows from the players' commands and when attacks arrive, so only those
+        // are recorded and shared. A replay from before arrivals were recorded goes on without them
+        let recorded = match command {
+            Command::Impact { .. } => self.replay.impacts,
+            _ => true,
+        };
+        if applied && recorded {
+            match command {
+                Command::Move { .. }
+                | Command::UseAbility { .. }
+                | Command::Drop { .. }
+                | Command::DropItem { .. }
+                | Command::Swap { .. }
+                | Command::Trade { .. }
+                | Command::ControlEnemy { .. }
+                | Command::Choose { .. }
+                | Command::EndTurn
+                | Command::Impact { .. } => {
+                    if !self.playback {
+                        self.replay.commands.push(command);
+                    }
+                    // The host passes on everything it applies, the guest's orders included
+                    if self.online && self.is_host() {
+                        self.share(command);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        applied
+    }
+
+    // Send a command to the other player, guests' orders go through the host first
+    fn share(&mut self, command: Command) {
+        let json = serde_json::to_string(&command).unwrap().to_variant();
+        if self.is_host() {
+            self.base_mut().rpc("sync_command".into(), &[json]);
+        } else {
+            self.base_mut()
+                .rpc_id(HOST as i64, "receive_command".into(), &[json]);
+        }
+    }
+
+    // The host says when bolts and dashes arrive, or the recording does when playing one back
+    // that has them
+    pub fn lands_impacts(&self) -> bool {
+        self.is_host() && !(self.playback && self.replay.impacts)
+    }
+
+    pub fn is_host(&self) -> bool {
+        !self.online || self.base().get_multiplayer().unwrap().is_server()
+    }
+
+    // Peer that gives orders to an ally, the host keeps everyone but Alukrod
+    fn owner(&self, ally_id: AllyId) -> i32 {
+        match ally_id {
+            AllyId::Alukrod => self.guest,
+            _ => HOST,
+        }
+    }
+
+    // How fast the current phase plays before the animation speed setting. Only enemies are sped
+    // up, unless the level is catching up to a checkpoint
+    pub fn phase_speed(&self) -> f64 {
+        if self.catching_up {
+            return CATCH_UP_SPEED;
+        }
+        match self.turn {
+            Turn::Ally | Turn::Npc => 1.0,
+            Turn::Enemy(..) => FAST_FORWARD[self.fast_forward],
+        }
+    }
+
+    // Who or what landed the killing blow on a unit, for the death screen
+    pub fn describe_death(&self, unit: UnitId) -> String {
+        let Some(cause) = self.stats.deaths.get(&unit) else {
+            return "Died of unknown causes".into();
+        };
+        // The killer may have died since
+        let killer = match cause.source {
+            Some(UnitId::Ally(ally_id)) => Some(ally_id.name()),
+            Some(UnitId::Enemy(enemy_id)) if self.enemies.contains_key(&enemy_id) => self
+                .try_get_enemy(enemy_id)
+                .map(|enemy| format!("a {}", enemy.bind().name())),
+            _ => None,
+        }
+        .unwrap_or_else(|| "something".into());
+
+        match cause.ability {
+            Some(ability) => format!(
+                "Killed by {} with {}, taking {} {} damage",
+                killer,
+                abilities().get(&ability).unwrap().name,
+                cause.damage,
+                cause.damage_kind.name().to_lowercase()
+            ),
+            None => format!(
+                "Worn down by what {} left behind, taking {} damage",
+                killer, cause.damage
+            ),
+        }
+    }
+
+    fn defeat_rules(&self) -> DefeatRules {
+        DefeatRules {
+            ash_must_survive: self.ash_must_survive,
+            turn_limit: Some(self.turn_limit).filter(|limit| *limit > 0),
+        }
+    }
+
+    // Ends the level if its rules say it is lost, returning whether it was
+    pub fn check_defeat(&mut self, fallen: Option<AllyId>) -> bool {
+        let defeat = self.defeat_rules().check(
+            self.allies.contains_key(&AllyId::AshMagnum),
+            self.allies.len(),
+            self.round,
+        );
+        match defeat {
+            Some(defeat) => {
+                self.lose(defeat, fallen);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // For whatever the level was set to protect or get done
+    pub fn fail_objective(&mut self, reason: String) {
+        self.lose(Defeat::ObjectiveFailed(reason), None);
+    }
+
+    // Every way of losing a level ends here, on the death screen saying why
+    pub fn lose(&mut self, defeat: Defeat, fallen: Option<AllyId>) {
+        if self.defeated {
+            return;
+        }
+        self.defeated = true;
+
+        let cause = defeat
+            .reason()
+            .or_else(|| fallen.map(|ally_id| self.describe_death(UnitId::Ally(ally_id))))
+            .unwrap_or_default();
+
+        self.log(Entry::Defeat {
+            room: self.room,
+            reason: defeat
+                .headline()
+                .into_iter()
+                .chain(Some(cause.clone()).filter(|cause| !cause.is_empty()))
+                .collect::<Vec<_>>()
+                .join(": "),
+        });
+
+        let scene = load::<PackedScene>("res://scenes/death.tscn");
+        let mut scene: Gd<DeathScreen> = scene.instantiate().unwrap().cast();
+
+        {
+            let mut scene = scene.bind_mut();
+            scene.room = self.room;
+            scene.defeat = Some(defeat);
+            scene.cause = cause;
+            scene.round = self.round;
+            scene.stats = self.stats.clone();
+        }
+
+        let mut tree = self.base().get_tree().unwrap();
+        tree.get_root().unwrap().add_child(scene.clone().upcast());
+        tree.set_current_scene(scene.upcast());
+
+        self.base_mut().queue_free();
+    }
+
+    pub fn log(&mut self, entry: Entry) {
+        self.log.write(self.round, entry);
+    }
+
+    pub fn play_time(&self) -> f64 {
+        self.play_time_before + save::now() - self.entered_at
+    }
+
+    // Online games aren't saved, as only one side could load them
+    // Barks are skipped while catching up, as nobody would have time to read them
+    pub fn bark(&mut self, event: GameEvent) {
+        if self.catching_up {
+            return;
+        }
+        let Some((bark, unit)) = Bark::of(event, self) else {
+            return;
+        };
+        let Some(line) = bark.line(unit) else {
+            return;
+        };
+        if !self.barks.allow(bark, unit, save::now()) {
+            return;
+        }
+
+        let (node, speaker) = match unit {
+            UnitId::Ally(ally_id) => (self.try_get_ally(ally_id).map(Gd::upcast), ally_id.name()),
+            UnitId::Enemy(enemy_id) => {
+                let enemy = self.try_get_enemy(enemy_id);
+                let speaker = enemy
+                    .as_ref()
+                    .map_or_else(String::new, |enemy| enemy.bind().name());
+                (enemy.map(Gd::upcast), speaker)
+            }
+            UnitId::Npc(npc_id) => {
+                let npc = self.try_get_npc(npc_id);
+                let speaker = npc
+                    .as_ref()
+                    .map_or_else(String::new, |npc| npc.bind().name());
+                (npc.map(Gd::upcast), speaker)
+            }
+        };
+        if let Some(node) = node {
+            barks::show(node, line);
+        }
+        if let Some(mut dialogue) = nodes::dialogue(&self.base()) {
+            dialogue.bind_mut().record(Some(speaker), line.into());
+        }
+    }
+
+    // Damage over whoever an ability hit and a line in the history, skipped while catching up
+    // like barks
+    fn report(&mut self, unit: UnitId, ability: Ability, result: &ActionResult) {
+        if self.catching_up {
+            return;
+        }
+
+        for (hit, damage) in &result.hits {
+            if let Some(node) = self.unit_node(*hit) {
+                let popup = if damage.evaded {
+                    "Missed".to_string()
+                } else {
+                    format!("-{}", damage.applied)
+                };
+                barks::show(node, &popup);
+            }
+        }
+
+        let name = abilities().get(&ability).unwrap().name.clone();
+        let line = result.narrate(&self.unit_name(unit), &name, |unit| self.unit_name(unit));
+        if let Some(mut dialogue) = nodes::dialogue(&self.base()) {
+            dialogue.bind_mut().record(None, line);
+        }
+    }
+
+    // Empty for anyone no longer in the level
+    fn unit_name(&self, unit: UnitId) -> String {
+        match unit {
+            UnitId::Ally(ally_id) => ally_id.name(),
+            UnitId::Enemy(enemy_id) => self
+                .try_get_enemy(enemy_id)
+                .map_or_else(String::new, |enemy| enemy.bind().name()),
+            UnitId::Npc(npc_id) => self
+                .try_get_npc(npc_id)
+                .map_or_else(String::new, |npc| npc.bind().name()),
+        }
+    }
+
+    // Daily challenges are left out so they never overwrite the game in the slot
+    fn autosave(&self) {
+        if !self.playback && !self.online && self.modifiers.seed.is_none() {
+            Save::of(self).save(save::slot());
+        }
+    }
+
+    // What the allies are working towards in this room
+    pub fn objectives(&self) -> Vec<String> {
+        let mut objectives = vec![match self.room {
+            Room::EntranceHall => "Reach the door to the great hall".to_string(),
+            Room::GreatHall => "Escape through the far door".to_string(),
+        }];
+        objectives.extend(self.scripted_objective.clone());
+        match survival::turns_until_sunrise(self.sunrise, self.round) {
+            Some(1) => objectives.push("Survive until sunrise, 1 turn left".into()),
+            Some(turns) => objectives.push(format!("Survive until sunrise, {} turns left", turns)),
+            None => (),
+        }
+        objectives
+    }
+
+    // Enemies due by the round arrive where they were placed, marked the spawn warning ahead of
+    // time. Any whose spot is taken try again the round after
+    fn spawn_waves(&mut self, round: u16) {
+        let (due, later): (Vec<_>, Vec<_>) = mem::take(&mut self.waves)
+            .into_iter()
+            .partition(|(due, _, _)| *due <= round + self.spawn_warning);
+        self.waves = later;
+
+        for (due, enemy_kind, position) in due {
+            if !has_room(position, enemy_kind.dimensions(), &self.grid) {
+                self.waves.push((due, enemy_kind, position));
+            } else if self.spawn_warning == 0 {
+                self.spawn_enemy(enemy_kind, position);
+            } else {
+                self.telegraph_spawn(enemy_kind, position, due.max(round + 1));
+            }
+        }
+    }
+
+    // Holds and marks the tiles of an enemy that appears at the start of a later round
+    fn telegraph_spawn(&mut self, enemy_kind: EnemyKind, position: Position, round: u16) {
+        self.hold_tiles(enemy_kind, position, true);
+
+        let (width, height) = enemy_kind.dimensions();
+        let mut marker = SpawnMarker::new_alloc();
+        {
+            let mut marker = marker.bind_mut();
+            marker.width = width as u16;
+            marker.height = height as u16;
+        }
+        marker.set_position(position.to_vector());
+        self.base_mut().add_child(marker.clone().upcast());
+
+        self.pending_spawns.push(PendingSpawn {
+            round,
+            enemy_kind,
+            position,
+            marker,
+        });
+    }
+
+    // Pending spawns due by the round appear. One pushed onto while it waited holds out another
+    // round
+    fn arrive(&mut self, round: u16) {
+        let (due, later): (Vec<_>, Vec<_>) = mem::take(&mut self.pending_spawns)
+            .into_iter()
+            .partition(|spawn| spawn.round <= round);
+        self.pending_spawns = later;
+
+        for mut spawn in due {
+            self.hold_tiles(spawn.enemy_kind, spawn.position, false);
+            if has_room(spawn.position, spawn.enemy_kind.dimensions(), &self.grid) {
+                spawn.marker.queue_free();
+                self.spawn_enemy(spawn.enemy_kind, spawn.position);
+            } else {
+                self.hold_tiles(spawn.enemy_kind, spawn.position, true);
+                spawn.round = round + 1;
+                self.pending_spawns.push(spawn);
+            }
+        }
+    }
+
+    fn hold_tiles(&mut self, enemy_kind: EnemyKind, position: Position, arriving: bool) {
+        let (width, height) = enemy_kind.dimensions();
+        for i in 0..width {
+            for j in 0..height {
+                if self.grid.contains(position.offset(i, j)) {
+                    self.grid[position.offset(i, j)].arriving = arriving;
+                }
+            }
+        }
+    }
+
+    // The sun floods the sunlit tiles, burning any enemy caught outside the shade, and the doors
+    // held shut through the night open
+    fn dawn(&mut self) {
+        if let Some(mut sun) = self.base().try_get_node_as::<CanvasLayer>("SunLayer") {
+            sun.set_visible(true);
+        }
+
+        let sunlit: Vec<Position> = self.sunlit.iter().copied().collect();
+        for position in sunlit {
+            if self.grid.contains(position) {
+                self.add_terrain(position, Terrain::Sunlight);
+            }
+        }
+
+        let doors = self.base().get_node_as::<CanvasLayer>("DoorLayer");
+        for child in doors.get_children().iter_shared() {
+            if let Ok(mut door) = child.try_cast::<Door>() {
+                door.bind_mut().locked = false;
+            }
+        }
+    }
+
+    // Enemies standing in sunlight burn, when it first falls on them and at the start of every
+    // round after
+    fn burn_sunlit(&mut self) {
+        let mut enemy_ids: Vec<EnemyId> = self.enemies.keys().copied().collect();
+        enemy_ids.sort();
+        for enemy_id in enemy_ids {
+            let Some(mut enemy) = self.try_get_enemy(enemy_id) else {
+                continue;
+            };
+            let mut enemy = enemy.bind_mut();
+            let sunlit = enemy.tiles().iter().any(|tile| {
+                self.grid.contains(*tile) && self.grid[*tile].terrain.contains(&Terrain::Sunlight)
+            });
+            if sunlit && !enemy.animator.is_dying() {
+                enemy.hit(
+                    Damage {
+                        amount: SUNLIGHT_DAMAGE,
+                        kind: DamageKind::Sunlight,
+                        source: None,
+                        ability: None,
+                    },
+                    &mut self.events,
+                );
+            }
+        }
+    }
+
+    // Everything due at a phase boundary, in the order passives::hooks lists it
+    fn run_hooks(&mut self, boundary: Boundary) {
+        for hook in passives::hooks(boundary) {
+            match hook {
+                Hook::Passives => self.run_passives(boundary),
+                Hook::Effects => self.tick_effects(boundary),
+                Hook::Coffins => self.heal_at_coffins(),
+                Hook::Sunlight => self.burn_sunlit(),
+                Hook::Fires => self.burn_fires(),
+                Hook::Auras => self.refresh_auras(),
+            }
+        }
+    }
+
+    fn run_passives(&mut self, boundary: Boundary) {
+        let mut units = Vec::new();
+        for ally_id in self.allies.keys() {
+            if let Some(ally) = self.try_get_ally(*ally_id) {
+                units.push((UnitId::Ally(*ally_id), ally.bind().traits()));
+            }
+        }
+        for enemy_id in self.enemies.keys() {
+            if let Some(enemy) = self.try_get_enemy(*enemy_id) {
+                let enemy = enemy.bind();
+                if !enemy.animator.is_dying() {
+                    units.push((UnitId::Enemy(*enemy_id), enemy.traits()));
+                }
+            }
+        }
+
+        for (passive, unit) in passives::due(&units, boundary) {
+            match (passive, unit) {
+                (Passive::Regenerate, UnitId::Ally(ally_id)) => {
+                    self.get_ally(ally_id).bind_mut().vitals.regenerate();
+                }
+                (Passive::Regenerate, UnitId::Enemy(enemy_id)) => {
+                    if let Some(mut enemy) = self.try_get_enemy(enemy_id) {
+                        enemy.bind_mut().vitals.regenerate();
+                    }
+                }
+                (Passive::HolyAura, source) => self.sear_undead(source),
+                (Passive::RecoverBolt, UnitId::Ally(ally_id)) => {
+                    let mut ally = self.get_ally(ally_id);
+                    let mut ally = ally.bind_mut();
+                    let bolt = Ability::CrossbowIronBolt;
+                    if ally.bolts_fired > 0 && has_room_for(&ally.uses, ally.capacity, bolt) {
+                        ally.acquire(bolt);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    // Carried allies' effects tick down with everyone else's
+    fn tick_effects(&mut self, boundary: Boundary) {
+        match boundary {
+            Boundary::AllyStart => {
+                for ally_id in self.allies.keys() {
+                    if let Some(mut ally) = self.try_get_ally(*ally_id) {
+                        ally.bind_mut().start_phase(&mut self.events);
+                    }
+                }
+            }
+            Boundary::EnemyStart => {
+                for enemy_id in self.enemies.keys() {
+                    if let Some(mut enemy) = self.try_get_enemy(*enemy_id) {
+                        enemy.bind_mut().start_phase(&mut self.events);
+                    }
+                }
+            }
+            Boundary::AllyEnd | Boundary::EnemyEnd => (),
+        }
+    }
+
+    fn heal_at_coffins(&mut self) {
+        let coffin_tiles = self.coffin_tiles();
+        for enemy_id in self.enemies.keys() {
+            let Some(mut enemy) = self.try_get_enemy(*enemy_id) else {
+                continue;
+            };
+            let mut enemy = enemy.bind_mut();
+            if enemy.kind == EnemyKind::Vampire
+                && enemy.tiles().iter().any(|tile| coffin_tiles.contains(tile))
+            {
+                enemy.heal(COFFIN_HEAL);
+            }
+        }
+    }
+
+    // Undead enemies next to the source are seared, unless a stronger holy aura is over them
+    fn sear_undead(&mut self, source: UnitId) {
+        let mut enemy_ids: Vec<EnemyId> = self.enemies.keys().copied().collect();
+        enemy_ids.sort();
+        for enemy_id in enemy_ids {
+            let Some(mut enemy) = self.try_get_enemy(enemy_id) else {
+                continue;
+            };
+            let mut enemy = enemy.bind_mut();
+            if enemy.animator.is_dying() || !enemy.traits().contains(&Trait::Undead) {
+                continue;
+            }
+            let holy = enemy
+                .tiles()
+                .iter()
+                .filter_map(|tile| self.auras.holy(*tile))
+                .max_by_key(|(_, amount)| *amount)
+                .filter(|(unit, _)| *unit == source);
+            if let Some((source, amount)) = holy {
+                enemy.hit(
+                    Damage {
+                        amount,
+                        kind: DamageKind::Holy,
+                        source: Some(source),
+                        ability: None,
+                    },
+                    &mut self.events,
+                );
+            }
+        }
+    }
+
+    // Opens or closes everything on a switch: portcullises rise and fall, shutters swing and the
+    // light behind them comes and goes. Chandeliers on it come down
+    pub fn throw_switch(&mut self, switch: &str) {
+        let mut obstacle_ids: Vec<ObstacleId> = self.obstacles.keys().copied().collect();
+        obstacle_ids.sort();
+        for obstacle_id in obstacle_ids {
+            let Some(obstacle) = self.try_get_obstacle(obstacle_id) else {
+                continue;
+            };
+            let open = {
+                let obstacle = obstacle.bind();
+                (obstacle.kind.switched() && obstacle.switch.to_string() == switch)
+                    .then_some(!obstacle.open)
+            };
+            if let Some(open) = open {
+                self.set_open(obstacle_id, open);
+            }
+        }
+
+        let mut chandelier_ids: Vec<ObstacleId> = self.chandeliers.values().copied().collect();
+        chandelier_ids.sort();
+        chandelier_ids.dedup();
+        for obstacle_id in chandelier_ids {
+            if self
+                .try_get_obstacle(obstacle_id)
+                .is_some_and(|obstacle| obstacle.bind().switch.to_string() == switch)
+            {
+                self.cut_chandelier(obstacle_id);
+            }
+        }
+
+        if let Some(lights) = self.base().try_get_node_as::<CanvasLayer>("LightLayer") {
+            for child in lights.get_children().iter_shared() {
+                let mut sunlight: Gd<Sunlight> = child.cast();
+                if sunlight.bind().switch.to_string() != switch {
+                    continue;
+                }
+
+                let lit = !sunlight.is_visible();
+                sunlight.set_visible(lit);
+                let position = Position::from_vector(sunlight.get_position());
+                let tiles = sunlight.bind().tiles(position);
+                for tile in tiles.into_iter().filter(|tile| self.grid.contains(*tile)) {
+                    if lit {
+                        self.add_terrain(tile, Terrain::Sunlight);
+                    } else {
+                        self.remove_terrain(tile, Terrain::Sunlight);
+                    }
+                }
+            }
+        }
+
+        self.burn_sunlit();
+    }
+
+    // An open obstacle is taken off the grid and left faded where it was. It can only close again
+    // once nothing stands beneath it, so a unit in a gateway holds the portcullis up
+    fn set_open(&mut self, obstacle_id: ObstacleId, open: bool) {
+        let Some(mut obstacle) = self.try_get_obstacle(obstacle_id) else {
+            return;
+        };
+        let tiles: Vec<Position> = {
+            let obstacle = obstacle.bind();
+            (0..obstacle.width as usize)
+                .flat_map(|i| (0..obstacle.height as usize).map(move |j| (i, j)))
+                .map(|(i, j)| obstacle.position.offset(i, j))
+                .filter(|tile| self.grid.contains(*tile))
+                .collect()
+        };
+
+        if open {
+            for tile in &tiles {
+                self.grid[*tile].tile = Tile::Empty;
+            }
+        } else if tiles.iter().all(|tile| self.at(*tile).is_empty()) {
+            for tile in &tiles {
+                self.grid[*tile].tile = Tile::Obstacle(obstacle_id);
+            }
+        } else {
+            return;
+        }
+
+        obstacle.bind_mut().open = open;
+        obstacle.set_modulate(Color::from_rgba(
+            1.0,
+            1.0,
+            1.0,
+            if open { 0.3 } else { 1.0 },
+        ));
+        self.fov_cache.borrow_mut().invalidate();
+        self.shadows_cast = false;
+    }
+
+    // Enemies still standing, counting those spawned this turn or on their way and not those
+    // already dying
+    pub fn enemies_remaining(&self) -> usize {
+        self.pending_spawns.len()
+            + self
+                .enemies
+                .keys()
+                .filter(|enemy_id| {
+                    self.try_get_enemy(**enemy_id)
+                        .is_some_and(|enemy| !enemy.bind().animator.is_dying())
+                })
+                .count()
+    }
+
+    // Allies the player here can still act with this turn, in a fixed order to cycle through
+    pub fn unacted_allies(&self) -> Vec<AllyId> {
+        let mut ally_ids: Vec<AllyId> = self
+            .allies
+            .keys()
+            .copied()
+            .filter(|ally_id| self.controls(*ally_id) && !self.is_carried(*ally_id))
+            .filter(|ally_id| {
+                self.try_get_ally(*ally_id)
+                    .is_some_and(|ally| !ally.bind().has_acted)
+            })
+            .collect();
+        ally_ids.sort();
+        ally_ids
+    }
+
+    fn mark_unacted_allies(&self) {
+        let unacted = if self.turn == Turn::Ally && !self.playback {
+            self.unacted_allies()
+        } else {
+            Vec::new()
+        };
+        for ally_id in self.allies.keys() {
+            if let Some(mut ally) = self.try_get_ally(*ally_id) {
+                ally.bind_mut().set_marked(unacted.contains(ally_id));
+            }
+        }
+    }
+
+    // Only the host ends the ally turn, by key or with the button
+    fn can_end_turn(&self) -> bool {
+        self.turn == Turn::Ally
+            && !self.playback
+            && self.is_host()
+            && self.in_flight.is_empty()
+            && self.swap.is_none()
+            && !self.cinematic
+    }
+
+    // The exported loadouts, with allies and abilities that can't be read warned about and skipped
+    fn loadouts(&self) -> HashMap<AllyId, Vec<(Ability, u16)>> {
+        let mut loadouts = HashMap::new();
+        for (ally, loadout) in self.loadouts.iter_shared() {
+            let name = ally.to_string();
+            let (Ok(ally_id), Ok(loadout)) = (
+                serde_json::from_value::<AllyId>(serde_json::Value::String(name.clone())),
+                loadout.try_to::<Dictionary>(),
+            ) else {
+                godot_warn!("Loadout for unknown ally {}", name);
+                continue;
+            };
+
+            let entries: Vec<(String, u16)> = loadout
+                .iter_shared()
+                .filter_map(|(ability, uses)| {
+                    Some((ability.to_string(), uses.try_to::<u16>().ok()?))
+                })
+                .collect();
+            let parsed = parse_loadout(&entries);
+            // An ally with nothing to use is left with their usual list instead
+            if parsed.is_empty() {
+                godot_warn!("Loadout for {} has no abilities, it is left out", name);
+                continue;
+            }
+            if parsed.len() < entries.len() {
+                godot_warn!("Loadout for {} has abilities that don't exist", name);
+            }
+            loadouts.insert(ally_id, parsed);
+        }
+        loadouts
+    }
+
+    // How far a unit sees right now, after what is on it and then the weather
+    pub fn ally_view(&self, ally: &Ally) -> u16 {
+        self.weather.ally_view(effects::view_distance(
+            ally.view_distance,
+            &ally.vitals.effects,
+        ))
+    }
+
+    pub fn enemy_view(&self, enemy: &Enemy) -> u16 {
+        self.weather.enemy_view(effects::view_distance(
+            enemy.view_distance,
+            &enemy.vitals.effects,
+        ))
+    }
+
+    // Enemies only see what is in front of them
+    pub fn enemy_sight(&self, enemy: &Enemy) -> HashSet<Position> {
+        let visible = compute_fov(enemy.position, self.enemy_view(enemy), self);
+        cone(visible, enemy.position, enemy.facing)
+    }
+
+    pub fn controls(&self, ally_id: AllyId) -> bool {
+        !self.online
+            || self.owner(ally_id) == self.base().get_multiplayer().unwrap().get_unique_id()
+    }
+
+    // Apply the next recorded command once the last one has finished playing out
+    fn play_next(&mut self, delta: f64) {
+        let mut cursor = self.base().get_node_as::<Cursor>("CursorLayer/Cursor");
+        // An attack arriving is what the cursor is left waiting on
+        let arriving = matches!(
+            self.replay.commands.get(self.playback_step),
+            Some(Command::Impact { .. })
+        );
+        if !cursor.bind().can_interact && !arriving {
+            return;
+        }
+
+        self.playback_timer += delta;
+        if self.playback_timer < PLAYBACK_STEP && !self.catching_up {
+            return;
+        }
+        self.playback_timer = 0.0;
+
+        let command = match self.replay.commands.get(self.playback_step) {
+            Some(command) => *command,
+            None => {
+                // The player takes over where the recording ends
+                self.playback = false;
+                if self.catching_up {
+                    self.catching_up = false;
+                    settings::set_time_scale(self.phase_speed());
+                }
+                return;
+            }
+        };
+        self.playback_step += 1;
+
+        if let Some(position) = command.position() {
+            cursor.set_position(position.to_vector() + Vector2::new(8.0, 8.0));
+            cursor.bind_mut().position = position;
+        }
+
+        if self.apply(command) {
+            match command {
+                Command::Move { .. } | Command::UseAbility { .. } => {
+                    cursor.bind_mut().can_interact = false
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn pick_up_item(&mut self, ally_id: AllyId, item_id: ItemId) -> bool {
+        let (Some(mut ally), Some(item)) = (self.try_get_ally(ally_id), self.try_get_item(item_id))
+        else {
+            return false;
+        };
+        let mut ally = ally.bind_mut();
+        let ability = item.bind().ability();
+
+        let picked_up = has_room_for(&ally.uses, ally.capacity, ability) && ally.acquire(ability);
+        if picked_up {
+            let item_kind = self.remove_item(item_id);
+            self.events
+                .publish(GameEvent::ItemPickedUp { ally_id, item_kind });
+        }
+
+        picked_up
+    }
+
+    // Off the ground, whether picked up or spoiled, taking any garlic's smell with it
+    fn remove_item(&mut self, item_id: ItemId) -> ItemKind {
+        let mut item = self.get_item(item_id);
+        let (item_kind, position) = {
+            let item = item.bind();
+            (item.kind, item.position)
+        };
+        item.queue_free();
+
+        self.items.remove(&item_id);
+        self.grid[position].item = None;
+        if item_kind == ItemKind::Garlic {
+            self.remove_terrain(position, Terrain::Garlic);
+        }
+        item_kind
+    }
+
+    // Everything the party carries and the level still holds of a consumable, so spending the last
+    // of it is never a surprise
+    pub fn supply(&self, ability: Ability) -> Supply {
+        let carried = self
+            .allies
+            .keys()
+            .filter_map(|ally_id| self.try_get_ally(*ally_id))
+            .map(|ally| *ally.bind().uses.get(&ability).unwrap_or(&0))
+            .sum();
+        let lying = self
+            .items
+            .keys()
+            .filter_map(|item_id| self.try_get_item(*item_id))
+            .filter(|item| item.bind().kind.ability() == ability)
+            .count() as u16;
+        let hidden = self
+            .obstacles
+            .keys()
+            .filter_map(|obstacle_id| self.try_get_obstacle(*obstacle_id))
+            .any(|obstacle| {
+                let obstacle = obstacle.bind();
+                let table: Vec<String> = obstacle
+                    .loot
+                    .to_vec()
+                    .iter()
+                    .map(|entry| entry.to_string())
+                    .collect();
+                obstacle.kind.searchable()
+                    && !obstacle.searched
+                    && loot::could_hold(&table, ability)
+            });
+        Supply {
+            carried,
+            lying,
+            hidden,
+        }
+    }
+
+    fn enemy_act(
+        &mut self,
+        enemy_id: EnemyId,
+        ability: Ability,
+        action: EnemyAction,
+    ) -> Option<ActionResult> {
+        let mut enemy = self.try_get_enemy(enemy_id)?;
+        let mut enemy = enemy.bind_mut();
+
+        let (position, result) = match action {
+            EnemyAction::Attack {
+                ally_id,
+                damage_kind,
+                damage,
+            }
+            | EnemyAction::DashAttack {
+                ally_id,
+                damage_kind,
+                damage,
+                ..
+            } => {
+                let guards: Vec<(AllyId, Position, u16)> = self
+                    .allies
+                    .keys()
+                    .filter(|guard_id| !self.is_carried(**guard_id))
+                    .filter_map(|guard_id| {
+                        let guard = self.try_get_ally(*guard_id)?;
+                        let guard = guard.bind();
+                        guard
+                            .vitals
+                            .effects
+                            .get(Effect::Guarding)
+                            .map(|active| (*guard_id, guard.position, active.stats.magnitude))
+                    })
+                    .collect();
+                let target = self.try_get_ally(ally_id)?.bind().position;
+                // Guarding only holds for the one blow
+                let (ally_id, damage) = match combat::intercept(ally_id, target, damage, &guards) {
+                    Some((guard_id, damage)) => {
+                        if let Some(mut guard) = self.try_get_ally(guard_id) {
+                            guard.bind_mut().vitals.effects.remove(Effect::Guarding);
+                        }
+                        (guard_id, damage)
+                    }
+                    None => (ally_id, damage),
+                };
+
+                let mut ally = self.try_get_ally(ally_id)?;
+                let mut ally = ally.bind_mut();
+                let result = ally.hit(
+                    Damage {
+                        amount: damage,
+                        kind: damage_kind,
+                        source: Some(UnitId::Enemy(enemy_id)),
+                        ability: Some(ability),
+                    },
+                    &mut self.events,
+                );
+                enemy.vitals.drain(result.life_stolen);
+
+                (
+                    ally.position,
+                    ActionResult::hit(UnitId::Ally(ally_id), result),
+                )
+            }
+            EnemyAction::Spawn {
+                enemy_kind,
+                position,
+            } => {
+                let stats = abilities().get(&ability).unwrap();
+                match stats.action {
+                    Action::Spawn { cooldown, .. } => {
+                        enemy.cooldowns.insert(ability, cooldown);
+                    }
+                    _ => (),
+                }
+
+                self.apply(Command::Spawn {
+                    enemy_kind,
+                    position,
+                });
+                (position, ActionResult::default())
+            }
+            EnemyAction::Resurrect {
+                enemy_kind,
+                position,
+            } => {
+                let stats = abilities().get(&ability).unwrap();
+                match stats.action {
+                    Action::Resurrect { cooldown } => {
+                        enemy.cooldowns.insert(ability, cooldown);
+                    }
+                    _ => (),
+                }
+
+                if let Some(i) = self
+                    .graves
+                    .iter()
+                    .position(|grave| *grave == (enemy_kind, position))
+                {
+                    self.graves.remove(i);
+                }
+                if let Some(mut dust_pile) = self.dust_piles.remove(&position) {
+                    dust_pile.queue_free();
+                }
+                self.apply(Command::Spawn {
+                    enemy_kind,
+                    position,
+                });
+                (position, ActionResult::default())
+            }
+            EnemyAction::Charge {
+                ally_id,
+                damage_kind,
+                damage,
+                distance,
+            } => {
+                let (position, result) = {
+                    let mut ally = self.try_get_ally(ally_id)?;
+                    let mut ally = ally.bind_mut();
+                    let result = ally.hit(
+                        Damage {
+                            amount: damage,
+                            kind: damage_kind,
+                            source: Some(UnitId::Enemy(enemy_id)),
+                            ability: Some(ability),
+                        },
+                        &mut self.events,
+                    );
+                    enemy.vitals.drain(result.life_stolen);
+                    (ally.position, result)
+                };
+
+                // Nobody is left standing to be knocked back
+                if !result.killed {
+                    self.apply(Command::Push {
+                        ally_id,
+                        direction: enemy.position.direction_to(position),
+                        distance,
+                    });
+                }
+                (position, ActionResult::hit(UnitId::Ally(ally_id), result))
+            }
+            EnemyAction::AttackNpc {
+                npc_id,
+                damage_kind,
+                damage,
+            } => {
+                let mut npc = self.try_get_npc(npc_id)?;
+                let (result, position, name) = {
+                    let mut npc = npc.bind_mut();
+                    let result = npc.hit(
+                        Damage {
+                            amount: damage,
+                            kind: damage_kind,
+                            source: Some(UnitId::Enemy(enemy_id)),
+                            ability: Some(ability),
+                        },
+                        &mut self.events,
+                    );
+                    (result, npc.position, npc.name())
+                };
+                enemy.vitals.drain(result.life_stolen);
+
+                if result.killed {
+                    self.grid[position].tile = Tile::Empty;
+                    self.npcs.remove(&npc_id);
+                    npc.queue_free();
+                    self.fail_objective(format!("{} didn't make it out", name));
+                }
+                (position, ActionResult::hit(UnitId::Npc(npc_id), result))
+            }
+            EnemyAction::Push {
+                ally_id,
+                damage_kind,
+                damage,
+                distance,
+            } => {
+                let (position, result) = {
+                    let mut ally = self.try_get_ally(ally_id)?;
+                    let mut ally = ally.bind_mut();
+                    let result = ally.hit(
+                        Damage {
+                            amount: damage,
+                            kind: damage_kind,
+                            source: Some(UnitId::Enemy(enemy_id)),
+                            ability: Some(ability),
+                        },
+                        &mut self.events,
+                    );
+                    enemy.vitals.drain(result.life_stolen);
+                    (ally.position, result)
+                };
+
+                self.apply(Command::Push {
+                    ally_id,
+                    direction: enemy.position.direction_to(position),
+                    distance,
+                });
+                (position, ActionResult::hit(UnitId::Ally(ally_id), result))
+            }
+            EnemyAction::Effect { effect, stats } => {
+                enemy
+                    .vitals
+                    .effects
+                    .apply(effect, stats, Some(UnitId::Enemy(enemy_id)));
+                (
+                    enemy.position,
+                    ActionResult::effect(UnitId::Enemy(enemy_id), effect),
+                )
+            }
+            EnemyAction::Inflict {
+                ally_id,
+                effect,
+                stats,
+            } => {
+                let mut ally = self.try_get_ally(ally_id)?;
+                let mut ally = ally.bind_mut();
+                ally.vitals
+                    .effects
+                    .apply(effect, stats, Some(UnitId::Enemy(enemy_id)));
+                // What the party can see may have just shrunk
+                self.shadows_cast = false;
+                (
+                    ally.position,
+                    ActionResult::effect(UnitId::Ally(ally_id), effect),
+                )
+            }
+            EnemyAction::Spoil { position } => {
+                if let Some(item_id) = self.item_at(position) {
+                    self.remove_item(item_id);
+                }
+                (position, ActionResult::default())
+            }
+        };
+
+        enemy.use_ability(ability, position);
+        enemy.current_ability = None;
+        enemy.last_result = Some(result.clone());
+        Some(result)
+    }
+
+    fn follow_plan(&mut self, i: usize, enemy: &mut Enemy, (path, ability): Plan) {
+        if let Some(path) = path {
+            let position = *path.last().unwrap();
+
+            // Remove enemy from previous positions and add to new position
+            for i in 0..enemy.width as usize {
+                for j in 0..enemy.height as usize {
+                    self.grid[enemy.position.offset(i, j)].tile = Tile::Empty;
+                }
+            }
+
+            for i in 0..enemy.width as usize {
+                for j in 0..enemy.height as usize {
+                    self.grid[position.offset(i, j)].tile = Tile::Enemy(enemy.id);
+                }
+            }
+
+            enemy.current_ability = ability;
+            match ability {
+                Some((_, EnemyAction::Charge { .. })) => enemy.dash(position),
+                // Off-screen enemies skip the walk and the camera pan, one per frame so
+                // their abilities are still applied in turn order
+                _ if !self.is_watched(enemy.position, &path) => {
+                    enemy.move_immediately(position, self);
+                    return;
+                }
+                _ => enemy.follow_path(path),
+            }
+
+            self.turn = Turn::Enemy(i, true);
+        } else {
+            self.turn = Turn::Enemy(i + 1, false);
+        }
+    }
+
+    fn control_enemy(
+        &mut self,
+        enemy_id: EnemyId,
+        position: Position,
+        action: Option<(Ability, EnemyAction)>,
+    ) -> bool {
+        let i = match self.turn {
+            Turn::Enemy(i, false) if self.awaiting == Some(enemy_id) => i,
+            _ => return false,
+        };
+
+        let Some(mut enemy) = self.try_get_enemy(enemy_id) else {
+            return false;
+        };
+        let mut enemy = enemy.bind_mut();
+
+        if let Some((ability, action)) = action {
+            let target = match action {
+                EnemyAction::Attack { ally_id, .. }
+                | EnemyAction::Charge { ally_id, .. }
+                | EnemyAction::DashAttack { ally_id, .. }
+                | EnemyAction::Push { ally_id, .. }
+                | EnemyAction::Inflict { ally_id, .. } => {
+                    self.try_get_ally(ally_id).map(|ally| ally.bind().position)
+                }
+                EnemyAction::AttackNpc { npc_id, .. } => {
+                    self.try_get_npc(npc_id).map(|npc| npc.bind().position)
+                }
+                EnemyAction::Spawn { position, .. }
+                | EnemyAction::Resurrect { position, .. }
+                | EnemyAction::Spoil { position } => Some(position),
+                EnemyAction::Effect { .. } => Some(position),
+            };
+            let Some(target) = target else {
+                return false;
+            };
+            if self.enemy_action(&enemy, ability, position, target) != Some(action) {
+                return false;
+            }
+        }
+
+        let path = match action {
+            // Charges dash from where the enemy stands
+            Some((_, EnemyAction::Charge { .. })) if position != enemy.position => return false,
+            Some((_, EnemyAction::Charge { ally_id, .. })) => {
+                let Some(target) = self.try_get_ally(ally_id).map(|ally| ally.bind().position)
+                else {
+                    return false;
+                };
+                match line_to(enemy.position, target, &self.grid) {
+                    Some(line) if line.len() > 1 => Some(line[0..line.len() - 1].to_vec()),
+                    _ => Some(vec![enemy.position]),
+                }
+            }
+            Some((_, EnemyAction::DashAttack { distance, .. })) => {
+                match self.enemy_path_within(&enemy, position, distance) {
+                    Some(path) => Some(path),
+                    None => return false,
+                }
+            }
+            Some(_) => match self.enemy_path(&enemy, position) {
+                Some(path) => Some(path),
+                None => return false,
+            },
+            None if position == enemy.position => None,
+            None => match self.enemy_path(&enemy, position) {
+                Some(path) => Some(path),
+                None => return false,
+            },
+        };
+
+        self.awaiting = None;
+        self.follow_plan(i, &mut enemy, (path, action));
+        true
+    }
+
+    // Run an ally would make to strike at a target with a dash attack, empty if already in reach
+    pub fn ally_dash_path(
+        &self,
+        ally: &Ally,
+        target: Position,
+        stats: &AbilityStats,
+    ) -> Option<Vec<Position>> {
+        let Action::DashAttack { distance, .. } = stats.action else {
+            return None;
+        };
+        dash_path(
+            &mut self.pathfinder.borrow_mut(),
+            ally.position,
+            target,
+            (stats.range, distance),
+            &self.grid,
+            Mover {
+                tile: Tile::Ally(ally.id),
+                dimensions: (1, 1),
+                movement: Movement::Walk,
+                diagonal: self.diagonal_movement,
+                avoids: None,
+            },
+        )
+    }
+
+    // Path an enemy would walk to reach a position this turn
+    pub fn enemy_path(&self, enemy: &Enemy, position: Position) -> Option<Vec<Position>> {
+        self.enemy_path_within(enemy, position, enemy.speed)
+    }
+
+    fn enemy_path_within(
+        &self,
+        enemy: &Enemy,
+        position: Position,
+        max_cost: u16,
+    ) -> Option<Vec<Position>> {
+        if position == enemy.position {
+            return Some(vec![enemy.position]);
+        }
+
+        let movement = if enemy.traits().contains(&Trait::Incorporeal) {
+            Movement::Phase
+        } else {
+            Movement::Walk
+        };
+        self.pathfinder.borrow_mut().a_star(
+            enemy.position,
+            position,
+            &self.grid,
+            Mover {
+                tile: Tile::Enemy(enemy.id),
+                dimensions: (enemy.width as usize, enemy.height as usize),
+                movement,
+                diagonal: self.diagonal_movement,
+                avoids: enemy
+                    .traits()
+                    .contains(&Trait::GarlicAllergy)
+                    .then_some(Terrain::Garlic),
+            },
+            Some(max_cost),
+        )
+    }
+
+    // What an enemy's ability would do to a tile if used from a position
+    pub fn enemy_action(
+        &self,
+        enemy: &Enemy,
+        ability: Ability,
+        origin: Position,
+        target: Position,
+    ) -> Option<EnemyAction> {
+        let stats = abilities().get(&ability).unwrap();
+        let cooldown_finished = *enemy.cooldowns.get(&ability).unwrap_or(&0) == 0;
+        let ally_id = match self.at(target) {
+            Tile::Ally(ally_id) => Some(ally_id),
+            _ => None,
+        };
+
+        match stats.action {
+            Action::Attack {
+                damage_kind,
+                damage,
+                ..
+            } => match (
+                self.at(target),
+                self.ability_line(origin, target, stats, Tile::Enemy(enemy.id)),
+            ) {
+                (Tile::Ally(ally_id), Some(_)) => Some(EnemyAction::Attack {
+                    ally_id,
+                    damage_kind,
+                    damage,
+                }),
+                (Tile::Npc(npc_id), Some(_)) => Some(EnemyAction::AttackNpc {
+                    npc_id,
+                    damage_kind,
+                    damage,
+                }),
+                _ => None,
+            },
+            Action::Push {
+                damage_kind,
+                damage,
+                distance,
+            } => match (
+                self.at(target),
+                self.ability_line(origin, target, stats, Tile::Enemy(enemy.id)),
+            ) {
+                (Tile::Ally(ally_id), Some(_)) => Some(EnemyAction::Push {
+                    ally_id,
+                    damage_kind,
+                    damage,
+                    distance,
+                }),
+                (Tile::Npc(npc_id), Some(_)) => Some(EnemyAction::AttackNpc {
+                    npc_id,
+                    damage_kind,
+                    damage,
+                }),
+                _ => None,
+            },
+            Action::DashAttack {
+                damage_kind,
+                damage,
+                distance,
+            } => ally_id
+                .filter(|_| {
+                    self.ability_line(origin, target, stats, Tile::Enemy(enemy.id))
+                        .is_some()
+                })
+                .map(|ally_id| EnemyAction::DashAttack {
+                    ally_id,
+                    damage_kind,
+                    damage,
+                    distance,
+                }),
+            Action::Effect { effect, stats } if target == origin => {
+                Some(EnemyAction::Effect { effect, stats })
+            }
+            Action::Inflict {
+                effect,
+                stats: effect_stats,
+            } => ally_id
+                .filter(|_| {
+                    self.ability_line(origin, target, stats, Tile::Enemy(enemy.id))
+                        .is_some()
+                })
+                .map(|ally_id| EnemyAction::Inflict {
+                    ally_id,
+                    effect,
+                    stats: effect_stats,
+                }),
+            Action::Spoil => self
+                .item_at(target)
+                .filter(|_| {
+                    self.ability_line(origin, target, stats, Tile::Enemy(enemy.id))
+                        .is_some()
+                })
+                .map(|_| EnemyAction::Spoil { position: target }),
+            Action::Charge {
+                damage_kind,
+                damage,
+                distance,
+            } => match (ally_id, line_to(origin, target, &self.grid)) {
+                (Some(ally_id), Some(line)) if line.len() as u16 <= stats.range + 1 => {
+                    Some(EnemyAction::Charge {
+                        ally_id,
+                        damage_kind,
+                        damage,
+                        distance,
+                    })
+                }
+                _ => None,
+            },
+            Action::Spawn { enemy_kind, .. } => {
+                let adjacent = (0..enemy.width as usize)
+                    .flat_map(|i| (0..enemy.height as usize).map(move |j| (i, j)))
+                    .any(|(i, j)| {
+                        Position {
+                            x: origin.x + i,
+                            y: origin.y + j,
+                        }
+                        .adjacent()
+                        .contains(&target)
+                    });
+                if cooldown_finished
+                    && adjacent
+                    && has_room(target, enemy_kind.dimensions(), &self.grid)
+                {
+                    Some(EnemyAction::Spawn {
+                        enemy_kind,
+                        position: target,
+                    })
+                } else {
+                    None
+                }
+            }
+            Action::Resurrect { .. } => self
+                .graves
+                .iter()
+                .find(|(_, position)| *position == target)
+                .filter(|(enemy_kind, _)| {
+                    cooldown_finished
+                        && origin.distance(target) <= stats.range
+                        && has_room(target, enemy_kind.dimensions(), &self.grid)
+                })
+                .map(|(enemy_kind, position)| EnemyAction::Resurrect {
+                    enemy_kind: *enemy_kind,
+                    position: *position,
+                }),
+            _ => None,
+        }
+    }
+
+    // Abilities of a unit on either side and which of them is selected
+    pub fn unit_abilities(&self, unit_id: UnitId) -> (Vec<Ability>, usize) {
+        match unit_id {
+            UnitId::Ally(ally_id) => {
+                let ally = self.get_ally(ally_id);
+                let ally = ally.bind();
+                (ally.abilities.clone(), ally.selected_ability)
+            }
+            UnitId::Enemy(enemy_id) => {
+                let enemy = self.get_enemy(enemy_id);
+                let enemy = enemy.bind();
+                (enemy.abilities.clone(), enemy.selected_ability)
+            }
+            UnitId::Npc(_) => (Vec::new(), 0),
+        }
+    }
+
+    pub fn select_unit_ability(&self, unit_id: UnitId, i: usize) {
+        match unit_id {
+            UnitId::Ally(ally_id) => self.get_ally(ally_id).bind_mut().selected_ability = i,
+            UnitId::Enemy(enemy_id) => self.get_enemy(enemy_id).bind_mut().selected_ability = i,
+            UnitId::Npc(_) => (),
+        }
+    }
+
+    pub fn is_carried(&self, ally_id: AllyId) -> bool {
+        carry::is_carried(&self.carried, ally_id)
+    }
+
+    // Whether the allies can see any step of a move
+    pub fn is_watched(&self, start: Position, path: &[Position]) -> bool {
+        let shadow_map = self
+            .base()
+            .get_node_as::<ShadowMap>("ShadowLayer/ShadowMap");
+        let shadow_map = shadow_map.bind();
+        shadow_map.visible.contains(&start)
+            || path
+                .iter()
+                .any(|position| shadow_map.visible.contains(position))
+    }
+
+    // A fight is on whenever an enemy can see one of the allies
+    pub fn in_combat(&self) -> bool {
+        let positions: Vec<Position> = self
+            .allies
+            .keys()
+            .filter(|ally_id| !self.is_carried(**ally_id))
+            .map(|ally_id| self.get_ally(*ally_id).bind().position)
+            .collect();
+
+        self.enemies.keys().any(|enemy_id| {
+            let enemy = self.get_enemy(*enemy_id);
+            let enemy = enemy.bind();
+            let visible = self.enemy_sight(&enemy);
+            positions.iter().any(|position| visible.contains(position))
+        })
+    }
+
+    // Carrying another ally halves movement
+    pub fn ally_speed(&self, ally: &Ally) -> u16 {
+        if self.carried.contains_key(&ally.id) {
+            cmp::max(ally.speed / 2, 1)
+        } else {
+            ally.speed
+        }
+    }
+
+    pub fn can_carry(&self, carrier: &Ally, ally_id: AllyId) -> bool {
+        *carrier.current_ability() == Ability::Carry
+            && carrier.id != ally_id
+            && !self.carried.contains_key(&carrier.id)
+            && !self.carried.contains_key(&ally_id)
+            && self.try_get_ally(ally_id).is_some_and(|ally| {
+                let ally = ally.bind();
+                carrier.position.distance(ally.position) == 1
+                    && carry::can_be_carried(&ally.vitals, self.ally_speed(&ally))
+            })
+    }
+
+    // Only picked up abilities go back on the ground, underfoot or on an open tile beside the ally
+    pub fn can_drop_item(&self, ally: &Ally, ability: Ability, position: Position) -> bool {
+        let stats = abilities().get(&ability).unwrap();
+        stats.acquirable
+            && stats.consumable
+            && ItemKind::from_ability(ability).is_some()
+            && ally.uses.get(&ability).is_some_and(|uses| *uses > 0)
+            && !self.is_carried(ally.id)
+            && self.grid.contains(position)
+            && self.grid[position].item.is_none()
+            && (position == ally.position
+                || (ally.position.adjacent().contains(&position) && self.at(position).is_empty()))
+    }
+
+    // The giver's selected ability, to an ally beside them who can take it
+    pub fn can_trade(&self, giver: &Ally, ally_id: AllyId) -> bool {
+        let ability = *giver.current_ability();
+        giver.id != ally_id
+            && !giver.has_traded
+            && giver.uses.get(&ability).is_some_and(|uses| *uses > 0)
+            && !self.is_carried(giver.id)
+            && !self.is_carried(ally_id)
+            && self.try_get_ally(ally_id).is_some_and(|ally| {
+                let ally = ally.bind();
+                giver.position.distance(ally.position) == 1
+                    && can_hand_over(ability, ally.abilities.contains(&ability))
+                    && has_room_for(&ally.uses, ally.capacity, ability)
+            })
+    }
+
+    pub fn can_drop(&self, carrier: &Ally, position: Position) -> bool {
+        *carrier.current_ability() == Ability::Carry
+            && self.carried.contains_key(&carrier.id)
+            && self.at(position).is_empty()
+            && carrier.position.distance(position) == 1
+    }
+
+    // The line an attack on the tile would take from the ally, ending on whatever it would strike
+    fn aim(
+        &self,
+        ally: &Ally,
+        position: Position,
+        enemy_id: Option<EnemyId>,
+        stats: &AbilityStats,
+    ) -> Option<Vec<Position>> {
+        // Shots meant for a chandelier's rope go over the head of anyone beneath it
+        let chandelier = self.chandelier_at(position).filter(|_| {
+            matches!(stats.action, Action::Attack { .. }) && stats.action.clears_low_obstacles()
+        });
+        if chandelier.is_some() {
+            return self.ability_line(ally.position, position, stats, Tile::Ally(ally.id));
+        }
+
+        // Area attacks reach the enemies around their target when they burst, so only the target
+        // itself has to be in sight
+        if let Some(enemy) = enemy_id.and_then(|enemy_id| self.try_get_enemy(enemy_id)) {
+            let enemy = enemy.bind();
+            for i in 0..enemy.width as usize {
+                for j in 0..enemy.height as usize {
+                    let position = Position {
+                        x: enemy.position.x + i,
+                        y: enemy.position.y + j,
+                    };
+                    // There must not be obstacles obstructing line of sight
+                    let line =
+                        self.ability_line(ally.position, position, stats, Tile::Ally(ally.id));
+                    if line.is_some() {
+                        return line;
+                    }
+                }
+            }
+        } else if let Tile::Obstacle(obstacle_id) = self.at(position) {
+            if self
+                .try_get_obstacle(obstacle_id)
+                .is_some_and(|obstacle| obstacle.bind().kind == ObstacleKind::Coffin)
+            {
+                return self.ability_line(ally.position, position, stats, Tile::Ally(ally.id));
+            }
+        }
+        None
+    }
+
+    // Where a bolt fired from the origin at the tile comes down, on whoever stands in its line by
+    // the time it arrives
+    fn intercept(&self, origin: Position, position: Position) -> Position {
+        line_of_sight(origin, position, |_| true)
+            .and_then(|line| {
+                math::intercept(&line, |tile| {
+                    matches!(self.at(tile), Tile::Ally(_) | Tile::Enemy(_) | Tile::Npc(_))
+                })
+            })
+            .unwrap_or(position)
+    }
+
+    // Whatever is on the tile takes the attack, which for a bolt is wherever it came down
+    fn land(&mut self, ally: &mut Ally, ability: Ability, position: Position) -> ActionResult {
+        let stats = abilities().get(&ability).unwrap();
+        if let Some(loudness) = noise::loudness(ability) {
+            self.make_noise(ally.id, ally.position, loudness);
+        }
+        let (damage_kind, damage) = match stats.action {
+            Action::Attack {
+                damage_kind,
+                damage,
+                ..
+            }
+            | Action::Push {
+                damage_kind,
+                damage,
+                ..
+            }
+            | Action::DashAttack {
+                damage_kind,
+                damage,
+                ..
+            } => (damage_kind, damage),
+            _ => return ActionResult::default(),
+        };
+        // Shooting from inside a fear aura
+        let hit = Damage {
+            amount: auras::frightened(damage, self.auras.fear(ally.position)),
+            kind: damage_kind,
+            source: Some(UnitId::Ally(ally.id)),
+            ability: Some(ability),
+        };
+
+        let chandelier = self.chandelier_at(position).filter(|_| {
+            matches!(stats.action, Action::Attack { .. }) && stats.action.clears_low_obstacles()
+        });
+        if let Some(obstacle_id) = chandelier {
+            self.cut_chandelier(obstacle_id);
+            return ActionResult::default();
+        }
+
+        match stats.action {
+            Action::Attack { aoe, .. } if aoe => return self.explode(ally, position, hit),
+            _ => (),
+        }
+
+        match self.at(position) {
+            Tile::Enemy(enemy_id) => {
+                let Some(mut enemy) = self.try_get_enemy(enemy_id) else {
+                    return ActionResult::default();
+                };
+                let mut enemy = enemy.bind_mut();
+                let result = enemy.hit(hit, &mut self.events);
+                enemy.notice(ally.id, ally.position);
+                ally.vitals.drain(result.life_stolen);
+
+                match stats.action {
+                    Action::Push { distance, .. } => {
+                        let direction = ally.position.direction_to(enemy.position);
+                        enemy.push(self, direction, distance);
+                    }
+                    _ => (),
+                }
+
+                ActionResult::hit(UnitId::Enemy(enemy_id), result)
+            }
+            Tile::Obstacle(obstacle_id) => {
+                if self
+                    .try_get_obstacle(obstacle_id)
+                    .is_some_and(|obstacle| obstacle.bind().kind == ObstacleKind::Coffin)
+                {
+                    self.hit_obstacle(obstacle_id, damage, damage_kind);
+                }
+                ActionResult::default()
+            }
+            // Someone who stepped into the line of fire
+            Tile::Ally(ally_id) if ally_id != ally.id => {
+                let unit = UnitId::Ally(ally_id);
+                ActionResult::hit(unit, self.hit_unit(unit, hit, "didn't make it out"))
+            }
+            Tile::Npc(npc_id) => {
+                let unit = UnitId::Npc(npc_id);
+                ActionResult::hit(unit, self.hit_unit(unit, hit, "didn't make it out"))
+            }
+            _ => ActionResult::default(),
+        }
+    }
+
+    // A flask bursts over its tile and the ones beside it. Everyone caught is hit once, whichever
+    // side they are on, barrels are blown apart and fire flasks leave the ground burning
+    fn explode(&mut self, ally: &mut Ally, position: Position, damage: Damage) -> ActionResult {
+        let mut result = ActionResult::default();
+        let mut struck = HashSet::new();
+        let mut obstacles = HashSet::new();
+        for tile in explosion::burst(position, &self.grid) {
+            match self.at(tile) {
+                Tile::Enemy(enemy_id) if struck.insert(enemy_id) => {
+                    if let Some(mut enemy) = self.try_get_enemy(enemy_id) {
+                        let mut enemy = enemy.bind_mut();
+                        let hit = enemy.hit(damage, &mut self.events);
+                        enemy.notice(ally.id, ally.position);
+                        ally.vitals.drain(hit.life_stolen);
+                        result.add_hit(UnitId::Enemy(enemy_id), hit);
+                    }
+                }
+                // The thrower is already bound, so they are hit directly
+                Tile::Ally(ally_id) if ally_id == ally.id => {
+                    let hit = ally.hit(damage, &mut self.events);
+                    result.add_hit(UnitId::Ally(ally_id), hit);
+                }
+                Tile::Ally(ally_id) => {
+                    let unit = UnitId::Ally(ally_id);
+                    result.add_hit(unit, self.hit_unit(unit, damage, "didn't make it out"));
+                }
+                Tile::Npc(npc_id) => {
+                    let unit = UnitId::Npc(npc_id);
+                    result.add_hit(unit, self.hit_unit(unit, damage, "didn't make it out"));
+                }
+                Tile::Obstacle(obstacle_id) if obstacles.insert(obstacle_id) => {
+                    let kind = self
+                        .try_get_obstacle(obstacle_id)
+                        .map(|obstacle| obstacle.bind().kind);
+                    match kind {
+                        Some(ObstacleKind::Barrel) => self.remove_obstacle(obstacle_id),
+                        Some(ObstacleKind::Coffin) => {
+                            self.hit_obstacle(obstacle_id, damage.amount, damage.kind)
+                        }
+                        _ => (),
+                    }
+                }
+                _ => (),
+            }
+
+            if damage.kind == DamageKind::Fire && !matches!(self.at(tile), Tile::Obstacle(_)) {
+                self.ignite(tile);
+            }
+        }
+
+        if !self.catching_up {
+            let mut explosion = Explosion::new_alloc();
+            explosion.bind_mut().damage_kind = Some(damage.kind);
+            explosion.set_position(position.to_vector() + Vector2::new(8.0, 8.0));
+            self.base_mut().add_child(explosion.upcast());
+        }
+        self.make_noise(ally.id, position, EXPLOSION_NOISE);
+
+        result
+    }
+
+    // Enemies within earshot go looking where the sound came from, even without seeing who made
+    // it. Those who can see them will have their real position when they next plan anyway
+    fn make_noise(&mut self, ally_id: AllyId, origin: Position, loudness: u16) {
+        for enemy_id in self.enemies.keys() {
+            if let Some(mut enemy) = self.try_get_enemy(*enemy_id) {
+                let mut enemy = enemy.bind_mut();
+                if noise::hears(enemy.position, origin, loudness) {
+                    enemy.notice(ally_id, origin);
+                }
+            }
+        }
+
+        if !self.catching_up {
+            let mut ring = NoiseRing::new_alloc();
+            ring.bind_mut().loudness = loudness;
+            ring.set_position(origin.to_vector() + Vector2::new(8.0, 8.0));
+            self.base_mut().add_child(ring.upcast());
+        }
+    }
+
+    // Flames already on the tile burn on for longer instead
+    fn ignite(&mut self, position: Position) {
+        let round = self.round + FIRE_ROUNDS;
+        if let Some((out, _)) = self.fires.get_mut(&position) {
+            *out = round;
+            return;
+        }
+
+        let mut flames = Flames::new_alloc();
+        flames.set_position(position.to_vector());
+        self.base_mut().add_child(flames.clone().upcast());
+        self.add_terrain(position, Terrain::Fire);
+        self.fires.insert(position, (round, flames));
+    }
+
+    // Fires that have burnt out go first, then everyone still standing in one catches alight
+    // unless fire can't touch them
+    fn burn_fires(&mut self) {
+        let mut out: Vec<Position> = self
+            .fires
+            .iter()
+            .filter(|(_, (round, _))| *round <= self.round)
+            .map(|(position, _)| *position)
+            .collect();
+        out.sort();
+        for position in out {
+            let (_, mut flames) = self.fires.remove(&position).unwrap();
+            flames.queue_free();
+            self.remove_terrain(position, Terrain::Fire);
+        }
+
+        let burning = |level: &Level, tiles: Vec<Position>| {
+            tiles.iter().any(|tile| {
+                level.grid.contains(*tile) && level.grid[*tile].terrain.contains(&Terrain::Fire)
+            })
+        };
+        let burn = EffectStats {
+            magnitude: 1,
+            duration: 3,
+        };
+
+        let mut enemy_ids: Vec<EnemyId> = self.enemies.keys().copied().collect();
+        enemy_ids.sort();
+        for enemy_id in enemy_ids {
+            let Some(mut enemy) = self.try_get_enemy(enemy_id) else {
+                continue;
+            };
+            let mut enemy = enemy.bind_mut();
+            if burning(self, enemy.tiles())
+                && !enemy.animator.is_dying()
+                && !combat::is_immune(DamageKind::Fire, &enemy.vitals.traits())
+            {
+                enemy.vitals.effects.apply(Effect::Burn, burn, None);
+            }
+        }
+
+        let mut ally_ids: Vec<AllyId> = self.allies.keys().copied().collect();
+        ally_ids.sort();
+        for ally_id in ally_ids {
+            if self.is_carried(ally_id) {
+                continue;
+            }
+            let Some(mut ally) = self.try_get_ally(ally_id) else {
+                continue;
+            };
+            let mut ally = ally.bind_mut();
+            if burning(self, vec![ally.position])
+                && !combat::is_immune(DamageKind::Fire, &ally.vitals.traits())
+            {
+                ally.vitals.effects.apply(Effect::Burn, burn, None);
+            }
+        }
+    }
+
+    // Everything near a fallen leader checks its nerve. Rolled from the level's seed, the round and
+    // the enemy, so replays and both ends of an online game break the same way
+    pub fn break_morale(&mut self, position: Position) {
+        let mut enemy_ids: Vec<EnemyId> = self.enemies.keys().copied().collect();
+        enemy_ids.sort();
+        for enemy_id in enemy_ids {
+            let Some(mut enemy) = self.try_get_enemy(enemy_id) else {
+                continue;
+            };
+            let courage = {
+                let enemy = enemy.bind();
+                (enemy.position.distance(position) <= MORALE_RADIUS && !enemy.animator.is_dying())
+                    .then(|| enemy.kind.courage())
+            };
+            let Some(courage) = courage.filter(|courage| *courage < 100) else {
+                continue;
+            };
+
+            let stream = MORALE_STREAM + ((self.round as u64) << 16) + enemy_id as u64;
+            let roll = Rng::stream(self.loot_seed, stream).below(100) as u16;
+            match Morale::check(courage, roll) {
+                Morale::Holds => (),
+                Morale::Flees => enemy.bind_mut().fleeing = FLEE_TURNS,
+                Morale::Scatters => self.scatter(enemy_id),
+            }
+        }
+    }
+
+    // Gone for good without a fight, leaving no dust behind
+    fn scatter(&mut self, enemy_id: EnemyId) {
+        let mut enemy = self.get_enemy(enemy_id);
+        let tiles = enemy.bind().tiles();
+        for tile in tiles {
+            if self.grid.contains(tile) {
+                self.grid[tile].tile = Tile::Empty;
+            }
+        }
+
+        self.enemies.remove(&enemy_id);
+        if let Some(i) = self.turn_order.iter().position(|(id, _)| *id == enemy_id) {
+            self.turn_order.remove(i);
+        }
+        enemy.queue_free();
+    }
+
+    // A later death on the same tile leaves its dust over the earlier one
+    pub fn leave_dust(
+        &mut self,
+        enemy_kind: EnemyKind,
+        position: Position,
+        dimensions: (u16, u16),
+    ) {
+        self.deaths.push(Death {
+            enemy_kind,
+            position,
+            round: self.round,
+        });
+
+        let mut dust_pile = DustPile::new_alloc();
+        {
+            let mut dust_pile = dust_pile.bind_mut();
+            dust_pile.enemy_kind = enemy_kind;
+            (dust_pile.width, dust_pile.height) = dimensions;
+        }
+        dust_pile.set_position(position.to_vector());
+        dust_pile.set_z_index(-1);
+        self.base_mut().add_child(dust_pile.clone().upcast());
+        if let Some(mut old) = self.dust_piles.insert(position, dust_pile) {
+            old.queue_free();
+        }
+    }
+
+    // Dropping a carried ally is a free action
+    fn drop_item(&mut self, ally_id: AllyId, ability: Ability, position: Position) -> bool {
+        let Some(mut ally) = self.try_get_ally(ally_id) else {
+            return false;
+        };
+        let mut ally = ally.bind_mut();
+        if !self.can_drop_item(&ally, ability, position) {
+            return false;
+        }
+
+        let item_kind = ItemKind::from_ability(ability).unwrap();
+        ally.hand_over(ability);
+        self.spawn_item(item_kind, position);
+        self.events
+            .publish(GameEvent::ItemDropped { ally_id, item_kind });
+        true
+    }
+
+    // Puts up a choice of what to leave behind, only for whoever controls the ally and never
+    // while playing back, where the recorded answer follows on its own
+    pub fn prompt_swap(&mut self, ally_id: AllyId, item_id: ItemId, held: Vec<Ability>) {
+        let options: Vec<Option<Ability>> = std::iter::once(None)
+            .chain(held.into_iter().map(Some))
+            .collect();
+        let mut swap = PendingSwap {
+            ally_id,
+            item_id,
+            options: options.clone(),
+            prompt: None,
+        };
+        if self.playback || !self.controls(ally_id) {
+            self.swap = Some(swap);
+            return;
+        }
+
+        let item_kind = self.get_item(item_id).bind().kind;
+        let mut list = VBoxContainer::new_alloc();
+        let mut title = Label::new_alloc();
+        title.set_text(format!("No room for the {}, leave what behind?", item_kind.name()).into());
+        list.add_child(title.upcast());
+        let mut first = None;
+        for (i, option) in options.iter().enumerate() {
+            let mut button = Button::new_alloc();
+            button.set_text(match option {
+                Some(ability) => abilities().get(ability).unwrap().name.clone().into(),
+                None => item_kind.name().into(),
+            });
+            button.connect(
+                "pressed".into(),
+                Callable::from_object_method(&self.base(), "_on_swap_chosen")
+                    .bindv(varray![i as i64]),
+            );
+            list.add_child(button.clone().upcast());
+            first.get_or_insert(button);
+        }
+
+        let mut panel = PanelContainer::new_alloc();
+        panel.add_child(list.upcast());
+        let mut layer = CanvasLayer::new_alloc();
+        layer.set_layer(50);
+        layer.add_child(panel.upcast());
+        self.base_mut().add_child(layer.clone().upcast());
+        if let Some(mut first) = first {
+            first.grab_focus();
+        }
+
+        swap.prompt = Some(layer);
+        self.swap = Some(swap);
+    }
+
+    fn swap_item(&mut self, ally_id: AllyId, item_id: ItemId, ability: Option<Ability>) -> bool {
+        if let Some(mut prompt) = self.swap.take().and_then(|swap| swap.prompt) {
+            prompt.queue_free();
+        }
+        let Some(ability) = ability else {
+            return true;
+        };
+
+        let (Some(mut ally), Some(item)) = (self.try_get_ally(ally_id), self.try_get_item(item_id))
+        else {
+            return false;
+        };
+        let mut ally = ally.bind_mut();
+        let position = item.bind().position;
+        let Some(dropped) = ItemKind::from_ability(ability) else {
+            return false;
+        };
+        if position != ally.position || !ally.hand_over(ability) {
+            return false;
+        }
+
+        let item_kind = self.remove_item(item_id);
+        ally.acquire(item_kind.ability());
+        self.spawn_item(dropped, position);
+        self.events.publish(GameEvent::ItemDropped {
+            ally_id,
+            item_kind: dropped,
+        });
+        self.events
+            .publish(GameEvent::ItemPickedUp { ally_id, item_kind });
+        true
+    }
+
+    fn trade(&mut self, from: AllyId, to: AllyId, ability: Ability) -> bool {
+        let (Some(mut giver), Some(mut taker)) = (self.try_get_ally(from), self.try_get_ally(to))
+        else {
+            return false;
+        };
+        let mut giver = giver.bind_mut();
+        if *giver.current_ability() != ability || !self.can_trade(&giver, to) {
+            return false;
+        }
+
+        giver.hand_over(ability);
+        giver.has_traded = true;
+        taker.bind_mut().acquire(ability);
+        self.events
+            .publish(GameEvent::ItemTraded { from, to, ability });
+        true
+    }
+
+    fn drop_ally(&mut self, carrier_id: AllyId, position: Position) -> bool {
+        let Some(carrier) = self.try_get_ally(carrier_id) else {
+            return false;
+        };
+        if self.can_drop(&carrier.bind(), position) {
+            let carried_id = self.carried.remove(&carrier_id).unwrap();
+            let Some(mut ally) = self.try_get_ally(carried_id) else {
+                return false;
+            };
+            let mut ally = ally.bind_mut();
+            ally.teleport(position);
+            self.grid[position].tile = Tile::Ally(carried_id);
+            self.shadows_cast = false;
+            return true;
+        }
+        false
+    }
+
+    fn move_ally(&mut self, ally_id: AllyId, position: Position) -> bool {
+        let Some(mut ally) = self.try_get_ally(ally_id) else {
+            return false;
+        };
+        let mut ally = ally.bind_mut();
+        let speed = self.ally_speed(&ally);
+        if !ally.has_moved {
+            let path = self.pathfinder.borrow_mut().a_star(
+                ally.position,
+                position,
+                &self.grid,
+                Mover {
+                    tile: Tile::Ally(ally.id),
+                    dimensions: (1, 1),
+                    movement: Movement::Walk,
+                    diagonal: self.diagonal_movement,
+                    avoids: None,
+                },
+                Some(speed),
+            );
+            match path {
+                Some(mut path) if !path.is_empty() => {
+                    // Stopping on the stairs goes on up or down them
+                    if let Some(stairs) = self.stairs_from(*path.last().unwrap()) {
+                        path.push(stairs);
+                    }
+                    self.grid[ally.position].tile = Tile::Empty;
+                    ally.follow_path(path);
+                    return true;
+                }
+                _ => (),
+            }
+        }
+        false
+    }
+
+    fn use_ability(
+        &mut self,
+        ally_id: AllyId,
+        position: Position,
+        enemy_id: Option<EnemyId>,
+    ) -> Option<ActionResult> {
+        let Some(mut ally) = self.try_get_ally(ally_id) else {
+            return None;
+        };
+        let mut ally = ally.bind_mut();
+
+        // Mist form prevents allies from using abilities, as does being mid-dash
+        if !ally.has_acted
+            && !self.in_flight.contains(&ally_id)
+            && !ally.vitals.effects.contains(Effect::Mist)
+        {
+            let ability = *ally.current_ability();
+            let stats = abilities().get(&ability).unwrap();
+            match stats.action {
+                Action::Attack { .. } | Action::Push { .. } => {
+                    if let Some(line) = self.aim(&ally, position, enemy_id, stats) {
+                        // Where the shot comes down is settled as it is fired, nothing can move
+                        // into or out of the way while it is in the air
+                        let stream = WEATHER_STREAM + ((self.round as u64) << 16) + ally.id as u64;
+                        let roll = Rng::stream(self.loot_seed, stream).below(100) as u16;
+                        let target = self.weather.landing(ally.position, &line, roll);
+                        match ally.use_ability(target) {
+                            // Nothing is hit until the bolt lands, which may be short of the target
+                            Some(mut projectile) => {
+                                projectile.bind_mut().shooter = Some((ally.id, ability));
+                                self.in_flight.insert(ally.id);
+                                self.base_mut().add_child(projectile.upcast());
+                                return Some(ActionResult::default());
+                            }
+                            None => return Some(self.land(&mut ally, ability, target)),
+                        }
+                    }
+                }
+                // Takes the ally's move as well, the strike landing as an impact once they arrive
+                Action::DashAttack { .. } if !ally.has_moved && enemy_id.is_some() => {
+                    if let Some(mut path) = self.ally_dash_path(&ally, position, stats) {
+                        if path.is_empty() {
+                            path.push(ally.position);
+                        }
+                        self.grid[ally.position].tile = Tile::Empty;
+                        ally.has_moved = true;
+                        ally.lunge = Some((ability, position));
+                        ally.follow_path(path);
+                        self.in_flight.insert(ally.id);
+                        return Some(ActionResult::default());
+                    }
+                }
+                Action::Effect { effect, stats } => {
+                    let position = ally.position;
+                    ally.use_ability(position);
+                    ally.vitals
+                        .effects
+                        .apply(effect, stats, Some(UnitId::Ally(ally.id)));
+                    return Some(ActionResult::effect(UnitId::Ally(ally.id), effect));
+                }
+                Action::Inflict {
+                    effect,
+                    stats: effect_stats,
+                } => {
+                    if let Some(enemy_id) = enemy_id {
+                        match self.ability_line(ally.position, position, stats, Tile::Ally(ally.id))
+                        {
+                            Some(_) => {
+                                let Some(mut enemy) = self.try_get_enemy(enemy_id) else {
+                                    return None;
+                                };
+                                let mut enemy = enemy.bind_mut();
+                                ally.use_ability(position);
+                                enemy.vitals.effects.apply(
+                                    effect,
+                                    effect_stats,
+                                    Some(UnitId::Ally(ally.id)),
+                                );
+                                enemy.notice(ally.id, ally.position);
+                                return Some(ActionResult::effect(UnitId::Enemy(enemy_id), effect));
+                            }
+                            _ => (),
+                        }
+                    }
+                }
+                Action::Carry => {
+                    if let Tile::Ally(carried_id) = self.at(position) {
+                        if self.can_carry(&ally, carried_id) {
+                            let Some(mut carried) = self.try_get_ally(carried_id) else {
+                                return None;
+                            };
+                            let mut carried = carried.bind_mut();
+                            self.grid[position].tile = Tile::Empty;
+                            carried.has_moved = true;
+                            carried.has_acted = true;
+                            self.carried.insert(ally.id, carried_id);
+                            self.shadows_cast = false;
+                            ally.use_ability(position);
+                            return Some(ActionResult::default());
+                        }
+                    }
+                }
+                Action::Teleport => {
+                    if self.can_teleport(ally.position, position, stats.range) {
+                        self.grid[ally.position].tile = Tile::Empty;
+                        ally.use_ability(position);
+                        ally.teleport(position);
+                        self.grid[position].tile = Tile::Ally(ally.id);
+                        self.shadows_cast = false;
+                        return Some(ActionResult::default());
+                    }
+                }
+                Action::PlaceItem { kind } => {
+                    if self.grid[position].tile == Tile::Empty && self.grid[position].item.is_none()
+                    {
+                        match self.ability_line(ally.position, position, stats, Tile::Ally(ally.id))
+                        {
+                            Some(_) => {
+                                ally.use_ability(position);
                                 self.spawn_item(kind, position);
-                                return true;
+                                return Some(ActionResult::default());
                             }
                             _ => (),
                         }
                     }
                 }
-                _ => unreachable!(),
+                Action::Unlock { turns } => {
+                    if let Tile::Obstacle(obstacle_id) = self.at(position) {
+                        let Some(mut obstacle) = self.try_get_obstacle(obstacle_id) else {
+                            return None;
+                        };
+                        if obstacle.bind().kind == ObstacleKind::LockedDoor
+                            && ally.position.adjacent().contains(&position)
+                        {
+                            ally.use_ability(position);
+                            let opened = {
+                                let mut obstacle = obstacle.bind_mut();
+                                obstacle.picked += 1;
+                                obstacle.picked >= turns
+                            };
+                            if opened {
+                                self.remove_obstacle(obstacle_id);
+                            }
+                            return Some(ActionResult::default());
+                        }
+                    }
+                }
+                Action::Pull => {
+                    if let Tile::Obstacle(obstacle_id) = self.at(position) {
+                        let Some(mut obstacle) = self.try_get_obstacle(obstacle_id) else {
+                            return None;
+                        };
+                        let switch = {
+                            let obstacle = obstacle.bind();
+                            (obstacle.kind == ObstacleKind::Lever
+                                && ally.position.adjacent().contains(&position))
+                            .then(|| obstacle.switch.to_string())
+                        };
+
+                        if let Some(switch) = switch {
+                            ally.use_ability(position);
+                            let pulled = {
+                                let mut obstacle = obstacle.bind_mut();
+                                obstacle.open = !obstacle.open;
+                                obstacle.open
+                            };
+                            if let Some(mut sprite) = obstacle.try_get_node_as::<Sprite2D>("Sprite")
+                            {
+                                sprite.set_flip_h(pulled);
+                            }
+                            self.throw_switch(&switch);
+                            return Some(ActionResult::default());
+                        }
+                    }
+                }
+                Action::Search => {
+                    if let Tile::Obstacle(obstacle_id) = self.at(position) {
+                        let Some(mut obstacle) = self.try_get_obstacle(obstacle_id) else {
+                            return None;
+                        };
+                        let table = {
+                            let obstacle = obstacle.bind();
+                            (obstacle.kind.searchable()
+                                && !obstacle.searched
+                                && ally.position.adjacent().contains(&position))
+                            .then(|| {
+                                obstacle
+                                    .loot
+                                    .to_vec()
+                                    .iter()
+                                    .map(|entry| entry.to_string())
+                                    .collect::<Vec<_>>()
+                            })
+                        };
+
+                        if let Some(table) = table {
+                            ally.use_ability(position);
+                            obstacle.bind_mut().searched = true;
+                            obstacle.set_modulate(Color::from_rgb(0.6, 0.6, 0.6));
+
+                            let mut rng =
+                                Rng::stream(self.loot_seed, LOOT_STREAM + obstacle_id as u64);
+                            match loot::roll(&table, &mut rng) {
+                                Loot::Nothing => (),
+                                // With no room for it, it is left at the searcher's feet
+                                Loot::Item(item_kind) => {
+                                    let ability = item_kind.ability();
+                                    if !has_room_for(&ally.uses, ally.capacity, ability) {
+                                        if self.item_at(ally.position).is_none() {
+                                            self.spawn_item(item_kind, ally.position);
+                                        }
+                                    } else if ally.acquire(ability) {
+                                        self.events.publish(GameEvent::ItemPickedUp {
+                                            ally_id: ally.id,
+                                            item_kind,
+                                        });
+                                    }
+                                }
+                                Loot::Ambush(enemy_kind) => {
+                                    let tile = self
+                                        .grid
+                                        .adjacent(position)
+                                        .into_iter()
+                                        .find(|tile| self.at(*tile).is_empty());
+                                    if let Some(tile) = tile {
+                                        self.spawn_enemy(enemy_kind, tile);
+                                    }
+                                }
+                            }
+                            return Some(ActionResult::default());
+                        }
+                    }
+                }
+                // What only enemies use, and a dash once the ally has already moved
+                _ => (),
+            }
+        }
+
+        None
+    }
+
+    // Fills the room with bats for the pathfinding, sight and planning to be timed against
+    fn start_benchmark(&mut self) {
+        let mut spawned = 0;
+        for position in self.grid.positions().collect::<Vec<_>>() {
+            if spawned == BENCH_BATS {
+                break;
+            }
+            if self.at(position).is_empty() && self.door_at(position).is_none() {
+                self.spawn_enemy(EnemyKind::Bat, position);
+                spawned += 1;
+            }
+        }
+
+        if bench::overlay() {
+            let mut layer = CanvasLayer::new_alloc();
+            layer.set_layer(100);
+            let label = Label::new_alloc();
+            layer.add_child(label.clone().upcast());
+            self.base_mut().add_child(layer.upcast());
+            self.timings = Some(label);
+        }
+    }
+
+    pub fn spawn_enemy(&mut self, enemy_kind: EnemyKind, position: Position) {
+        let Some(scene) = nodes::try_scene(enemy_kind.scene()) else {
+            return;
+        };
+
+        let mut enemy: Gd<Enemy> = scene.instantiate().unwrap().cast();
+        let instance_id = enemy.instance_id().to_i64();
+        enemy.set_position(position.to_vector());
+
+        {
+            let mut enemy = enemy.bind_mut();
+            enemy.id = self.enemy_id;
+            enemy.position = position;
+
+            for i in 0..enemy.width as usize {
+                for j in 0..enemy.height as usize {
+                    self.grid[position.offset(i, j)].tile = Tile::Enemy(self.enemy_id);
+                }
+            }
+        }
+
+        self.spawn_queue.push(self.enemy_id);
+        self.enemies.insert(self.enemy_id, instance_id);
+        self.enemy_id += 1;
+
+        // Its lists are only filled in once it is in the tree
+        let mut enemies = self.base().get_node_as::<Node2D>("UnitLayer/Enemies");
+        enemies.add_child(enemy.clone().upcast());
+        let mut enemy = enemy.bind_mut();
+        self.modifiers.upgrade(&mut enemy);
+        enemy.appear();
+    }
+
+    // Anyone on neither side runs for the nearest open door, getting out once they reach it.
+    // Taken in id order so replays move them the same way
+    fn move_npcs(&mut self) {
+        let mut exits: Vec<Position> = self
+            .doors
+            .keys()
+            .copied()
+            .filter(|tile| self.door_at(*tile).is_some_and(|door| !door.bind().locked))
+            .collect();
+        exits.sort_by_key(|tile| (tile.y, tile.x));
+
+        let mut npc_ids: Vec<NpcId> = self.npcs.keys().copied().collect();
+        npc_ids.sort();
+        for npc_id in npc_ids {
+            let Some(mut npc) = self.try_get_npc(npc_id) else {
+                continue;
+            };
+            let (start, speed) = {
+                let npc = npc.bind();
+                (npc.position, npc.speed)
+            };
+
+            let mover = Mover {
+                tile: Tile::Npc(npc_id),
+                dimensions: (1, 1),
+                movement: Movement::Walk,
+                diagonal: self.diagonal_movement,
+                avoids: None,
+            };
+            let found = self
+                .pathfinder
+                .borrow_mut()
+                .pathfind_to_any(start, &exits, &self.grid, mover, None);
+            let Some((mut path, _)) = found else {
+                continue;
+            };
+
+            // As far as they can run this round without stopping on anyone
+            let mut len = 0;
+            while len < path.len() && path_cost(start, &path[0..=len]) <= speed {
+                len += 1;
+            }
+            while len > 0 && !self.grid[path[len - 1]].tile.is_empty() {
+                len -= 1;
+            }
+            path.truncate(len);
+            let Some(end) = path.last().copied() else {
+                continue;
+            };
+
+            self.grid[start].tile = Tile::Empty;
+            let escaping = exits.contains(&end);
+            if escaping {
+                self.npc_escaped(npc_id);
+            } else {
+                self.grid[end].tile = Tile::Npc(npc_id);
+            }
+            npc.bind_mut().walk(path, escaping);
+        }
+    }
+
+    // Ash is given the reward if he is still around, otherwise whoever is
+    fn npc_escaped(&mut self, npc_id: NpcId) {
+        let reward = self.get_npc(npc_id).bind().reward;
+        self.npcs.remove(&npc_id);
+
+        let ally_id = if self.allies.contains_key(&AllyId::AshMagnum) {
+            Some(AllyId::AshMagnum)
+        } else {
+            self.allies.keys().min().copied()
+        };
+        if let Some(ally_id) = ally_id {
+            let mut ally = self.get_ally(ally_id);
+            if ally.bind_mut().acquire(reward.ability()) {
+                self.events.publish(GameEvent::ItemPickedUp {
+                    ally_id,
+                    item_kind: reward,
+                });
             }
         }
+        self.events.publish(GameEvent::NpcEscaped(npc_id));
+    }
 
-        false
+    // Captives join the party when an ally reaches their cell
+    pub fn free_captives(&mut self, position: Position) {
+        for adjacent in self.grid.adjacent(position) {
+            if let Tile::Obstacle(obstacle_id) = self.at(adjacent) {
+                let (captive, cell_position) = {
+                    let obstacle = self.get_obstacle(obstacle_id);
+                    let obstacle = obstacle.bind();
+                    (obstacle.kind.captive(), obstacle.position)
+                };
+
+                if let Some(ally_id) = captive {
+                    self.remove_obstacle(obstacle_id);
+                    self.recruit_ally(ally_id, cell_position);
+                }
+            }
+        }
     }
 
-    pub fn spawn_enemy(&mut self, enemy_kind: EnemyKind, position: Position) {
-        let scene = match enemy_kind {
-            EnemyKind::Bat => load::<PackedScene>("res://scenes/enemies/bat.tscn"),
-            EnemyKind::Vampire => load::<PackedScene>("res://scenes/enemies/vampire.tscn"),
-            EnemyKind::BigBatty => load::<PackedScene>("res://scenes/enemies/big_batty.tscn"),
+    pub fn recruit_ally(&mut self, ally_id: AllyId, position: Position) {
+        let Some(path) = ally_id.scene() else {
+            godot_error!("{:?} has no scene to be recruited from", ally_id);
+            return;
+        };
+        let Some(scene) = nodes::try_scene(path) else {
+            return;
         };
 
-        let mut enemy: Gd<Enemy> = scene.instantiate().unwrap().cast();
-        let instance_id = enemy.instance_id().to_i64();
-        enemy.set_position(position.to_vector());
+        let mut ally: Gd<Ally> = scene.instantiate().unwrap().cast();
+        let instance_id = ally.instance_id().to_i64();
+        ally.set_position(position.to_vector());
+
+        let mut allies = self.base().get_node_as::<Node2D>("UnitLayer/Allies");
+        allies.add_child(ally.clone().upcast());
 
         {
-            let mut enemy = enemy.bind_mut();
-            enemy.id = self.enemy_id;
-            enemy.position = position;
+            // Recruits wait until the next ally turn to act
+            let mut ally = ally.bind_mut();
+            ally.position = position;
+            ally.has_moved = true;
+            ally.has_acted = true;
+        }
 
-            for i in 0..enemy.width as usize {
-                for j in 0..enemy.height as usize {
-                    self.grid[position.x + i][position.y + j] = Tile::Enemy(self.enemy_id);
+        self.allies.insert(ally_id, instance_id);
+        self.grid[position].tile = Tile::Ally(ally_id);
+        self.shadows_cast = false;
+
+        self.events.publish(GameEvent::AllyRecruited(ally_id));
+    }
+
+    // Carried allies are off the board and cannot be targeted
+    pub fn snapshot(&self) -> Snapshot {
+        let allies = self
+            .allies
+            .keys()
+            .filter(|ally_id| !self.is_carried(**ally_id))
+            .map(|ally_id| {
+                let ally = self.get_ally(*ally_id);
+                let ally = ally.bind();
+                (
+                    *ally_id,
+                    Target {
+                        position: ally.position,
+                        traits: ally.traits(),
+                        taunting: ally
+                            .vitals
+                            .effects
+                            .get(Effect::Taunting)
+                            .map(|active| active.stats.magnitude),
+                        effects: ally
+                            .vitals
+                            .effects
+                            .iter()
+                            .map(|(effect, _)| effect)
+                            .collect(),
+                    },
+                )
+            })
+            .collect();
+
+        let low_obstacles = self
+            .grid
+            .positions()
+            .filter(|position| match self.grid[*position].tile {
+                Tile::Obstacle(obstacle_id) => {
+                    let obstacle = self.get_obstacle(obstacle_id);
+                    let obstacle = obstacle.bind();
+                    !obstacle.kind.blocks_sight()
                 }
-            }
-        }
+                _ => false,
+            })
+            .collect();
 
-        self.spawn_queue.push(self.enemy_id);
-        self.enemies.insert(self.enemy_id, instance_id);
-        self.enemy_id += 1;
+        let npcs = self
+            .npcs
+            .keys()
+            .map(|npc_id| {
+                let npc = self.get_npc(*npc_id);
+                let npc = npc.bind();
+                (
+                    *npc_id,
+                    Target {
+                        position: npc.position,
+                        traits: npc.vitals.traits(),
+                        taunting: None,
+                        effects: Vec::new(),
+                    },
+                )
+            })
+            .collect();
 
-        let mut enemies = self.base().get_node_as::<Node2D>("UnitLayer/Enemies");
-        enemies.add_child(enemy.upcast());
+        Snapshot {
+            grid: self.grid.clone(),
+            allies,
+            npcs,
+            coffin_tiles: self.coffin_tiles().into_iter().collect(),
+            low_obstacles,
+            diagonal_movement: self.diagonal_movement,
+            graves: self.graves.clone(),
+        }
     }
 
     pub fn spawn_item(&mut self, item_kind: ItemKind, position: Position) {
-        let scene = match item_kind {
-            ItemKind::Garlic => load::<PackedScene>("res://scenes/items/garlic.tscn"),
-            _ => unreachable!(),
-        };
+        let scene = load::<PackedScene>(item_kind.scene());
 
         let mut item: Gd<Item> = scene.instantiate().unwrap().cast();
         let instance_id = item.instance_id().to_i64();
@@ -2020,7 +6193,10 @@ impl Level {
             item.position = position;
         }
 
-        self.item_grid[position.x][position.y] = Some(self.item_id);
+        self.grid[position].item = Some(self.item_id);
+        if item_kind == ItemKind::Garlic {
+            self.add_terrain(position, Terrain::Garlic);
+        }
         self.items.insert(self.item_id, instance_id);
         self.item_id += 1;
 
@@ -2037,9 +6213,10 @@ pub struct ShadowMap {
 }
 
 impl ShadowMap {
-    pub fn cast_shadows(&mut self, visible: HashSet<Position>) {
-        for x in 0..LEVEL_WIDTH {
-            for y in 0..LEVEL_HEIGHT {
+    pub fn cast_shadows(&mut self, visible: HashSet<Position>, dimensions: (usize, usize)) {
+        let (width, height) = dimensions;
+        for x in 0..width {
+            for y in 0..height {
                 if visible.contains(&Position { x, y }) {
                     self.base_mut()
                         .erase_cell(0, Vector2i::new(x as i32, y as i32));
@@ -2056,137 +6233,320 @@ impl ShadowMap {
     }
 }
 
+// What the cursor is doing on the ally turn: looking around, holding an ally ready to move, or
+// aiming the held ally's ability
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CursorMode {
+    #[default]
+    Browse,
+    UnitSelected(AllyId),
+    Targeting(AllyId),
+}
+
+impl CursorMode {
+    // An ally that has already moved can only be aimed with
+    pub fn holding(ally_id: AllyId, has_moved: bool) -> Self {
+        if has_moved {
+            Self::Targeting(ally_id)
+        } else {
+            Self::UnitSelected(ally_id)
+        }
+    }
+
+    pub fn ally(&self) -> Option<AllyId> {
+        match self {
+            Self::Browse => None,
+            Self::UnitSelected(ally_id) | Self::Targeting(ally_id) => Some(*ally_id),
+        }
+    }
+
+    pub fn is_targeting(&self) -> bool {
+        matches!(self, Self::Targeting(_))
+    }
+
+    pub fn toggle_targeting(self, has_moved: bool) -> Self {
+        match self {
+            Self::Browse => Self::Browse,
+            Self::UnitSelected(ally_id) => Self::Targeting(ally_id),
+            Self::Targeting(ally_id) => Self::holding(ally_id, has_moved),
+        }
+    }
+
+    // Backs out a step, from aiming to holding the ally and from holding it to browsing
+    pub fn cancel(self, has_moved: bool) -> Self {
+        match self {
+            Self::Targeting(ally_id) if !has_moved => Self::UnitSelected(ally_id),
+            _ => Self::Browse,
+        }
+    }
+
+    // Moving leaves the ally aiming, acting lets it go
+    pub fn moved(self, ally_id: AllyId) -> Self {
+        match self.ally() {
+            Some(held) if held == ally_id => Self::Targeting(ally_id),
+            _ => self,
+        }
+    }
+
+    pub fn acted(self, ally_id: AllyId) -> Self {
+        match self.ally() {
+            Some(held) if held == ally_id => Self::Browse,
+            _ => self,
+        }
+    }
+}
+
 #[derive(GodotClass)]
 #[class(init, base=Sprite2D)]
 pub struct Cursor {
     pub position: Position,
-    pub selected: Option<AllyId>,
-    pub acting: bool,
+    pub mode: CursorMode,
     #[init(default = true)]
     pub can_interact: bool,
+    // Enemy being steered in versus mode and where it was told to move
+    pub controlled: Option<EnemyId>,
+    pub destination: Option<Position>,
+    // The camera is panned on its own and the cursor stays put
+    pub free_camera: bool,
+    // Corners of the floor the cursor is on, or the whole grid, that it is kept inside
+    pub origin: Position,
+    pub bounds: (usize, usize),
+    pub floor: Option<usize>,
+    // A direction held down keeps the cursor going that way
+    repeat: Repeat,
+    repeated: Option<Direction>,
+    // The camera trails the cursor, looking ahead the way it last went, or whatever else it is
+    // told to watch
+    follow: Follow,
+    heading: Vector2,
+    pub focus: Option<Vector2>,
+    // A door selected once, waiting to be selected again before the ally walks out
+    confirming: Option<Position>,
     base: Base<Sprite2D>,
 }
 
 #[godot_api]
 impl ISprite2D for Cursor {
-    fn process(&mut self, _delta: f64) {
-        let mut level = self.base().get_node_as::<Level>("../..");
-        let mut level = level.bind_mut();
-
-        let dialogue = self.base().get_node_as::<Dialogue>("../../Dialogue");
+    fn process(&mut self, delta: f64) {
+        let Some(mut level_node) = find_level(&self.base()) else {
+            return;
+        };
+        let (
+            Some(dialogue),
+            Some(mut ability_bar),
+            Some(shadow_map),
+            Some(mut path_node),
+            Some(info_panel),
+        ) = (
+            nodes::dialogue(&level_node),
+            nodes::ability_bar(&level_node),
+            nodes::shadow_map(&level_node),
+            nodes::path_node(&level_node),
+            nodes::info_panel(&level_node),
+        )
+        else {
+            return;
+        };
+        if ui::sheet_open(&level_node) {
+            return;
+        }
+        self.repeated = self.repeat.tick(controls::held(), delta);
+        let mut level = level_node.bind_mut();
         let dialogue = dialogue.bind();
-
-        let mut ability_bar = self
-            .base()
-            .get_node_as::<AbilityBar>("../../UILayer/AbilityBar");
         let mut ability_bar = ability_bar.bind_mut();
+        self.follow_floor(&level);
+        if self.free_camera || level.cinematic {
+            self.follow.reset();
+        } else {
+            self.follow_camera(delta);
+        }
+
+        if level.turn == Turn::Ally && !dialogue.active && level.swap.is_none() && !level.cinematic
+        {
+            let input = Input::singleton();
+            if input.is_action_just_pressed("free_camera".into()) {
+                self.free_camera = !self.free_camera;
+                if !self.free_camera {
+                    self.reset_camera();
+                }
+            }
+            if input.is_action_just_pressed("center".into()) {
+                self.center_on_active(&level);
+            }
+
+            if self.free_camera {
+                self.pan_camera(delta);
+                return;
+            }
+
+            // Leaving the ability bar comes first, then the cursor backs out a step at a time
+            if input.is_action_just_pressed("cancel".into()) && !level.playback {
+                let mut info_panel = info_panel.clone();
+                let mut info_panel = info_panel.bind_mut();
+                if ability_bar.hovered.is_some() {
+                    ability_bar.unhover(&level, &mut info_panel);
+                } else if self.can_interact {
+                    self.cancel(&level, &mut ability_bar, &mut path_node.bind_mut());
+                }
+            }
+        } else {
+            self.free_camera = false;
+        }
 
         if self.can_interact
             && level.turn == Turn::Ally
+            && !level.playback
             && !dialogue.active
+            && !level.cinematic
             && ability_bar.hovered.is_none()
         {
             let input = Input::singleton();
 
-            let shadow_map = self
-                .base()
-                .get_node_as::<ShadowMap>("../../ShadowLayer/ShadowMap");
             let shadow_map = shadow_map.bind();
 
             let mut position = self.base().get_position();
             let last_position = self.position;
-            if input.is_action_just_pressed("left".into()) {
-                let last = self.position;
-                if self.move_in_direction(Direction::Left) {
-                    if shadow_map.visible.contains(&self.position) {
-                        position.x -= 16.0;
-                    } else {
-                        self.position = last;
-                    }
-                }
-            }
-            if input.is_action_just_pressed("right".into()) {
-                let last = self.position;
-                if self.move_in_direction(Direction::Right) {
-                    if shadow_map.visible.contains(&self.position) {
-                        position.x += 16.0;
-                    } else {
-                        self.position = last;
-                    }
+            let jumping = input.is_action_pressed(controls::Action::Jump.name().into());
+            for direction in Direction::iter() {
+                if !self.direction_pressed(direction) {
+                    continue;
                 }
-            }
-            if input.is_action_just_pressed("up".into()) {
                 let last = self.position;
-                if self.move_in_direction(Direction::Up) {
+                let moved = if jumping {
+                    self.jump(direction, &level, &shadow_map.visible)
+                } else {
+                    self.move_in_direction(direction)
+                };
+                if moved {
                     if shadow_map.visible.contains(&self.position) {
-                        position.y -= 16.0;
+                        position += self.position.to_vector() - last.to_vector();
+                        self.heading = (self.position.to_vector() - last.to_vector()).normalized();
                     } else {
                         self.position = last;
                     }
                 }
             }
-            if input.is_action_just_pressed("down".into()) {
-                let last = self.position;
-                if self.move_in_direction(Direction::Down) {
+            if level.diagonal_movement {
+                if let Some(last) = self.move_diagonally() {
                     if shadow_map.visible.contains(&self.position) {
-                        position.y += 16.0;
+                        position += self.position.to_vector() - last.to_vector();
                     } else {
                         self.position = last;
                     }
                 }
             }
             self.base_mut().set_position(position);
+            if self.position != last_position {
+                self.confirming = None;
+                audio::play(Sfx::Navigate);
+            }
 
-            let mut path_node = self.base().get_node_as::<Path>("../../PathLayer/Path");
             let mut path_node = path_node.bind_mut();
 
-            if input.is_action_just_pressed("use_ability".into()) && self.selected.is_some() {
-                if let Some(selected) = self.selected {
-                    let ally = level.get_ally(selected);
-                    let ally = ally.bind();
+            if input.is_action_just_pressed("next_ally".into()) {
+                self.select_next_ally(&level, &mut ability_bar, &mut path_node);
+            }
 
-                    if ally.has_moved {
-                        self.acting = true;
-                    } else {
-                        self.acting = !self.acting;
-                    }
+            if input.is_action_just_pressed("use_ability".into()) {
+                if let Some(selected) = self.mode.ally() {
+                    let has_moved = level.get_ally(selected).bind().has_moved;
+                    self.mode = self.mode.toggle_targeting(has_moved);
                 }
             }
 
             if input.is_action_just_pressed("select".into()) {
                 match level.at(self.position) {
                     Tile::Empty => {
-                        if let Some(selected) = self.selected {
-                            if self.acting {
-                                if level.use_ability(selected, self.position, None) {
+                        if let Some(selected) = self.mode.ally() {
+                            if self.mode.is_targeting() {
+                                let command = self.ability_command(&level, selected);
+                                if level.apply(Command::Drop {
+                                    ally_id: selected,
+                                    position: self.position,
+                                }) {
+                                    // Dropping is free, so the carrier is still held but no
+                                    // longer aiming whether or not it has moved
+                                    path_node.clear_path();
+                                    self.mode = CursorMode::UnitSelected(selected);
+                                } else if level.apply(command) {
                                     path_node.clear_path();
                                     self.can_interact = false;
-                                    self.acting = false;
+                                    self.mode = CursorMode::Browse;
 
-                                    let mut info_panel = self
-                                        .base()
-                                        .get_node_as::<InfoPanel>("../../UILayer/InfoPanel");
+                                    let mut info_panel = info_panel.clone();
                                     let mut info_panel = info_panel.bind_mut();
                                     info_panel.deselect_tile();
+                                } else if self.drop_item(&mut level, selected) {
+                                    path_node.clear_path();
                                 }
+                            } else if settings::confirm_doors()
+                                && level
+                                    .door_at(self.position)
+                                    .is_some_and(|door| !door.bind().locked)
+                                && self.confirming != Some(self.position)
+                            {
+                                self.confirming = Some(self.position);
+                                audio::play(Sfx::Select);
                             } else {
-                                if level.move_ally(selected, self.position) {
+                                self.confirming = None;
+                                let stairs = level.stairs_from(self.position);
+                                if level.apply(Command::Move {
+                                    ally_id: selected,
+                                    position: self.position,
+                                }) {
                                     path_node.clear_path();
                                     self.can_interact = false;
+                                    if let Some(stairs) = stairs {
+                                        self.position = stairs;
+                                        self.base_mut().set_position(
+                                            stairs.to_vector() + Vector2::new(8.0, 8.0),
+                                        );
+                                    }
                                 }
                             }
                         }
                     }
-                    Tile::Ally(id) => match self.selected {
+                    Tile::Ally(id) => match self.mode.ally() {
                         Some(selected) if selected == id => {
-                            if level.use_ability(selected, self.position, None) {
+                            let command = self.ability_command(&level, selected);
+                            if self.mode.is_targeting() && self.drop_item(&mut level, selected) {
+                                path_node.clear_path();
+                            } else if level.apply(command) {
+                                path_node.clear_path();
+                                self.can_interact = false;
+                                self.mode = CursorMode::Browse;
+
+                                let mut info_panel = info_panel.clone();
+                                let mut info_panel = info_panel.bind_mut();
+                                info_panel.deselect_tile();
+                            }
+                        }
+                        Some(selected)
+                            if self.mode.is_targeting()
+                                && level.can_trade(&level.get_ally(selected).bind(), id) =>
+                        {
+                            let ability = *level.get_ally(selected).bind().current_ability();
+                            if level.apply(Command::Trade {
+                                from: selected,
+                                to: id,
+                                ability,
+                            }) {
+                                let has_moved = level.get_ally(selected).bind().has_moved;
+                                self.mode = CursorMode::holding(selected, has_moved);
+                                audio::play(Sfx::Select);
+                            }
+                        }
+                        Some(selected)
+                            if self.mode.is_targeting()
+                                && level.can_carry(&level.get_ally(selected).bind(), id) =>
+                        {
+                            let command = self.ability_command(&level, selected);
+                            if level.apply(command) {
                                 path_node.clear_path();
                                 self.can_interact = false;
-                                self.acting = false;
+                                self.mode = CursorMode::Browse;
 
-                                let mut info_panel = self
-                                    .base()
-                                    .get_node_as::<InfoPanel>("../../UILayer/InfoPanel");
+                                let mut info_panel = info_panel.clone();
                                 let mut info_panel = info_panel.bind_mut();
                                 info_panel.deselect_tile();
                             }
@@ -2195,24 +6555,35 @@ impl ISprite2D for Cursor {
                             let ally = level.get_ally(id);
                             let ally = ally.bind();
 
-                            if !ally.has_acted {
-                                self.acting = ally.has_moved;
-
-                                self.selected = Some(id);
-                                ability_bar.select_ally(&ally);
+                            if !ally.has_acted && level.controls(id) {
+                                self.mode = CursorMode::holding(id, ally.has_moved);
+                                ability_bar.select_ally(&ally, level);
                             }
                         }
                     },
-                    Tile::Enemy(id) if self.acting => {
-                        if let Some(selected) = self.selected {
-                            if level.use_ability(selected, self.position, Some(id)) {
+                    Tile::Enemy(id) if self.mode.is_targeting() => {
+                        if let Some(selected) = self.mode.ally() {
+                            let command = self.ability_command(&level, selected);
+                            if level.apply(command) {
+                                path_node.clear_path();
+                                self.can_interact = false;
+                                self.mode = CursorMode::Browse;
+
+                                let mut info_panel = info_panel.clone();
+                                let mut info_panel = info_panel.bind_mut();
+                                info_panel.deselect_tile();
+                            }
+                        }
+                    }
+                    Tile::Obstacle(_) if self.mode.is_targeting() => {
+                        if let Some(selected) = self.mode.ally() {
+                            let command = self.ability_command(&level, selected);
+                            if level.apply(command) {
                                 path_node.clear_path();
                                 self.can_interact = false;
-                                self.acting = false;
+                                self.mode = CursorMode::Browse;
 
-                                let mut info_panel = self
-                                    .base()
-                                    .get_node_as::<InfoPanel>("../../UILayer/InfoPanel");
+                                let mut info_panel = info_panel.clone();
                                 let mut info_panel = info_panel.bind_mut();
                                 info_panel.deselect_tile();
                             }
@@ -2223,18 +6594,22 @@ impl ISprite2D for Cursor {
             } else {
                 match level.at(self.position) {
                     Tile::Empty => {
-                        if let Some(selected) = self.selected {
+                        if let Some(selected) = self.mode.ally() {
                             let ally = level.get_ally(selected);
                             let ally = ally.bind();
 
                             if self.position != ally.position {
-                                if self.acting {
+                                if self.mode.is_targeting() {
                                     let stats = abilities().get(ally.current_ability()).unwrap();
                                     match stats.action {
                                         Action::PlaceItem { .. } => {
-                                            match line_to(ally.position, self.position, level.grid)
-                                            {
-                                                Some(path) if path.len() as u16 <= stats.range => {
+                                            match level.ability_line(
+                                                ally.position,
+                                                self.position,
+                                                stats,
+                                                Tile::Ally(ally.id),
+                                            ) {
+                                                Some(path) => {
                                                     path_node.set_path(path, PathKind::Attack);
                                                 }
                                                 _ => path_node.set_path(
@@ -2243,21 +6618,46 @@ impl ISprite2D for Cursor {
                                                 ),
                                             }
                                         }
+                                        Action::Teleport => {
+                                            let kind = if level.can_teleport(
+                                                ally.position,
+                                                self.position,
+                                                stats.range,
+                                            ) {
+                                                PathKind::Move
+                                            } else {
+                                                PathKind::Attack
+                                            };
+                                            path_node.set_path(vec![self.position], kind);
+                                        }
+                                        Action::Carry => {
+                                            let kind = if level.can_drop(&ally, self.position) {
+                                                PathKind::Move
+                                            } else {
+                                                PathKind::Attack
+                                            };
+                                            path_node.set_path(vec![self.position], kind);
+                                        }
                                         _ => path_node
                                             .set_path(vec![self.position], PathKind::Attack),
                                     }
                                 } else {
-                                    match pathfind(
+                                    let path = level.pathfinder.borrow_mut().a_star(
                                         ally.position,
                                         self.position,
-                                        level.grid,
-                                        Tile::Ally(ally.id),
-                                        (1, 1),
-                                    ) {
-                                        Some(path) if path.len() as u16 <= ally.speed => {
-                                            path_node.set_path(path, PathKind::Move);
-                                        }
-                                        _ => path_node.clear_path(),
+                                        &level.grid,
+                                        Mover {
+                                            tile: Tile::Ally(ally.id),
+                                            dimensions: (1, 1),
+                                            movement: Movement::Walk,
+                                            diagonal: level.diagonal_movement,
+                                            avoids: None,
+                                        },
+                                        Some(level.ally_speed(&ally)),
+                                    );
+                                    match path {
+                                        Some(path) => path_node.set_path(path, PathKind::Move),
+                                        None => path_node.clear_path(),
                                     }
                                 }
                             } else {
@@ -2265,22 +6665,40 @@ impl ISprite2D for Cursor {
                             }
                         }
                     }
-                    Tile::Enemy(_) if self.acting => {
-                        if let Some(selected) = self.selected {
+                    Tile::Enemy(_) if self.mode.is_targeting() => {
+                        if let Some(selected) = self.mode.ally() {
                             let ally = level.get_ally(selected);
                             let ally = ally.bind();
 
                             let stats = abilities().get(ally.current_ability()).unwrap();
                             match stats.action {
-                                Action::Attack { .. } | Action::Push { .. } => {
-                                    match line_to(ally.position, self.position, level.grid) {
-                                        Some(path) if path.len() as u16 <= stats.range => {
+                                Action::Attack { .. }
+                                | Action::Push { .. }
+                                | Action::Inflict { .. } => {
+                                    match level.ability_line(
+                                        ally.position,
+                                        self.position,
+                                        stats,
+                                        Tile::Ally(ally.id),
+                                    ) {
+                                        Some(path) => {
                                             path_node.set_path(path, PathKind::Attack);
                                         }
                                         _ => path_node
                                             .set_path(vec![self.position], PathKind::Attack),
                                     }
                                 }
+                                // The run, then the target it ends on
+                                Action::DashAttack { .. } if !ally.has_moved => {
+                                    match level.ally_dash_path(&ally, self.position, stats) {
+                                        Some(mut path) => {
+                                            path.push(self.position);
+                                            path_node.set_path(path, PathKind::Move);
+                                        }
+                                        None => path_node
+                                            .set_path(vec![self.position], PathKind::Attack),
+                                    }
+                                }
                                 _ => path_node.set_path(vec![self.position], PathKind::Attack),
                             }
                         }
@@ -2289,19 +6707,10 @@ impl ISprite2D for Cursor {
                 }
 
                 if last_position != self.position {
-                    let mut info_panel = self
-                        .base()
-                        .get_node_as::<InfoPanel>("../../UILayer/InfoPanel");
+                    let mut info_panel = info_panel.clone();
                     let mut info_panel = info_panel.bind_mut();
 
-                    match level.at(self.position) {
-                        Tile::Empty | Tile::Obstacle(_) => match level.item_at(self.position) {
-                            Some(item_id) => info_panel.select_item(item_id, &level),
-                            None => info_panel.deselect_tile(),
-                        },
-                        Tile::Ally(ally_id) => info_panel.select_ally(ally_id, &level),
-                        Tile::Enemy(enemy_id) => info_panel.select_enemy(enemy_id, &level),
-                    }
+                    info_panel.select_tile(self.position, &level);
                 }
 
                 let mut atlas: Gd<AtlasTexture> = self.base().get_texture().unwrap().cast();
@@ -2320,33 +6729,301 @@ impl ISprite2D for Cursor {
                     }
                 }
             }
+        } else if let Some(enemy_id) = level.awaiting {
+            if self.can_interact
+                && !level.playback
+                && !dialogue.active
+                && ability_bar.hovered.is_none()
+            {
+                let mut path_node = path_node.bind_mut();
+                self.control_enemy(&mut level, &mut ability_bar, &mut path_node, enemy_id);
+            }
         }
     }
 }
 
 impl Cursor {
+    // Where the middle of the view can go without it showing past the camera limits
+    fn view_limits(&self, camera: &Gd<Camera2D>) -> (Vector2, Vector2) {
+        let half = self.base().get_viewport_rect().size / camera.get_zoom() / 2.0;
+        let min = Vector2::new(
+            camera.get_limit(Side::LEFT) as f32,
+            camera.get_limit(Side::TOP) as f32,
+        ) + half;
+        let max = Vector2::new(
+            camera.get_limit(Side::RIGHT) as f32,
+            camera.get_limit(Side::BOTTOM) as f32,
+        ) - half;
+        (min, max)
+    }
+
+    // Kept within the camera limits so the view never drifts off the level
+    fn pan_camera(&mut self, delta: f64) {
+        let input = Input::singleton();
+        let direction = input.get_vector("left".into(), "right".into(), "up".into(), "down".into());
+
+        let mut camera = self.base().get_node_as::<Camera2D>("Camera");
+        let (min, max) = self.view_limits(&camera);
+        let origin = self.base().get_position();
+        let target = origin + camera.get_position() + direction * PAN_SPEED * delta as f32;
+        camera.set_position(camera::clamp(target, min, max) - origin);
+    }
+
+    // The camera hangs under the cursor, so it is moved back against wherever the cursor went
+    fn follow_camera(&mut self, delta: f64) {
+        let mut camera = self.base().get_node_as::<Camera2D>("Camera");
+        let limits = self.view_limits(&camera);
+        let origin = self.base().get_position();
+        let (target, heading) = match self.focus {
+            Some(focus) => (focus, Vector2::ZERO),
+            None => (origin, self.heading),
+        };
+        let center = self.follow.step(
+            origin + camera.get_position(),
+            target,
+            heading,
+            limits,
+            delta,
+        );
+        camera.set_position(center - origin);
+    }
+
+    // Moving onto another floor keeps the cursor and the camera on it
+    fn follow_floor(&mut self, level: &Level) {
+        let floor = level.floor_at(self.position);
+        if floor == self.floor {
+            return;
+        }
+        self.floor = floor;
+
+        let area = match floor {
+            Some(floor) => level.floors[floor],
+            None => Area {
+                origin: Position::default(),
+                dimensions: level.grid.dimensions(),
+            },
+        };
+        self.origin = area.origin;
+        self.bounds = (
+            area.origin.x + area.dimensions.0,
+            area.origin.y + area.dimensions.1,
+        );
+
+        let mut camera = self.base().get_node_as::<Camera2D>("Camera");
+        camera.set_limit(Side::LEFT, (self.origin.x as f32 * TILE_SIZE) as i32);
+        camera.set_limit(Side::TOP, (self.origin.y as f32 * TILE_SIZE) as i32);
+        camera.set_limit(Side::RIGHT, (self.bounds.0 as f32 * TILE_SIZE) as i32);
+        camera.set_limit(Side::BOTTOM, (self.bounds.1 as f32 * TILE_SIZE) as i32);
+    }
+
+    fn reset_camera(&mut self) {
+        let mut camera = self.base().get_node_as::<Camera2D>("Camera");
+        camera.set_position(Vector2::default());
+        self.follow.reset();
+    }
+
+    // Back to the selected ally, or Ash when nobody is selected
+    fn center_on_active(&mut self, level: &Level) {
+        let ally_id = self.mode.ally().unwrap_or(AllyId::AshMagnum);
+        if !level.allies.contains_key(&ally_id) {
+            return;
+        }
+
+        let position = level.get_ally(ally_id).bind().position;
+        self.position = position;
+        self.base_mut()
+            .set_position(position.to_vector() + Vector2::new(8.0, 8.0));
+        self.free_camera = false;
+        self.reset_camera();
+    }
+
+    fn cancel(&mut self, level: &Level, ability_bar: &mut AbilityBar, path_node: &mut Path) {
+        self.confirming = None;
+        let Some(ally_id) = self.mode.ally() else {
+            return;
+        };
+        let has_moved = level
+            .try_get_ally(ally_id)
+            .is_some_and(|ally| ally.bind().has_moved);
+        self.mode = self.mode.cancel(has_moved);
+
+        path_node.clear_path();
+        if self.mode == CursorMode::Browse {
+            ability_bar.select_none();
+        }
+        audio::play(Sfx::Navigate);
+    }
+
+    // Puts the selected item down where the cursor is, still holding the ally afterwards
+    fn drop_item(&mut self, level: &mut Level, selected: AllyId) -> bool {
+        let ability = *level.get_ally(selected).bind().current_ability();
+        let dropped = level.apply(Command::DropItem {
+            ally_id: selected,
+            ability,
+            position: self.position,
+        });
+        if dropped {
+            let has_moved = level.get_ally(selected).bind().has_moved;
+            self.mode = CursorMode::holding(selected, has_moved);
+            audio::play(Sfx::Select);
+        }
+        dropped
+    }
+
+    // Jumps to and selects the next ally that can still act, after the one selected
+    fn select_next_ally(
+        &mut self,
+        level: &Level,
+        ability_bar: &mut AbilityBar,
+        path_node: &mut Path,
+    ) {
+        let unacted = level.unacted_allies();
+        let current = self
+            .mode
+            .ally()
+            .and_then(|selected| unacted.iter().position(|ally_id| *ally_id == selected));
+        let next = match current {
+            Some(i) => unacted[(i + 1) % unacted.len()],
+            None => match unacted.first() {
+                Some(ally_id) => *ally_id,
+                None => return,
+            },
+        };
+
+        let ally = level.get_ally(next);
+        let ally = ally.bind();
+        self.position = ally.position;
+        self.base_mut()
+            .set_position(ally.position.to_vector() + Vector2::new(8.0, 8.0));
+        self.mode = CursorMode::holding(next, ally.has_moved);
+        ability_bar.select_ally(&ally, level);
+        path_node.clear_path();
+        audio::play(Sfx::Select);
+    }
+
+    // The second player moves the enemy whose turn it is, then picks a target for its ability
+    fn control_enemy(
+        &mut self,
+        level: &mut Level,
+        ability_bar: &mut AbilityBar,
+        path_node: &mut Path,
+        enemy_id: EnemyId,
+    ) {
+        let enemy = level.get_enemy(enemy_id);
+        let enemy = enemy.bind();
+
+        if self.controlled != Some(enemy_id) {
+            self.controlled = Some(enemy_id);
+            self.destination = None;
+            self.position = enemy.position;
+            self.base_mut()
+                .set_position(enemy.position.to_vector() + Vector2::new(8.0, 8.0));
+            self.reset_camera();
+
+            ability_bar.select_enemy(&enemy);
+            return;
+        }
+
+        let input = Input::singleton();
+
+        let mut position = self.base().get_position();
+        for direction in Direction::iter() {
+            let last = self.position;
+            if self.direction_pressed(direction) && self.move_in_direction(direction) {
+                position += self.position.to_vector() - last.to_vector();
+            }
+        }
+        if level.diagonal_movement {
+            if let Some(last) = self.move_diagonally() {
+                position += self.position.to_vector() - last.to_vector();
+            }
+        }
+        self.base_mut().set_position(position);
+
+        let select = input.is_action_just_pressed("select".into());
+        match self.destination {
+            None => {
+                let path = level.enemy_path(&enemy, self.position);
+                match &path {
+                    Some(path) => path_node.set_path(path.clone(), PathKind::Move),
+                    None => path_node.clear_path(),
+                }
+
+                if select && path.is_some() {
+                    self.destination = Some(self.position);
+                }
+            }
+            Some(destination) => {
+                let ability = enemy.abilities[enemy.selected_ability];
+                let action = level.enemy_action(&enemy, ability, destination, self.position);
+                match action {
+                    Some(_) => path_node.set_path(vec![self.position], PathKind::Attack),
+                    None => path_node.clear_path(),
+                }
+
+                // Selecting the destination again ends the turn without using an ability
+                if select && (action.is_some() || self.position == destination) {
+                    drop(enemy);
+                    if level.apply(Command::ControlEnemy {
+                        enemy_id,
+                        position: destination,
+                        action: action.map(|action| (ability, action)),
+                    }) {
+                        path_node.clear_path();
+                        ability_bar.select_none();
+                        self.controlled = None;
+                        self.destination = None;
+                    }
+                }
+            }
+        }
+    }
+
+    // Use the ally's current ability on the tile under the cursor
+    fn ability_command(&self, level: &Level, ally_id: AllyId) -> Command {
+        let ally = level.get_ally(ally_id);
+        let ally = ally.bind();
+        Command::UseAbility {
+            ally_id,
+            ability: *ally.current_ability(),
+            position: self.position,
+        }
+    }
+
+    // Shown on the HUD while a door waits to be confirmed
+    pub fn prompt(&self) -> Option<&'static str> {
+        self.confirming
+            .map(|_| "Leave the room? Select the door again to go through")
+    }
+
+    // Pressed through whatever the direction is bound to, or repeated by a held stick
+    fn direction_pressed(&self, direction: Direction) -> bool {
+        Input::singleton().is_action_just_pressed(controls::Action::of(direction).name().into())
+            || self.repeated == Some(direction)
+    }
+
     pub fn move_in_direction(&mut self, direction: Direction) -> bool {
         match direction {
             Direction::Left => {
-                if self.position.x > 0 {
+                if self.position.x > self.origin.x {
                     self.position.x -= 1;
                     return true;
                 }
             }
             Direction::Right => {
-                if self.position.x < LEVEL_WIDTH - 1 {
+                if self.position.x + 1 < self.bounds.0 {
                     self.position.x += 1;
                     return true;
                 }
             }
             Direction::Up => {
-                if self.position.y > 0 {
+                if self.position.y > self.origin.y {
                     self.position.y -= 1;
                     return true;
                 }
             }
             Direction::Down => {
-                if self.position.y < LEVEL_HEIGHT - 1 {
+                if self.position.y + 1 < self.bounds.1 {
                     self.position.y += 1;
                     return true;
                 }
@@ -2354,6 +7031,63 @@ impl Cursor {
         }
         false
     }
+
+    // Snaps to the next unit that way, or failing that goes as far as it can see up to a few tiles
+    fn jump(&mut self, direction: Direction, level: &Level, visible: &HashSet<Position>) -> bool {
+        let last = self.position;
+        let units: Vec<Position> = visible
+            .iter()
+            .copied()
+            .filter(|position| matches!(level.at(*position), Tile::Ally(_) | Tile::Enemy(_)))
+            .collect();
+        if let Some(unit) = self.position.nearest_ahead(direction, &units) {
+            self.position = unit;
+            return true;
+        }
+
+        for _ in 0..JUMP_TILES {
+            let step = self.position;
+            if !self.move_in_direction(direction) {
+                break;
+            }
+            if !visible.contains(&self.position) {
+                self.position = step;
+                break;
+            }
+        }
+        self.position != last
+    }
+
+    // Steps along both axes for a diagonal key, returning where the cursor was if it moved
+    fn move_diagonally(&mut self) -> Option<Position> {
+        let input = Input::singleton();
+        for (action, horizontal, vertical) in DIAGONAL_KEYS {
+            if input.is_action_just_pressed(action.into()) {
+                let last = self.position;
+                if self.move_in_direction(horizontal) && self.move_in_direction(vertical) {
+                    return Some(last);
+                }
+                self.position = last;
+            }
+        }
+        None
+    }
+}
+
+impl Listener for Cursor {
+    fn on_event(&mut self, event: GameEvent, _level: &Level) {
+        match event {
+            GameEvent::AllyMoved(ally_id) => {
+                self.can_interact = true;
+                self.mode = self.mode.moved(ally_id);
+            }
+            GameEvent::AllyActed(ally_id) => {
+                self.can_interact = true;
+                self.mode = self.mode.acted(ally_id);
+            }
+            _ => (),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -2403,3 +7137,51 @@ impl Path {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancelling_backs_the_cursor_out_a_step_at_a_time() {
+        let mode = CursorMode::holding(AllyId::AshMagnum, false);
+        assert_eq!(mode, CursorMode::UnitSelected(AllyId::AshMagnum));
+
+        let mode = mode.toggle_targeting(false);
+        assert!(mode.is_targeting());
+        let mode = mode.cancel(false);
+        assert_eq!(mode, CursorMode::UnitSelected(AllyId::AshMagnum));
+        assert_eq!(mode.cancel(false), CursorMode::Browse);
+
+        // Having moved, the ally can only aim, so backing out lets go of it
+        let mode = mode.moved(AllyId::AshMagnum);
+        assert_eq!(mode, CursorMode::Targeting(AllyId::AshMagnum));
+        assert_eq!(mode.toggle_targeting(true), mode);
+        assert_eq!(mode.cancel(true), CursorMode::Browse);
+
+        // Other allies moving or acting leave the held one alone
+        assert_eq!(mode.acted(AllyId::Alukrod), mode);
+        assert_eq!(mode.acted(AllyId::AshMagnum), CursorMode::Browse);
+        assert_eq!(
+            CursorMode::Browse.moved(AllyId::AshMagnum),
+            CursorMode::Browse
+        );
+    }
+
+    #[test]
+    fn dropped_abilities_go_down_as_the_items_they_came_from() {
+        for kind in [
+            ItemKind::IronBolt,
+            ItemKind::SilverBolt,
+            ItemKind::WoodenStake,
+            ItemKind::Garlic,
+            ItemKind::HolyWater,
+            ItemKind::Key,
+            ItemKind::FireFlask,
+        ] {
+            assert_eq!(ItemKind::from_ability(kind.ability()), Some(kind));
+        }
+        assert_eq!(ItemKind::from_ability(Ability::Whip), None);
+        assert_eq!(ItemKind::from_ability(Ability::Mist), None);
+    }
+}
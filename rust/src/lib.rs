@@ -1,14 +1,70 @@
 use godot::prelude::*;
 
 mod ability;
+mod achievements;
+mod ai;
+mod animation;
+mod audio;
+mod auras;
+mod barks;
+mod bench;
+mod bestiary;
+mod camera;
+mod carry;
+mod choices;
+mod cinematic;
+mod combat;
+mod command;
+mod controls;
+mod core;
+mod daily;
 mod death_screen;
+mod defeat;
 mod dialogue;
+mod door;
+mod dust;
+mod effects;
+mod end_screen;
+mod event;
+mod explosion;
+mod floor;
+mod grid;
+#[cfg(feature = "harness")]
+mod harness;
+mod juice;
 mod level;
+mod loot;
+mod main_menu;
 mod math;
+mod modifiers;
+mod mods;
+mod music;
+mod network;
+mod nodes;
+mod noise;
+mod passives;
+mod pause_menu;
+mod progress;
+mod replay;
+mod rng;
+mod run_log;
+mod save;
+mod scoring;
+mod settings;
+mod stats;
+mod summary;
+mod survival;
 mod traits;
 mod ui;
+mod weather;
 
 struct GameExtension;
 
 #[gdextension]
-unsafe impl ExtensionLibrary for GameExtension {}
+unsafe impl ExtensionLibrary for GameExtension {
+    fn on_level_init(level: InitLevel) {
+        if level == InitLevel::Scene {
+            mods::load();
+        }
+    }
+}
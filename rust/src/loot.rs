@@ -0,0 +1,151 @@
+use crate::ability::Ability;
+use crate::level::{EnemyKind, ItemKind};
+use crate::rng::Rng;
+
+// Keeps container rolls apart from the daily challenge's own draws off the same seed
+pub const LOOT_STREAM: u64 = 0x200;
+
+// What searching a chest or bookshelf turns up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Loot {
+    Nothing,
+    Item(ItemKind),
+    // Something was hiding inside and comes out beside it
+    Ambush(EnemyKind),
+}
+
+impl Loot {
+    // Entries of a container's loot table are written as "Nothing", an item kind such as
+    // "HolyWater", or an enemy kind such as "Bat" for an ambush
+    pub fn from_entry(entry: &str) -> Option<Self> {
+        let name = format!("\"{}\"", entry.trim());
+        if entry.trim() == "Nothing" {
+            Some(Self::Nothing)
+        } else if let Ok(kind) = serde_json::from_str(&name) {
+            Some(Self::Item(kind))
+        } else if let Ok(kind) = serde_json::from_str(&name) {
+            Some(Self::Ambush(kind))
+        } else {
+            None
+        }
+    }
+}
+
+// Whether searching a container with this table could turn up more uses of the ability
+pub fn could_hold(table: &[String], ability: Ability) -> bool {
+    table.iter().any(|entry| {
+        matches!(Loot::from_entry(entry), Some(Loot::Item(kind)) if kind.ability() == ability)
+    })
+}
+
+// What the party has left of a consumable, and what the level still has to give
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Supply {
+    // Uses left across every ally
+    pub carried: u16,
+    // Lying on the floor waiting to be picked up
+    pub lying: u16,
+    // Could still turn up in a chest or bookshelf nobody has searched
+    pub hidden: bool,
+}
+
+impl Supply {
+    pub fn more_in_level(&self) -> bool {
+        self.lying > 0 || self.hidden
+    }
+
+    // Spending it leaves the party with none and nothing left to find
+    pub fn is_last(&self) -> bool {
+        self.carried <= 1 && !self.more_in_level()
+    }
+
+    pub fn describe(&self) -> String {
+        let found = if self.lying > 0 {
+            format!("{} more lying about", self.lying)
+        } else if self.hidden {
+            "more may turn up in a search".into()
+        } else {
+            "none left to find".into()
+        };
+        format!("{} across the party, {}", self.carried, found)
+    }
+}
+
+// Every entry is as likely as any other, so listing one twice doubles its chances. An empty
+// table, or an entry that can't be read, turns up nothing
+pub fn roll(table: &[String], rng: &mut Rng) -> Loot {
+    if table.is_empty() {
+        return Loot::Nothing;
+    }
+
+    let entry = &table[rng.below(table.len() as u64) as usize];
+    Loot::from_entry(entry).unwrap_or(Loot::Nothing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loot_tables_roll_items_nothing_or_an_ambush() {
+        assert_eq!(
+            Loot::from_entry("HolyWater"),
+            Some(Loot::Item(ItemKind::HolyWater))
+        );
+        assert_eq!(Loot::from_entry("Bat"), Some(Loot::Ambush(EnemyKind::Bat)));
+        assert_eq!(Loot::from_entry("Nothing"), Some(Loot::Nothing));
+        assert_eq!(Loot::from_entry("Gold"), None);
+
+        let mut rng = Rng::new(7);
+        assert_eq!(roll(&[], &mut rng), Loot::Nothing);
+
+        let table: Vec<String> = vec!["Garlic".into(), "Key".into(), "Bat".into()];
+        let rolls: Vec<Loot> = (0..30).map(|_| roll(&table, &mut rng)).collect();
+        assert!(rolls.contains(&Loot::Item(ItemKind::Garlic)));
+        assert!(rolls.contains(&Loot::Item(ItemKind::Key)));
+        assert!(rolls.contains(&Loot::Ambush(EnemyKind::Bat)));
+
+        let mut again = Rng::new(7);
+        roll(&[], &mut again);
+        assert_eq!(
+            (0..30)
+                .map(|_| roll(&table, &mut again))
+                .collect::<Vec<_>>(),
+            rolls
+        );
+    }
+
+    #[test]
+    fn the_last_silver_bolt_is_flagged_before_it_is_spent() {
+        let table: Vec<String> = vec!["Nothing".into(), "SilverBolt".into()];
+        assert!(could_hold(&table, Ability::CrossbowSilverBolt));
+        assert!(!could_hold(&table, Ability::HolyWater));
+        assert!(!could_hold(&[], Ability::CrossbowSilverBolt));
+
+        let last = Supply {
+            carried: 1,
+            lying: 0,
+            hidden: false,
+        };
+        assert!(last.is_last());
+        assert_eq!(last.describe(), "1 across the party, none left to find");
+
+        let hidden = Supply {
+            hidden: true,
+            ..last
+        };
+        assert!(!hidden.is_last());
+        assert_eq!(
+            hidden.describe(),
+            "1 across the party, more may turn up in a search"
+        );
+
+        let shared = Supply {
+            carried: 3,
+            lying: 2,
+            hidden: true,
+        };
+        assert!(shared.more_in_level());
+        assert_eq!(shared.describe(), "3 across the party, 2 more lying about");
+    }
+}
@@ -0,0 +1,293 @@
+use crate::ability::Ability;
+use crate::achievements::{self, Achievement, Achievements, Stat};
+use crate::bestiary::Bestiary;
+use crate::daily::{self, Daily};
+use crate::dialogue::Room;
+use crate::level::{AllyId, EnemyKind, Level};
+use crate::modifiers::Modifiers;
+use crate::progress::Progress;
+use crate::save::{self, Save, SLOTS};
+use crate::settings::SettingsPanel;
+use crate::ui;
+
+use godot::engine::{
+    Button, CenterContainer, Control, ICenterContainer, ItemList, Label, SceneTree,
+};
+use godot::prelude::*;
+use std::collections::HashMap;
+
+pub const MENU_SCENE: &str = "res://scenes/main_menu.tscn";
+// A new game starts with the intro, which goes on to the entrance hall
+const NEW_GAME_SCENE: &str = "res://scenes/intro.tscn";
+
+// What picking a save slot does
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum SlotPick {
+    #[default]
+    Continue,
+    NewGame,
+    NewGamePlus,
+}
+
+#[derive(GodotClass)]
+#[class(init, base=CenterContainer)]
+pub struct MainMenu {
+    progress: Progress,
+    saves: Vec<Option<Save>>,
+    bestiary: Bestiary,
+    slot_pick: SlotPick,
+    base: Base<CenterContainer>,
+}
+
+#[godot_api]
+impl ICenterContainer for MainMenu {
+    fn ready(&mut self) {
+        self.progress = Progress::load();
+        self.saves = (0..SLOTS).map(Save::load).collect();
+        self.bestiary = Bestiary::load();
+
+        let mut continue_button = self.base().get_node_as::<Button>("Panel/Menu/Continue");
+        continue_button.set_disabled(self.saves.iter().all(Option::is_none));
+        let mut new_game_plus = self.base().get_node_as::<Button>("Panel/Menu/NewGamePlus");
+        new_game_plus.set_visible(self.progress.new_game_plus_unlocked());
+        let mut level_select = self.base().get_node_as::<Button>("Panel/Menu/LevelSelect");
+        level_select.set_disabled(self.progress.completed.is_empty());
+
+        // Rooms are listed in order with their best rank, only those made it out of can be picked
+        let mut list = self.base().get_node_as::<ItemList>("Panel/Levels/List");
+        for room in Room::ALL {
+            let text = match self.progress.best.get(&room) {
+                Some(best) => format!("{}  {} ({})", room.name(), best.rank.letter(), best.total()),
+                None => room.name().into(),
+            };
+            let index = list.add_item(text.into());
+            list.set_item_disabled(index, !self.progress.is_completed(room));
+        }
+
+        let mut list = self
+            .base()
+            .get_node_as::<ItemList>("Panel/Bestiary/Columns/List");
+        for kind in EnemyKind::ALL {
+            list.add_item(self.bestiary.title(kind).into());
+        }
+
+        ui::focus_first(self.base().get_node_as::<Node>("Panel/Menu"));
+    }
+}
+
+#[godot_api]
+impl MainMenu {
+    #[func]
+    fn _on_new_game_pressed(&mut self) {
+        self.show_slots(SlotPick::NewGame);
+    }
+
+    #[func]
+    fn _on_new_game_plus_pressed(&mut self) {
+        self.show_slots(SlotPick::NewGamePlus);
+    }
+
+    #[func]
+    fn _on_continue_pressed(&mut self) {
+        self.show_slots(SlotPick::Continue);
+    }
+
+    #[func]
+    fn _on_slot_activated(&mut self, index: i64) {
+        let slot = index as usize;
+        match self.slot_pick {
+            SlotPick::NewGame => {
+                save::set_slot(slot);
+                self.tree().change_scene_to_file(NEW_GAME_SCENE.into());
+            }
+            SlotPick::NewGamePlus => {
+                save::set_slot(slot);
+                self.start(
+                    Modifiers::new_game_plus(self.progress.cycles_finished),
+                    self.progress.carried.clone(),
+                );
+            }
+            SlotPick::Continue => {
+                if let Some(Some(save)) = self.saves.get(slot) {
+                    save::set_slot(slot);
+                    save.restore(self.tree());
+                }
+            }
+        }
+    }
+
+    #[func]
+    fn _on_daily_pressed(&mut self) {
+        let seed = daily::today();
+        let mut title = self.base().get_node_as::<Label>("Panel/Daily/Title");
+        title.set_text(format!("Daily challenge {}", daily::date_of(seed)).into());
+
+        let mut lines: Vec<String> = Daily::load()
+            .on(seed)
+            .iter()
+            .map(|entry| {
+                format!(
+                    "{}: {} ({})",
+                    entry.room.name(),
+                    entry.score.total(),
+                    entry.score.rank.letter()
+                )
+            })
+            .collect();
+        if lines.is_empty() {
+            lines.push("Not played yet today".into());
+        }
+        let mut scores = self.base().get_node_as::<Label>("Panel/Daily/Scores");
+        scores.set_text(lines.join("\n").into());
+
+        self.show_panel("Panel/Daily");
+    }
+
+    // Everyone starting on the same day plays the same upgrades and items, without the party
+    // carrying anything in
+    #[func]
+    fn _on_daily_start_pressed(&mut self) {
+        self.start(Modifiers::daily(daily::today()), HashMap::new());
+    }
+
+    #[func]
+    fn _on_level_select_pressed(&mut self) {
+        self.show_panel("Panel/Levels");
+    }
+
+    #[func]
+    fn _on_level_activated(&mut self, index: i64) {
+        if let Some(room) = Room::ALL.get(index as usize) {
+            if self.progress.is_completed(*room) {
+                self.tree().change_scene_to_file(room.scene().into());
+            }
+        }
+    }
+
+    #[func]
+    fn _on_bestiary_pressed(&mut self) {
+        self.show_panel("Panel/Bestiary");
+    }
+
+    #[func]
+    fn _on_bestiary_selected(&mut self, index: i64) {
+        if let Some(kind) = EnemyKind::ALL.get(index as usize) {
+            let mut entry = self
+                .base()
+                .get_node_as::<Label>("Panel/Bestiary/Columns/Entry");
+            entry.set_text(self.bestiary.entry(*kind).into());
+        }
+    }
+
+    #[func]
+    fn _on_achievements_pressed(&mut self) {
+        let achievements = Achievements::load();
+
+        let mut list = self
+            .base()
+            .get_node_as::<ItemList>("Panel/Achievements/Columns/List");
+        list.clear();
+        for achievement in Achievement::ALL {
+            let def = achievements::definition(achievement);
+            let mark = if achievements.unlocked.contains(&achievement) {
+                "[x]"
+            } else {
+                "[ ]"
+            };
+            list.add_item(format!("{} {}: {}", mark, def.name, def.description).into());
+        }
+
+        let lines: Vec<String> = Stat::ALL
+            .into_iter()
+            .map(|stat| format!("{}: {}", stat.name(), achievements.get(stat)))
+            .collect();
+        let mut stats = self
+            .base()
+            .get_node_as::<Label>("Panel/Achievements/Columns/Stats");
+        stats.set_text(lines.join("\n").into());
+
+        self.show_panel("Panel/Achievements");
+    }
+
+    #[func]
+    fn _on_back_pressed(&mut self) {
+        self.show_panel("Panel/Menu");
+    }
+
+    #[func]
+    fn _on_settings_pressed(&mut self) {
+        self.base()
+            .get_node_as::<SettingsPanel>("Panel/Settings")
+            .bind_mut()
+            .open();
+    }
+
+    #[func]
+    fn _on_quit_pressed(&mut self) {
+        self.tree().quit();
+    }
+}
+
+impl MainMenu {
+    fn tree(&self) -> Gd<SceneTree> {
+        self.base().get_tree().unwrap()
+    }
+
+    // Starting a new game can overwrite any slot, continuing needs one with a save in it
+    fn show_slots(&mut self, slot_pick: SlotPick) {
+        self.slot_pick = slot_pick;
+
+        let mut title = self.base().get_node_as::<Label>("Panel/Slots/Title");
+        title.set_text(match slot_pick {
+            SlotPick::NewGame => "Start a new game in which slot?".into(),
+            SlotPick::NewGamePlus => "Start New Game+ in which slot?".into(),
+            SlotPick::Continue => "Continue which game?".into(),
+        });
+
+        let mut list = self.base().get_node_as::<ItemList>("Panel/Slots/List");
+        list.clear();
+        for (slot, save) in self.saves.iter().enumerate() {
+            let text = match save {
+                Some(save) => format!("Slot {}: {}", slot + 1, save.summary()),
+                None => format!("Slot {}: empty", slot + 1),
+            };
+            let index = list.add_item(text.into());
+            list.set_item_disabled(index, slot_pick == SlotPick::Continue && save.is_none());
+        }
+
+        self.show_panel("Panel/Slots");
+    }
+
+    // Skips the intro, going straight into the entrance hall
+    fn start(&mut self, modifiers: Modifiers, inventory: HashMap<AllyId, Vec<(Ability, u16)>>) {
+        let scene = load::<PackedScene>(Room::EntranceHall.scene());
+        let mut level: Gd<Level> = scene.instantiate().unwrap().cast();
+
+        {
+            let mut level = level.bind_mut();
+            level.modifiers = modifiers;
+            level.inventory = inventory;
+        }
+
+        let mut tree = self.tree();
+        tree.get_root().unwrap().add_child(level.clone().upcast());
+        tree.set_current_scene(level.upcast());
+        self.base_mut().queue_free();
+    }
+
+    fn show_panel(&mut self, shown: &str) {
+        for path in [
+            "Panel/Menu",
+            "Panel/Levels",
+            "Panel/Slots",
+            "Panel/Bestiary",
+            "Panel/Achievements",
+            "Panel/Daily",
+        ] {
+            self.base()
+                .get_node_as::<Control>(path)
+                .set_visible(path == shown);
+        }
+        ui::focus_first(self.base().get_node_as::<Node>(shown));
+    }
+}
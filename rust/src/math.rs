@@ -1,14 +1,18 @@
-use crate::level::{Level, ObstacleKind, Tile, LEVEL_HEIGHT, LEVEL_WIDTH, TILE_SIZE};
+use crate::bench::{self, Stage};
+use crate::core::{Occupancy, Terrain, Tile};
+use crate::grid::Grid;
+use crate::level::{Level, TILE_SIZE};
 
 use godot::prelude::*;
 use num_integer::Roots;
 use num_rational::Rational32;
+use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::collections::{BinaryHeap, HashMap, HashSet};
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
-#[derive(Debug, Clone, Copy, EnumIter)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, Serialize, Deserialize)]
 pub enum Direction {
     Left,
     Right,
@@ -16,7 +20,26 @@ pub enum Direction {
     Down,
 }
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+// How a unit moves across the grid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Movement {
+    Walk,
+    Phase,
+}
+
+impl Movement {
+    pub fn passes(&self, tile: Tile) -> bool {
+        match (self, tile) {
+            (_, Tile::Empty) => true,
+            (Self::Phase, Tile::Obstacle(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
 pub struct Position {
     pub x: usize,
     pub y: usize,
@@ -34,6 +57,14 @@ impl Position {
         Vector2::new(self.x as f32, self.y as f32) * TILE_SIZE
     }
 
+    pub fn offset(&self, dx: usize, dy: usize) -> Self {
+        Self {
+            x: self.x + dx,
+            y: self.y + dy,
+        }
+    }
+
+    // Neighbours that do not go below zero, Grid::adjacent also keeps them inside the level
     pub fn adjacent(&self) -> Vec<Self> {
         let mut positions = Vec::new();
 
@@ -44,40 +75,94 @@ impl Position {
             });
         }
 
-        if self.x < LEVEL_WIDTH - 1 {
+        positions.push(Position {
+            x: self.x + 1,
+            y: self.y,
+        });
+
+        if self.y > 0 {
             positions.push(Position {
-                x: self.x + 1,
-                y: self.y,
+                x: self.x,
+                y: self.y - 1,
+            });
+        }
+
+        positions.push(Position {
+            x: self.x,
+            y: self.y + 1,
+        });
+
+        positions
+    }
+
+    // Diagonal neighbours that do not go below zero
+    pub fn diagonals(&self) -> Vec<Self> {
+        let mut positions = vec![Position {
+            x: self.x + 1,
+            y: self.y + 1,
+        }];
+
+        if self.x > 0 {
+            positions.push(Position {
+                x: self.x - 1,
+                y: self.y + 1,
             });
         }
 
         if self.y > 0 {
             positions.push(Position {
-                x: self.x,
+                x: self.x + 1,
                 y: self.y - 1,
             });
         }
 
-        if self.y < LEVEL_HEIGHT - 1 {
+        if self.x > 0 && self.y > 0 {
             positions.push(Position {
-                x: self.x,
-                y: self.y + 1,
+                x: self.x - 1,
+                y: self.y - 1,
             });
         }
 
         positions
     }
 
+    // The closest of some positions lying ahead within 45 degrees of a direction, ties going to
+    // whichever is furthest along it
+    pub fn nearest_ahead(&self, direction: Direction, positions: &[Self]) -> Option<Self> {
+        let (x, y) = (self.x as isize, self.y as isize);
+        positions
+            .iter()
+            .filter_map(|position| {
+                let (px, py) = (position.x as isize, position.y as isize);
+                let (along, across) = match direction {
+                    Direction::Left => (x - px, py - y),
+                    Direction::Right => (px - x, py - y),
+                    Direction::Up => (y - py, px - x),
+                    Direction::Down => (py - y, px - x),
+                };
+                (along > 0 && across.abs() <= along)
+                    .then_some(((along + across.abs(), -along, *position), *position))
+            })
+            .min_by_key(|(key, _)| *key)
+            .map(|(_, position)| position)
+    }
+
+    pub fn is_diagonal_to(&self, other: Self) -> bool {
+        self.x != other.x && self.y != other.y
+    }
+
     pub fn distance(&self, other: Self) -> u16 {
         let dx = self.x as i16 - other.x as i16;
         let dy = self.y as i16 - other.y as i16;
         (dx * dx + dy * dy).sqrt() as u16
     }
 
+    // Diagonals face along whichever axis they go further on, sideways when it's even
     pub fn direction_to(&self, other: Self) -> Direction {
-        if other.x < self.x {
+        let horizontal = self.x.abs_diff(other.x) >= self.y.abs_diff(other.y);
+        if horizontal && other.x < self.x {
             Direction::Left
-        } else if other.x > self.x {
+        } else if horizontal && other.x > self.x {
             Direction::Right
         } else if other.y < self.y {
             Direction::Up
@@ -100,16 +185,10 @@ impl Position {
                     })
                 }
             }
-            Direction::Right => {
-                if self.x + dist >= LEVEL_WIDTH {
-                    None
-                } else {
-                    Some(Position {
-                        x: self.x + dist,
-                        y: self.y,
-                    })
-                }
-            }
+            Direction::Right => Some(Position {
+                x: self.x + dist,
+                y: self.y,
+            }),
             Direction::Up => {
                 if self.y < dist {
                     None
@@ -120,16 +199,10 @@ impl Position {
                     })
                 }
             }
-            Direction::Down => {
-                if self.y + dist >= LEVEL_HEIGHT {
-                    None
-                } else {
-                    Some(Position {
-                        x: self.x,
-                        y: self.y + dist,
-                    })
-                }
-            }
+            Direction::Down => Some(Position {
+                x: self.x,
+                y: self.y + dist,
+            }),
         }
     }
 }
@@ -152,92 +225,334 @@ impl PartialOrd for Frontier {
     }
 }
 
-// A* algorithm
-pub fn pathfind(
-    start: Position,
-    goal: Position,
-    grid: [[Tile; LEVEL_HEIGHT]; LEVEL_WIDTH],
-    start_tile: Tile,
-    dimensions: (usize, usize),
-) -> Option<Vec<Position>> {
+// Path costs are counted in half steps so a diagonal step can cost one and a half. Costs given
+// to and returned from a Pathfinder are whole steps rounded down, so diagonals alternate between
+// costing one step and two
+const STEP_COST: u16 = 2;
+const DIAGONAL_COST: u16 = 3;
+
+// Cost in whole steps of walking a path, which doesn't include where it starts
+pub fn path_cost(start: Position, path: &[Position]) -> u16 {
+    let mut cost = 0;
+    let mut position = start;
+    for next in path {
+        cost += if position.is_diagonal_to(*next) {
+            DIAGONAL_COST
+        } else {
+            STEP_COST
+        };
+        position = *next;
+    }
+    cost / STEP_COST
+}
+
+// The unit being moved: the tile it leaves behind, its footprint and how it gets around
+#[derive(Debug, Clone, Copy)]
+pub struct Mover {
+    pub tile: Tile,
+    pub dimensions: (usize, usize),
+    pub movement: Movement,
+    // Whether the level's rules allow stepping diagonally
+    pub diagonal: bool,
+    // Terrain the unit refuses to stand in
+    pub avoids: Option<Terrain>,
+}
+
+impl Mover {
+    fn passes(&self, occupancy: &Occupancy) -> bool {
+        (occupancy.tile == self.tile || self.movement.passes(occupancy.tile))
+            && !occupancy.arriving
+            && !self
+                .avoids
+                .is_some_and(|terrain| occupancy.terrain.contains(&terrain))
+    }
+
+    // Whether the whole footprint fits with its top left corner on a position
+    pub fn fits(&self, position: Position, grid: &Grid<Occupancy>) -> bool {
+        let (width, height) = self.dimensions;
+        (0..width).all(|i| {
+            (0..height).all(|j| match grid.get(position.offset(i, j)) {
+                Some(occupancy) => self.passes(occupancy),
+                None => false,
+            })
+        })
+    }
+
+    fn is_wide(&self) -> bool {
+        self.dimensions != (1, 1)
+    }
+}
+
+// Whether a new unit of some size could be placed with its top left corner on a position
+pub fn has_room(position: Position, dimensions: (usize, usize), grid: &Grid<Occupancy>) -> bool {
     let (width, height) = dimensions;
-    let mut frontier = BinaryHeap::new();
-    let mut came_from = HashMap::new();
-    let mut costs = HashMap::new();
-
-    frontier.push(Frontier {
-        priority: 0,
-        position: start,
-    });
-    costs.insert(start, 0);
-
-    while let Some(Frontier {
-        priority: _,
-        position,
-    }) = frontier.pop()
-    {
-        if position == goal {
-            break;
+    (0..width).all(|i| {
+        (0..height).all(|j| {
+            grid.get(position.offset(i, j))
+                .is_some_and(|occupancy| occupancy.tile.is_empty() && !occupancy.arriving)
+        })
+    })
+}
+
+// Search buffers kept between searches so planning a turn does not allocate for every path tried
+#[derive(Debug, Default)]
+pub struct Pathfinder {
+    frontier: BinaryHeap<Frontier>,
+    came_from: HashMap<Position, Position>,
+    costs: HashMap<Position, u16>,
+    // Side of the largest open square with its top left corner on each tile, only filled in
+    // when the mover is wider than a tile
+    clearance: Grid<u16>,
+    start: Position,
+}
+
+impl Pathfinder {
+    // A* algorithm, giving up on anything costing more than max_cost
+    pub fn a_star(
+        &mut self,
+        start: Position,
+        goal: Position,
+        grid: &Grid<Occupancy>,
+        mover: Mover,
+        max_cost: Option<u16>,
+    ) -> Option<Vec<Position>> {
+        self.reset(start);
+        bench::time(Stage::Pathfinding, || {
+            self.search(&[goal], grid, mover, max_cost)
+        });
+        self.path_to(goal)
+    }
+
+    // Cheapest path to whichever goal is closest and what it costs, so callers weighing
+    // positions against each other need not search for each one
+    pub fn pathfind_to_any(
+        &mut self,
+        start: Position,
+        goals: &[Position],
+        grid: &Grid<Occupancy>,
+        mover: Mover,
+        max_cost: Option<u16>,
+    ) -> Option<(Vec<Position>, u16)> {
+        self.reset(start);
+        let goal = bench::time(Stage::Pathfinding, || {
+            self.search(goals, grid, mover, max_cost)
+        })?;
+        Some((self.path_to(goal)?, self.cost_to(goal)?))
+    }
+
+    // Dijkstra's algorithm over everything within max_cost, after which any number of goals can
+    // be looked up with path_to and cost_to
+    pub fn flood(
+        &mut self,
+        start: Position,
+        grid: &Grid<Occupancy>,
+        mover: Mover,
+        max_cost: Option<u16>,
+    ) {
+        self.reset(start);
+        bench::time(Stage::Pathfinding, || {
+            self.search(&[], grid, mover, max_cost)
+        });
+    }
+
+    // Path from the start of the last search to a position it reached, without the start itself
+    pub fn path_to(&self, goal: Position) -> Option<Vec<Position>> {
+        let mut position = goal;
+        let mut path = Vec::new();
+
+        while position != self.start {
+            path.push(position);
+            position = *self.came_from.get(&position)?;
         }
+        path.reverse();
 
-        'a: for adjacent in &position.adjacent() {
-            for i in 0..width {
-                for j in 0..height {
-                    if adjacent.x + i >= LEVEL_WIDTH || adjacent.y + j >= LEVEL_HEIGHT {
-                        continue 'a;
-                    }
-
-                    if grid[adjacent.x + i][adjacent.y + j] != start_tile {
-                        if !grid[adjacent.x + i][adjacent.y + j].is_empty() {
-                            continue 'a;
-                        }
-                    }
-                }
-            }
+        Some(path)
+    }
+
+    pub fn cost_to(&self, goal: Position) -> Option<u16> {
+        self.costs.get(&goal).map(|cost| cost / STEP_COST)
+    }
 
-            let new_cost = costs.get(&position).unwrap() + 1;
-            if !costs.contains_key(&adjacent) || new_cost < *costs.get(&adjacent).unwrap() {
-                let diagonal = if position.x != adjacent.x && position.y != adjacent.y {
-                    1
+    fn reset(&mut self, start: Position) {
+        self.frontier.clear();
+        self.came_from.clear();
+        self.costs.clear();
+        self.start = start;
+
+        self.frontier.push(Frontier {
+            priority: 0,
+            position: start,
+        });
+        self.costs.insert(start, 0);
+    }
+
+    /*
+     * Clearance is worked out once per search the way Brogue does it, from the bottom right corner
+     * up: a tile's clearance is one more than the smallest clearance to its right, below it and
+     * diagonally below it. A wide unit then fits anywhere the clearance is at least its widest
+     * side, so it can never clip a wall or squeeze through a gap narrower than itself.
+     */
+    fn fill_clearance(&mut self, grid: &Grid<Occupancy>, mover: Mover) {
+        let (width, height) = grid.dimensions();
+        if self.clearance.dimensions() != (width, height) {
+            self.clearance = Grid::new(width, height, 0);
+        }
+
+        for x in (0..width).rev() {
+            for y in (0..height).rev() {
+                let position = Position { x, y };
+                let clearance = if mover.passes(&grid[position]) {
+                    let clearance = |position| *self.clearance.get(position).unwrap_or(&0);
+                    1 + clearance(position.offset(1, 0))
+                        .min(clearance(position.offset(0, 1)))
+                        .min(clearance(position.offset(1, 1)))
                 } else {
                     0
                 };
-                frontier.push(Frontier {
-                    priority: new_cost + adjacent.distance(goal) + diagonal,
-                    position: *adjacent,
-                });
-                came_from.insert(*adjacent, Some(position));
-                costs.insert(*adjacent, new_cost);
+                self.clearance[position] = clearance;
             }
         }
     }
 
-    let mut position = goal;
-    let mut path = Vec::new();
+    fn fits(&self, mover: Mover, position: Position, grid: &Grid<Occupancy>) -> bool {
+        if mover.is_wide() {
+            let (width, height) = mover.dimensions;
+            self.clearance
+                .get(position)
+                .is_some_and(|clearance| *clearance as usize >= width.max(height))
+        } else {
+            mover.fits(position, grid)
+        }
+    }
 
-    while position != start {
-        path.push(position);
-        position = match came_from.get(&position) {
-            Some(Some(position)) => *position,
-            _ => return None,
-        };
+    // Diagonal steps can't cut a corner, so both tiles beside the step have to fit as well
+    fn steps(
+        &self,
+        mover: Mover,
+        position: Position,
+        grid: &Grid<Occupancy>,
+    ) -> Vec<(Position, u16)> {
+        let mut steps: Vec<(Position, u16)> = grid
+            .adjacent(position)
+            .into_iter()
+            .filter(|adjacent| self.fits(mover, *adjacent, grid))
+            .map(|adjacent| (adjacent, STEP_COST))
+            .collect();
+
+        // Stairs are too narrow for anything wide
+        if let Some(stairs) = grid[position].stairs {
+            if !mover.is_wide() && self.fits(mover, stairs, grid) {
+                steps.push((stairs, STEP_COST));
+            }
+        }
+
+        if mover.diagonal {
+            for diagonal in position.diagonals() {
+                let beside = [
+                    Position {
+                        x: diagonal.x,
+                        y: position.y,
+                    },
+                    Position {
+                        x: position.x,
+                        y: diagonal.y,
+                    },
+                ];
+                if self.fits(mover, diagonal, grid)
+                    && beside
+                        .iter()
+                        .all(|position| self.fits(mover, *position, grid))
+                {
+                    steps.push((diagonal, DIAGONAL_COST));
+                }
+            }
+        }
+
+        steps
     }
-    path.reverse();
 
-    Some(path)
+    // Stops at the first goal reached. Only a single goal steers the search, otherwise positions
+    // are expanded in order of cost
+    fn search(
+        &mut self,
+        goals: &[Position],
+        grid: &Grid<Occupancy>,
+        mover: Mover,
+        max_cost: Option<u16>,
+    ) -> Option<Position> {
+        if mover.is_wide() {
+            self.fill_clearance(grid, mover);
+        }
+        // Stairs can join tiles that lie far apart, so distance is no guide on levels with them
+        let guided = grid
+            .positions()
+            .all(|position| grid[position].stairs.is_none());
+
+        while let Some(Frontier {
+            priority: _,
+            position,
+        }) = self.frontier.pop()
+        {
+            if goals.contains(&position) {
+                return Some(position);
+            }
+
+            let cost = self.costs[&position];
+            for (adjacent, step_cost) in self.steps(mover, position, grid) {
+                let new_cost = cost + step_cost;
+                if max_cost.is_some_and(|max_cost| new_cost / STEP_COST > max_cost) {
+                    continue;
+                }
+
+                if self
+                    .costs
+                    .get(&adjacent)
+                    .map_or(true, |cost| new_cost < *cost)
+                {
+                    // Straight line distance never overestimates, diagonals or not
+                    let priority = match goals {
+                        [goal] if guided => new_cost + adjacent.distance(*goal) * STEP_COST,
+                        _ => new_cost,
+                    };
+                    self.frontier.push(Frontier {
+                        priority,
+                        position: adjacent,
+                    });
+                    self.came_from.insert(adjacent, position);
+                    self.costs.insert(adjacent, new_cost);
+                }
+            }
+        }
+        None
+    }
 }
 
-// Find a straight line, unobstructed, between two points
-pub fn line_to(
+// Furthest position reached by sliding in a direction until something is in the way of any
+// part of the mover
+pub fn slide(
     start: Position,
-    goal: Position,
-    grid: [[Tile; LEVEL_HEIGHT]; LEVEL_WIDTH],
-) -> Option<Vec<Position>> {
+    direction: Direction,
+    distance: u16,
+    grid: &Grid<Occupancy>,
+    mover: Mover,
+) -> Position {
+    let mut position = start;
+    for dist in 1..=distance {
+        match grid.in_direction(start, direction, dist as usize) {
+            Some(next) if mover.fits(next, grid) => position = next,
+            _ => break,
+        }
+    }
+    position
+}
+
+// Find a straight line, unobstructed, between two points
+pub fn line_to(start: Position, goal: Position, grid: &Grid<Occupancy>) -> Option<Vec<Position>> {
     let distance = start.distance(goal) as usize;
     for direction in Direction::iter() {
         let mut path = Vec::new();
         for dist in 1..=distance {
-            let position = match start.in_direction(direction, dist) {
+            let position = match grid.in_direction(start, direction, dist) {
                 Some(position) => position,
                 None => break,
             };
@@ -247,7 +562,7 @@ pub fn line_to(
                 return Some(path);
             }
 
-            if grid[position.x][position.y].is_empty() {
+            if grid[position].tile.is_empty() {
                 path.push(position);
             } else {
                 break;
@@ -257,43 +572,117 @@ pub fn line_to(
     None
 }
 
-// All positions from which an enemy can attack an ally within the given range
+/*
+ * Line of sight for ranged abilities, walked as a supercover from the centre of one tile to the
+ * centre of the other so every tile the line touches is checked. Where the line passes exactly
+ * through a corner it only stops if both tiles beside the corner are blocked, so a shot can't
+ * squeeze between two diagonal walls but isn't stopped by one either. Returns the tiles after the
+ * start up to and including the goal, which itself is never checked.
+ */
+pub fn line_of_sight(
+    start: Position,
+    goal: Position,
+    passes: impl Fn(Position) -> bool,
+) -> Option<Vec<Position>> {
+    let dx = goal.x as i32 - start.x as i32;
+    let dy = goal.y as i32 - start.y as i32;
+    let (nx, ny) = (dx.abs(), dy.abs());
+    let (sign_x, sign_y) = (dx.signum(), dy.signum());
+
+    // The line stays inside the box between start and goal, so nothing goes below zero
+    let at = |x: i32, y: i32| Position {
+        x: x as usize,
+        y: y as usize,
+    };
+    let blocked = |position: Position| position != goal && !passes(position);
+
+    let (mut x, mut y) = (start.x as i32, start.y as i32);
+    let (mut ix, mut iy) = (0, 0);
+    let mut line = Vec::new();
+
+    while ix < nx || iy < ny {
+        let decision = (1 + 2 * ix) * ny - (1 + 2 * iy) * nx;
+        if decision == 0 {
+            if blocked(at(x + sign_x, y)) && blocked(at(x, y + sign_y)) {
+                return None;
+            }
+            x += sign_x;
+            y += sign_y;
+            ix += 1;
+            iy += 1;
+        } else if decision < 0 {
+            x += sign_x;
+            ix += 1;
+        } else {
+            y += sign_y;
+            iy += 1;
+        }
+
+        let position = at(x, y);
+        if blocked(position) {
+            return None;
+        }
+        line.push(position);
+    }
+
+    Some(line)
+}
+
+// Where a shot along a line from line_of_sight comes down: on the first tile someone stands on, or
+// at the end of the line when nobody is in the way
+pub fn intercept(line: &[Position], occupied: impl Fn(Position) -> bool) -> Option<Position> {
+    line.iter()
+        .copied()
+        .find(|position| occupied(*position))
+        .or(line.last().copied())
+}
+
+// All positions the mover fits from which a target can be hit within the given range, with how
+// far away each one is
 pub fn attack_positions(
-    position: Position,
+    target: Position,
     range: u16,
-    grid: [[Tile; LEVEL_HEIGHT]; LEVEL_WIDTH],
-    dimensions: (usize, usize),
+    grid: &Grid<Occupancy>,
+    mover: Mover,
+    passes: impl Fn(Position) -> bool,
 ) -> Vec<(Position, u16)> {
-    let (width, height) = dimensions;
+    let reach = range as usize;
     let mut positions = Vec::new();
-    for i in 0..width {
-        for j in 0..height {
-            if position.x + i >= LEVEL_WIDTH || position.y + j >= LEVEL_HEIGHT {
+    for x in target.x.saturating_sub(reach)..=target.x + reach {
+        for y in target.y.saturating_sub(reach)..=target.y + reach {
+            let position = Position { x, y };
+            if !mover.fits(position, grid) {
                 continue;
             }
-            let position = Position {
-                x: position.x + i,
-                y: position.y + j,
-            };
-            for direction in Direction::iter() {
-                for dist in 1..=range {
-                    let position = match position.in_direction(direction, dist as usize) {
-                        Some(position) => position,
-                        None => break,
-                    };
 
-                    if grid[position.x][position.y].is_empty() {
-                        positions.push((position, dist));
-                    } else {
-                        break;
-                    }
-                }
+            let distance = position.distance(target);
+            if distance <= range && line_of_sight(position, target, &passes).is_some() {
+                positions.push((position, distance));
             }
         }
     }
     positions
 }
 
+// Cheapest run of at most distance to anywhere within reach of the target, empty for a runner
+// already there
+pub fn dash_path(
+    pathfinder: &mut Pathfinder,
+    start: Position,
+    target: Position,
+    (reach, distance): (u16, u16),
+    grid: &Grid<Occupancy>,
+    mover: Mover,
+) -> Option<Vec<Position>> {
+    let goals: Vec<Position> = attack_positions(target, reach, grid, mover, |_| true)
+        .into_iter()
+        .map(|(position, _)| position)
+        .collect();
+    pathfinder
+        .pathfind_to_any(start, &goals, grid, mover, Some(distance))
+        .map(|(path, _)| path)
+}
+
 #[derive(Debug, Clone, Copy, EnumIter)]
 pub enum Cardinal {
     North,
@@ -366,18 +755,95 @@ impl Row {
 
 // Calculate visible tiles, obstructed by walls, within view distance
 pub fn compute_fov(origin: Position, distance: u16, level: &Level) -> HashSet<Position> {
+    // Other floors are out of sight entirely, not just behind a wall
+    bench::time(Stage::Fov, || {
+        level
+            .fov_cache
+            .borrow_mut()
+            .get(origin, distance, level.grid.dimensions(), &|position| {
+                blocks_sight(position, level) || !level.same_floor(origin, position)
+            })
+            .iter()
+            .copied()
+            .filter(|position| level.same_floor(origin, *position))
+            .collect()
+    })
+}
+
+// Ahead of a unit facing some way, out to 45 degrees either side. Nothing beside or behind it is
+// seen, not even the tiles right next to it
+pub fn in_cone(origin: Position, facing: Direction, position: Position) -> bool {
+    let dx = position.x as i32 - origin.x as i32;
+    let dy = position.y as i32 - origin.y as i32;
+    match facing {
+        Direction::Left => -dx >= dy.abs(),
+        Direction::Right => dx >= dy.abs(),
+        Direction::Up => -dy >= dx.abs(),
+        Direction::Down => dy >= dx.abs(),
+    }
+}
+
+// A field of view cut down to what a unit facing some way can see of it
+pub fn cone(visible: HashSet<Position>, origin: Position, facing: Direction) -> HashSet<Position> {
+    visible
+        .into_iter()
+        .filter(|position| in_cone(origin, facing, *position))
+        .collect()
+}
+
+// Fields of view by origin and view distance. Only obstacles block sight, so the cache is
+// invalidated whenever one is removed
+#[derive(Debug, Clone, Default)]
+pub struct FovCache {
+    fovs: HashMap<(Position, u16), HashSet<Position>>,
+}
+
+impl FovCache {
+    pub fn get(
+        &mut self,
+        origin: Position,
+        distance: u16,
+        dimensions: (usize, usize),
+        blocks_sight: &dyn Fn(Position) -> bool,
+    ) -> &HashSet<Position> {
+        self.fovs
+            .entry((origin, distance))
+            .or_insert_with(|| shadowcast(origin, distance, dimensions, blocks_sight))
+    }
+
+    pub fn invalidate(&mut self) {
+        self.fovs.clear();
+    }
+}
+
+// Field of view over any map, given its size and which tiles inside it block sight
+pub fn shadowcast(
+    origin: Position,
+    distance: u16,
+    dimensions: (usize, usize),
+    blocks_sight: &dyn Fn(Position) -> bool,
+) -> HashSet<Position> {
+    let (width, height) = dimensions;
+    let is_wall =
+        |position: Position| position.x >= width || position.y >= height || blocks_sight(position);
+
     let mut visible = HashSet::new();
     visible.insert(origin);
     for cardinal in Cardinal::iter() {
         let quadrant = Quadrant::new(origin, cardinal);
         let first_row = Row::new(1, Rational32::from_integer(-1), Rational32::from_integer(1));
-        visible.extend(scan(quadrant, first_row, distance, level));
+        visible.extend(scan(quadrant, first_row, distance, &is_wall));
     }
 
     visible
 }
 
-fn scan(quadrant: Quadrant, mut row: Row, distance: u16, level: &Level) -> HashSet<Position> {
+fn scan(
+    quadrant: Quadrant,
+    mut row: Row,
+    distance: u16,
+    is_wall: &dyn Fn(Position) -> bool,
+) -> HashSet<Position> {
     if distance == 0 {
         return HashSet::new();
     }
@@ -388,20 +854,20 @@ fn scan(quadrant: Quadrant, mut row: Row, distance: u16, level: &Level) -> HashS
     for tile in row.tiles() {
         let position = quadrant.transform(tile);
 
-        if is_wall(position, level) || is_symmetric(row, tile) {
+        if is_wall(position) || is_symmetric(row, tile) {
             visible.insert(position);
         }
 
         match prev_position {
             Some(prev_position) => {
-                if is_wall(prev_position, level) && !is_wall(position, level) {
+                if is_wall(prev_position) && !is_wall(position) {
                     row.start_slope = slope(tile);
                 }
 
-                if !is_wall(prev_position, level) && is_wall(position, level) {
+                if !is_wall(prev_position) && is_wall(position) {
                     let mut next_row = row.next();
                     next_row.end_slope = slope(tile);
-                    visible.extend(scan(quadrant, next_row, distance - 1, level));
+                    visible.extend(scan(quadrant, next_row, distance - 1, is_wall));
                 }
             }
             None => (),
@@ -411,8 +877,8 @@ fn scan(quadrant: Quadrant, mut row: Row, distance: u16, level: &Level) -> HashS
     }
 
     match prev_position {
-        Some(prev_position) if !is_wall(prev_position, level) => {
-            visible.extend(scan(quadrant, row.next(), distance - 1, level));
+        Some(prev_position) if !is_wall(prev_position) => {
+            visible.extend(scan(quadrant, row.next(), distance - 1, is_wall));
         }
         _ => (),
     }
@@ -420,21 +886,14 @@ fn scan(quadrant: Quadrant, mut row: Row, distance: u16, level: &Level) -> HashS
     visible
 }
 
-fn is_wall(position: Position, level: &Level) -> bool {
-    if position.x >= LEVEL_WIDTH || position.y >= LEVEL_HEIGHT {
-        true
-    } else {
-        match level.grid[position.x][position.y] {
-            Tile::Obstacle(id) => {
-                let obstacle = level.get_obstacle(id);
-                let obstacle = obstacle.bind();
-                match obstacle.kind {
-                    ObstacleKind::Wall | ObstacleKind::Barrel => true,
-                    ObstacleKind::LowWall => false,
-                }
-            }
-            _ => false,
+fn blocks_sight(position: Position, level: &Level) -> bool {
+    match level.grid[position].tile {
+        Tile::Obstacle(id) => {
+            let obstacle = level.get_obstacle(id);
+            let obstacle = obstacle.bind();
+            obstacle.kind.blocks_sight()
         }
+        _ => false,
     }
 }
 
@@ -457,3 +916,55 @@ fn round_ties_up(n: Rational32) -> i32 {
 fn round_ties_down(n: Rational32) -> i32 {
     (n - Rational32::new(1, 2)).ceil().to_integer()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jumping_snaps_to_the_nearest_unit_ahead() {
+        let from = Position { x: 5, y: 5 };
+        let units = [
+            Position { x: 5, y: 9 },
+            Position { x: 7, y: 7 },
+            Position { x: 9, y: 5 },
+            Position { x: 4, y: 5 },
+        ];
+        assert_eq!(
+            from.nearest_ahead(Direction::Down, &units),
+            Some(Position { x: 5, y: 9 })
+        );
+        // The diagonal is as close, but the one straight ahead wins the tie
+        assert_eq!(
+            from.nearest_ahead(Direction::Right, &units),
+            Some(Position { x: 9, y: 5 })
+        );
+        assert_eq!(
+            from.nearest_ahead(Direction::Left, &units),
+            Some(Position { x: 4, y: 5 })
+        );
+        assert_eq!(from.nearest_ahead(Direction::Up, &units), None);
+    }
+
+    #[test]
+    fn shots_stop_on_whoever_stepped_into_the_line() {
+        let start = Position { x: 0, y: 0 };
+        let target = Position { x: 4, y: 0 };
+        let line = line_of_sight(start, target, |_| true).unwrap();
+
+        assert_eq!(intercept(&line, |_| false), Some(target));
+        assert_eq!(
+            intercept(&line, |position| position == target),
+            Some(target)
+        );
+        // The nearer of two in the way takes it
+        let blockers = [Position { x: 3, y: 0 }, Position { x: 2, y: 0 }];
+        assert_eq!(
+            intercept(&line, |position| blockers.contains(&position)),
+            Some(Position { x: 2, y: 0 })
+        );
+        // The shooter's own tile isn't on the line
+        assert_eq!(intercept(&line, |position| position == start), Some(target));
+        assert_eq!(intercept(&[], |_| true), None);
+    }
+}
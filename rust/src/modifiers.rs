@@ -0,0 +1,130 @@
+use crate::ability::Ability;
+use crate::core::Vitals;
+use crate::level::{Enemy, EnemyKind};
+use crate::rng::Rng;
+use crate::traits::Trait;
+
+use serde::{Deserialize, Serialize};
+
+// Keeps item draws apart from the per-kind upgrade draws
+const ITEM_STREAM: u64 = 0x100;
+
+// Changes a run makes to every room it loads, on top of what the room's scene sets up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Modifiers {
+    // 0 on the first playthrough, counting up with each New Game+
+    pub cycle: u8,
+    // Set for a daily challenge, which draws the day's upgrades and items from it
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+impl Modifiers {
+    pub fn new_game_plus(cycle: u8) -> Self {
+        Self { cycle, seed: None }
+    }
+
+    pub fn daily(seed: u64) -> Self {
+        Self {
+            cycle: 0,
+            seed: Some(seed),
+        }
+    }
+
+    pub fn is_new_game_plus(&self) -> bool {
+        self.cycle > 0
+    }
+
+    // Called once the enemy's scene has set up its lists, whether placed in the room or spawned
+    pub fn upgrade(&self, enemy: &mut Enemy) {
+        if !self.upgrades(enemy.kind) {
+            return;
+        }
+
+        for (ability, uses) in extra_abilities(enemy.kind) {
+            if !enemy.abilities.contains(&ability) {
+                enemy.abilities.push(ability);
+                enemy.uses.insert(ability, uses);
+            }
+        }
+
+        let mut traits = enemy.vitals.base_traits.clone();
+        for extra in extra_traits(enemy.kind) {
+            if !traits.contains(&extra) {
+                traits.push(extra);
+            }
+        }
+
+        // Half as much health again each cycle, a daily challenge counting as one
+        enemy.max_health += enemy.max_health * self.cycle.max(1) as u16 / 2;
+        enemy.vitals = Vitals::new(enemy.max_health, traits);
+    }
+
+    // A daily challenge upgrades the kinds its seed picks, about half of them
+    fn upgrades(&self, kind: EnemyKind) -> bool {
+        match self.seed {
+            Some(seed) => Rng::stream(seed, kind as u64).below(2) == 0,
+            None => self.is_new_game_plus(),
+        }
+    }
+
+    // Every other item placed in a room is left out after the first playthrough, and a daily
+    // challenge leaves out about a third, picked by its seed
+    pub fn keeps_item(&self, index: usize) -> bool {
+        match self.seed {
+            Some(seed) => Rng::stream(seed, ITEM_STREAM + index as u64).below(3) != 0,
+            None => !self.is_new_game_plus() || index % 2 == 0,
+        }
+    }
+}
+
+// Vampires calling bats of their own make for more spawners
+fn extra_abilities(kind: EnemyKind) -> Vec<(Ability, u16)> {
+    match kind {
+        EnemyKind::Vampire => vec![(Ability::SpawnBat, 1)],
+        EnemyKind::Ghost => vec![(Ability::DrainLife, 1)],
+        EnemyKind::Bat | EnemyKind::BigBatty | EnemyKind::Necromancer | EnemyKind::Werewolf => {
+            Vec::new()
+        }
+    }
+}
+
+fn extra_traits(kind: EnemyKind) -> Vec<Trait> {
+    match kind {
+        EnemyKind::Vampire | EnemyKind::Werewolf => vec![Trait::Regeneration],
+        EnemyKind::BigBatty => vec![Trait::PierceResistant],
+        EnemyKind::Necromancer => vec![Trait::FireImmune],
+        EnemyKind::Bat | EnemyKind::Ghost => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialogue::Room;
+    use crate::replay::Replay;
+    use crate::save::Save;
+
+    #[test]
+    fn new_game_plus_thins_out_items_and_survives_older_replays() {
+        let first = Modifiers::default();
+        let plus = Modifiers::new_game_plus(1);
+        assert_eq!((0..6).filter(|index| first.keeps_item(*index)).count(), 6);
+        assert_eq!((0..6).filter(|index| plus.keeps_item(*index)).count(), 3);
+
+        let mut save = Save {
+            version: 1,
+            room: Room::EntranceHall,
+            play_time: 0.0,
+            health: Vec::new(),
+            replay: Replay::default(),
+        };
+        let json = serde_json::to_string(&save).unwrap();
+        let old = json.replace(",\"modifiers\":{\"cycle\":0}", "");
+        assert_ne!(old, json);
+        assert_eq!(Save::from_json(&old).unwrap().replay.modifiers, first);
+
+        save.replay.modifiers = plus;
+        assert!(save.summary().starts_with("NG+ Entrance Hall"));
+    }
+}
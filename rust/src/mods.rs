@@ -0,0 +1,502 @@
+use crate::ability::{self, Ability, AbilityStats};
+use crate::dialogue::{Condition, Room, Trigger};
+use crate::level::{Enemy, EnemyKind};
+use crate::traits::{self, Trait, TraitStats};
+
+use godot::engine::file_access::ModeFlags;
+use godot::engine::{DirAccess, FileAccess};
+use godot::prelude::*;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::mem::discriminant;
+use std::sync::OnceLock;
+
+const DIR: &str = "user://mods";
+
+static PACKS: OnceLock<Vec<ModPack>> = OnceLock::new();
+
+/*
+ * A pack is one JSON file in user://mods, laid over the built-in tables after any pack whose
+ * file name sorts before it. Abilities are redefined whole, or added as { "Custom": n } and given
+ * out through a list. Lists are replaced by index, or added one past the last, and an enemy
+ * kind's stats are changed field by field. Dialogue triggers are added to a room's own. Traits
+ * are redefined whole, or added as { "Custom": n } with only what their damage rules do. A pack
+ * with any mistake in it is left out entirely.
+ */
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModPack {
+    // Pairs for the same reason as traits
+    pub abilities: Vec<(Ability, AbilityStats)>,
+    pub ability_lists: BTreeMap<usize, Vec<(Ability, u16)>>,
+    pub trait_lists: BTreeMap<usize, Vec<Trait>>,
+    // Pairs rather than a map, since custom traits can't be written as JSON keys
+    pub traits: Vec<(Trait, TraitStats)>,
+    pub enemies: HashMap<EnemyKind, EnemyStats>,
+    pub triggers: HashMap<Room, Vec<TriggerDef>>,
+}
+
+// Whatever is left out keeps the value the enemy's scene gives it
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnemyStats {
+    pub max_health: Option<u16>,
+    pub speed: Option<u16>,
+    pub view_distance: Option<u16>,
+    pub ability_list: Option<u8>,
+    pub trait_list: Option<u8>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TriggerDef {
+    pub condition: Condition,
+    pub timeline: String,
+    #[serde(default)]
+    pub repeatable: bool,
+}
+
+impl ModPack {
+    // Counts are the lists there are so far, built in or added by earlier packs, and are moved
+    // on past any this pack adds. Abilities and traits likewise grow by the ones this pack defines
+    pub fn validate(
+        &self,
+        ability_lists: &mut usize,
+        trait_lists: &mut usize,
+        abilities: &mut HashSet<Ability>,
+        traits: &mut HashSet<Trait>,
+    ) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        let mut new_traits = traits.clone();
+        for (trait_, stats) in &self.traits {
+            if stats.summary.trim().is_empty() {
+                errors.push(format!("{:?} has no summary", trait_));
+            }
+            new_traits.insert(*trait_);
+        }
+        for list in self.trait_lists.values() {
+            for trait_ in list {
+                if !new_traits.contains(trait_) {
+                    errors.push(format!("{:?} is listed but never defined", trait_));
+                }
+            }
+        }
+
+        // Abilities are used by what kind of action they are all over, so a redefinition has to
+        // keep it
+        let built_in = ability::init_abilities();
+        for (ability, stats) in &self.abilities {
+            if stats.name.trim().is_empty() {
+                errors.push(format!("{:?} has no name", ability));
+            }
+            if built_in
+                .get(ability)
+                .is_some_and(|old| discriminant(&old.action) != discriminant(&stats.action))
+            {
+                errors.push(format!(
+                    "{:?} can't become a different kind of action",
+                    ability
+                ));
+            }
+        }
+        let defined: HashMap<Ability, &AbilityStats> = self
+            .abilities
+            .iter()
+            .map(|(ability, stats)| (*ability, stats))
+            .collect();
+        let mut new_abilities = abilities.clone();
+        new_abilities.extend(defined.keys().copied());
+        for list in self.ability_lists.values() {
+            for (ability, _) in list {
+                if !new_abilities.contains(ability) {
+                    errors.push(format!("{:?} is listed but never defined", ability));
+                }
+            }
+        }
+
+        let mut new_ability_lists = *ability_lists;
+        for index in self.ability_lists.keys() {
+            if *index > new_ability_lists {
+                errors.push(format!(
+                    "Ability list {} skips ahead, the next new list is {}",
+                    index, new_ability_lists
+                ));
+            } else if *index == new_ability_lists {
+                new_ability_lists += 1;
+            }
+        }
+        let mut new_trait_lists = *trait_lists;
+        for index in self.trait_lists.keys() {
+            if *index > new_trait_lists {
+                errors.push(format!(
+                    "Trait list {} skips ahead, the next new list is {}",
+                    index, new_trait_lists
+                ));
+            } else if *index == new_trait_lists {
+                new_trait_lists += 1;
+            }
+        }
+
+        for (kind, stats) in &self.enemies {
+            if stats.max_health == Some(0) {
+                errors.push(format!("{:?} can't have 0 max health", kind));
+            }
+            if stats
+                .ability_list
+                .is_some_and(|list| list as usize >= new_ability_lists)
+            {
+                errors.push(format!(
+                    "{:?} is given an ability list that doesn't exist",
+                    kind
+                ));
+            }
+            if stats
+                .trait_list
+                .is_some_and(|list| list as usize >= new_trait_lists)
+            {
+                errors.push(format!(
+                    "{:?} is given a trait list that doesn't exist",
+                    kind
+                ));
+            }
+        }
+
+        // Lists this pack leaves alone are still the built-in ones as far as it can tell
+        let built_in_lists = ability::init_ability_lists();
+        let enemy_lists: BTreeSet<usize> = ability::ENEMY_ABILITY_LISTS
+            .into_iter()
+            .chain(
+                self.enemies
+                    .values()
+                    .filter_map(|stats| stats.ability_list.map(usize::from)),
+            )
+            .collect();
+        for index in enemy_lists {
+            let Some(list) = self
+                .ability_lists
+                .get(&index)
+                .or_else(|| built_in_lists.get(index))
+            else {
+                continue;
+            };
+            for (ability, _) in list {
+                let action = defined
+                    .get(ability)
+                    .copied()
+                    .or_else(|| built_in.get(ability))
+                    .map(|stats| stats.action);
+                if action.is_some_and(|action| !action.plannable()) {
+                    errors.push(format!(
+                        "Ability list {} is an enemy's, but enemies can't use {:?}",
+                        index, ability
+                    ));
+                }
+            }
+        }
+
+        for (room, triggers) in &self.triggers {
+            if triggers
+                .iter()
+                .any(|trigger| trigger.timeline.trim().is_empty())
+            {
+                errors.push(format!("A trigger in {:?} has no timeline", room));
+            }
+        }
+
+        if errors.is_empty() {
+            *ability_lists = new_ability_lists;
+            *trait_lists = new_trait_lists;
+            *abilities = new_abilities;
+            *traits = new_traits;
+        }
+        errors
+    }
+}
+
+// Read once as the game starts, before any of the tables are first looked at
+pub fn load() {
+    let mut packs = Vec::new();
+    let mut ability_lists = ability::init_ability_lists().len();
+    let mut trait_lists = traits::init_trait_lists().len();
+    let mut known_abilities = ability::init_abilities().keys().copied().collect();
+    let mut known_traits = traits::init_trait_stats().keys().copied().collect();
+
+    if let Some(mut dir) = DirAccess::open(DIR.into()) {
+        let mut files: Vec<String> = dir
+            .get_files()
+            .to_vec()
+            .iter()
+            .map(|file| file.to_string())
+            .filter(|file| file.ends_with(".json"))
+            .collect();
+        files.sort();
+
+        for file in files {
+            let path = format!("{}/{}", DIR, file);
+            let Some(text) = FileAccess::open(path.into(), ModeFlags::READ)
+                .map(|file| file.get_as_text().to_string())
+            else {
+                godot_error!("Mod {} couldn't be opened", file);
+                continue;
+            };
+
+            match serde_json::from_str::<ModPack>(&text) {
+                Ok(pack) => {
+                    let errors = pack.validate(
+                        &mut ability_lists,
+                        &mut trait_lists,
+                        &mut known_abilities,
+                        &mut known_traits,
+                    );
+                    if errors.is_empty() {
+                        godot_print!("Loaded mod {}", file);
+                        packs.push(pack);
+                    } else {
+                        for error in errors {
+                            godot_error!("Mod {} left out: {}", file, error);
+                        }
+                    }
+                }
+                Err(error) => godot_error!("Mod {} couldn't be read: {}", file, error),
+            }
+        }
+    }
+
+    PACKS.set(packs).ok();
+}
+
+// Nothing until loaded, so the tables are only ever the built-in ones without the engine
+pub fn packs() -> &'static [ModPack] {
+    PACKS.get().map(Vec::as_slice).unwrap_or_default()
+}
+
+pub fn overlay_abilities(abilities: &mut HashMap<Ability, AbilityStats>) {
+    for pack in packs() {
+        abilities.extend(pack.abilities.iter().cloned());
+    }
+}
+
+pub fn overlay_traits(traits: &mut BTreeMap<Trait, TraitStats>) {
+    for pack in packs() {
+        traits.extend(pack.traits.clone());
+    }
+}
+
+pub fn overlay_lists<T: Clone>(
+    lists: &mut Vec<T>,
+    overlay: impl Fn(&ModPack) -> &BTreeMap<usize, T>,
+) {
+    for pack in packs() {
+        for (index, list) in overlay(pack) {
+            match lists.get_mut(*index) {
+                Some(existing) => *existing = list.clone(),
+                None => lists.push(list.clone()),
+            }
+        }
+    }
+}
+
+pub fn overlay_triggers(triggers: &mut HashMap<Room, Vec<Trigger>>) {
+    for pack in packs() {
+        for (room, defs) in &pack.triggers {
+            // Loaded once for the whole game, like the built-in timelines
+            triggers
+                .entry(*room)
+                .or_default()
+                .extend(defs.iter().map(|def| Trigger {
+                    condition: def.condition.clone(),
+                    timeline: Box::leak(def.timeline.clone().into_boxed_str()),
+                    repeatable: def.repeatable,
+                }));
+        }
+    }
+}
+
+// Applied as the enemy is readied, before its lists are filled in
+pub fn overlay_enemy(enemy: &mut Enemy) {
+    for stats in packs()
+        .iter()
+        .filter_map(|pack| pack.enemies.get(&enemy.kind))
+    {
+        if let Some(max_health) = stats.max_health {
+            enemy.max_health = max_health;
+        }
+        if let Some(speed) = stats.speed {
+            enemy.speed = speed;
+        }
+        if let Some(view_distance) = stats.view_distance {
+            enemy.view_distance = view_distance;
+        }
+        if let Some(ability_list) = stats.ability_list {
+            enemy.ability_list = ability_list;
+        }
+        if let Some(trait_list) = stats.trait_list {
+            enemy.trait_list = trait_list;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mod_packs_are_read_and_checked_before_they_are_laid_over() {
+        let pack: ModPack = serde_json::from_str(
+            r#"{
+                "abilities": [[
+                    "Whip",
+                    {
+                        "name": "Chain Whip",
+                        "description": "Heavier and longer",
+                        "action": { "Attack": { "damage_kind": "Silver", "damage": 3, "aoe": false } },
+                        "range": 2,
+                        "acquirable": false,
+                        "consumable": false,
+                        "persistent": false
+                    }
+                ]],
+                "ability_lists": { "9": [["BatBite", 1], ["SpawnBat", 2]] },
+                "trait_lists": { "1": ["Undead"] },
+                "enemies": { "Bat": { "max_health": 3, "ability_list": 9 } },
+                "triggers": {
+                    "GreatHall": [{ "condition": { "Round": 5 }, "timeline": "modded-taunt" }]
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(pack.abilities[0].1.range, 2);
+
+        let mut known_abilities = ability::init_abilities().keys().copied().collect();
+        let mut known_traits = traits::init_trait_stats().keys().copied().collect();
+        let (mut ability_lists, mut trait_lists) = (9, 6);
+        assert!(pack
+            .validate(
+                &mut ability_lists,
+                &mut trait_lists,
+                &mut known_abilities,
+                &mut known_traits
+            )
+            .is_empty());
+        assert_eq!((ability_lists, trait_lists), (10, 6));
+
+        // Lists can only be added one past the last
+        let (mut ability_lists, mut trait_lists) = (8, 6);
+        assert_eq!(
+            pack.validate(
+                &mut ability_lists,
+                &mut trait_lists,
+                &mut known_abilities,
+                &mut known_traits,
+            )
+            .len(),
+            2
+        );
+        assert_eq!(ability_lists, 8);
+
+        assert!(serde_json::from_str::<ModPack>(r#"{ "abilites": {} }"#).is_err());
+    }
+
+    #[test]
+    fn mod_packs_cant_hand_enemies_what_they_dont_know_how_to_use() {
+        let mut known_abilities = ability::init_abilities().keys().copied().collect();
+        let mut known_traits = traits::init_trait_stats().keys().copied().collect();
+        let mut validate = |json: &str| {
+            let pack: ModPack = serde_json::from_str(json).unwrap();
+            let (mut ability_lists, mut trait_lists) = (9, 6);
+            pack.validate(
+                &mut ability_lists,
+                &mut trait_lists,
+                &mut known_abilities,
+                &mut known_traits,
+            )
+        };
+
+        // A whip that teleports is no longer something anything knows how to swing
+        let errors = validate(
+            r#"{
+                "abilities": [[
+                    "Whip",
+                    {
+                        "name": "Blink",
+                        "description": "Gone",
+                        "action": "Teleport",
+                        "range": 5,
+                        "acquirable": false,
+                        "consumable": false,
+                        "persistent": false
+                    }
+                ]]
+            }"#,
+        );
+        assert_eq!(errors.len(), 1);
+
+        // Whether a vampire's own list is changed or a bat is pointed at Ash's
+        assert_eq!(
+            validate(r#"{ "ability_lists": { "3": [["VampireBite", 1], ["Carry", 1]] } }"#).len(),
+            1
+        );
+        assert_eq!(
+            validate(r#"{ "enemies": { "Bat": { "ability_list": 0 } } }"#).len(),
+            4
+        );
+        assert!(validate(r#"{ "ability_lists": { "1": [["Teleport", 1]] } }"#).is_empty());
+    }
+
+    #[test]
+    fn mod_packs_can_bring_abilities_of_their_own() {
+        let pack: ModPack = serde_json::from_str(
+            r#"{
+                "abilities": [[
+                    { "Custom": 0 },
+                    {
+                        "name": "Grave Dirt",
+                        "description": "A fistful of earth from its coffin, thrown in the eyes",
+                        "action": { "Attack": { "damage_kind": "Normal", "damage": 1, "aoe": false } },
+                        "range": 2,
+                        "acquirable": false,
+                        "consumable": false,
+                        "persistent": false
+                    }
+                ]],
+                "ability_lists": { "9": [[{ "Custom": 0 }, 1], ["VampireBite", 1]] },
+                "enemies": { "Vampire": { "ability_list": 9 } }
+            }"#,
+        )
+        .unwrap();
+        let mut known_abilities = ability::init_abilities().keys().copied().collect();
+        let mut known_traits = traits::init_trait_stats().keys().copied().collect();
+        let (mut ability_lists, mut trait_lists) = (9, 6);
+        assert!(pack
+            .validate(
+                &mut ability_lists,
+                &mut trait_lists,
+                &mut known_abilities,
+                &mut known_traits
+            )
+            .is_empty());
+        assert!(known_abilities.contains(&Ability::Custom(0)));
+
+        // Laid over the registry like a redefinition, so it has stats wherever it's looked up
+        let mut abilities = ability::init_abilities();
+        abilities.extend(pack.abilities.iter().cloned());
+        assert_eq!(abilities[&Ability::Custom(0)].name, "Grave Dirt");
+
+        // A later pack can hand it out again, but not one nobody defined
+        let pack: ModPack = serde_json::from_str(
+            r#"{ "ability_lists": { "10": [[{ "Custom": 0 }, 2], [{ "Custom": 1 }, 1]] } }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            pack.validate(
+                &mut ability_lists,
+                &mut trait_lists,
+                &mut known_abilities,
+                &mut known_traits
+            ),
+            vec!["Custom(1) is listed but never defined".to_string()]
+        );
+        assert!(!known_abilities.contains(&Ability::Custom(1)));
+    }
+}
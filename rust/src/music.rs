@@ -0,0 +1,142 @@
+use crate::dialogue::Room;
+use crate::event::{GameEvent, Listener};
+use crate::level::Level;
+
+use godot::engine::{AudioStream, AudioStreamPlayer, INode};
+use godot::prelude::*;
+
+const SILENT: f32 = -80.0;
+
+// Each room has an exploration theme and an optional combat stem of the same length,
+// both play at once and the combat stem is faded in whenever an enemy can see an ally.
+// For now only the entrance hall has a theme and no room has a combat stem, so the one theme
+// plays throughout and fights don't change it until the rest of the tracks are written
+#[derive(GodotClass)]
+#[class(init, base=Node)]
+pub struct Music {
+    #[export]
+    #[init(default = 2.0)]
+    pub combat_fade: f64,
+    #[export]
+    #[init(default = 1.0)]
+    pub room_fade: f64,
+    #[export]
+    pub entrance_hall_exploration: Option<Gd<AudioStream>>,
+    #[export]
+    pub entrance_hall_combat: Option<Gd<AudioStream>>,
+    #[export]
+    pub great_hall_exploration: Option<Gd<AudioStream>>,
+    #[export]
+    pub great_hall_combat: Option<Gd<AudioStream>>,
+    // Whose tracks are playing
+    room: Option<Room>,
+    combat: bool,
+    base: Base<Node>,
+}
+
+#[godot_api]
+impl INode for Music {
+    fn ready(&mut self) {
+        let mut exploration = self.base().get_node_as::<AudioStreamPlayer>("Exploration");
+        exploration.connect(
+            "finished".into(),
+            Callable::from_object_method(&self.base(), "on_finished"),
+        );
+
+        self.play_room(Room::EntranceHall);
+    }
+}
+
+#[godot_api]
+impl Music {
+    // Stems are restarted together so they stay in sync
+    #[func]
+    fn on_finished(&mut self) {
+        for name in ["Exploration", "Combat"] {
+            let mut player = self.base().get_node_as::<AudioStreamPlayer>(name);
+            if player.get_stream().is_some() {
+                player.play();
+            }
+        }
+    }
+}
+
+impl Music {
+    fn tracks(&self, room: Room) -> (Option<Gd<AudioStream>>, Option<Gd<AudioStream>>) {
+        match room {
+            Room::EntranceHall => (
+                self.entrance_hall_exploration.clone(),
+                self.entrance_hall_combat.clone(),
+            ),
+            Room::GreatHall => (
+                self.great_hall_exploration.clone(),
+                self.great_hall_combat.clone(),
+            ),
+        }
+    }
+
+    // A room without a theme of its own carries on with whichever one is already playing
+    pub fn play_room(&mut self, room: Room) {
+        let (exploration, combat) = self.tracks(room);
+        if self.room == Some(room) || exploration.is_none() {
+            return;
+        }
+        self.room = Some(room);
+        self.combat = false;
+
+        for (name, stream) in [("Exploration", exploration), ("Combat", combat)] {
+            let mut player = self.base().get_node_as::<AudioStreamPlayer>(name);
+            match stream {
+                Some(stream) => {
+                    player.set_stream(stream);
+                    player.set_volume_db(SILENT);
+                    player.play();
+                }
+                None => player.stop(),
+            }
+        }
+
+        self.fade("Exploration", 0.0, self.room_fade);
+    }
+
+    pub fn set_combat(&mut self, combat: bool) {
+        let has_combat = self.room.is_some_and(|room| self.tracks(room).1.is_some());
+        if combat == self.combat || !has_combat {
+            return;
+        }
+        self.combat = combat;
+
+        let (exploration, combat) = if combat { (SILENT, 0.0) } else { (0.0, SILENT) };
+        self.fade("Exploration", exploration, self.combat_fade);
+        self.fade("Combat", combat, self.combat_fade);
+    }
+
+    fn fade(&mut self, name: &str, volume_db: f32, duration: f64) {
+        let player = self.base().get_node_as::<AudioStreamPlayer>(name);
+        let mut tween = self.base_mut().create_tween().unwrap();
+        tween.tween_property(
+            player.upcast(),
+            "volume_db".into(),
+            Variant::from(volume_db),
+            duration,
+        );
+    }
+}
+
+impl Listener for Music {
+    fn on_event(&mut self, event: GameEvent, level: &Level) {
+        match event {
+            GameEvent::LevelReady => {
+                self.play_room(level.room);
+                self.set_combat(level.in_combat());
+            }
+            GameEvent::AllyMoved(_)
+            | GameEvent::EnemyMoved(..)
+            | GameEvent::AllyDied(_)
+            | GameEvent::EnemyDied(..)
+            | GameEvent::AllyTurnStarted
+            | GameEvent::EnemyTurnStarted => self.set_combat(level.in_combat()),
+            _ => (),
+        }
+    }
+}
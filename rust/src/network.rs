@@ -0,0 +1,124 @@
+use crate::level::Level;
+
+use godot::engine::multiplayer_api::RpcMode;
+use godot::engine::multiplayer_peer::TransferMode;
+use godot::engine::{CenterContainer, ENetMultiplayerPeer, ICenterContainer, Label, LineEdit};
+use godot::global::Error;
+use godot::obj::EngineEnum;
+use godot::prelude::*;
+
+pub const PORT: i32 = 7777;
+// Godot always gives the host this peer id
+pub const HOST: i32 = 1;
+
+// Remote calls are sent reliably and in order so both sides apply commands the same way
+pub fn rpc_config(rpc_mode: RpcMode, call_local: bool) -> Variant {
+    dict! {
+        "rpc_mode": rpc_mode.ord(),
+        "transfer_mode": TransferMode::RELIABLE.ord(),
+        "call_local": call_local,
+        "channel": 0,
+    }
+    .to_variant()
+}
+
+#[derive(GodotClass)]
+#[class(init, base=CenterContainer)]
+pub struct Lobby {
+    base: Base<CenterContainer>,
+}
+
+#[godot_api]
+impl ICenterContainer for Lobby {
+    fn ready(&mut self) {
+        self.base_mut()
+            .rpc_config("start_game".into(), rpc_config(RpcMode::AUTHORITY, true));
+    }
+}
+
+#[godot_api]
+impl Lobby {
+    #[func]
+    fn _on_host_button_pressed(&mut self) {
+        let mut peer = ENetMultiplayerPeer::new_gd();
+        if peer.create_server(PORT) != Error::OK {
+            self.set_status("Could not host a game".into());
+            return;
+        }
+
+        let mut multiplayer = self.base().get_multiplayer().unwrap();
+        multiplayer.set_multiplayer_peer(peer.upcast());
+        multiplayer.connect(
+            "peer_connected".into(),
+            Callable::from_object_method(&self.to_gd(), "_on_peer_connected"),
+        );
+
+        self.set_status(format!("Waiting for a second player on port {}", PORT));
+    }
+
+    #[func]
+    fn _on_join_button_pressed(&mut self) {
+        let address = self
+            .base()
+            .get_node_as::<LineEdit>("VBoxContainer/Address")
+            .get_text();
+
+        let mut peer = ENetMultiplayerPeer::new_gd();
+        if peer.create_client(address, PORT) != Error::OK {
+            self.set_status("Could not join the game".into());
+            return;
+        }
+
+        let mut multiplayer = self.base().get_multiplayer().unwrap();
+        multiplayer.set_multiplayer_peer(peer.upcast());
+        multiplayer.connect(
+            "connection_failed".into(),
+            Callable::from_object_method(&self.to_gd(), "_on_connection_failed"),
+        );
+
+        self.set_status("Connecting...".into());
+    }
+
+    // The host starts the game for both players as soon as someone joins
+    #[func]
+    fn _on_peer_connected(&mut self, id: i64) {
+        self.base_mut().rpc("start_game".into(), &[id.to_variant()]);
+    }
+
+    #[func]
+    fn _on_connection_failed(&mut self) {
+        self.set_status("Could not connect".into());
+    }
+
+    #[func]
+    fn start_game(&mut self, guest: i64) {
+        let scene = load::<PackedScene>("res://scenes/levels/1-entrance-hall.tscn");
+        let mut level: Gd<Level> = scene.instantiate().unwrap().cast();
+
+        {
+            let mut level = level.bind_mut();
+            level.online = true;
+            level.guest = guest as i32;
+        }
+
+        self.base()
+            .get_tree()
+            .unwrap()
+            .get_root()
+            .unwrap()
+            .add_child(level.clone().upcast());
+        self.base()
+            .get_tree()
+            .unwrap()
+            .set_current_scene(level.upcast());
+
+        self.base_mut().queue_free();
+    }
+}
+
+impl Lobby {
+    fn set_status(&self, status: String) {
+        let mut label = self.base().get_node_as::<Label>("VBoxContainer/Status");
+        label.set_text(status.into());
+    }
+}
@@ -0,0 +1,115 @@
+use crate::dialogue::Dialogue;
+use crate::level::{Cursor, Level, Path, ShadowMap};
+use crate::ui::{AbilityBar, InfoPanel, UnitSheet};
+
+use godot::engine::Button;
+use godot::global::instance_from_id;
+use godot::prelude::*;
+use std::fmt::Debug;
+
+// Lookups that log what is missing and hand back None, so a misconfigured scene skips the actor
+// that needed the node instead of taking the whole extension down
+
+pub fn try_node<T>(from: &Node, path: &str) -> Option<Gd<T>>
+where
+    T: GodotClass + Inherits<Node>,
+{
+    let node = from.try_get_node_as::<T>(path);
+    if node.is_none() {
+        godot_error!(
+            "No {} at {:?} from {}",
+            T::class_name(),
+            path,
+            from.get_path()
+        );
+    }
+    node
+}
+
+// Units, items and obstacles are kept by instance id, which can outlive a node freed early
+pub fn try_instance<T>(instance_id: Option<i64>, what: &str, key: impl Debug) -> Option<Gd<T>>
+where
+    T: GodotClass + Inherits<Object>,
+{
+    let Some(instance_id) = instance_id else {
+        godot_error!("No {} {:?} in the level", what, key);
+        return None;
+    };
+
+    match instance_from_id(instance_id).map(|object| object.try_cast::<T>()) {
+        Some(Ok(node)) => Some(node),
+        _ => {
+            godot_error!(
+                "Instance {} for {} {:?} is gone or not a {}",
+                instance_id,
+                what,
+                key,
+                T::class_name()
+            );
+            None
+        }
+    }
+}
+
+// Scenes named in code rather than placed in the editor, so a missing file is only found at
+// runtime
+pub fn try_scene(path: &str) -> Option<Gd<PackedScene>> {
+    let scene = try_load::<PackedScene>(path).ok();
+    if scene.is_none() {
+        godot_error!("No scene at {:?}", path);
+    }
+    scene
+}
+
+// The level a node belongs to, found by walking up the tree rather than counting parents
+pub fn find_level(from: &Node) -> Option<Gd<Level>> {
+    let mut parent = from.get_parent();
+    while let Some(node) = parent {
+        match node.try_cast::<Level>() {
+            Ok(level) => return Some(level),
+            Err(node) => parent = node.get_parent(),
+        }
+    }
+
+    godot_error!("{} is not inside a level", from.get_path());
+    None
+}
+
+// Typed handles to the nodes the level scene is expected to have
+
+pub fn cursor(level: &Node) -> Option<Gd<Cursor>> {
+    try_node(level, "CursorLayer/Cursor")
+}
+
+pub fn path_node(level: &Node) -> Option<Gd<Path>> {
+    try_node(level, "PathLayer/Path")
+}
+
+pub fn shadow_map(level: &Node) -> Option<Gd<ShadowMap>> {
+    try_node(level, "ShadowLayer/ShadowMap")
+}
+
+pub fn info_panel(level: &Node) -> Option<Gd<InfoPanel>> {
+    try_node(level, "UILayer/InfoPanel")
+}
+
+pub fn ability_bar(level: &Node) -> Option<Gd<AbilityBar>> {
+    try_node(level, "UILayer/AbilityBar")
+}
+
+pub fn unit_sheet(level: &Node) -> Option<Gd<UnitSheet>> {
+    try_node(level, "UILayer/UnitSheet")
+}
+
+pub fn end_turn_button(level: &Node) -> Option<Gd<Button>> {
+    try_node(level, "UILayer/EndTurn")
+}
+
+pub fn dialogue(level: &Node) -> Option<Gd<Dialogue>> {
+    try_node(level, "Dialogue")
+}
+
+// Dialogic is an autoload, so it sits beside the level under the root
+pub fn dialogic(level: &Node) -> Option<Gd<Node>> {
+    try_node(level, "../Dialogic")
+}
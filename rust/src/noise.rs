@@ -0,0 +1,71 @@
+use crate::ability::Ability;
+use crate::level::TILE_SIZE;
+use crate::math::Position;
+
+use godot::prelude::*;
+
+// How many tiles off a sound carries
+pub const CROSSBOW_NOISE: u16 = 6;
+pub const EXPLOSION_NOISE: u16 = 8;
+const RING_TIME: f64 = 0.6;
+
+// Only the loud abilities give away where they were used from
+pub fn loudness(ability: Ability) -> Option<u16> {
+    match ability {
+        Ability::CrossbowIronBolt | Ability::CrossbowSilverBolt => Some(CROSSBOW_NOISE),
+        _ => None,
+    }
+}
+
+// Walls don't muffle anything, sound carries around corners
+pub fn hears(listener: Position, origin: Position, loudness: u16) -> bool {
+    listener.distance(origin) <= loudness
+}
+
+// A ring spreading out from where a sound was made to as far as it carries, then freeing itself
+#[derive(GodotClass)]
+#[class(init, base=Node2D)]
+pub struct NoiseRing {
+    pub loudness: u16,
+    age: f64,
+    base: Base<Node2D>,
+}
+
+#[godot_api]
+impl INode2D for NoiseRing {
+    fn process(&mut self, delta: f64) {
+        self.age += delta;
+        if self.age >= RING_TIME {
+            self.base_mut().queue_free();
+        } else {
+            self.base_mut().queue_redraw();
+        }
+    }
+
+    fn draw(&mut self) {
+        let t = (self.age / RING_TIME) as f32;
+        let radius = TILE_SIZE * (0.5 + self.loudness as f32 * t);
+        let color = Color::from_rgba(0.9, 0.9, 1.0, 0.6 * (1.0 - t));
+        self.base_mut()
+            .draw_arc_ex(Vector2::ZERO, radius, 0.0, std::f32::consts::TAU, 48, color)
+            .width(2.0)
+            .done();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crossbows_carry_further_than_quiet_work_but_not_as_far_as_blasts() {
+        let origin = Position { x: 0, y: 0 };
+        assert_eq!(loudness(Ability::WoodenStake), None);
+        let loudness = loudness(Ability::CrossbowIronBolt).unwrap();
+        assert!(loudness < EXPLOSION_NOISE);
+
+        assert!(hears(Position { x: 6, y: 0 }, origin, loudness));
+        assert!(!hears(Position { x: 7, y: 0 }, origin, loudness));
+        assert!(hears(Position { x: 7, y: 0 }, origin, EXPLOSION_NOISE));
+    }
+}
@@ -0,0 +1,160 @@
+use crate::core::UnitId;
+use crate::level::AllyId;
+use crate::traits::Trait;
+
+// The points between phases where things happen without anyone acting, in the order a round
+// passes them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Boundary {
+    AllyStart,
+    AllyEnd,
+    EnemyStart,
+    EnemyEnd,
+}
+
+// What fires at a boundary, one after the other in the order hooks lists them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    // Every passive due at the boundary, see Passive
+    Passives,
+    // The starting side's effects tick down and their shields fall away
+    Effects,
+    // Vampires resting beside a coffin heal
+    Coffins,
+    Sunlight,
+    Fires,
+    // Rebuilt before anything reads them
+    Auras,
+}
+
+// Passives go before effects tick, so regeneration still sees the fire and holy damage taken
+// since the unit's last phase
+pub fn hooks(boundary: Boundary) -> &'static [Hook] {
+    match boundary {
+        Boundary::AllyStart => &[
+            Hook::Sunlight,
+            Hook::Fires,
+            Hook::Auras,
+            Hook::Passives,
+            Hook::Effects,
+        ],
+        Boundary::AllyEnd => &[Hook::Passives],
+        Boundary::EnemyStart => &[Hook::Passives, Hook::Effects],
+        Boundary::EnemyEnd => &[Hook::Coffins, Hook::Passives],
+    }
+}
+
+// Abilities a unit has that use themselves, in the order they resolve when several are due at
+// once
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Passive {
+    // Heals at the start of its own phase, unless burned or blessed since the last one
+    Regenerate,
+    // Sears undead enemies next to it at the start of the ally phase
+    HolyAura,
+    // Picks a spent iron bolt back up at the end of an ally phase it shot one in
+    RecoverBolt,
+}
+
+impl Passive {
+    pub fn boundary(&self, unit: UnitId) -> Boundary {
+        match self {
+            Self::Regenerate => match unit {
+                UnitId::Enemy(_) => Boundary::EnemyStart,
+                UnitId::Ally(_) | UnitId::Npc(_) => Boundary::AllyStart,
+            },
+            Self::HolyAura => Boundary::AllyStart,
+            Self::RecoverBolt => Boundary::AllyEnd,
+        }
+    }
+}
+
+// Some passives come with a unit's traits, others with who it is
+pub fn passives(unit: UnitId, traits: &[Trait]) -> Vec<Passive> {
+    let mut passives = Vec::new();
+    if traits.contains(&Trait::Regeneration) {
+        passives.push(Passive::Regenerate);
+    }
+    if traits.contains(&Trait::HolyAura) {
+        passives.push(Passive::HolyAura);
+    }
+    if unit == UnitId::Ally(AllyId::AshMagnum) {
+        passives.push(Passive::RecoverBolt);
+    }
+    passives
+}
+
+// Every passive due at the boundary, by passive and then by unit so they resolve the same way
+// every time
+pub fn due(units: &[(UnitId, Vec<Trait>)], boundary: Boundary) -> Vec<(Passive, UnitId)> {
+    let mut due: Vec<(Passive, UnitId)> = units
+        .iter()
+        .flat_map(|(unit, traits)| {
+            passives(*unit, traits)
+                .into_iter()
+                .filter(|passive| passive.boundary(*unit) == boundary)
+                .map(|passive| (passive, *unit))
+        })
+        .collect();
+    due.sort();
+    due
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ability::DamageKind;
+    use crate::combat::{self, Damage};
+    use crate::core::Vitals;
+    use crate::event::EventBus;
+    use crate::traits::trait_lists;
+
+    #[test]
+    fn passives_fire_at_their_boundary_before_effects_tick() {
+        let ash = UnitId::Ally(AllyId::AshMagnum);
+        let priest = UnitId::Ally(AllyId::Priest);
+        let units = [
+            (UnitId::Enemy(1), vec![Trait::Regeneration]),
+            (priest, trait_lists()[5].clone()),
+            (ash, Vec::new()),
+            (UnitId::Enemy(0), vec![Trait::Regeneration]),
+        ];
+
+        assert_eq!(
+            due(&units, Boundary::AllyStart),
+            vec![(Passive::HolyAura, priest)]
+        );
+        assert_eq!(
+            due(&units, Boundary::AllyEnd),
+            vec![(Passive::RecoverBolt, ash)]
+        );
+        assert_eq!(
+            due(&units, Boundary::EnemyStart),
+            vec![
+                (Passive::Regenerate, UnitId::Enemy(0)),
+                (Passive::Regenerate, UnitId::Enemy(1)),
+            ]
+        );
+        assert!(due(&units, Boundary::EnemyEnd).is_empty());
+
+        // Regeneration has to see the fire damage before the effects tick forgets it
+        for boundary in [Boundary::AllyStart, Boundary::EnemyStart] {
+            let hooks = hooks(boundary);
+            let position = |hook| hooks.iter().position(|h| *h == hook).unwrap();
+            assert!(position(Hook::Passives) < position(Hook::Effects));
+        }
+        let mut werewolf = Vitals::new(6, vec![Trait::Regeneration]);
+        let fire = Damage {
+            amount: 2,
+            kind: DamageKind::Fire,
+            source: None,
+            ability: None,
+        };
+        combat::resolve_damage(ash, &mut werewolf, fire, &mut EventBus::default());
+        werewolf.regenerate();
+        assert_eq!(werewolf.health, 4);
+        werewolf.start_phase();
+        werewolf.regenerate();
+        assert_eq!(werewolf.health, 5);
+    }
+}
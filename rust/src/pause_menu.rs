@@ -0,0 +1,140 @@
+use crate::audio::{self, Sfx};
+use crate::main_menu::MENU_SCENE;
+use crate::nodes::{self, find_level};
+use crate::settings::{self, SettingsPanel};
+use crate::ui;
+
+use godot::engine::{Button, CenterContainer, Control, ICenterContainer, RichTextLabel, SceneTree};
+use godot::prelude::*;
+
+// Pausing the tree stops the level, its units and their tweens where they are. The menu itself
+// is set to always process so it can be closed again
+#[derive(GodotClass)]
+#[class(init, base=CenterContainer)]
+pub struct PauseMenu {
+    base: Base<CenterContainer>,
+}
+
+#[godot_api]
+impl ICenterContainer for PauseMenu {
+    fn process(&mut self, _delta: f64) {
+        if !Input::singleton().is_action_just_pressed("pause".into())
+            || self.settings_panel().bind().is_listening()
+        {
+            return;
+        }
+        if self.tree().is_paused() {
+            self.resume();
+            return;
+        }
+
+        let Some(level) = find_level(&self.base()) else {
+            return;
+        };
+        let dialogue_active =
+            nodes::dialogue(&level).is_some_and(|dialogue| dialogue.bind().active);
+        if dialogue_active || ui::sheet_open(&level) {
+            return;
+        }
+
+        // Restarting one side of an online game would leave the other behind
+        let mut restart = self.base().get_node_as::<Button>("Panel/Menu/Restart");
+        restart.set_disabled(level.bind().online);
+
+        audio::play(Sfx::Select);
+        self.base_mut().set_visible(true);
+        self.show_history(false);
+        self.settings_panel().bind_mut().close();
+        self.tree().set_pause(true);
+    }
+}
+
+#[godot_api]
+impl PauseMenu {
+    #[func]
+    fn _on_resume_pressed(&mut self) {
+        self.resume();
+    }
+
+    #[func]
+    fn _on_restart_pressed(&mut self) {
+        let Some(level) = find_level(&self.base()) else {
+            return;
+        };
+        let room = level.bind().room;
+        self.resume();
+        self.tree().change_scene_to_file(room.scene().into());
+    }
+
+    // Everything said this level, for whoever skipped through it too fast
+    #[func]
+    fn _on_history_pressed(&mut self) {
+        let Some(level) = find_level(&self.base()) else {
+            return;
+        };
+        let lines: Vec<String> = nodes::dialogue(&level)
+            .map(|dialogue| {
+                dialogue
+                    .bind()
+                    .log
+                    .iter()
+                    .map(|line| line.bbcode())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut log = self
+            .base()
+            .get_node_as::<RichTextLabel>("Panel/History/Log");
+        log.set_text(lines.join("\n").into());
+        let font_size = (12.0 * settings::text_size()).round() as i32;
+        for font in ["normal_font_size", "bold_font_size", "italics_font_size"] {
+            log.add_theme_font_size_override(font.into(), font_size);
+        }
+        self.show_history(true);
+    }
+
+    #[func]
+    fn _on_history_back_pressed(&mut self) {
+        self.show_history(false);
+    }
+
+    #[func]
+    fn _on_settings_pressed(&mut self) {
+        self.settings_panel().bind_mut().open();
+    }
+
+    #[func]
+    fn _on_quit_pressed(&mut self) {
+        self.resume();
+        self.tree().change_scene_to_file(MENU_SCENE.into());
+    }
+}
+
+impl PauseMenu {
+    fn tree(&self) -> Gd<SceneTree> {
+        self.base().get_tree().unwrap()
+    }
+
+    fn settings_panel(&self) -> Gd<SettingsPanel> {
+        self.base().get_node_as::<SettingsPanel>("Panel/Settings")
+    }
+
+    fn show_history(&mut self, shown: bool) {
+        let mut history = self.base().get_node_as::<Control>("Panel/History");
+        history.set_visible(shown);
+        let mut menu = self.base().get_node_as::<Control>("Panel/Menu");
+        menu.set_visible(!shown);
+        ui::focus_first(if shown { history } else { menu }.upcast());
+    }
+
+    fn resume(&mut self) {
+        self.base_mut().set_visible(false);
+        self.tree().set_pause(false);
+
+        // The animation speed may have changed while paused
+        if let Some(level) = find_level(&self.base()) {
+            settings::set_time_scale(level.bind().phase_speed());
+        }
+    }
+}
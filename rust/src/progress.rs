@@ -0,0 +1,72 @@
+use crate::ability::Ability;
+use crate::dialogue::Room;
+use crate::level::AllyId;
+use crate::modifiers::Modifiers;
+use crate::scoring::Score;
+
+use godot::engine::file_access::ModeFlags;
+use godot::engine::FileAccess;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const PATH: &str = "user://progress.json";
+
+// Rooms the player has made it out of and their best scores, kept across runs for the main menu
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Progress {
+    pub completed: Vec<Room>,
+    #[serde(default)]
+    pub best: HashMap<Room, Score>,
+    // Playthroughs finished, which is the cycle the next New Game+ plays on
+    #[serde(default)]
+    pub cycles_finished: u8,
+    // What the party left the last room with, taken into New Game+
+    #[serde(default)]
+    pub carried: HashMap<AllyId, Vec<(Ability, u16)>>,
+}
+
+impl Progress {
+    pub fn load() -> Self {
+        FileAccess::open(PATH.into(), ModeFlags::READ)
+            .and_then(|file| serde_json::from_str(&file.get_as_text().to_string()).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) {
+        if let (Some(mut file), Ok(json)) = (
+            FileAccess::open(PATH.into(), ModeFlags::WRITE),
+            serde_json::to_string(self),
+        ) {
+            file.store_string(json.into());
+        }
+    }
+
+    // Returns the best score the room had before this one
+    pub fn complete(room: Room, score: Score) -> Option<Score> {
+        let mut progress = Self::load();
+        if !progress.is_completed(room) {
+            progress.completed.push(room);
+        }
+        let best = progress.best.get(&room).copied();
+        if best.map_or(true, |best| score.total() > best.total()) {
+            progress.best.insert(room, score);
+        }
+        progress.save();
+        best
+    }
+
+    pub fn finish_run(modifiers: Modifiers, inventory: HashMap<AllyId, Vec<(Ability, u16)>>) {
+        let mut progress = Self::load();
+        progress.cycles_finished = progress.cycles_finished.max(modifiers.cycle + 1);
+        progress.carried = inventory;
+        progress.save();
+    }
+
+    pub fn new_game_plus_unlocked(&self) -> bool {
+        self.cycles_finished > 0
+    }
+
+    pub fn is_completed(&self, room: Room) -> bool {
+        self.completed.contains(&room)
+    }
+}
@@ -0,0 +1,106 @@
+use crate::ability::Ability;
+use crate::choices::Choices;
+use crate::command::Command;
+use crate::dialogue::Room;
+use crate::level::AllyId;
+use crate::modifiers::Modifiers;
+
+use godot::engine::file_access::ModeFlags;
+use godot::engine::FileAccess;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Everything needed to play a level back: how it was set up and what the players did
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    pub inventory: HashMap<AllyId, Vec<(Ability, u16)>>,
+    pub versus: bool,
+    #[serde(default)]
+    pub modifiers: Modifiers,
+    #[serde(default)]
+    pub choices: Choices,
+    // Where the party came in, if through a door with a spawn
+    #[serde(default)]
+    pub entrance: Option<String>,
+    // What the room's chests and bookshelves roll their loot from
+    #[serde(default)]
+    pub loot_seed: u64,
+    pub commands: Vec<Command>,
+    // Commands played before the last checkpoint, which a restored level catches up to
+    #[serde(default)]
+    pub checkpoint: usize,
+    // Whether attacks arriving are among the commands. Older replays leave them to the
+    // projectiles and dashes as they play
+    #[serde(default)]
+    pub impacts: bool,
+}
+
+impl Replay {
+    fn path(room: Room) -> &'static str {
+        match room {
+            Room::EntranceHall => "user://entrance-hall.replay",
+            Room::GreatHall => "user://great-hall.replay",
+        }
+    }
+
+    pub fn load(room: Room) -> Option<Self> {
+        let file = FileAccess::open(Self::path(room).into(), ModeFlags::READ)?;
+        serde_json::from_str(&file.get_as_text().to_string()).ok()
+    }
+
+    pub fn save(&self, room: Room) {
+        if let (Some(mut file), Ok(json)) = (
+            FileAccess::open(Self::path(room).into(), ModeFlags::WRITE),
+            serde_json::to_string(self),
+        ) {
+            file.store_string(json.into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::choices::Approach;
+    use crate::math::Position;
+
+    #[test]
+    fn replays_survive_a_round_trip() {
+        let replay = Replay {
+            inventory: HashMap::from([
+                (AllyId::AshMagnum, vec![(Ability::CrossbowIronBolt, 3)]),
+                (AllyId::Alukrod, vec![(Ability::Hellfire, 1)]),
+            ]),
+            versus: true,
+            modifiers: Modifiers::daily(42),
+            choices: Choices {
+                alukrod_loyalty: 2,
+                approach: Approach::Sneak,
+            },
+            entrance: Some("SideDoor".into()),
+            loot_seed: 7,
+            commands: vec![
+                Command::Move {
+                    ally_id: AllyId::AshMagnum,
+                    position: Position { x: 1, y: 2 },
+                },
+                Command::EndTurn,
+            ],
+            checkpoint: 1,
+            impacts: true,
+        };
+
+        let json = serde_json::to_string(&replay).unwrap();
+        assert_eq!(serde_json::from_str::<Replay>(&json).unwrap(), replay);
+    }
+
+    #[test]
+    fn replays_from_before_later_fields_still_load() {
+        let json = r#"{"inventory":{},"versus":false,"commands":["EndTurn"]}"#;
+        let replay: Replay = serde_json::from_str(json).unwrap();
+        assert_eq!(replay.commands, vec![Command::EndTurn]);
+        assert_eq!(replay.modifiers, Modifiers::default());
+        assert_eq!(replay.checkpoint, 0);
+        assert!(!replay.impacts);
+    }
+}
@@ -0,0 +1,90 @@
+use crate::ability::DamageKind;
+use crate::command::Command;
+use crate::dialogue::Room;
+use crate::scoring::Score;
+use crate::settings;
+
+use godot::engine::file_access::ModeFlags;
+use godot::engine::{DirAccess, FileAccess};
+use godot::prelude::*;
+use serde::Serialize;
+
+const DIR: &str = "user://logs";
+
+// One line of a run's log, written as JSON for bug reports and balancing
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Entry {
+    RoomEntered {
+        room: Room,
+    },
+    Command {
+        command: Command,
+    },
+    // Units are written as their debug names, such as Enemy(3)
+    Damage {
+        unit: String,
+        damage: u16,
+        damage_kind: DamageKind,
+    },
+    Killed {
+        unit: String,
+        source: Option<String>,
+    },
+    RoomCleared {
+        room: Room,
+        score: Score,
+    },
+    Defeat {
+        room: Room,
+        reason: String,
+    },
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    round: u16,
+    #[serde(flatten)]
+    entry: &'a Entry,
+}
+
+// A log per save slot, started over by each new run and added to by every room after. Nothing is
+// written when the player has turned it off in the settings
+#[derive(Default)]
+pub struct RunLog {
+    file: Option<Gd<FileAccess>>,
+}
+
+impl RunLog {
+    pub fn open(slot: usize, new_run: bool) -> Self {
+        if !settings::log_runs() {
+            return Self::default();
+        }
+
+        DirAccess::make_dir_recursive_absolute(DIR.into());
+        let path: GString = format!("{}/run-{}.jsonl", DIR, slot).into();
+        let file = if new_run || !FileAccess::file_exists(path.clone()) {
+            FileAccess::open(path, ModeFlags::WRITE)
+        } else {
+            FileAccess::open(path, ModeFlags::READ_WRITE).map(|mut file| {
+                file.seek_end();
+                file
+            })
+        };
+        Self { file }
+    }
+
+    // Flushed line by line so the log is whole up to a crash
+    pub fn write(&mut self, round: u16, entry: Entry) {
+        let Some(file) = &mut self.file else {
+            return;
+        };
+        if let Ok(json) = serde_json::to_string(&Line {
+            round,
+            entry: &entry,
+        }) {
+            file.store_line(json.into());
+            file.flush();
+        }
+    }
+}
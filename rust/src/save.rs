@@ -0,0 +1,180 @@
+use crate::dialogue::Room;
+use crate::level::{AllyId, Level};
+use crate::replay::Replay;
+
+use godot::engine::file_access::ModeFlags;
+use godot::engine::{FileAccess, SceneTree, Time};
+use godot::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const SLOTS: usize = 3;
+
+// Bumped whenever the format changes, with a migration from the version before
+const VERSION: u64 = 1;
+// MIGRATIONS[n - 1] brings a version n save up to version n + 1
+const MIGRATIONS: &[fn(&mut Map<String, Value>)] = &[];
+
+// The slot the game in progress saves to, picked from the main menu
+static SLOT: AtomicUsize = AtomicUsize::new(0);
+
+pub fn slot() -> usize {
+    SLOT.load(Ordering::Relaxed)
+}
+
+pub fn set_slot(slot: usize) {
+    SLOT.store(slot, Ordering::Relaxed);
+}
+
+// A room in progress, restored by catching up on its replay
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Save {
+    pub version: u64,
+    pub room: Room,
+    // Seconds, over every room so far
+    pub play_time: f64,
+    // Health and max health of each ally when the game was saved
+    pub health: Vec<(AllyId, u16, u16)>,
+    pub replay: Replay,
+}
+
+impl Save {
+    fn path(slot: usize) -> String {
+        format!("user://save-{}.json", slot + 1)
+    }
+
+    // Taken at the start of the ally phase, so the replay is caught up to the end of it
+    pub fn of(level: &Level) -> Self {
+        let mut health: Vec<(AllyId, u16, u16)> = level
+            .allies
+            .keys()
+            .filter_map(|ally_id| level.try_get_ally(*ally_id))
+            .map(|ally| {
+                let ally = ally.bind();
+                (ally.id, ally.vitals.health, ally.max_health)
+            })
+            .collect();
+        health.sort();
+
+        let mut replay = level.replay.clone();
+        replay.checkpoint = replay.commands.len();
+        Self {
+            version: VERSION,
+            room: level.room,
+            play_time: level.play_time(),
+            health,
+            replay,
+        }
+    }
+
+    pub fn load(slot: usize) -> Option<Self> {
+        let file = FileAccess::open(Self::path(slot).into(), ModeFlags::READ)?;
+        Self::from_json(&file.get_as_text().to_string())
+    }
+
+    // Saves from before a format change are migrated, those from a newer build are left alone
+    pub fn from_json(text: &str) -> Option<Self> {
+        let mut json: Map<String, Value> = serde_json::from_str(text).ok()?;
+
+        let version = json.get("version").and_then(Value::as_u64).unwrap_or(1);
+        if version == 0 || version > VERSION {
+            return None;
+        }
+        for migrate in &MIGRATIONS[version as usize - 1..] {
+            migrate(&mut json);
+        }
+        json.insert("version".into(), VERSION.into());
+
+        serde_json::from_value(Value::Object(json)).ok()
+    }
+
+    pub fn save(&self, slot: usize) {
+        if let (Some(mut file), Ok(json)) = (
+            FileAccess::open(Self::path(slot).into(), ModeFlags::WRITE),
+            serde_json::to_string(self),
+        ) {
+            file.store_string(json.into());
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let seconds = self.play_time as u64;
+        let health: Vec<String> = self
+            .health
+            .iter()
+            .map(|(ally_id, health, max_health)| {
+                format!("{} {}/{}", ally_id.name(), health, max_health)
+            })
+            .collect();
+        let new_game_plus = match self.replay.modifiers.cycle {
+            0 => "",
+            _ => "NG+ ",
+        };
+        format!(
+            "{}{}, {}:{:02}:{:02} played\n{}",
+            new_game_plus,
+            self.room.name(),
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+            health.join(", ")
+        )
+    }
+
+    // Swaps the current scene for the saved room, which plays the replay back at speed
+    pub fn restore(&self, mut tree: Gd<SceneTree>) {
+        let scene = load::<PackedScene>(self.room.scene());
+        let mut level: Gd<Level> = scene.instantiate().unwrap().cast();
+
+        {
+            let mut level = level.bind_mut();
+            level.playback = true;
+            level.catching_up = true;
+            level.restored = Some(self.replay.clone());
+            level.play_time_before = self.play_time;
+        }
+
+        if let Some(mut current) = tree.get_current_scene() {
+            current.queue_free();
+        }
+        tree.get_root().unwrap().add_child(level.clone().upcast());
+        tree.set_current_scene(level.upcast());
+    }
+}
+
+// Real time since the engine started, for timing play
+pub fn now() -> f64 {
+    Time::singleton().get_ticks_msec() as f64 / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_survive_format_changes_but_not_newer_builds() {
+        let save = Save {
+            version: 1,
+            room: Room::GreatHall,
+            play_time: 3725.0,
+            health: vec![(AllyId::AshMagnum, 5, 8)],
+            replay: Replay::default(),
+        };
+        let json = serde_json::to_string(&save).unwrap();
+        let loaded = Save::from_json(&json).unwrap();
+        assert_eq!(loaded.room, Room::GreatHall);
+        assert_eq!(
+            loaded.summary(),
+            "Great Hall, 1:02:05 played\nAsh Magnum 5/8"
+        );
+
+        // Fields added since are filled in with defaults
+        let old = json.replace(",\"checkpoint\":0", "");
+        assert_ne!(old, json);
+        assert_eq!(Save::from_json(&old).unwrap().replay.checkpoint, 0);
+
+        let newer = json.replace("\"version\":1", "\"version\":99");
+        assert!(Save::from_json(&newer).is_none());
+    }
+}
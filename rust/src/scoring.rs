@@ -0,0 +1,127 @@
+use crate::dialogue::Room;
+use crate::stats::RunStats;
+
+use serde::{Deserialize, Serialize};
+
+const KILL_POINTS: u32 = 100;
+const SPEED_BONUS: u32 = 500;
+// Taken off the speed bonus for every turn over par
+const TURN_PENALTY: u32 = 50;
+const NO_DAMAGE_BONUS: u32 = 500;
+const ITEM_BONUS: u32 = 300;
+// Taken off the item bonus for every consumable used
+const ITEM_PENALTY: u32 = 100;
+
+// Turns a room should be cleared in for the full speed bonus
+pub fn par(room: Room) -> u16 {
+    match room {
+        Room::EntranceHall => 12,
+        Room::GreatHall => 20,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Rank {
+    S,
+    A,
+    B,
+    C,
+    D,
+}
+
+impl Rank {
+    // Share of the most a room could have scored needed for each rank, in percent
+    fn of(total: u32, max: u32) -> Self {
+        match total * 100 / max.max(1) {
+            90.. => Self::S,
+            75.. => Self::A,
+            60.. => Self::B,
+            40.. => Self::C,
+            _ => Self::D,
+        }
+    }
+
+    pub fn letter(&self) -> &'static str {
+        match self {
+            Self::S => "S",
+            Self::A => "A",
+            Self::B => "B",
+            Self::C => "C",
+            Self::D => "D",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Score {
+    pub kills: u32,
+    pub speed: u32,
+    pub no_damage: u32,
+    pub items: u32,
+    pub rank: Rank,
+}
+
+impl Score {
+    // Scored when the room is left, out of every enemy it had
+    pub fn new(room: Room, rounds: u16, stats: &RunStats, enemies: u16) -> Self {
+        let over_par = rounds.saturating_sub(par(room)) as u32;
+        let kills = stats.enemies_slain() as u32 * KILL_POINTS;
+        let speed = SPEED_BONUS.saturating_sub(over_par * TURN_PENALTY);
+        let no_damage = if stats.damage_taken == 0 {
+            NO_DAMAGE_BONUS
+        } else {
+            0
+        };
+        let items = ITEM_BONUS.saturating_sub(stats.consumables_used as u32 * ITEM_PENALTY);
+
+        let total = kills + speed + no_damage + items;
+        let max = enemies as u32 * KILL_POINTS + SPEED_BONUS + NO_DAMAGE_BONUS + ITEM_BONUS;
+        Self {
+            kills,
+            speed,
+            no_damage,
+            items,
+            rank: Rank::of(total, max),
+        }
+    }
+
+    pub fn total(&self) -> u32 {
+        self.kills + self.speed + self.no_damage + self.items
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        vec![
+            format!("Kills: {}", self.kills),
+            format!("Speed bonus: {}", self.speed),
+            format!("No damage bonus: {}", self.no_damage),
+            format!("Items conserved: {}", self.items),
+            format!("Total: {}", self.total()),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::UnitId;
+    use crate::level::AllyId;
+
+    #[test]
+    fn a_fast_clean_clear_ranks_above_a_slow_bloody_one() {
+        let par = par(Room::EntranceHall);
+        let mut stats = RunStats::default();
+        stats.kills.insert(UnitId::Ally(AllyId::AshMagnum), 4);
+
+        let clean = Score::new(Room::EntranceHall, par, &stats, 4);
+        assert_eq!(clean.total(), 400 + 500 + 500 + 300);
+        assert_eq!(clean.rank, Rank::S);
+
+        stats.damage_taken = 3;
+        stats.consumables_used = 2;
+        let bloody = Score::new(Room::EntranceHall, par + 4, &stats, 6);
+        assert_eq!(bloody.speed, 300);
+        assert_eq!(bloody.no_damage, 0);
+        assert_eq!(bloody.items, 100);
+        assert_eq!(bloody.rank, Rank::C);
+    }
+}
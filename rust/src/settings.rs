@@ -0,0 +1,480 @@
+use crate::audio::{Bus, SoundEffects};
+use crate::controls::{Action, Binding, Controls, Device};
+use crate::juice::{Intensity, Juice};
+use crate::ui;
+
+use godot::engine::file_access::ModeFlags;
+use godot::engine::{
+    Button, CheckButton, Control, Engine, FileAccess, GridContainer, HSlider, INode,
+    IVBoxContainer, InputEvent, Label, OptionButton, SceneTree, VBoxContainer,
+};
+use godot::prelude::*;
+use serde::{Deserialize, Serialize};
+
+const PATH: &str = "user://settings.json";
+
+// Multipliers on the engine's time scale, on top of fast-forward
+const ANIMATION_SPEEDS: [(f64, &str); 3] = [(1.0, "Normal"), (1.5, "Fast"), (2.0, "Fastest")];
+
+const SHAKES: [(Intensity, &str); 3] = [
+    (Intensity::Off, "Off"),
+    (Intensity::Reduced, "Reduced"),
+    (Intensity::Full, "Full"),
+];
+
+// Scales on the panels and bubbles that carry text
+const TEXT_SIZES: [(f32, &str); 3] = [(1.0, "Normal"), (1.25, "Large"), (1.5, "Largest")];
+
+const TEXT_SPEEDS: [(TextSpeed, &str); 4] = [
+    (TextSpeed::Slow, "Slow"),
+    (TextSpeed::Normal, "Normal"),
+    (TextSpeed::Fast, "Fast"),
+    (TextSpeed::Instant, "Instant"),
+];
+
+// How quickly dialogue is typed out and barks fade
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TextSpeed {
+    Slow,
+    #[default]
+    Normal,
+    Fast,
+    Instant,
+}
+
+impl TextSpeed {
+    // Dialogic's multiplier on the delay between letters
+    fn letter_delay(&self) -> f64 {
+        match self {
+            Self::Slow => 2.0,
+            Self::Normal => 1.0,
+            Self::Fast => 0.5,
+            Self::Instant => 0.0,
+        }
+    }
+
+    // How long a bark stays up next to the usual time, left long enough to read when instant
+    pub fn linger(&self) -> f64 {
+        match self {
+            Self::Slow => 1.5,
+            Self::Normal => 1.0,
+            Self::Fast | Self::Instant => 0.75,
+        }
+    }
+}
+
+// What the player has chosen in the settings, written out whenever it changes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub music_volume: f32,
+    pub sfx_volume: f32,
+    pub ui_volume: f32,
+    pub animation_speed: f64,
+    pub shake: Intensity,
+    pub text_size: f32,
+    pub text_speed: TextSpeed,
+    // The ally phase ends by itself once every ally has acted
+    pub auto_end_turn: bool,
+    // Stepping onto a door has to be selected twice
+    pub confirm_doors: bool,
+    // Each run is written to user://logs for bug reports
+    pub log_runs: bool,
+    pub controls: Controls,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            music_volume: 1.0,
+            sfx_volume: 1.0,
+            ui_volume: 1.0,
+            animation_speed: 1.0,
+            shake: Intensity::Full,
+            text_size: 1.0,
+            text_speed: TextSpeed::Normal,
+            auto_end_turn: true,
+            confirm_doors: true,
+            log_runs: true,
+            controls: Controls::default(),
+        }
+    }
+}
+
+impl Config {
+    fn load() -> Self {
+        FileAccess::open(PATH.into(), ModeFlags::READ)
+            .and_then(|file| serde_json::from_str(&file.get_as_text().to_string()).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) {
+        if let (Some(mut file), Ok(json)) = (
+            FileAccess::open(PATH.into(), ModeFlags::WRITE),
+            serde_json::to_string(self),
+        ) {
+            file.store_string(json.into());
+        }
+    }
+}
+
+// Autoload holding the config, loaded after the sound effects and juice so it can set them up
+#[derive(GodotClass)]
+#[class(init, base=Node)]
+pub struct Settings {
+    config: Config,
+    base: Base<Node>,
+}
+
+#[godot_api]
+impl INode for Settings {
+    fn ready(&mut self) {
+        self.config = Config::load();
+        self.apply();
+    }
+}
+
+impl Settings {
+    pub fn config(&self) -> Config {
+        self.config.clone()
+    }
+
+    pub fn set(&mut self, config: Config) {
+        if config != self.config {
+            self.config = config;
+            self.apply();
+            self.config.save();
+        }
+    }
+
+    fn apply(&mut self) {
+        let root = self.base().get_tree().unwrap().get_root().unwrap();
+        if let Some(mut sound_effects) = root.try_get_node_as::<SoundEffects>("SoundEffects") {
+            let mut sound_effects = sound_effects.bind_mut();
+            sound_effects.set_volume(Bus::Music, self.config.music_volume);
+            sound_effects.set_volume(Bus::Sfx, self.config.sfx_volume);
+            sound_effects.set_volume(Bus::Ui, self.config.ui_volume);
+        }
+        if let Some(mut juice) = root.try_get_node_as::<Juice>("Juice") {
+            juice.bind_mut().intensity = self.config.shake;
+        }
+        if let Some(dialogic) = root.try_get_node_as::<Node>("Dialogic") {
+            set_dialogic_speed(&dialogic, self.config.text_speed);
+        }
+        self.config.controls.apply();
+    }
+}
+
+pub fn settings() -> Gd<Settings> {
+    let tree: Gd<SceneTree> = Engine::singleton().get_main_loop().unwrap().cast();
+    tree.get_root().unwrap().get_node_as::<Settings>("Settings")
+}
+
+// Sliders and option lists for the config, shared by the main and pause menus
+#[derive(GodotClass)]
+#[class(init, base=VBoxContainer)]
+pub struct SettingsPanel {
+    // Shown again when the panel is closed
+    #[export]
+    back_to: Option<Gd<Control>>,
+    // One button per action and device, filled in from the config
+    binding_buttons: Vec<(Action, Device, Gd<Button>)>,
+    // Waiting on the next key or button for an action
+    listening: Option<(Action, Device)>,
+    base: Base<VBoxContainer>,
+}
+
+#[godot_api]
+impl IVBoxContainer for SettingsPanel {
+    fn ready(&mut self) {
+        let mut animation_speed = self.option_button("AnimationSpeed");
+        for (_, name) in ANIMATION_SPEEDS {
+            animation_speed.add_item(name.into());
+        }
+        let mut shake = self.option_button("Shake");
+        for (_, name) in SHAKES {
+            shake.add_item(name.into());
+        }
+        let mut text_size = self.option_button("TextSize");
+        for (_, name) in TEXT_SIZES {
+            text_size.add_item(name.into());
+        }
+        let mut text_speed = self.option_button("TextSpeed");
+        for (_, name) in TEXT_SPEEDS {
+            text_speed.add_item(name.into());
+        }
+
+        let mut grid = self
+            .base()
+            .get_node_as::<GridContainer>("Controls/Scroll/Bindings");
+        for (index, action) in Action::ALL.into_iter().enumerate() {
+            let mut label = Label::new_alloc();
+            label.set_text(action.label().into());
+            grid.add_child(label.upcast());
+
+            for device in [Device::Keyboard, Device::Gamepad] {
+                let mut button = Button::new_alloc();
+                button.set_custom_minimum_size(Vector2::new(96.0, 0.0));
+                button.connect(
+                    "pressed".into(),
+                    Callable::from_object_method(&self.base(), "_on_binding_pressed")
+                        .bindv(varray![index as i64, device as i64]),
+                );
+                grid.add_child(button.clone().upcast());
+                self.binding_buttons.push((action, device, button));
+            }
+        }
+    }
+
+    // Takes the next key for a keyboard binding or the next button or push for a pad one
+    fn input(&mut self, event: Gd<InputEvent>) {
+        let Some((action, device)) = self.listening else {
+            return;
+        };
+        let Some(binding) = Binding::from_event(&event).filter(|b| b.device() == device) else {
+            return;
+        };
+
+        self.listening = None;
+        change(|config| config.controls.rebind(action, binding));
+        self.show_bindings();
+        if let Some(mut viewport) = self.base().get_viewport() {
+            viewport.set_input_as_handled();
+        }
+    }
+}
+
+#[godot_api]
+impl SettingsPanel {
+    #[func]
+    fn _on_music_volume_changed(&mut self, value: f64) {
+        change(|config| config.music_volume = value as f32);
+    }
+
+    #[func]
+    fn _on_sfx_volume_changed(&mut self, value: f64) {
+        change(|config| config.sfx_volume = value as f32);
+    }
+
+    #[func]
+    fn _on_ui_volume_changed(&mut self, value: f64) {
+        change(|config| config.ui_volume = value as f32);
+    }
+
+    #[func]
+    fn _on_animation_speed_selected(&mut self, index: i64) {
+        if let Some((speed, _)) = ANIMATION_SPEEDS.get(index as usize) {
+            change(|config| config.animation_speed = *speed);
+        }
+    }
+
+    #[func]
+    fn _on_shake_selected(&mut self, index: i64) {
+        if let Some((shake, _)) = SHAKES.get(index as usize) {
+            change(|config| config.shake = *shake);
+        }
+    }
+
+    #[func]
+    fn _on_text_size_selected(&mut self, index: i64) {
+        if let Some((size, _)) = TEXT_SIZES.get(index as usize) {
+            change(|config| config.text_size = *size);
+        }
+    }
+
+    #[func]
+    fn _on_text_speed_selected(&mut self, index: i64) {
+        if let Some((speed, _)) = TEXT_SPEEDS.get(index as usize) {
+            change(|config| config.text_speed = *speed);
+        }
+    }
+
+    #[func]
+    fn _on_auto_end_turn_toggled(&mut self, on: bool) {
+        change(|config| config.auto_end_turn = on);
+    }
+
+    #[func]
+    fn _on_confirm_doors_toggled(&mut self, on: bool) {
+        change(|config| config.confirm_doors = on);
+    }
+
+    #[func]
+    fn _on_log_runs_toggled(&mut self, on: bool) {
+        change(|config| config.log_runs = on);
+    }
+
+    #[func]
+    fn _on_controls_pressed(&mut self) {
+        self.show_controls(true);
+    }
+
+    #[func]
+    fn _on_binding_pressed(&mut self, index: i64, device: i64) {
+        let device = if device == Device::Keyboard as i64 {
+            Device::Keyboard
+        } else {
+            Device::Gamepad
+        };
+        let Some(action) = Action::ALL.get(index as usize).copied() else {
+            return;
+        };
+
+        self.listening = Some((action, device));
+        self.show_bindings();
+    }
+
+    #[func]
+    fn _on_reset_controls_pressed(&mut self) {
+        self.listening = None;
+        change(|config| config.controls = Controls::default());
+        self.show_bindings();
+    }
+
+    #[func]
+    fn _on_controls_back_pressed(&mut self) {
+        self.show_controls(false);
+    }
+
+    #[func]
+    fn _on_back_pressed(&mut self) {
+        self.close();
+    }
+}
+
+impl SettingsPanel {
+    fn option_button(&self, path: &str) -> Gd<OptionButton> {
+        self.base().get_node_as::<OptionButton>(path)
+    }
+
+    // Fills the controls in from the config without setting it again
+    pub fn open(&mut self) {
+        let config = settings().bind().config();
+        let volumes = [
+            ("MusicVolume", config.music_volume),
+            ("SfxVolume", config.sfx_volume),
+            ("UiVolume", config.ui_volume),
+        ];
+        for (path, volume) in volumes {
+            let mut slider = self.base().get_node_as::<HSlider>(path);
+            slider.set_value_no_signal(volume as f64);
+        }
+
+        let speed = ANIMATION_SPEEDS
+            .iter()
+            .position(|(speed, _)| *speed == config.animation_speed)
+            .unwrap_or(0);
+        self.option_button("AnimationSpeed").select(speed as i32);
+        let shake = SHAKES
+            .iter()
+            .position(|(shake, _)| *shake == config.shake)
+            .unwrap_or(0);
+        self.option_button("Shake").select(shake as i32);
+        let text_size = TEXT_SIZES
+            .iter()
+            .position(|(size, _)| *size == config.text_size)
+            .unwrap_or(0);
+        self.option_button("TextSize").select(text_size as i32);
+        let text_speed = TEXT_SPEEDS
+            .iter()
+            .position(|(speed, _)| *speed == config.text_speed)
+            .unwrap_or(1);
+        self.option_button("TextSpeed").select(text_speed as i32);
+        let toggles = [
+            ("AutoEndTurn", config.auto_end_turn),
+            ("ConfirmDoors", config.confirm_doors),
+            ("LogRuns", config.log_runs),
+        ];
+        for (path, on) in toggles {
+            let mut toggle = self.base().get_node_as::<CheckButton>(path);
+            toggle.set_pressed_no_signal(on);
+        }
+
+        if let Some(back_to) = &mut self.back_to {
+            back_to.set_visible(false);
+        }
+        self.base_mut().set_visible(true);
+        self.show_controls(false);
+    }
+
+    pub fn close(&mut self) {
+        self.listening = None;
+        self.base_mut().set_visible(false);
+        if let Some(back_to) = &mut self.back_to {
+            back_to.set_visible(true);
+            ui::focus_first(back_to.clone().upcast());
+        }
+    }
+
+    // A rebind in progress holds on to every key, the pause key included
+    pub fn is_listening(&self) -> bool {
+        self.listening.is_some()
+    }
+
+    // The controls take the panel's place while they are open
+    fn show_controls(&mut self, shown: bool) {
+        self.listening = None;
+        for child in self.base().get_children().iter_shared() {
+            if let Ok(mut control) = child.try_cast::<Control>() {
+                let is_controls = control.get_name() == "Controls".into();
+                control.set_visible(is_controls == shown);
+            }
+        }
+        self.show_bindings();
+        ui::focus_first(self.base().clone().upcast());
+    }
+
+    fn show_bindings(&mut self) {
+        let controls = settings().bind().config.controls.clone();
+        for (action, device, button) in &mut self.binding_buttons {
+            let text = if self.listening == Some((*action, *device)) {
+                "Press...".into()
+            } else {
+                controls
+                    .binding(*action, *device)
+                    .map_or_else(|| "None".into(), |binding| binding.label())
+            };
+            button.set_text(text.into());
+        }
+    }
+}
+
+fn change(change: impl FnOnce(&mut Config)) {
+    let mut settings = settings();
+    let mut config = settings.bind().config();
+    change(&mut config);
+    settings.bind_mut().set(config);
+}
+
+pub fn text_size() -> f32 {
+    settings().bind().config.text_size
+}
+
+pub fn text_speed() -> TextSpeed {
+    settings().bind().config.text_speed
+}
+
+pub fn auto_end_turn() -> bool {
+    settings().bind().config.auto_end_turn
+}
+
+pub fn confirm_doors() -> bool {
+    settings().bind().config.confirm_doors
+}
+
+pub fn log_runs() -> bool {
+    settings().bind().config.log_runs
+}
+
+// Dialogic keeps settings of its own and loads after this, so the level's dialogue hands it the
+// text speed again once it is up
+pub fn set_dialogic_speed(dialogic: &Gd<Node>, speed: TextSpeed) {
+    let mut dialogic_settings = dialogic.get("Settings".into()).to::<Gd<Object>>();
+    dialogic_settings.set("text_speed".into(), Variant::from(speed.letter_delay()));
+}
+
+// Sets the engine's time scale to a fast-forward speed, sped up further by the animation speed
+pub fn set_time_scale(fast_forward: f64) {
+    let animation_speed = settings().bind().config.animation_speed;
+    Engine::singleton().set_time_scale(fast_forward * animation_speed);
+}
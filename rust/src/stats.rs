@@ -0,0 +1,100 @@
+use crate::ability::{abilities, Ability, DamageKind};
+use crate::core::UnitId;
+use crate::event::GameEvent;
+use crate::level::EnemyKind;
+
+use std::collections::HashMap;
+
+// The blow that killed a unit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeathCause {
+    pub source: Option<UnitId>,
+    // None when an effect did it
+    pub ability: Option<Ability>,
+    pub damage: u16,
+    pub damage_kind: DamageKind,
+}
+
+// Tallies the level keeps over a room from its events
+#[derive(Debug, Clone, Default)]
+pub struct RunStats {
+    pub kills: HashMap<UnitId, u16>,
+    pub deaths: HashMap<UnitId, DeathCause>,
+    pub abilities_used: u16,
+    // Uses spent of abilities that run out
+    pub consumables_used: u16,
+    pub damage_dealt: u32,
+    pub damage_taken: u32,
+    // Every enemy turned to dust, by kind
+    pub slain: HashMap<EnemyKind, u16>,
+}
+
+impl RunStats {
+    pub fn record(&mut self, event: GameEvent) {
+        match event {
+            GameEvent::UnitKilled {
+                unit,
+                source,
+                ability,
+                damage,
+                damage_kind,
+            } => {
+                if let Some(source) = source {
+                    *self.kills.entry(source).or_insert(0) += 1;
+                }
+                self.deaths.insert(
+                    unit,
+                    DeathCause {
+                        source,
+                        ability,
+                        damage,
+                        damage_kind,
+                    },
+                );
+            }
+            GameEvent::EnemyDied(_, enemy_kind) => *self.slain.entry(enemy_kind).or_insert(0) += 1,
+            GameEvent::UnitDamaged {
+                unit: UnitId::Ally(_),
+                damage,
+                ..
+            } => self.damage_taken += damage as u32,
+            GameEvent::UnitDamaged {
+                unit: UnitId::Enemy(_),
+                damage,
+                ..
+            } => self.damage_dealt += damage as u32,
+            GameEvent::AbilityUsed {
+                unit: UnitId::Ally(_),
+                ability,
+            } => {
+                self.abilities_used += 1;
+                if abilities()[&ability].consumable {
+                    self.consumables_used += 1;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // Such as "3 Bat, 1 Vampire", in bestiary order
+    pub fn dust(&self) -> String {
+        EnemyKind::ALL
+            .iter()
+            .filter_map(|kind| {
+                self.slain
+                    .get(kind)
+                    .map(|count| format!("{} {}", count, kind.name()))
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    // Kills by allies, whoever landed them
+    pub fn enemies_slain(&self) -> u16 {
+        self.kills
+            .iter()
+            .filter(|(unit, _)| matches!(unit, UnitId::Ally(_)))
+            .map(|(_, kills)| kills)
+            .sum()
+    }
+}
@@ -0,0 +1,91 @@
+use crate::daily;
+use crate::dialogue::Room;
+use crate::end_screen::END_SCENE;
+use crate::level::Level;
+use crate::main_menu::MENU_SCENE;
+use crate::scoring::{self, Score};
+use crate::ui;
+
+use godot::engine::{CenterContainer, ICenterContainer, Label};
+use godot::prelude::*;
+
+// Shown on the way out of a room, before going through to the next one
+#[derive(GodotClass)]
+#[class(init, base=CenterContainer)]
+pub struct Summary {
+    pub room: Room,
+    pub round: u16,
+    pub score: Option<Score>,
+    // The room's best score before this one
+    pub best: Option<Score>,
+    // The daily challenge the room was played in, if any
+    pub seed: Option<u64>,
+    // The room the door leads to, already given the party, or None after the last room
+    pub next: Option<Gd<Level>>,
+    base: Base<CenterContainer>,
+}
+
+#[godot_api]
+impl ICenterContainer for Summary {
+    fn ready(&mut self) {
+        let Some(score) = self.score else {
+            return;
+        };
+
+        let mut title = self.base().get_node_as::<Label>("VBoxContainer/Title");
+        title.set_text(format!("{} cleared", self.room.name()).into());
+
+        let mut rank = self.base().get_node_as::<Label>("VBoxContainer/Rank");
+        rank.set_text(format!("Rank {}", score.rank.letter()).into());
+
+        let mut lines = vec![format!(
+            "{} turns, par {}",
+            self.round,
+            scoring::par(self.room)
+        )];
+        if let Some(seed) = self.seed {
+            lines.insert(0, format!("Daily challenge {}", daily::date_of(seed)));
+        }
+        lines.extend(score.lines());
+        lines.push(match self.best {
+            Some(best) if best.total() >= score.total() => {
+                format!("Best: {} ({})", best.total(), best.rank.letter())
+            }
+            _ => "New best!".into(),
+        });
+        let mut stats = self.base().get_node_as::<Label>("VBoxContainer/Stats");
+        stats.set_text(lines.join("\n").into());
+
+        ui::focus_first(self.base().clone().upcast());
+    }
+}
+
+#[godot_api]
+impl Summary {
+    #[func]
+    fn _on_continue_button_pressed(&mut self) {
+        let mut tree = self.base().get_tree().unwrap();
+        match self.next.take() {
+            Some(next) => {
+                tree.get_root().unwrap().add_child(next.clone().upcast());
+                tree.set_current_scene(next.upcast());
+                self.base_mut().queue_free();
+            }
+            None => {
+                tree.change_scene_to_file(END_SCENE.into());
+            }
+        }
+    }
+
+    #[func]
+    fn _on_menu_button_pressed(&mut self) {
+        // The next room was never added to the tree, so nothing else will free it
+        if let Some(next) = self.next.take() {
+            next.free();
+        }
+        self.base()
+            .get_tree()
+            .unwrap()
+            .change_scene_to_file(MENU_SCENE.into());
+    }
+}
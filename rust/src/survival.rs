@@ -0,0 +1,104 @@
+use crate::level::{EnemyKind, TILE_SIZE};
+use crate::math::Position;
+
+use godot::prelude::*;
+
+// Dealt to every enemy caught in the light at sunrise, which is all it takes for the vulnerable
+pub const SUNLIGHT_DAMAGE: u16 = 1;
+
+// An enemy that turns up at the start of a round in a level to be survived, placed in the level's
+// WaveLayer where it will appear. A wave is every marker sharing a round
+#[derive(GodotClass)]
+#[class(init, base=Node2D)]
+pub struct Wave {
+    #[export]
+    pub round: u16,
+    #[export]
+    pub kind: EnemyKind,
+    base: Base<Node2D>,
+}
+
+// Tiles the sun shines on at sunrise, placed in the level's SunLayer at their top left corner.
+// The layer is hidden until then, so it can hold the light itself. Light let in through a shutter
+// goes in the LightLayer instead, and is hidden until its switch opens the shutter
+#[derive(GodotClass)]
+#[class(init, base=Node2D)]
+pub struct Sunlight {
+    #[export]
+    #[init(default = 1)]
+    pub width: u16,
+    #[export]
+    #[init(default = 1)]
+    pub height: u16,
+    #[export]
+    pub switch: GString,
+    base: Base<Node2D>,
+}
+
+impl Sunlight {
+    pub fn tiles(&self, position: Position) -> Vec<Position> {
+        (0..self.width as usize)
+            .flat_map(|i| (0..self.height as usize).map(move |j| position.offset(i, j)))
+            .collect()
+    }
+}
+
+// An enemy on its way in, appearing at the start of its round
+pub struct PendingSpawn {
+    pub round: u16,
+    pub enemy_kind: EnemyKind,
+    pub position: Position,
+    pub marker: Gd<SpawnMarker>,
+}
+
+// Drawn over the tiles of a pending spawn, so the allies have a turn to get ready for it
+#[derive(GodotClass)]
+#[class(init, base=Node2D)]
+pub struct SpawnMarker {
+    pub width: u16,
+    pub height: u16,
+    base: Base<Node2D>,
+}
+
+#[godot_api]
+impl INode2D for SpawnMarker {
+    fn draw(&mut self) {
+        let size = Vector2::new(self.width as f32, self.height as f32) * TILE_SIZE;
+        let rect = Rect2::new(Vector2::default(), size);
+        self.base_mut()
+            .draw_rect(rect, Color::from_rgba(0.8, 0.1, 0.1, 0.3));
+        self.base_mut()
+            .draw_rect_ex(rect.grow(-0.5), Color::from_rgb(0.8, 0.1, 0.1))
+            .filled(false)
+            .width(1.0)
+            .done();
+    }
+}
+
+// Rounds left before the sun comes up, none once it has or in a level without a sunrise
+pub fn turns_until_sunrise(sunrise: u16, round: u16) -> Option<u16> {
+    (sunrise > 0 && round < sunrise).then(|| sunrise - round)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ability::DamageKind;
+    use crate::combat;
+    use crate::traits::Trait;
+
+    #[test]
+    fn sunrise_counts_down_and_burns_the_vulnerable() {
+        assert_eq!(turns_until_sunrise(10, 1), Some(9));
+        assert_eq!(turns_until_sunrise(10, 9), Some(1));
+        assert_eq!(turns_until_sunrise(10, 10), None);
+        assert_eq!(turns_until_sunrise(0, 1), None);
+
+        let vulnerable = [Trait::SunlightVulnerable];
+        assert!(combat::damage_taken(SUNLIGHT_DAMAGE, DamageKind::Sunlight, &vulnerable) >= 10);
+        assert_eq!(
+            combat::damage_taken(SUNLIGHT_DAMAGE, DamageKind::Sunlight, &[]),
+            SUNLIGHT_DAMAGE
+        );
+    }
+}
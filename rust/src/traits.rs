@@ -1,6 +1,13 @@
+use crate::ability::DamageKind;
+use crate::core::Effect;
+use crate::effects::Effects;
+use crate::mods;
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::sync::OnceLock;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum Trait {
     SilverVulnerable,
     HolyVulnerable,
@@ -8,14 +15,249 @@ pub enum Trait {
     SunlightVulnerable,
     HolyFromSunlight,
     GarlicAllergy,
+    Incorporeal,
+    Regeneration,
+    FireImmune,
+    PierceResistant,
+    Undead,
+    Devout,
+    BloodShield,
+    FearAura,
+    HolyAura,
+    // Added by a mod, with nothing to it but what its entry in the registry says
+    Custom(u16),
+}
+
+// Extra damage of 1000 or more kills outright
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub enum DamageRule {
+    Extra(DamageKind, u16),
+    Reduced(DamageKind, u16),
+    Immune(DamageKind),
+    // Immune to every kind but these
+    OnlyHarmedBy(Vec<DamageKind>),
+}
+
+// What a unit does to the tiles around it, reaching as far as the radius
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Aura {
+    pub radius: u16,
+    pub kind: AuraKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+pub enum AuraKind {
+    // Allies in it deal this much less damage
+    Fear(u16),
+    // Undead enemies in it take this much holy damage at the start of every round
+    Holy(u16),
+}
+
+// Text is shown as written, in English like every other name and description in the game, and
+// a mod pack's traits bring their own
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TraitStats {
+    // One line for the info panel
+    pub summary: String,
+    // The full rules, for tooltips
+    pub description: String,
+    #[serde(default)]
+    pub damage: Vec<DamageRule>,
+    #[serde(default)]
+    pub aura: Option<Aura>,
+}
+
+impl TraitStats {
+    fn new(summary: &str, description: &str, damage: Vec<DamageRule>) -> Self {
+        Self {
+            summary: summary.into(),
+            description: description.into(),
+            damage,
+            aura: None,
+        }
+    }
+
+    fn with_aura(self, radius: u16, kind: AuraKind) -> Self {
+        Self {
+            aura: Some(Aura { radius, kind }),
+            ..self
+        }
+    }
+}
+
+// Every trait there is, built in or added by mods, in a steady order for listing them
+pub fn trait_stats() -> &'static BTreeMap<Trait, TraitStats> {
+    static TRAIT_STATS: OnceLock<BTreeMap<Trait, TraitStats>> = OnceLock::new();
+    TRAIT_STATS.get_or_init(|| {
+        let mut stats = init_trait_stats();
+        mods::overlay_traits(&mut stats);
+        stats
+    })
+}
+
+pub fn init_trait_stats() -> BTreeMap<Trait, TraitStats> {
+    [
+        (
+            Trait::SilverVulnerable,
+            TraitStats::new(
+                "Vulnerable to silver",
+                "Takes 1 extra damage from silver",
+                vec![DamageRule::Extra(DamageKind::Silver, 1)],
+            ),
+        ),
+        (
+            Trait::HolyVulnerable,
+            TraitStats::new(
+                "Vulnerable to holy",
+                "Takes 2 extra damage from holy",
+                vec![DamageRule::Extra(DamageKind::Holy, 2)],
+            ),
+        ),
+        (
+            Trait::StakeVulnerable,
+            TraitStats::new(
+                "Vulnerable to stakes",
+                "Killed outright by a stake through the heart, unless in mist form",
+                vec![DamageRule::Extra(DamageKind::Stake, 1_000)],
+            ),
+        ),
+        (
+            Trait::SunlightVulnerable,
+            TraitStats::new(
+                "Vulnerable to sunlight",
+                "Killed outright by sunlight",
+                vec![DamageRule::Extra(DamageKind::Sunlight, 1_000)],
+            ),
+        ),
+        (
+            Trait::HolyFromSunlight,
+            TraitStats::new(
+                "Sunlight deals holy damage",
+                "Takes 2 extra damage from sunlight",
+                vec![DamageRule::Extra(DamageKind::Sunlight, 2)],
+            ),
+        ),
+        (
+            Trait::GarlicAllergy,
+            TraitStats::new(
+                "Allergic to garlic",
+                "Will not step onto a tile warded by garlic",
+                Vec::new(),
+            ),
+        ),
+        (
+            Trait::Incorporeal,
+            TraitStats::new(
+                "Harmed only by silver and holy",
+                "Immune to every kind of damage but silver and holy",
+                vec![DamageRule::OnlyHarmedBy(vec![
+                    DamageKind::Silver,
+                    DamageKind::Holy,
+                ])],
+            ),
+        ),
+        (
+            Trait::Regeneration,
+            TraitStats::new(
+                "Regenerates unless burned or blessed",
+                "Heals at the start of each phase, unless it took fire or holy damage last round",
+                Vec::new(),
+            ),
+        ),
+        (
+            Trait::FireImmune,
+            TraitStats::new(
+                "Immune to fire",
+                "Takes no fire damage and never burns",
+                vec![DamageRule::Immune(DamageKind::Fire)],
+            ),
+        ),
+        (
+            Trait::PierceResistant,
+            TraitStats::new(
+                "Resistant to piercing",
+                "Takes 1 less damage from piercing",
+                vec![DamageRule::Reduced(DamageKind::Pierce, 1)],
+            ),
+        ),
+        (
+            Trait::Undead,
+            TraitStats::new(
+                "Immune to life steal",
+                "There is no life in it to drain, though a vampire's bite still drinks from it",
+                vec![DamageRule::Immune(DamageKind::LifeSteal)],
+            ),
+        ),
+        (
+            Trait::Devout,
+            TraitStats::new(
+                "Faith wards off life steal",
+                "Its faith keeps life steal from draining it",
+                vec![DamageRule::Immune(DamageKind::LifeSteal)],
+            ),
+        ),
+        (
+            Trait::BloodShield,
+            TraitStats::new(
+                "Gorges on stolen life",
+                "Life stolen past full health becomes a shield that soaks damage until its next phase",
+                Vec::new(),
+            ),
+        ),
+        (
+            Trait::FearAura,
+            TraitStats::new(
+                "Unnerves allies nearby",
+                "Allies within 2 tiles deal 1 less damage, though never less than 1",
+                Vec::new(),
+            )
+            .with_aura(2, AuraKind::Fear(1)),
+        ),
+        (
+            Trait::HolyAura,
+            TraitStats::new(
+                "Sears undead beside it",
+                "Undead enemies next to it take 1 holy damage at the start of every round",
+                Vec::new(),
+            )
+            .with_aura(1, AuraKind::Holy(1)),
+        ),
+    ]
+    .into()
+}
+
+// Traits the built-in lists and effects name that have no entry in the registry
+pub fn missing_traits(known: &BTreeMap<Trait, TraitStats>) -> Vec<Trait> {
+    let mut referenced: Vec<Trait> = init_trait_lists().into_iter().flatten().collect();
+    for modifiers in init_trait_modifiers().values() {
+        referenced.extend(modifiers.iter().map(|modifier| match modifier {
+            TraitModifier::Grant(trait_) | TraitModifier::Suppress(trait_) => *trait_,
+        }));
+    }
+    referenced.sort();
+    referenced.dedup();
+    referenced.retain(|trait_| !known.contains_key(trait_));
+    referenced
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraitModifier {
+    Grant(Trait),
+    Suppress(Trait),
 }
 
 pub fn trait_lists() -> &'static Vec<Vec<Trait>> {
     static TRAIT_LISTS: OnceLock<Vec<Vec<Trait>>> = OnceLock::new();
-    TRAIT_LISTS.get_or_init(|| init_trait_lists())
+    TRAIT_LISTS.get_or_init(|| {
+        let mut lists = init_trait_lists();
+        mods::overlay_lists(&mut lists, |pack| &pack.trait_lists);
+        lists
+    })
 }
 
-fn init_trait_lists() -> Vec<Vec<Trait>> {
+pub fn init_trait_lists() -> Vec<Vec<Trait>> {
     vec![
         Vec::new(),
         vec![
@@ -23,6 +265,8 @@ fn init_trait_lists() -> Vec<Vec<Trait>> {
             Trait::HolyVulnerable,
             Trait::HolyFromSunlight,
             Trait::GarlicAllergy,
+            Trait::BloodShield,
+            Trait::FireImmune,
         ],
         vec![
             Trait::SilverVulnerable,
@@ -30,6 +274,115 @@ fn init_trait_lists() -> Vec<Vec<Trait>> {
             Trait::StakeVulnerable,
             Trait::SunlightVulnerable,
             Trait::GarlicAllergy,
+            Trait::Undead,
         ],
+        vec![
+            Trait::SilverVulnerable,
+            Trait::Regeneration,
+            Trait::PierceResistant,
+        ],
+        vec![Trait::Incorporeal, Trait::Undead],
+        vec![Trait::Devout, Trait::HolyAura],
+        vec![Trait::FearAura],
+    ]
+}
+
+pub fn trait_modifiers() -> &'static HashMap<Effect, Vec<TraitModifier>> {
+    static TRAIT_MODIFIERS: OnceLock<HashMap<Effect, Vec<TraitModifier>>> = OnceLock::new();
+    TRAIT_MODIFIERS.get_or_init(|| init_trait_modifiers())
+}
+
+fn init_trait_modifiers() -> HashMap<Effect, Vec<TraitModifier>> {
+    [
+        (
+            Effect::Mist,
+            vec![TraitModifier::Suppress(Trait::StakeVulnerable)],
+        ),
+        (
+            Effect::Blessing,
+            vec![TraitModifier::Grant(Trait::HolyVulnerable)],
+        ),
     ]
+    .into()
+}
+
+// Base traits combined with whatever active effects grant or suppress
+pub fn active_traits(base: &[Trait], effects: &Effects) -> Vec<Trait> {
+    let mut traits = base.to_vec();
+    for (effect, _) in effects.iter() {
+        for modifier in trait_modifiers().get(&effect).unwrap_or(&Vec::new()) {
+            match modifier {
+                TraitModifier::Grant(trait_) => {
+                    if !traits.contains(trait_) {
+                        traits.push(*trait_);
+                    }
+                }
+                TraitModifier::Suppress(_) => (),
+            }
+        }
+    }
+
+    for (effect, _) in effects.iter() {
+        for modifier in trait_modifiers().get(&effect).unwrap_or(&Vec::new()) {
+            match modifier {
+                TraitModifier::Suppress(trait_) => traits.retain(|t| t != trait_),
+                TraitModifier::Grant(_) => (),
+            }
+        }
+    }
+
+    traits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ability;
+    use crate::mods::ModPack;
+
+    #[test]
+    fn traits_are_registered_before_anything_lists_them() {
+        assert!(missing_traits(&init_trait_stats()).is_empty());
+
+        let pack: ModPack = serde_json::from_str(
+            r#"{
+                "traits": [[
+                    { "Custom": 0 },
+                    {
+                        "summary": "Hates mirrors",
+                        "description": "Takes 3 extra damage from silver",
+                        "damage": [{ "Extra": ["Silver", 3] }]
+                    }
+                ]],
+                "trait_lists": { "6": [{ "Custom": 0 }, "Undead"] }
+            }"#,
+        )
+        .unwrap();
+        let mut known_abilities = ability::init_abilities().keys().copied().collect();
+        let mut known_traits = init_trait_stats().keys().copied().collect();
+        let (mut ability_lists, mut trait_lists) = (9, 6);
+        assert!(pack
+            .validate(
+                &mut ability_lists,
+                &mut trait_lists,
+                &mut known_abilities,
+                &mut known_traits
+            )
+            .is_empty());
+        assert!(known_traits.contains(&Trait::Custom(0)));
+
+        let pack: ModPack =
+            serde_json::from_str(r#"{ "trait_lists": { "7": [{ "Custom": 1 }] } }"#).unwrap();
+        let errors = pack.validate(
+            &mut ability_lists,
+            &mut trait_lists,
+            &mut known_abilities,
+            &mut known_traits,
+        );
+        assert_eq!(
+            errors,
+            vec!["Custom(1) is listed but never defined".to_string()]
+        );
+        assert!(!known_traits.contains(&Trait::Custom(1)));
+    }
 }
@@ -1,9 +1,23 @@
 use crate::ability::{abilities, Ability, Action, DamageKind};
-use crate::dialogue::Dialogue;
-use crate::level::{Ally, AllyId, Effect, EnemyId, ItemId, ItemKind, Level};
-use crate::traits::Trait;
-
-use godot::engine::{AtlasTexture, HBoxContainer, IHBoxContainer, Label, TextureRect};
+use crate::achievements;
+use crate::audio::{self, Sfx};
+use crate::combat::damage_taken;
+use crate::core::{Effect, Terrain, Tile, UnitId};
+use crate::effects::{self, ActiveEffect};
+use crate::event::{GameEvent, Listener};
+use crate::level::{Ally, AllyId, Enemy, EnemyId, ItemId, ItemKind, Level, NpcId};
+use crate::loot::Supply;
+use crate::math::Position;
+use crate::nodes::{self, find_level};
+use crate::settings;
+use crate::traits::{trait_stats, Trait};
+use crate::weather::Weather;
+
+use godot::engine::control::FocusMode;
+use godot::engine::{
+    AtlasTexture, BaseButton, Control, Engine, HBoxContainer, IHBoxContainer, IPanelContainer,
+    ITextureRect, Label, PanelContainer, TextureRect,
+};
 use godot::prelude::*;
 
 #[derive(GodotClass)]
@@ -13,6 +27,8 @@ pub struct InfoPanel {
     pub selected_enemy: Option<EnemyId>,
     pub selected_item: Option<ItemId>,
     pub selected_ability: Option<Ability>,
+    // Tooltips for the trait and effect lines of Stats3, in the same order
+    details: Vec<String>,
     base: Base<TextureRect>,
 }
 
@@ -30,6 +46,7 @@ impl InfoPanel {
         let mut stats_text = self.base().get_node_as::<Label>("Info/Stats3");
         stats_text.set_text("".into());
 
+        self.details.clear();
         self.base_mut().set_visible(false);
     }
 
@@ -41,19 +58,36 @@ impl InfoPanel {
         title.set_text(ally.name().into());
 
         let mut stats_text = self.base().get_node_as::<Label>("Info/Stats1");
-        stats_text.set_text(format!("{}/{} health", ally.health, ally.max_health).into());
+        stats_text.set_text(ally.vitals.health_text().into());
 
         let mut stats_text = self.base().get_node_as::<Label>("Info/Stats2");
         stats_text.set_text(format!("{} speed", ally.speed).into());
 
         let mut stats_text = self.base().get_node_as::<Label>("Info/Stats3");
         let text = ally
-            .traits
+            .traits()
             .iter()
             .map(|trait_| trait_description(*trait_))
+            .chain(
+                ally.vitals
+                    .effects
+                    .iter()
+                    .map(|(effect, active)| effect_description(effect, active)),
+            )
             .collect::<Vec<String>>()
             .join("\n");
         stats_text.set_text(text.into());
+        self.details = ally
+            .traits()
+            .iter()
+            .map(|trait_| trait_tooltip(*trait_))
+            .chain(
+                ally.vitals
+                    .effects
+                    .iter()
+                    .map(|(effect, active)| effect_tooltip(effect, active)),
+            )
+            .collect();
 
         self.base_mut().set_visible(true);
     }
@@ -66,19 +100,58 @@ impl InfoPanel {
         title.set_text(enemy.name().into());
 
         let mut stats_text = self.base().get_node_as::<Label>("Info/Stats1");
-        stats_text.set_text(format!("{}/{} health", enemy.health, enemy.max_health).into());
+        stats_text.set_text(enemy.vitals.health_text().into());
 
         let mut stats_text = self.base().get_node_as::<Label>("Info/Stats2");
         stats_text.set_text(format!("{} speed", enemy.speed).into());
 
         let mut stats_text = self.base().get_node_as::<Label>("Info/Stats3");
         let text = enemy
-            .traits
+            .traits()
             .iter()
             .map(|trait_| trait_description(*trait_))
+            .chain(
+                enemy
+                    .vitals
+                    .effects
+                    .iter()
+                    .map(|(effect, active)| effect_description(effect, active)),
+            )
             .collect::<Vec<String>>()
             .join("\n");
         stats_text.set_text(text.into());
+        self.details = enemy
+            .traits()
+            .iter()
+            .map(|trait_| trait_tooltip(*trait_))
+            .chain(
+                enemy
+                    .vitals
+                    .effects
+                    .iter()
+                    .map(|(effect, active)| effect_tooltip(effect, active)),
+            )
+            .collect();
+
+        self.base_mut().set_visible(true);
+    }
+
+    pub fn select_npc(&mut self, npc_id: NpcId, level: &Level) {
+        let npc = level.get_npc(npc_id);
+        let npc = npc.bind();
+
+        let mut title = self.base().get_node_as::<Label>("Info/Title");
+        title.set_text(npc.name().into());
+
+        let mut stats_text = self.base().get_node_as::<Label>("Info/Stats1");
+        stats_text.set_text(npc.vitals.health_text().into());
+
+        let mut stats_text = self.base().get_node_as::<Label>("Info/Stats2");
+        stats_text.set_text(format!("{} speed", npc.speed).into());
+
+        let mut stats_text = self.base().get_node_as::<Label>("Info/Stats3");
+        stats_text.set_text("Making for the way out".into());
+        self.details.clear();
 
         self.base_mut().set_visible(true);
     }
@@ -104,9 +177,59 @@ impl InfoPanel {
         let mut stats_text = self.base().get_node_as::<Label>("Info/Stats3");
         stats_text.set_text("".into());
 
+        self.details.clear();
         self.base_mut().set_visible(true);
     }
 
+    // Shows whatever stands on a tile, with anything lying beneath it added as notes
+    pub fn select_tile(&mut self, position: Position, level: &Level) {
+        let occupancy = &level.grid[position];
+        let mut notes = Vec::new();
+
+        match occupancy.tile {
+            Tile::Ally(ally_id) => self.select_ally(ally_id, level),
+            Tile::Enemy(enemy_id) => self.select_enemy(enemy_id, level),
+            Tile::Npc(npc_id) => self.select_npc(npc_id, level),
+            Tile::Empty | Tile::Obstacle(_) => match (occupancy.item, occupancy.terrain.first()) {
+                (Some(item_id), _) => self.select_item(item_id, level),
+                (None, Some(terrain)) => {
+                    let mut title = self.base().get_node_as::<Label>("Info/Title");
+                    title.set_text(terrain_name(*terrain).into());
+                    for label in ["Info/Stats1", "Info/Stats2", "Info/Stats3"] {
+                        self.base().get_node_as::<Label>(label).set_text("".into());
+                    }
+                    self.details.clear();
+                    self.base_mut().set_visible(true);
+                }
+                (None, None) => {
+                    self.deselect_tile();
+                    return;
+                }
+            },
+        }
+
+        if let Tile::Ally(_) | Tile::Enemy(_) | Tile::Npc(_) = occupancy.tile {
+            if let Some(item_id) = occupancy.item {
+                let item = level.get_item(item_id);
+                notes.push(format!("On {}", item.bind().name()));
+            }
+        }
+        for terrain in &occupancy.terrain {
+            let note = terrain_description(*terrain);
+            if !notes.contains(&note) {
+                notes.push(note);
+            }
+        }
+
+        if !notes.is_empty() {
+            let mut stats_text = self.base().get_node_as::<Label>("Info/Stats3");
+            let mut lines = vec![stats_text.get_text().to_string()];
+            lines.retain(|line| !line.is_empty());
+            lines.extend(notes);
+            stats_text.set_text(lines.join("\n").into());
+        }
+    }
+
     pub fn deselect_tile(&mut self) {
         self.selected_ally = None;
         self.selected_enemy = None;
@@ -128,6 +251,7 @@ impl InfoPanel {
         let mut stats_text = self.base().get_node_as::<Label>("Info/Stats3");
         stats_text.set_text("".into());
 
+        self.details.clear();
         self.base_mut().set_visible(true);
     }
 
@@ -144,16 +268,189 @@ impl InfoPanel {
             self.clear_info();
         }
     }
+
+    // The trait or effect line under the mouse, or every one of them while details is held
+    pub fn tooltip(&self) -> Option<(String, Vector2)> {
+        if !self.base().is_visible() || self.details.is_empty() {
+            return None;
+        }
+
+        let stats_text = self.base().get_node_as::<Label>("Info/Stats3");
+        let position = stats_text.get_global_position();
+        if Input::singleton().is_action_pressed("details".into()) {
+            return Some((self.details.join("\n\n"), position));
+        }
+
+        let mouse = stats_text.get_local_mouse_position();
+        if !Rect2::new(Vector2::ZERO, stats_text.get_size()).has_point(mouse) {
+            return None;
+        }
+
+        // Lines can only be told apart by height while none of them wrap
+        let text = stats_text.get_text().to_string();
+        if stats_text.get_line_count() as usize != text.lines().count() {
+            return None;
+        }
+        let line_height =
+            stats_text.get_line_height() + stats_text.get_theme_constant("line_spacing".into());
+        let line = (mouse.y / line_height as f32) as usize;
+        self.details.get(line).map(|details| {
+            let offset = Vector2::new(0.0, (line as i32 * line_height) as f32);
+            (details.clone(), position + offset * self.base().get_scale())
+        })
+    }
+}
+
+#[godot_api]
+impl ITextureRect for InfoPanel {
+    fn process(&mut self, _delta: f64) {
+        scale_panel(self.base().clone().upcast(), Vector2::new(1.0, 0.0));
+    }
+}
+
+// Grows a panel with the text size from the corner it sits in, which stays where it is
+fn scale_panel(mut panel: Gd<Control>, corner: Vector2) {
+    let size = panel.get_size();
+    panel.set_pivot_offset(size * corner);
+    panel.set_scale(Vector2::splat(settings::text_size()));
 }
 
 fn trait_description(trait_: Trait) -> String {
-    match trait_ {
-        Trait::SilverVulnerable => "Vulnerable to silver".into(),
-        Trait::HolyVulnerable => "Vulnerable to holy".into(),
-        Trait::StakeVulnerable => "Vulnerable to stakes".into(),
-        Trait::SunlightVulnerable => "Vulnerable to sunlight".into(),
-        Trait::HolyFromSunlight => "Sunlight deals holy damage".into(),
-        Trait::GarlicAllergy => "Allergic to garlic".into(),
+    trait_stats().get(&trait_).unwrap().summary.clone()
+}
+
+pub fn trait_tooltip(trait_: Trait) -> String {
+    let stats = trait_stats().get(&trait_).unwrap();
+    format!("{}\n{}", stats.summary, stats.description)
+}
+
+fn effect_tooltip(effect: Effect, active: &ActiveEffect) -> String {
+    format!(
+        "{}\n{}",
+        effect_description(effect, active),
+        effects::definition(effect).description
+    )
+}
+
+// The full rules of an ability, with what is left of it for the unit holding it
+fn ability_tooltip(ability: Ability, remaining: Option<String>) -> String {
+    let stats = abilities().get(&ability).unwrap();
+    let mut lines = vec![
+        stats.name.clone(),
+        stats.description.clone(),
+        action_description(stats.action),
+    ];
+
+    match stats.action {
+        Action::Attack {
+            damage_kind,
+            damage,
+            ..
+        }
+        | Action::Push {
+            damage_kind,
+            damage,
+            ..
+        }
+        | Action::Charge {
+            damage_kind,
+            damage,
+            ..
+        }
+        | Action::DashAttack {
+            damage_kind,
+            damage,
+            ..
+        } => lines.extend(damage_formula(damage_kind, damage)),
+        _ => (),
+    }
+
+    if stats.range > 0 {
+        lines.push(format!("{} range", stats.range));
+        lines.push(range_diagram(stats.range));
+    }
+    if stats.acquirable && stats.consumable {
+        lines.push("Aim at an adjacent ally to hand one over, once a turn".into());
+        lines.push("Aim at yourself or an open tile beside you to put one down".into());
+    }
+    lines.extend(remaining);
+    lines.join("\n")
+}
+
+// How each trait changes the damage, worked out by the same rules hits are resolved with
+fn damage_formula(damage_kind: DamageKind, damage: u16) -> Vec<String> {
+    trait_stats()
+        .iter()
+        .filter_map(|(trait_, stats)| {
+            let taken = damage_taken(damage, damage_kind, &[*trait_]);
+            let summary = &stats.summary;
+            match taken {
+                _ if taken == damage => None,
+                0 => Some(format!("{}: no damage", summary)),
+                _ if taken >= 1_000 => Some(format!("{}: killed", summary)),
+                _ => Some(format!("{}: {} damage", summary, taken)),
+            }
+        })
+        .collect()
+}
+
+// The tiles in reach around the user, who stands at the centre
+fn range_diagram(range: u16) -> String {
+    let centre = Position {
+        x: range as usize,
+        y: range as usize,
+    };
+    (0..=range as usize * 2)
+        .map(|y| {
+            (0..=range as usize * 2)
+                .map(|x| {
+                    let position = Position { x, y };
+                    if position == centre {
+                        '@'
+                    } else if centre.distance(position) <= range {
+                        'o'
+                    } else {
+                        '.'
+                    }
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn effect_description(effect: Effect, active: &ActiveEffect) -> String {
+    let turns = active.stats.duration;
+    match effect {
+        Effect::Burn => format!(
+            "Burning for {}, {} turns left",
+            active.stats.magnitude, turns
+        ),
+        Effect::Mist => format!("In mist form, {} turns left", turns),
+        Effect::Blessing => format!("Blessed, {} turns left", turns),
+        Effect::Guarding => format!("Guarding, {} turns left", turns),
+        Effect::Taunting => format!("Taunting, {} turns left", turns),
+        Effect::Blinded => format!("Blinded, {} turns left", turns),
+        Effect::Darkness => format!(
+            "Sight shrunk by {}, {} turns left",
+            active.stats.magnitude, turns
+        ),
+    }
+}
+
+fn terrain_name(terrain: Terrain) -> String {
+    match terrain {
+        Terrain::Garlic => "Garlic".into(),
+        Terrain::Sunlight => "Sunlight".into(),
+        Terrain::Fire => "Fire".into(),
+    }
+}
+
+fn terrain_description(terrain: Terrain) -> String {
+    match terrain {
+        Terrain::Garlic => "Warded by garlic".into(),
+        Terrain::Sunlight => "Burns any vampire that stands in it".into(),
+        Terrain::Fire => "Sets alight anyone who stands in it".into(),
     }
 }
 
@@ -173,6 +470,7 @@ fn action_description(action: Action) -> String {
                 DamageKind::LifeSteal => format!("{} damage, life steal", damage),
                 DamageKind::Stake => "Insta-kill a vampire".into(),
                 DamageKind::Sunlight => format!("{} sunlight damage", damage),
+                DamageKind::Pierce => format!("{} piercing damage", damage),
             },
             if aoe { "\nArea of effect" } else { "".into() }
         ),
@@ -188,121 +486,172 @@ fn action_description(action: Action) -> String {
             DamageKind::LifeSteal => format!("{} damage, life steal, push {}", damage, distance),
             DamageKind::Stake => format!("Insta-kill a vampire, push {}", distance),
             DamageKind::Sunlight => format!("{} sunlight damage, push {}", damage, distance),
+            DamageKind::Pierce => format!("{} piercing damage, push {}", damage, distance),
         },
-        Action::Effect { effect, .. } => match effect {
+        Action::Charge {
+            damage, distance, ..
+        } => format!("Charge, {} damage, push {}", damage, distance),
+        Action::DashAttack {
+            damage_kind: DamageKind::Stake,
+            distance,
+            ..
+        } => format!("Run up to {} tiles, insta-kill a vampire", distance),
+        Action::DashAttack {
+            damage, distance, ..
+        } => format!("Run up to {} tiles, {} damage", distance, damage),
+        Action::Effect { effect, stats } => match effect {
             Effect::Mist => "Transform into mist".into(),
-            _ => unreachable!(),
+            Effect::Guarding => format!(
+                "Take the next blow meant for an adjacent ally, {} less damage",
+                stats.magnitude
+            ),
+            Effect::Taunting => format!("Enemies within {} come for you", stats.magnitude),
+            // Only a mod gives anyone these
+            effect => format!("{:?} for {} turns", effect, stats.duration),
+        },
+        Action::Inflict { effect, stats } => match effect {
+            Effect::Blessing => format!("Vulnerable to holy for {} turns", stats.duration),
+            Effect::Blinded => format!("Blind a foe for {} turns", stats.duration),
+            Effect::Darkness => format!(
+                "Shrink a foe's sight by {} for {} turns",
+                stats.magnitude, stats.duration
+            ),
+            effect => format!("Inflict {:?} for {} turns", effect, stats.duration),
         },
         Action::PlaceItem { kind } => format!("Places {}", kind.name()),
-        _ => unreachable!(),
+        Action::Teleport => "Teleport to a visible tile".into(),
+        Action::Carry => "Pick up or drop an adjacent ally".into(),
+        Action::Unlock { turns: 1 } => "Open an adjacent locked door".into(),
+        Action::Unlock { turns } => format!("Open an adjacent locked door over {} turns", turns),
+        Action::Search => "Search an adjacent chest or bookshelf".into(),
+        Action::Pull => "Pull an adjacent lever".into(),
+        Action::Spoil => "Ruin an item on the ground".into(),
+        Action::Spawn {
+            enemy_kind,
+            cooldown,
+        } => format!("Summon a {}, cooldown {}", enemy_kind.name(), cooldown),
+        Action::Resurrect { cooldown } => format!("Raise the dead, cooldown {}", cooldown),
     }
 }
 
-const NUM_ICONS: usize = 8;
+// Icons that fit across the bar, the rest are scrolled to
+const VISIBLE_ICONS: usize = 8;
 
 #[derive(GodotClass)]
 #[class(init, base=HBoxContainer)]
 pub struct AbilityBar {
-    pub selected: Option<AllyId>,
+    pub selected: Option<UnitId>,
     pub length: usize,
     pub hovered: Option<usize>,
+    // Index of the first ability in view
+    pub offset: usize,
     base: Base<HBoxContainer>,
 }
 
 #[godot_api]
 impl IHBoxContainer for AbilityBar {
     fn process(&mut self, _delta: f64) {
-        let dialogue = self.base().get_node_as::<Dialogue>("../../Dialogue");
+        let Some(level) = find_level(&self.base()) else {
+            return;
+        };
+        let (Some(dialogue), Some(mut info_panel)) =
+            (nodes::dialogue(&level), nodes::info_panel(&level))
+        else {
+            return;
+        };
         let dialogue = dialogue.bind();
 
-        if dialogue.active {
+        if dialogue.active || sheet_open(&level) {
             return;
         }
 
         if let Some(selected) = self.selected {
             let input = Input::singleton();
 
-            let level = self.base().get_node_as::<Level>("../..");
             let level = level.bind();
-            let mut ally = level.get_ally(selected);
-            let mut ally = ally.bind_mut();
+            let (abilities, selected_ability) = level.unit_abilities(selected);
 
-            let mut info_panel = self.base().get_node_as::<InfoPanel>("../InfoPanel");
             let mut info_panel = info_panel.bind_mut();
 
             let toggled = input.is_action_just_pressed("choose".into())
                 || input.is_action_just_pressed("select".into()) && self.hovered.is_some();
             if toggled {
+                audio::play(Sfx::Select);
                 match self.hovered {
-                    Some(i) => {
-                        self.hovered = None;
-
-                        let mut icon = self
-                            .base()
-                            .get_node_as::<AbilityIcon>(format!("AbilityIcon{}", i));
-                        let mut icon = icon.bind_mut();
-                        icon.set_selected(true);
-                        icon.set_hovered(false);
-
-                        info_panel.deselect_ability(&level);
-                    }
+                    Some(_) => self.unhover(&level, &mut info_panel),
                     None => {
-                        self.hovered = Some(ally.selected_ability);
+                        self.hovered = Some(selected_ability);
 
-                        let mut icon = self.base().get_node_as::<AbilityIcon>(format!(
-                            "AbilityIcon{}",
-                            ally.selected_ability
-                        ));
+                        let mut icon = self.icon(selected_ability);
                         let mut icon = icon.bind_mut();
                         icon.set_selected(false);
                         icon.set_hovered(true);
 
-                        info_panel.select_ability(*ally.current_ability());
+                        info_panel.select_ability(abilities[selected_ability]);
                     }
                 }
             }
 
             if let Some(i) = self.hovered {
                 if input.is_action_just_pressed("left".into()) {
-                    let mut icon = self
-                        .base()
-                        .get_node_as::<AbilityIcon>(format!("AbilityIcon{}", i));
+                    let mut icon = self.icon(i);
                     let mut icon = icon.bind_mut();
                     icon.set_hovered(false);
 
                     let i = if i > 0 { i - 1 } else { self.length - 1 };
+                    audio::play(Sfx::Navigate);
+                    self.scroll_to(i);
 
-                    let mut icon = self
-                        .base()
-                        .get_node_as::<AbilityIcon>(format!("AbilityIcon{}", i));
+                    let mut icon = self.icon(i);
                     let mut icon = icon.bind_mut();
                     icon.set_hovered(true);
 
-                    ally.selected_ability = i;
+                    level.select_unit_ability(selected, i);
                     self.hovered = Some(i);
 
-                    info_panel.select_ability(*ally.current_ability());
+                    info_panel.select_ability(abilities[i]);
                 }
 
                 if input.is_action_just_pressed("right".into()) {
-                    let mut icon = self
-                        .base()
-                        .get_node_as::<AbilityIcon>(format!("AbilityIcon{}", i));
+                    let mut icon = self.icon(i);
                     let mut icon = icon.bind_mut();
                     icon.set_hovered(false);
 
                     let i = if i < self.length - 1 { i + 1 } else { 0 };
+                    audio::play(Sfx::Navigate);
+                    self.scroll_to(i);
 
-                    let mut icon = self
-                        .base()
-                        .get_node_as::<AbilityIcon>(format!("AbilityIcon{}", i));
+                    let mut icon = self.icon(i);
                     let mut icon = icon.bind_mut();
                     icon.set_hovered(true);
 
-                    ally.selected_ability = i;
+                    level.select_unit_ability(selected, i);
                     self.hovered = Some(i);
 
-                    info_panel.select_ability(*ally.current_ability());
+                    info_panel.select_ability(abilities[i]);
+                }
+            }
+
+            // Number keys pick from the abilities in view and tab cycles through all of them,
+            // without hovering the bar first
+            if self.length > 0 {
+                let current = self.hovered.unwrap_or(selected_ability);
+                // Shift-tab also presses tab, so it is checked first
+                let picked = if input.is_action_just_pressed("previous_ability".into()) {
+                    Some((current + self.length - 1) % self.length)
+                } else if input.is_action_just_pressed("next_ability".into()) {
+                    Some((current + 1) % self.length)
+                } else {
+                    (0..VISIBLE_ICONS)
+                        .find(|k| input.is_action_just_pressed(format!("ability_{}", k + 1).into()))
+                        .map(|k| self.offset + k)
+                        .filter(|i| *i < self.length)
+                };
+
+                if let Some(i) = picked {
+                    self.pick(current, i);
+                    level.select_unit_ability(selected, i);
+                    info_panel.select_ability(abilities[i]);
                 }
             }
         }
@@ -310,11 +659,10 @@ impl IHBoxContainer for AbilityBar {
 }
 
 impl AbilityBar {
-    pub fn select_ally(&mut self, ally: &Ally) {
-        for i in 0..NUM_ICONS {
-            let mut icon = self
-                .base()
-                .get_node_as::<AbilityIcon>(format!("AbilityIcon{}", i));
+    pub fn select_ally(&mut self, ally: &Ally, level: &Level) {
+        self.add_icons(ally.abilities.len());
+        for i in 0..self.icon_count() {
+            let mut icon = self.icon(i);
             let mut icon = icon.bind_mut();
             let ability = ally.abilities.get(i);
             icon.set_ability(
@@ -322,29 +670,490 @@ impl AbilityBar {
                 *ability
                     .map(|ability| ally.uses.get(ability).unwrap())
                     .unwrap_or(&0),
+                ability.map(|ability| level.supply(*ability)),
             );
-
-            if i == ally.selected_ability {
-                icon.set_selected(true);
-            }
+            icon.set_selected(i == ally.selected_ability);
         }
         self.length = ally.abilities.len();
-        self.selected = Some(ally.id);
+        self.selected = Some(UnitId::Ally(ally.id));
+        self.offset = 0;
+        self.scroll_to(ally.selected_ability);
+    }
+
+    // Enemies have no limited uses, only cooldowns
+    pub fn select_enemy(&mut self, enemy: &Enemy) {
+        self.add_icons(enemy.abilities.len());
+        for i in 0..self.icon_count() {
+            let mut icon = self.icon(i);
+            let mut icon = icon.bind_mut();
+            icon.set_ability(enemy.abilities.get(i), 0, None);
+            icon.set_selected(i == enemy.selected_ability);
+        }
+        self.length = enemy.abilities.len();
+        self.selected = Some(UnitId::Enemy(enemy.id));
+        self.offset = 0;
+        self.scroll_to(enemy.selected_ability);
     }
 
     pub fn select_none(&mut self) {
-        for i in 0..NUM_ICONS {
-            let mut icon = self
-                .base()
-                .get_node_as::<AbilityIcon>(format!("AbilityIcon{}", i));
+        for i in 0..self.icon_count() {
+            let mut icon = self.icon(i);
             let mut icon = icon.bind_mut();
-            icon.set_ability(None, 0);
+            icon.set_ability(None, 0, None);
             icon.set_selected(false);
             icon.set_hovered(false);
         }
         self.length = 0;
         self.selected = None;
         self.hovered = None;
+        self.offset = 0;
+        self.scroll_to(0);
+    }
+
+    fn icon(&self, i: usize) -> Gd<AbilityIcon> {
+        self.base()
+            .get_node_as::<AbilityIcon>(format!("Icons/AbilityIcon{}", i))
+    }
+
+    fn icon_count(&self) -> usize {
+        self.base().get_node_as::<Node>("Icons").get_child_count() as usize
+    }
+
+    // Icons are made as abilities are picked up and kept for the next unit selected
+    fn add_icons(&mut self, count: usize) {
+        let mut icons = self.base().get_node_as::<Node>("Icons");
+        for i in self.icon_count()..count {
+            let scene = load::<PackedScene>("res://scenes/ability_icon.tscn");
+            let mut icon: Gd<AbilityIcon> = scene.instantiate().unwrap().cast();
+            icon.set_name(format!("AbilityIcon{}", i).into());
+            icons.add_child(icon.upcast());
+        }
+    }
+
+    // Leaves the bar with the hovered ability selected
+    pub fn unhover(&mut self, level: &Level, info_panel: &mut InfoPanel) {
+        let Some(i) = self.hovered.take() else {
+            return;
+        };
+
+        let mut icon = self.icon(i);
+        let mut icon = icon.bind_mut();
+        icon.set_selected(true);
+        icon.set_hovered(false);
+
+        info_panel.deselect_ability(level);
+    }
+
+    // The ability under the mouse, or the hovered one while details is held
+    pub fn tooltip(&self, level: &Level) -> Option<(String, Vector2)> {
+        let selected = self.selected?;
+        let under_mouse = (self.offset..self.length.min(self.offset + VISIBLE_ICONS)).find(|i| {
+            let icon = self.icon(*i);
+            icon.get_global_rect()
+                .has_point(icon.get_global_mouse_position())
+        });
+        let i = under_mouse.or(self
+            .hovered
+            .filter(|_| Input::singleton().is_action_pressed("details".into())))?;
+
+        let icon = self.icon(i);
+        let ability = icon.bind().ability?;
+        let remaining = match selected {
+            UnitId::Ally(ally_id) => {
+                let ally = level.try_get_ally(ally_id)?;
+                let ally = ally.bind();
+                let stats = abilities().get(&ability).unwrap();
+                stats.consumable.then(|| {
+                    format!(
+                        "{} uses left\n{}",
+                        ally.uses.get(&ability).unwrap_or(&0),
+                        level.supply(ability).describe()
+                    )
+                })
+            }
+            UnitId::Enemy(enemy_id) => {
+                let enemy = level.try_get_enemy(enemy_id)?;
+                let enemy = enemy.bind();
+                match enemy.cooldowns.get(&ability) {
+                    Some(cooldown) if *cooldown > 0 => Some(format!("Ready in {} turns", cooldown)),
+                    _ => None,
+                }
+            }
+            UnitId::Npc(_) => None,
+        };
+
+        let position = icon.get_global_position() + Vector2::new(0.0, icon.get_size().y + 2.0);
+        Some((ability_tooltip(ability, remaining), position))
+    }
+
+    // Moves the highlight between icons, as a hover if the bar is being hovered
+    fn pick(&mut self, from: usize, to: usize) {
+        audio::play(Sfx::Navigate);
+
+        let mut icon = self.icon(from);
+        let mut icon = icon.bind_mut();
+        icon.set_selected(false);
+        icon.set_hovered(false);
+        drop(icon);
+
+        self.scroll_to(to);
+
+        let mut icon = self.icon(to);
+        let mut icon = icon.bind_mut();
+        if self.hovered.is_some() {
+            icon.set_hovered(true);
+            self.hovered = Some(to);
+        } else {
+            icon.set_selected(true);
+        }
+    }
+
+    // Moves the window of icons just far enough to show the ability at i
+    fn scroll_to(&mut self, i: usize) {
+        if i < self.offset {
+            self.offset = i;
+        } else if i >= self.offset + VISIBLE_ICONS {
+            self.offset = i + 1 - VISIBLE_ICONS;
+        }
+
+        for j in 0..self.icon_count() {
+            let in_view =
+                j < self.length && (self.offset..self.offset + VISIBLE_ICONS).contains(&j);
+            self.icon(j).set_visible(in_view);
+        }
+
+        let mut previous = self.base().get_node_as::<Label>("Previous");
+        previous.set_visible(self.offset > 0);
+        let mut next = self.base().get_node_as::<Label>("Next");
+        next.set_visible(self.offset + VISIBLE_ICONS < self.length);
+    }
+}
+
+impl Listener for AbilityBar {
+    fn on_event(&mut self, event: GameEvent, level: &Level) {
+        match event {
+            GameEvent::AllyMoved(ally_id)
+            | GameEvent::ItemPickedUp { ally_id, .. }
+            | GameEvent::ItemDropped { ally_id, .. } => {
+                let ally = level.get_ally(ally_id);
+                self.select_ally(&ally.bind(), level);
+            }
+            // Whoever is selected gave or took it, and the bar shows their uses
+            GameEvent::ItemTraded { .. } => {
+                if let Some(UnitId::Ally(ally_id)) = self.selected {
+                    let ally = level.get_ally(ally_id);
+                    self.select_ally(&ally.bind(), level);
+                }
+            }
+            GameEvent::AllyActed(_) => self.select_none(),
+            _ => (),
+        }
+    }
+}
+
+// Everything known about one unit, opened over the whole screen with the inspect key
+#[derive(GodotClass)]
+#[class(init, base=PanelContainer)]
+pub struct UnitSheet {
+    // Frame the sheet closed on, so the key that closed it isn't taken by the cursor as well
+    closed_on: Option<u64>,
+    base: Base<PanelContainer>,
+}
+
+#[godot_api]
+impl IPanelContainer for UnitSheet {
+    fn process(&mut self, _delta: f64) {
+        let input = Input::singleton();
+        if self.base().is_visible() {
+            if input.is_action_just_pressed("inspect".into())
+                || input.is_action_just_pressed("cancel".into())
+            {
+                self.base_mut().set_visible(false);
+                self.closed_on = Some(Engine::singleton().get_process_frames());
+            }
+            return;
+        }
+
+        if !input.is_action_just_pressed("inspect".into()) {
+            return;
+        }
+        let Some(level) = find_level(&self.base()) else {
+            return;
+        };
+        let (Some(cursor), Some(dialogue)) = (nodes::cursor(&level), nodes::dialogue(&level))
+        else {
+            return;
+        };
+        if dialogue.bind().active {
+            return;
+        }
+
+        let position = cursor.bind().position;
+        let level = level.bind();
+        let unit = match level.at(position) {
+            Tile::Ally(ally_id) => UnitId::Ally(ally_id),
+            Tile::Enemy(enemy_id) => UnitId::Enemy(enemy_id),
+            _ => return,
+        };
+        if self.show_unit(unit, &level) {
+            audio::play(Sfx::Select);
+            self.base_mut().set_visible(true);
+        }
+    }
+}
+
+impl UnitSheet {
+    pub fn is_open(&self) -> bool {
+        self.base().is_visible() || self.closed_on == Some(Engine::singleton().get_process_frames())
+    }
+
+    fn show_unit(&mut self, unit: UnitId, level: &Level) -> bool {
+        let kills = level.stats.kills.get(&unit).copied().unwrap_or(0);
+        let (name, stats, abilities, vitals) = match unit {
+            UnitId::Ally(ally_id) => {
+                let Some(ally) = level.try_get_ally(ally_id) else {
+                    return false;
+                };
+                let ally = ally.bind();
+                let stats = format!(
+                    "{}\n{} speed\n{} view distance\n{} kills",
+                    ally.vitals.health_text(),
+                    ally.speed,
+                    ally.view_distance,
+                    kills
+                );
+                let abilities = ally
+                    .abilities
+                    .iter()
+                    .map(|ability| {
+                        let stats = abilities().get(ability).unwrap();
+                        let uses = ally.uses.get(ability).unwrap_or(&0);
+                        ability_sheet(
+                            *ability,
+                            stats.consumable.then(|| format!("{} uses left", uses)),
+                        )
+                    })
+                    .collect::<Vec<String>>();
+                (ally.name(), stats, abilities, ally.vitals.clone())
+            }
+            UnitId::Enemy(enemy_id) => {
+                let Some(enemy) = level.try_get_enemy(enemy_id) else {
+                    return false;
+                };
+                let enemy = enemy.bind();
+                let stats = format!(
+                    "{}\n{} speed\n{} view distance\n{} kills",
+                    enemy.vitals.health_text(),
+                    enemy.speed,
+                    enemy.view_distance,
+                    kills
+                );
+                let abilities = enemy
+                    .abilities
+                    .iter()
+                    .map(|ability| {
+                        let cooldown = enemy.cooldowns.get(ability).filter(|c| **c > 0);
+                        ability_sheet(
+                            *ability,
+                            cooldown.map(|cooldown| format!("Ready in {} turns", cooldown)),
+                        )
+                    })
+                    .collect::<Vec<String>>();
+                (enemy.name(), stats, abilities, enemy.vitals.clone())
+            }
+            UnitId::Npc(_) => return false,
+        };
+
+        let traits = vitals.traits();
+        let sections = [
+            ("Sheet/Title", name),
+            ("Sheet/Columns/Left/Stats", stats),
+            (
+                "Sheet/Columns/Left/Traits",
+                traits
+                    .iter()
+                    .map(|trait_| trait_tooltip(*trait_))
+                    .collect::<Vec<String>>()
+                    .join("\n\n"),
+            ),
+            (
+                "Sheet/Columns/Left/Effects",
+                vitals
+                    .effects
+                    .iter()
+                    .map(|(effect, active)| effect_tooltip(effect, active))
+                    .collect::<Vec<String>>()
+                    .join("\n\n"),
+            ),
+            ("Sheet/Columns/Left/Resistances", resistances(&traits)),
+            ("Sheet/Columns/Abilities", abilities.join("\n\n")),
+        ];
+        for (path, text) in sections {
+            let mut label = self.base().get_node_as::<Label>(path);
+            label.set_text(text.into());
+        }
+        true
+    }
+}
+
+// Whether a sheet is up or has just closed, for input that shouldn't reach the level behind it
+pub fn sheet_open(level: &Node) -> bool {
+    nodes::unit_sheet(level).is_some_and(|sheet| sheet.bind().is_open())
+}
+
+// Gives a pad somewhere to start from when a menu opens, the first control that can take it
+pub fn focus_first(node: Gd<Node>) -> bool {
+    for child in node.get_children().iter_shared() {
+        let Ok(mut control) = child.try_cast::<Control>() else {
+            continue;
+        };
+        if !control.is_visible() {
+            continue;
+        }
+        let disabled = control
+            .clone()
+            .try_cast::<BaseButton>()
+            .is_ok_and(|button| button.is_disabled());
+        if control.get_focus_mode() == FocusMode::ALL && !disabled {
+            control.grab_focus();
+            return true;
+        }
+        if focus_first(control.upcast()) {
+            return true;
+        }
+    }
+    false
+}
+
+fn ability_sheet(ability: Ability, remaining: Option<String>) -> String {
+    let stats = abilities().get(&ability).unwrap();
+    let mut lines = vec![
+        stats.name.clone(),
+        stats.description.clone(),
+        action_description(stats.action),
+        format!("{} range", stats.range),
+    ];
+    lines.extend(remaining);
+    lines.join("\n")
+}
+
+// What each kind of damage does against the unit's traits, next to a plain hit of the same size
+pub fn resistances(traits: &[Trait]) -> String {
+    const PROBE: u16 = 10;
+    let lines: Vec<String> = DamageKind::ALL
+        .iter()
+        .filter_map(|damage_kind| {
+            let taken = damage_taken(PROBE, *damage_kind, traits);
+            match taken {
+                _ if taken == PROBE => None,
+                0 => Some(format!("{}: immune", damage_kind.name())),
+                _ if taken >= 1_000 => Some(format!("{}: fatal", damage_kind.name())),
+                _ if taken > PROBE => Some(format!("{}: +{}", damage_kind.name(), taken - PROBE)),
+                _ => Some(format!("{}: -{}", damage_kind.name(), PROBE - taken)),
+            }
+        })
+        .collect();
+    if lines.is_empty() {
+        "No resistances".into()
+    } else {
+        lines.join("\n")
+    }
+}
+
+// Turn number, objectives and enemies left, kept up to date from the level
+#[derive(GodotClass)]
+#[class(init, base=PanelContainer)]
+pub struct Hud {
+    base: Base<PanelContainer>,
+}
+
+#[godot_api]
+impl IPanelContainer for Hud {
+    fn process(&mut self, _delta: f64) {
+        let Some(level) = find_level(&self.base()) else {
+            return;
+        };
+        let prompt = nodes::cursor(&level).and_then(|cursor| cursor.bind().prompt());
+        let level = level.bind();
+
+        let mut lines = vec![format!("Turn {}", level.round)];
+        if level.weather != Weather::Clear {
+            lines.push(format!(
+                "{}: {}",
+                level.weather.name(),
+                level.weather.description()
+            ));
+        }
+        lines.extend(
+            level
+                .objectives()
+                .into_iter()
+                .map(|objective| format!("- {}", objective)),
+        );
+        lines.push(match level.enemies_remaining() {
+            1 => "1 enemy left".into(),
+            remaining => format!("{} enemies left", remaining),
+        });
+        if let Some(prompt) = prompt {
+            lines.push(prompt.into());
+        }
+        for achievement in level.achievements.announcements() {
+            lines.push(format!(
+                "Achievement unlocked: {}",
+                achievements::definition(achievement).name
+            ));
+        }
+
+        let mut label = self.base().get_node_as::<Label>("Text");
+        label.set_text(lines.join("\n").into());
+        scale_panel(self.base().clone().upcast(), Vector2::ZERO);
+    }
+}
+
+// Full descriptions of whatever the mouse rests on, or of whatever is hovered while details is held
+#[derive(GodotClass)]
+#[class(init, base=PanelContainer)]
+pub struct Tooltip {
+    base: Base<PanelContainer>,
+}
+
+#[godot_api]
+impl IPanelContainer for Tooltip {
+    fn process(&mut self, _delta: f64) {
+        let Some(level) = find_level(&self.base()) else {
+            return;
+        };
+        let dialogue_active = nodes::dialogue(&level)
+            .is_some_and(|dialogue| dialogue.bind().active)
+            || sheet_open(&level);
+        let ability_bar = nodes::ability_bar(&level);
+        let info_panel = nodes::info_panel(&level);
+
+        let shown = if dialogue_active {
+            None
+        } else {
+            let level = level.bind();
+            ability_bar
+                .and_then(|ability_bar| ability_bar.bind().tooltip(&level))
+                .or_else(|| info_panel.and_then(|info_panel| info_panel.bind().tooltip()))
+        };
+
+        match shown {
+            Some((text, position)) => {
+                let mut label = self.base().get_node_as::<Label>("Text");
+                label.set_text(text.into());
+
+                // Slid back from the right edge to stay on screen
+                scale_panel(self.base().clone().upcast(), Vector2::ZERO);
+                let width = self.base().get_viewport_rect().size.x;
+                let size = self.base().get_size() * self.base().get_scale();
+                let x = position.x.min(width - size.x).max(0.0);
+                self.base_mut()
+                    .set_global_position(Vector2::new(x, position.y));
+                self.base_mut().set_visible(true);
+            }
+            None => self.base_mut().set_visible(false),
+        }
     }
 }
 
@@ -358,15 +1167,26 @@ pub struct AbilityIcon {
 }
 
 impl AbilityIcon {
-    pub fn set_ability(&mut self, ability: Option<&Ability>, uses: u16) {
+    // A + marks more to be found in the level, red the last the party has of it
+    pub fn set_ability(&mut self, ability: Option<&Ability>, uses: u16, supply: Option<Supply>) {
         match ability {
             Some(ability) => {
                 self.base_mut().set_visible(true);
 
                 let stats = abilities().get(ability).unwrap();
+                let supply = supply.unwrap_or_default();
                 let mut amount = self.base().get_node_as::<Label>("Amount");
                 amount.set_visible(stats.consumable);
-                amount.set_text(uses.to_string().into());
+                if supply.more_in_level() {
+                    amount.set_text(format!("{}+", uses).into());
+                } else {
+                    amount.set_text(uses.to_string().into());
+                }
+                amount.set_modulate(if supply.is_last() {
+                    Color::from_rgb(1.0, 0.3, 0.3)
+                } else {
+                    Color::from_rgb(1.0, 1.0, 1.0)
+                });
             }
             None => self.base_mut().set_visible(false),
         }
@@ -406,8 +1226,36 @@ impl AbilityIcon {
                     Ability::Mist => Vector2::new(168.0, y),
                     Ability::WoodenStake => Vector2::new(192.0, y),
                     Ability::Garlic => Vector2::new(216.0, y),
-                    Ability::HolyWater => Vector2::new(240.0, y),
-                    _ => unreachable!(),
+                    Ability::HolyWater | Ability::FireFlask => Vector2::new(240.0, y),
+                    Ability::Teleport => Vector2::new(264.0, y),
+                    Ability::Blessing => Vector2::new(288.0, y),
+                    Ability::Carry => Vector2::new(312.0, y),
+                    Ability::Smite => Vector2::new(336.0, y),
+                    Ability::BatBite => Vector2::new(360.0, y),
+                    Ability::VampireScratch => Vector2::new(384.0, y),
+                    Ability::BigBatBite => Vector2::new(408.0, y),
+                    Ability::SpawnBat => Vector2::new(432.0, y),
+                    Ability::DrainLife => Vector2::new(456.0, y),
+                    Ability::Resurrect => Vector2::new(480.0, y),
+                    Ability::Claw => Vector2::new(504.0, y),
+                    Ability::Charge => Vector2::new(528.0, y),
+                    Ability::ChillingTouch => Vector2::new(552.0, y),
+                    Ability::Key => Vector2::new(576.0, y),
+                    Ability::Lockpick => Vector2::new(600.0, y),
+                    Ability::Search => Vector2::new(624.0, y),
+                    Ability::Pull => Vector2::new(648.0, y),
+                    // Shares the search icon until it has its own
+                    Ability::Spoil => Vector2::new(624.0, y),
+                    // Borrowed from the club and the smite until they have their own
+                    Ability::Guard => Vector2::new(72.0, y),
+                    Ability::Taunt => Vector2::new(336.0, y),
+                    Ability::HolyFlash => Vector2::new(288.0, y),
+                    Ability::Darkness => Vector2::new(552.0, y),
+                    // Borrowed from the stake and the claw until they have their own
+                    Ability::LungingStake => Vector2::new(192.0, y),
+                    Ability::Pounce => Vector2::new(504.0, y),
+                    // Mods don't bring icons, so theirs all wear the stake's
+                    Ability::Custom(_) => Vector2::new(192.0, y),
                 };
                 atlas.set_region(Rect2::new(position, Vector2::new(24.0, 24.0)));
             }
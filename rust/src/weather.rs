@@ -0,0 +1,127 @@
+use crate::math::Position;
+
+use godot::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// Keeps storm rolls apart from loot and morale drawn off the same seed
+pub const WEATHER_STREAM: u64 = 0x400;
+
+// Out of 100, the chance a shot in a storm comes down a tile short
+pub const STORM_SHORT_CHANCE: u16 = 33;
+
+// What the night is like outside, set on the level scene. Clear changes nothing
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    GodotConvert,
+    Var,
+    Export,
+    Serialize,
+    Deserialize,
+)]
+#[godot(via = u8)]
+pub enum Weather {
+    #[default]
+    Clear,
+    // Enemies see a tile further
+    Moonlit,
+    // Everyone sees half as far
+    Fog,
+    // Shots are blown off course
+    Storm,
+}
+
+impl Weather {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Clear => "Clear",
+            Self::Moonlit => "Moonlit",
+            Self::Fog => "Fog",
+            Self::Storm => "Storm",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Clear => "",
+            Self::Moonlit => "Enemies see further",
+            Self::Fog => "Everyone sees half as far",
+            Self::Storm => "Shots may fall short",
+        }
+    }
+
+    pub fn ally_view(&self, view_distance: u16) -> u16 {
+        match self {
+            Self::Fog => (view_distance / 2).max(1),
+            _ => view_distance,
+        }
+    }
+
+    pub fn enemy_view(&self, view_distance: u16) -> u16 {
+        match self {
+            Self::Moonlit => view_distance + 1,
+            Self::Fog => (view_distance / 2).max(1),
+            _ => view_distance,
+        }
+    }
+
+    // A roll out of 100, only shots that travel past the next tile can be blown short
+    pub fn falls_short(&self, distance: u16, roll: u16) -> bool {
+        *self == Self::Storm && distance > 1 && roll < STORM_SHORT_CHANCE
+    }
+
+    // The tile a shot from the origin along the line comes down on, the one before the target
+    // when it falls short. The line is every tile after the origin up to the target
+    pub fn landing(&self, origin: Position, line: &[Position], roll: u16) -> Position {
+        let target = *line.last().unwrap();
+        if line.len() > 2 && self.falls_short(origin.distance(target), roll) {
+            line[line.len() - 2]
+        } else {
+            target
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weather_changes_who_sees_how_far_and_where_shots_land() {
+        assert_eq!(Weather::Clear.ally_view(8), 8);
+        assert_eq!(Weather::Moonlit.ally_view(8), 8);
+        assert_eq!(Weather::Moonlit.enemy_view(6), 7);
+        assert_eq!(Weather::Fog.ally_view(8), 4);
+        assert_eq!(Weather::Fog.enemy_view(1), 1);
+
+        assert!(Weather::Storm.falls_short(4, STORM_SHORT_CHANCE - 1));
+        assert!(!Weather::Storm.falls_short(4, STORM_SHORT_CHANCE));
+        assert!(!Weather::Storm.falls_short(1, 0));
+        assert!(!Weather::Fog.falls_short(4, 0));
+    }
+
+    #[test]
+    fn shots_come_down_where_they_were_fired_unless_a_storm_cuts_them_short() {
+        let origin = Position { x: 0, y: 0 };
+        let line: Vec<Position> = (1..=4).map(|x| Position { x, y: 0 }).collect();
+        let target = Position { x: 4, y: 0 };
+
+        // Settled from the roll alone, so the same shot lands the same way every time
+        assert_eq!(Weather::Clear.landing(origin, &line, 0), target);
+        assert_eq!(
+            Weather::Storm.landing(origin, &line, 0),
+            Position { x: 3, y: 0 }
+        );
+        assert_eq!(
+            Weather::Storm.landing(origin, &line, STORM_SHORT_CHANCE),
+            target
+        );
+        // Too close to be blown anywhere but the target
+        assert_eq!(Weather::Storm.landing(origin, &line[..2], 0), line[1]);
+    }
+}